@@ -31,15 +31,16 @@ fn brainfuck_benchmark(c: &mut Criterion) {
 fn parse_benchmark(c: &mut Criterion) {
     let source_file = File::open("benches/brainfuck.bbl").unwrap();
     let src = std::io::read_to_string(source_file).unwrap();
-    let lexer = Lexer::new(&src);
-    let mut parser = Parser::new(lexer);
-    c.bench_function("parse", |b| b.iter(|| parser.parse_program()));
+    // The lexer and the parser consume the source, so every iteration starts from scratch.
+    c.bench_function("lex", |b| b.iter(|| Lexer::new(black_box(&src)).count()));
+    c.bench_function("parse", |b| {
+        b.iter(|| Parser::new(Lexer::new(black_box(&src))).parse_program())
+    });
 
-    let ast = parser.parse_program().unwrap();
+    let ast = Parser::new(Lexer::new(&src)).parse_program().unwrap();
     let mut compiler = Compiler::default();
     c.bench_function("compile", |b| b.iter(|| compiler.compile(ast.clone())));
 
-    let ast = parser.parse_program().unwrap();
     let chunk = Rc::new(compiler.compile(ast).unwrap());
     let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![])));
     c.bench_function("run", |b| b.iter(|| vm.load_and_run(chunk.clone())));
@@ -0,0 +1,9 @@
+#![no_main]
+
+use brainterpreter::ast::Program;
+use brainterpreter::fuzz::fuzz_compile_and_run;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|program: Program| {
+    let _ = fuzz_compile_and_run(program);
+});
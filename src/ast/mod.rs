@@ -0,0 +1,324 @@
+//! Abstract syntax tree
+
+use crate::ast::Expression::{BinaryOperation, NumberLiteral, UnaryOperation};
+use crate::source::Span;
+
+pub mod visit;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BinaryOperator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    /// Raises the left operand to the power of the right one.
+    Pow,
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+    /// Logical conjunction. The right operand is only evaluated when the left one is true.
+    And,
+    /// Logical disjunction. The right operand is only evaluated when the left one is false.
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnaryOperator {
+    Not,
+    Negate,
+}
+
+/// Changes the value of a variable by one.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UpdateOperator {
+    Increment,
+    Decrement,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Program {
+    statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Expression {
+    Nil,
+    NumberLiteral(f64),
+    IntegerLiteral(i64),
+    BooleanLiteral(bool),
+    StringLiteral(String),
+    /// Access to array-like variable element by index
+    Index {
+        array: Box<Expression>,
+        index: Box<Expression>,
+    },
+    Variable(String),
+    /// Assignment to a variable or to the element of an array or map: `target = value`
+    Assign {
+        target: Box<Expression>,
+        value: Box<Expression>,
+    },
+    Array {
+        initial: Box<Expression>,
+        size: Box<Expression>,
+    },
+    /// Fixed group of values: `(a, b)`
+    Tuple(Vec<Expression>),
+    /// Map literal: `{ "key": value }`
+    Map(Vec<(Expression, Expression)>),
+    /// Anonymous function: `fun (a, b) { ... }`
+    Lambda(Vec<String>, Box<Statement>),
+    /// Statements in their own scope followed by the expression giving the value: `{ let a = 2; a * a }`.
+    /// The block without the final expression results in nil.
+    Block {
+        statements: Vec<Statement>,
+        value: Option<Box<Expression>>,
+    },
+    /// Increment or decrement of a variable: `++a`, `a++`, `--a`, or `a--`.
+    /// The prefix form results in the new value, the postfix one in the old value.
+    Update {
+        operator: UpdateOperator,
+        variable: String,
+        prefix: bool,
+    },
+    /// Call of the function the callee expression results in: `f(x)`, `handlers[0](x)`, or `make_handler()(x)`.
+    Call(Box<Expression>, Vec<Expression>),
+    BinaryOperation(BinaryOperator, Box<Expression>, Box<Expression>),
+    UnaryOperation(UnaryOperator, Box<Expression>),
+    /// Numbers from the start up to, but not including, the end: `start..end`.
+    Range {
+        start: Box<Expression>,
+        end: Box<Expression>,
+    },
+    /// Conditional expression: `condition ? then : otherwise`.
+    /// Only the branch selected by the condition is evaluated.
+    Conditional {
+        condition: Box<Expression>,
+        then: Box<Expression>,
+        otherwise: Box<Expression>,
+    },
+}
+
+/// Represents a statement of the language
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Statement {
+    Expression(Expression),
+    DeclareVariable(String),
+    DefineVariable(String, Expression),
+    /// Unpacks the tuple into new variables: `let (a, b) = value;`
+    DefineVariables(Vec<String>, Expression),
+    /// Global that cannot be assigned after the definition: `const NAME = value;`
+    DefineConstant(String, Expression),
+    Function {
+        name: String,
+        params: Vec<String>,
+        body: Box<Statement>,
+        /// Documentation collected from `///` comments preceding the declaration
+        doc: Option<String>,
+    },
+    Print(Expression),
+    Block(Vec<Statement>),
+    If(Expression, Box<Statement>, Option<Box<Statement>>),
+    While(Expression, Box<Statement>),
+    /// Loop checking the condition after the body, so the body runs at least once:
+    /// `do body while (condition);`
+    DoWhile(Box<Statement>, Expression),
+    /// C-style loop: `for (initializer; condition; increment) body`
+    For {
+        initializer: Option<Box<Statement>>,
+        condition: Option<Expression>,
+        increment: Option<Expression>,
+        body: Box<Statement>,
+    },
+    /// Loop over the elements of a collection: `for (variable in collection) body`
+    ForIn {
+        variable: String,
+        collection: Expression,
+        body: Box<Statement>,
+    },
+    /// Exits the innermost loop
+    Break,
+    /// Skips to the next iteration of the innermost loop
+    Continue,
+    Return(Expression),
+    /// Stops the execution and passes the value to the innermost enclosing `catch`.
+    Throw(Expression),
+    /// Runs the body and, if it throws, the handler with the thrown value in the variable.
+    Try {
+        body: Box<Statement>,
+        variable: String,
+        handler: Box<Statement>,
+    },
+    /// Runs the body of the first case with the value equal to the subject,
+    /// or the default body if no case matches.
+    Switch {
+        subject: Expression,
+        cases: Vec<(Expression, Statement)>,
+        default: Option<Box<Statement>>,
+    },
+    /// Stops the program with an error when the condition is false: `assert(condition, message)`.
+    Assert {
+        condition: Expression,
+        message: Option<Expression>,
+    },
+    /// Statement annotated with the span of its first token in the source code
+    Located(Span, Box<Statement>),
+}
+
+impl Program {
+    pub fn new(statements: Vec<Statement>) -> Self {
+        Program { statements }
+    }
+
+    pub fn add_statement(&mut self, statement: Statement) {
+        self.statements.push(statement);
+    }
+
+    pub fn statements(&self) -> &[Statement] {
+        &self.statements
+    }
+}
+
+impl Expression {
+    pub fn binary(op: BinaryOperator, lhs: Expression, rhs: Expression) -> Self {
+        BinaryOperation(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    pub fn unary(op: UnaryOperator, lhs: Expression) -> Self {
+        UnaryOperation(op, Box::new(lhs))
+    }
+
+    pub fn number(n: impl Into<f64>) -> Self {
+        NumberLiteral(n.into())
+    }
+
+    pub fn integer(n: i64) -> Self {
+        Expression::IntegerLiteral(n)
+    }
+
+    pub fn variable(name: &str) -> Self {
+        Expression::Variable(name.to_string())
+    }
+
+    pub fn call(callee: Expression, args: Vec<Expression>) -> Self {
+        Expression::Call(Box::new(callee), args)
+    }
+
+    pub fn assign(target: Expression, value: Expression) -> Self {
+        Expression::Assign {
+            target: Box::new(target),
+            value: Box::new(value),
+        }
+    }
+
+    pub fn update(operator: UpdateOperator, variable: &str, prefix: bool) -> Self {
+        Expression::Update {
+            operator,
+            variable: variable.to_string(),
+            prefix,
+        }
+    }
+}
+
+impl Statement {
+    pub fn expression(expr: Expression) -> Self {
+        Statement::Expression(expr)
+    }
+
+    pub fn function(name: &str, args: &[&str], body: Statement) -> Self {
+        let params = args.iter().map(|s| s.to_string()).collect();
+        Statement::Function {
+            name: name.to_string(),
+            params,
+            body: Box::new(body),
+            doc: None,
+        }
+    }
+
+    pub fn documented_function(name: &str, args: &[&str], body: Statement, doc: &str) -> Self {
+        let params = args.iter().map(|s| s.to_string()).collect();
+        Statement::Function {
+            name: name.to_string(),
+            params,
+            body: Box::new(body),
+            doc: Some(doc.to_string()),
+        }
+    }
+
+    pub fn if_statement(condition: Expression, then_branch: Statement) -> Self {
+        Statement::If(condition, Box::new(then_branch), None)
+    }
+
+    pub fn if_else_statement(
+        condition: Expression,
+        then_branch: Statement,
+        else_branch: Statement,
+    ) -> Self {
+        Statement::If(
+            condition,
+            Box::new(then_branch),
+            Some(Box::new(else_branch)),
+        )
+    }
+
+    pub fn while_loop(expr: Expression, body: Statement) -> Self {
+        Statement::While(expr, Box::new(body))
+    }
+
+    pub fn do_while_loop(body: Statement, expr: Expression) -> Self {
+        Statement::DoWhile(Box::new(body), expr)
+    }
+
+    pub fn for_loop(
+        initializer: Option<Statement>,
+        condition: Option<Expression>,
+        increment: Option<Expression>,
+        body: Statement,
+    ) -> Self {
+        Statement::For {
+            initializer: initializer.map(Box::new),
+            condition,
+            increment,
+            body: Box::new(body),
+        }
+    }
+
+    pub fn print(expr: Expression) -> Self {
+        Statement::Print(expr)
+    }
+
+    pub fn located(span: Span, statement: Statement) -> Self {
+        Statement::Located(span, Box::new(statement))
+    }
+
+    /// Span of the first token of the statement, if it is annotated with one.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Statement::Located(span, _) => Some(*span),
+            _ => None,
+        }
+    }
+
+    /// Returns the statement stripped of position annotations.
+    pub fn inner(&self) -> &Statement {
+        match self {
+            Statement::Located(_, statement) => statement.inner(),
+            statement => statement,
+        }
+    }
+}
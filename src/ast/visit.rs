@@ -0,0 +1,263 @@
+//! Traversal of the syntax tree.
+//!
+//! Tools working with programs implement [Visitor] and override the methods for the nodes they care about.
+//! The default methods go on to the children of the node with the `walk_*` functions,
+//! so the overriding methods call them to continue the traversal.
+
+use crate::ast::{Expression, Program, Statement};
+
+/// Visits the nodes of the syntax tree, stopping at the first error.
+///
+/// Visitors that cannot fail use [Infallible](std::convert::Infallible) as the error.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::convert::Infallible;
+/// # use brainterpreter::ast::visit::{walk_expression, Visitor};
+/// # use brainterpreter::ast::Expression;
+/// # use brainterpreter::lexer::Lexer;
+/// # use brainterpreter::parser::Parser;
+/// /// Collects the names of the variables the program reads.
+/// #[derive(Default)]
+/// struct Reads(Vec<String>);
+///
+/// impl Visitor for Reads {
+///     type Error = Infallible;
+///
+///     fn visit_expression(&mut self, expression: &Expression) -> Result<(), Infallible> {
+///         if let Expression::Variable(name) = expression {
+///             self.0.push(name.clone());
+///         }
+///         walk_expression(self, expression)
+///     }
+/// }
+///
+/// let program = Parser::new(Lexer::new("let a = b + 1; print a * c;")).parse_program().unwrap();
+/// let mut reads = Reads::default();
+/// reads.visit_program(&program).unwrap();
+///
+/// assert_eq!(reads.0, vec!["b", "a", "c"]);
+/// ```
+pub trait Visitor {
+    type Error;
+
+    fn visit_program(&mut self, program: &Program) -> Result<(), Self::Error> {
+        walk_program(self, program)
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) -> Result<(), Self::Error> {
+        walk_statement(self, statement)
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) -> Result<(), Self::Error> {
+        walk_expression(self, expression)
+    }
+}
+
+/// Visits the top-level statements of the program.
+pub fn walk_program<V>(visitor: &mut V, program: &Program) -> Result<(), V::Error>
+where
+    V: Visitor + ?Sized,
+{
+    program
+        .statements()
+        .iter()
+        .try_for_each(|statement| visitor.visit_statement(statement))
+}
+
+/// Visits the statements and expressions the statement consists of, in the order of the source code.
+pub fn walk_statement<V>(visitor: &mut V, statement: &Statement) -> Result<(), V::Error>
+where
+    V: Visitor + ?Sized,
+{
+    match statement {
+        Statement::Expression(expr)
+        | Statement::DefineVariable(_, expr)
+        | Statement::DefineVariables(_, expr)
+        | Statement::DefineConstant(_, expr)
+        | Statement::Print(expr)
+        | Statement::Return(expr)
+        | Statement::Throw(expr) => visitor.visit_expression(expr),
+        Statement::Located(_, statement)
+        | Statement::Function {
+            body: statement, ..
+        } => visitor.visit_statement(statement),
+        Statement::Block(statements) => statements
+            .iter()
+            .try_for_each(|statement| visitor.visit_statement(statement)),
+        Statement::If(condition, then, otherwise) => {
+            visitor.visit_expression(condition)?;
+            visitor.visit_statement(then)?;
+            match otherwise {
+                Some(otherwise) => visitor.visit_statement(otherwise),
+                None => Ok(()),
+            }
+        }
+        Statement::While(condition, body) => {
+            visitor.visit_expression(condition)?;
+            visitor.visit_statement(body)
+        }
+        Statement::DoWhile(body, condition) => {
+            visitor.visit_statement(body)?;
+            visitor.visit_expression(condition)
+        }
+        Statement::For {
+            initializer,
+            condition,
+            increment,
+            body,
+        } => {
+            if let Some(initializer) = initializer {
+                visitor.visit_statement(initializer)?;
+            }
+            condition
+                .iter()
+                .chain(increment.iter())
+                .try_for_each(|expr| visitor.visit_expression(expr))?;
+            visitor.visit_statement(body)
+        }
+        Statement::ForIn {
+            collection, body, ..
+        } => {
+            visitor.visit_expression(collection)?;
+            visitor.visit_statement(body)
+        }
+        Statement::Try { body, handler, .. } => {
+            visitor.visit_statement(body)?;
+            visitor.visit_statement(handler)
+        }
+        Statement::Switch {
+            subject,
+            cases,
+            default,
+        } => {
+            visitor.visit_expression(subject)?;
+            for (value, body) in cases {
+                visitor.visit_expression(value)?;
+                visitor.visit_statement(body)?;
+            }
+            match default {
+                Some(default) => visitor.visit_statement(default),
+                None => Ok(()),
+            }
+        }
+        Statement::Assert { condition, message } => {
+            visitor.visit_expression(condition)?;
+            match message {
+                Some(message) => visitor.visit_expression(message),
+                None => Ok(()),
+            }
+        }
+        Statement::DeclareVariable(_) | Statement::Break | Statement::Continue => Ok(()),
+    }
+}
+
+/// Visits the operands of the expression and the bodies of the functions and blocks it defines,
+/// in the order of the source code.
+pub fn walk_expression<V>(visitor: &mut V, expression: &Expression) -> Result<(), V::Error>
+where
+    V: Visitor + ?Sized,
+{
+    match expression {
+        Expression::UnaryOperation(_, operand) => visitor.visit_expression(operand),
+        Expression::Index {
+            array: lhs,
+            index: rhs,
+        }
+        | Expression::Assign {
+            target: lhs,
+            value: rhs,
+        }
+        | Expression::Array {
+            initial: lhs,
+            size: rhs,
+        }
+        | Expression::BinaryOperation(_, lhs, rhs)
+        | Expression::Range {
+            start: lhs,
+            end: rhs,
+        } => {
+            visitor.visit_expression(lhs)?;
+            visitor.visit_expression(rhs)
+        }
+        Expression::Tuple(items) => items
+            .iter()
+            .try_for_each(|item| visitor.visit_expression(item)),
+        Expression::Map(entries) => entries.iter().try_for_each(|(key, value)| {
+            visitor.visit_expression(key)?;
+            visitor.visit_expression(value)
+        }),
+        Expression::Call(callee, args) => {
+            visitor.visit_expression(callee)?;
+            args.iter()
+                .try_for_each(|arg| visitor.visit_expression(arg))
+        }
+        Expression::Lambda(_, body) => visitor.visit_statement(body),
+        Expression::Block { statements, value } => {
+            statements
+                .iter()
+                .try_for_each(|statement| visitor.visit_statement(statement))?;
+            match value {
+                Some(value) => visitor.visit_expression(value),
+                None => Ok(()),
+            }
+        }
+        Expression::Conditional {
+            condition,
+            then,
+            otherwise,
+        } => {
+            visitor.visit_expression(condition)?;
+            visitor.visit_expression(then)?;
+            visitor.visit_expression(otherwise)
+        }
+        Expression::Nil
+        | Expression::NumberLiteral(_)
+        | Expression::IntegerLiteral(_)
+        | Expression::BooleanLiteral(_)
+        | Expression::StringLiteral(_)
+        | Expression::Variable(_)
+        | Expression::Update { .. } => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    /// Finds the first `return` with a string, stopping the traversal there.
+    struct FirstStringReturn {
+        returns: usize,
+    }
+
+    impl Visitor for FirstStringReturn {
+        type Error = String;
+
+        fn visit_statement(&mut self, statement: &Statement) -> Result<(), String> {
+            if let Statement::Return(value) = statement {
+                self.returns += 1;
+                if let Expression::StringLiteral(text) = value {
+                    return Err(text.clone());
+                }
+            }
+            walk_statement(self, statement)
+        }
+    }
+
+    #[test]
+    fn stop_at_the_first_error() {
+        let program = Parser::new(Lexer::new(
+            "fun f(a) { if (a) { return 1; } return \"f\"; }
+            let g = fun() { return \"g\"; };",
+        ))
+        .parse_program()
+        .unwrap();
+        let mut visitor = FirstStringReturn { returns: 0 };
+
+        assert_eq!(visitor.visit_program(&program), Err("f".to_string()));
+        assert_eq!(visitor.returns, 2);
+    }
+}
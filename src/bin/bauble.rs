@@ -1,37 +1,221 @@
-use brainterpreter::compiler::Compiler;
-use brainterpreter::interpret;
+use brainterpreter::compiler::CompileError;
+use brainterpreter::compiler::{Compiler, OptLevel};
+use brainterpreter::diagnostic::{Diagnostic, Severity};
+use brainterpreter::doc::{self, DocFormat};
+use brainterpreter::fmt::{dropped_comments, format_program};
+use brainterpreter::lexer::token::Token;
 use brainterpreter::lexer::Lexer;
-use brainterpreter::parser::Parser as BaubleParser;
-use brainterpreter::vm::disassembler::disassemble;
-use clap::{Parser, Subcommand};
+use brainterpreter::lint::{lint, Level, LintConfig, Rule};
+use brainterpreter::parser::{Parser as BaubleParser, ParsingError};
+use brainterpreter::value::ValueType;
+use brainterpreter::vm::bytecode::BytecodeError;
+use brainterpreter::vm::coverage::Coverage;
+use brainterpreter::vm::disassembler::{disassemble, disassemble_with_source};
+use brainterpreter::vm::exec::Chunk;
+use brainterpreter::vm::profiler::Profiler;
+use brainterpreter::vm::trace::JsonTracer;
+use brainterpreter::vm::{TraceFrame, Vm, VmRuntimeError};
+use brainterpreter::{interpret_reader_with, BaubleError, Stage};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use env_logger::Builder;
 use log::{debug, error, LevelFilter};
+use std::cell::RefCell;
 use std::error::Error;
 use std::fs::File;
-use std::io::{stdout, Read};
+use std::io::{stdin, stdout, BufRead, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 #[derive(Parser, Debug)]
 #[command(name = "bauble")]
 #[command(about = "Interpret bauble source file")]
 #[command(author, version, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, arg_required_else_help = true)]
 struct Args {
     /// Enable trace output of the virtual machine.
-    #[arg(long)]
+    #[arg(long, global = true)]
     trace: bool,
+    /// Format of the reported errors and diagnostics.
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Human)]
+    error_format: ErrorFormat,
     #[command(subcommand)]
-    command: Commands,
-    /// The source file to run
-    source_path: PathBuf,
+    command: Option<Commands>,
+    /// The source file to run without a command, e.g. from the `#!/usr/bin/env bauble` line of a script
+    script: Option<PathBuf>,
+    /// Arguments the script gets from the `args` function
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    script_args: Vec<String>,
 }
 
-#[derive(Subcommand, Debug, Default)]
+impl Args {
+    /// The command to run, which is running the script when only its path is given.
+    fn command_to_run(&self) -> Commands {
+        match (&self.command, &self.script) {
+            (Some(command), _) => command.clone(),
+            (None, Some(script)) => Commands::Run {
+                source_path: script.clone(),
+                trace_json: None,
+                profile: false,
+                stats: false,
+                args: self.script_args.clone(),
+            },
+            (None, None) => Args::command()
+                .error(
+                    ErrorKind::MissingSubcommand,
+                    "expected a command or a script to run",
+                )
+                .exit(),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
 enum Commands {
     /// Create assembly file instead of running a program
-    Disassemble,
+    Disassemble {
+        /// The source file to disassemble
+        source_path: PathBuf,
+    },
     /// Runs the program from the source file
-    #[default]
-    Run,
+    Run {
+        /// The source file to run, or `-` to read the program from the standard input
+        source_path: PathBuf,
+        /// Write the execution events to the file as JSON lines
+        #[arg(long)]
+        trace_json: Option<PathBuf>,
+        /// Print the instruction counts and times per instruction and function after the run
+        #[arg(long, conflicts_with = "trace_json")]
+        profile: bool,
+        /// Print the executed instructions, stack depth, calls, allocations, and wall time after the run
+        #[arg(long)]
+        stats: bool,
+        /// Arguments the program gets from the `args` function
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Generate documentation from `///` comments of the source file
+    Doc {
+        /// The source file to document
+        source_path: PathBuf,
+        /// Output format of the documentation
+        #[arg(long, value_enum, default_value_t = DocOutput::Markdown)]
+        format: DocOutput,
+    },
+    /// Check the source file for suspicious code
+    Lint {
+        /// The source file to check
+        source_path: PathBuf,
+        /// Do not report the rule
+        #[arg(long, value_name = "RULE", value_parser = parse_rule)]
+        allow: Vec<Rule>,
+        /// Report the rule as a warning
+        #[arg(long, value_name = "RULE", value_parser = parse_rule)]
+        warn: Vec<Rule>,
+        /// Report the rule as an error, failing the check
+        #[arg(long, value_name = "RULE", value_parser = parse_rule)]
+        deny: Vec<Rule>,
+    },
+    /// Parse and compile the source file without running it
+    Check {
+        /// The source file to check
+        source_path: PathBuf,
+    },
+    /// Print the syntax tree of the source file
+    Ast {
+        /// The source file to parse
+        source_path: PathBuf,
+        /// Print the tree as JSON instead of the readable form
+        #[arg(long)]
+        json: bool,
+    },
+    /// Format the source file in the canonical style
+    Fmt {
+        /// The source file to format
+        source_path: PathBuf,
+        /// Overwrite the source file instead of printing the formatted code
+        #[arg(long)]
+        write: bool,
+        /// Exit with an error when the source file is not formatted, without changing it
+        #[arg(long, conflicts_with = "write")]
+        check: bool,
+    },
+    /// Compile and run the source file at every optimization level and compare the results
+    Compare {
+        /// The source file to compare
+        source_path: PathBuf,
+    },
+    /// Run statements typed in the console one by one
+    Repl,
+    /// Compile the source file into a bytecode file
+    Compile {
+        /// The source file to compile
+        source_path: PathBuf,
+        /// The bytecode file to write, defaults to the source file with the `.bbc` extension
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Run the program from a compiled bytecode file
+    Exec {
+        /// The bytecode file to run
+        bytecode_path: PathBuf,
+    },
+    /// Run the `*_test.bbl` files of the directory and its subdirectories
+    Test {
+        /// The directory to search for tests, or a single test file
+        #[arg(default_value = ".")]
+        path: PathBuf,
+        /// Report the source lines the tests never run
+        #[arg(long)]
+        coverage: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum ErrorFormat {
+    /// Human-readable messages
+    Human,
+    /// One JSON object per line
+    Json,
+}
+
+impl Commands {
+    fn source_name(&self) -> String {
+        match self {
+            Commands::Disassemble { source_path }
+            | Commands::Run { source_path, .. }
+            | Commands::Doc { source_path, .. }
+            | Commands::Lint { source_path, .. }
+            | Commands::Check { source_path }
+            | Commands::Ast { source_path, .. }
+            | Commands::Fmt { source_path, .. }
+            | Commands::Compare { source_path }
+            | Commands::Compile { source_path, .. } => source_path.display().to_string(),
+            Commands::Exec { bytecode_path } => bytecode_path.display().to_string(),
+            Commands::Test { path, .. } => path.display().to_string(),
+            Commands::Repl => REPL_SOURCE_NAME.to_string(),
+        }
+    }
+}
+
+const REPL_SOURCE_NAME: &str = "<repl>";
+/// Suffix of the file names `bauble test` runs.
+const TEST_SUFFIX: &str = "_test.bbl";
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum DocOutput {
+    Markdown,
+    Html,
+}
+
+impl From<DocOutput> for DocFormat {
+    fn from(output: DocOutput) -> Self {
+        match output {
+            DocOutput::Markdown => DocFormat::Markdown,
+            DocOutput::Html => DocFormat::Html,
+        }
+    }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -42,21 +226,100 @@ fn main() -> Result<(), Box<dyn Error>> {
         env_logger::init();
     }
 
-    let result = match args.command {
-        Commands::Disassemble => disassemble_file(&args),
-        Commands::Run => run(&args),
+    let command = args.command_to_run();
+    let result = match &command {
+        Commands::Disassemble { source_path } => disassemble_file(source_path),
+        Commands::Run {
+            source_path,
+            trace_json,
+            profile,
+            stats,
+            args,
+        } => run(source_path, trace_json.as_deref(), *profile, *stats, args),
+        Commands::Doc {
+            source_path,
+            format,
+        } => document_file(source_path, *format),
+        Commands::Lint {
+            source_path,
+            allow,
+            warn,
+            deny,
+        } => {
+            let mut config = LintConfig::default();
+            for (rules, level) in [
+                (allow, Level::Allow),
+                (warn, Level::Warn),
+                (deny, Level::Deny),
+            ] {
+                for rule in rules {
+                    config.set(*rule, level);
+                }
+            }
+            lint_file(source_path, &config, args.error_format)
+        }
+        Commands::Check { source_path } => check_file(source_path, args.error_format),
+        Commands::Ast { source_path, json } => print_ast(source_path, *json),
+        Commands::Fmt {
+            source_path,
+            write,
+            check,
+        } => format_file(source_path, *write, *check),
+        Commands::Compare { source_path } => compare_file(source_path),
+        Commands::Repl => repl(args.error_format),
+        Commands::Compile {
+            source_path,
+            output,
+        } => compile_file(source_path, output.as_deref()),
+        Commands::Exec { bytecode_path } => exec_file(bytecode_path),
+        Commands::Test { path, coverage } => test_files(path, *coverage),
     };
 
     if let Err(e) = result {
-        error!("{}", e);
+        if let Some(code) = exit_code(e.as_ref()) {
+            std::process::exit(code);
+        }
+        report_error(e.as_ref(), &command.source_name(), args.error_format);
+        std::process::exit(failure_code(e.as_ref()));
     }
 
     Ok(())
 }
 
-fn run(args: &Args) -> Result<(), Box<dyn Error>> {
-    let source = read_source_from_file(&args.source_path)?;
-    interpret(&source)?;
+fn run(
+    source_path: &Path,
+    trace_json: Option<&Path>,
+    profile: bool,
+    stats: bool,
+    args: &[String],
+) -> Result<(), Box<dyn Error>> {
+    debug!("running file: {}", source_path.display());
+    let mut vm = Vm::default().with_args(args);
+    if let Some(trace_path) = trace_json {
+        vm = vm.with_tracer(JsonTracer::new(BufWriter::new(File::create(trace_path)?)));
+    }
+    let profiler = Profiler::default();
+    if profile {
+        vm = vm.with_tracer(profiler.clone());
+    }
+    if stats {
+        vm = vm.with_stats();
+    }
+    let start = Instant::now();
+    let result = if source_path == Path::new("-") {
+        interpret_reader_with(stdin().lock(), &mut vm)
+    } else {
+        interpret_reader_with(File::open(source_path)?, &mut vm)
+    };
+    // The profile of a failed run still shows where the time went.
+    if profile {
+        eprint!("{}", profiler.profile());
+    }
+    if let Some(stats) = vm.stats() {
+        eprint!("{}", stats);
+        eprintln!("{:<16} {:>12?}", "wall time", start.elapsed());
+    }
+    result?;
     Ok(())
 }
 
@@ -68,24 +331,749 @@ fn read_source_from_file(path: &Path) -> Result<String, Box<dyn Error>> {
     Ok(source)
 }
 
-fn disassemble_file(args: &Args) -> Result<(), Box<dyn Error>> {
-    let source = read_source_from_file(&args.source_path)?;
+fn disassemble_file(source_path: &Path) -> Result<(), Box<dyn Error>> {
+    let source = read_source_from_file(source_path)?;
     let lexer = Lexer::new(&source);
     let mut parser = BaubleParser::new(lexer);
     let ast = parser.parse_program()?;
     let mut compiler = Compiler::default();
     let chunk = compiler.compile(ast)?;
-    disassemble(&chunk, stdout())?;
+    disassemble_with_source(&chunk, &source_path.display().to_string(), stdout())?;
     Ok(())
 }
 
+fn print_ast(source_path: &Path, json: bool) -> Result<(), Box<dyn Error>> {
+    let source = read_source_from_file(source_path)?;
+    let lexer = Lexer::new(&source);
+    let mut parser = BaubleParser::new(lexer);
+    let ast = parser.parse_program()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&ast)?);
+    } else {
+        println!("{:#?}", ast);
+    }
+    Ok(())
+}
+
+fn compile_file(source_path: &Path, output: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let source = read_source_from_file(source_path)?;
+    let lexer = Lexer::new(&source);
+    let mut parser = BaubleParser::new(lexer);
+    let ast = parser.parse_program()?;
+    let mut compiler = Compiler::default();
+    let chunk = compiler.compile(ast)?;
+    let output = output
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| source_path.with_extension("bbc"));
+    std::fs::write(output, chunk.serialize()?)?;
+    Ok(())
+}
+
+fn exec_file(bytecode_path: &Path) -> Result<(), Box<dyn Error>> {
+    debug!("running bytecode: {}", bytecode_path.display());
+    let bytes = std::fs::read(bytecode_path)?;
+    let chunk = Chunk::deserialize(&bytes)?;
+    let mut vm = Vm::default();
+    vm.load_and_run(Rc::new(chunk))?;
+    Ok(())
+}
+
+fn document_file(source_path: &Path, format: DocOutput) -> Result<(), Box<dyn Error>> {
+    let source = read_source_from_file(source_path)?;
+    let lexer = Lexer::new(&source);
+    let mut parser = BaubleParser::new(lexer);
+    let ast = parser.parse_program()?;
+    let title = source_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    print!("{}", doc::generate(&ast, &title, format.into()));
+    Ok(())
+}
+
+fn report_error(error: &(dyn Error + 'static), source_name: &str, error_format: ErrorFormat) {
+    match error_format {
+        ErrorFormat::Human => {
+            error!("{}", error);
+            for frame in backtrace(error) {
+                error!("    in {}", frame);
+            }
+        }
+        // The problems are on the output as diagnostics already.
+        ErrorFormat::Json if error.is::<ProblemsFound>() => {}
+        ErrorFormat::Json => println!("{}", error_diagnostic(error).to_json(source_name)),
+    }
+}
+
+/// Failure of a command that reported the problems it found as diagnostics.
+#[derive(Debug, thiserror::Error)]
+#[error("{errors} of the reported problems are errors")]
+struct ProblemsFound {
+    errors: usize,
+    /// Stage of the program the errors are found at, if they come from the parser or the compiler.
+    stage: Option<Stage>,
+}
+
+/// Failure of the `fmt --check` command for a source file that differs from its formatted form.
+#[derive(Debug, thiserror::Error)]
+#[error("{} is not formatted", .0.display())]
+struct NotFormatted(PathBuf);
+
+/// Refusal of the `fmt` command to change a source file whose comments the formatting would drop.
+#[derive(Debug, thiserror::Error)]
+#[error("formatting {} would drop {1} comments, so it is left as is", .0.display())]
+struct CommentsDropped(PathBuf, usize);
+
+/// Failure of the `test` command with the number of the failed tests.
+#[derive(Debug, thiserror::Error)]
+#[error("{0} of the tests failed")]
+struct TestsFailed(usize);
+
+fn backtrace<'a>(error: &'a (dyn Error + 'static)) -> &'a [TraceFrame] {
+    if let Some(e) = error.downcast_ref::<BaubleError>() {
+        e.backtrace()
+    } else if let Some(e) = error.downcast_ref::<VmRuntimeError>() {
+        e.backtrace()
+    } else {
+        &[]
+    }
+}
+
+/// Code the program asked to exit with, so the process exits with it instead of reporting an error.
+fn exit_code(error: &(dyn Error + 'static)) -> Option<i32> {
+    if let Some(e) = error.downcast_ref::<BaubleError>() {
+        e.exit_code()
+    } else if let Some(e) = error.downcast_ref::<VmRuntimeError>() {
+        e.exit_code()
+    } else {
+        None
+    }
+}
+
+/// Exit code telling the stage the command failed at, as clap exits with 2 on invalid arguments.
+fn failure_code(error: &(dyn Error + 'static)) -> i32 {
+    let stage = if let Some(e) = error.downcast_ref::<BaubleError>() {
+        Some(e.stage())
+    } else if error.is::<ParsingError>() {
+        Some(Stage::Parse)
+    } else if error.is::<CompileError>() {
+        Some(Stage::Compile)
+    } else if error.is::<VmRuntimeError>() {
+        Some(Stage::Run)
+    } else if let Some(e) = error.downcast_ref::<ProblemsFound>() {
+        e.stage
+    } else {
+        None
+    };
+    match stage {
+        Some(Stage::Parse) => 3,
+        Some(Stage::Compile) => 4,
+        Some(Stage::Run) => 5,
+        Some(Stage::Read) | None => 1,
+    }
+}
+
+fn error_diagnostic(error: &(dyn Error + 'static)) -> Diagnostic {
+    if let Some(e) = error.downcast_ref::<BaubleError>() {
+        e.into()
+    } else if let Some(e) = error.downcast_ref::<ParsingError>() {
+        e.into()
+    } else if let Some(e) = error.downcast_ref::<CompileError>() {
+        e.into()
+    } else if let Some(e) = error.downcast_ref::<VmRuntimeError>() {
+        e.into()
+    } else if error.is::<BytecodeError>() {
+        Diagnostic::new("invalid-bytecode", Severity::Error, error.to_string())
+    } else if error.is::<std::io::Error>() {
+        Diagnostic::new("io-error", Severity::Error, error.to_string())
+    } else {
+        Diagnostic::new("error", Severity::Error, error.to_string())
+    }
+}
+
+/// Finds the lint rule by its identifier, e.g. `empty-block`.
+fn parse_rule(id: &str) -> Result<Rule, String> {
+    Rule::from_id(id).ok_or_else(|| {
+        let ids: Vec<&str> = Rule::ALL.iter().map(Rule::id).collect();
+        format!("unknown rule, expected one of: {}", ids.join(", "))
+    })
+}
+
+fn lint_file(
+    source_path: &Path,
+    config: &LintConfig,
+    error_format: ErrorFormat,
+) -> Result<(), Box<dyn Error>> {
+    let source = read_source_from_file(source_path)?;
+    let lexer = Lexer::new(&source);
+    let mut parser = BaubleParser::new(lexer);
+    let ast = parser.parse_program()?;
+    let diagnostics = lint(&ast, config);
+    let path = source_path.display().to_string();
+    for diagnostic in diagnostics.iter() {
+        match error_format {
+            ErrorFormat::Human => print!("{}", diagnostic.render(&path, &source)),
+            ErrorFormat::Json => println!("{}", diagnostic.to_json(&path)),
+        }
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity() == Severity::Error)
+        .count();
+    if errors > 0 {
+        return Err(ProblemsFound {
+            errors,
+            stage: None,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn check_file(source_path: &Path, error_format: ErrorFormat) -> Result<(), Box<dyn Error>> {
+    let source = read_source_from_file(source_path)?;
+    let lexer = Lexer::new(&source);
+    let mut parser = BaubleParser::new(lexer);
+    let (ast, parsing_errors) = parser.parse_program_with_recovery();
+    let mut diagnostics: Vec<Diagnostic> = parsing_errors.iter().map(Diagnostic::from).collect();
+    // Statements after a parsing error may depend on the broken ones, so only valid programs compile.
+    if diagnostics.is_empty() {
+        let mut compiler = Compiler::default();
+        let result = compiler.compile(ast);
+        diagnostics.extend(compiler.warnings().iter().map(Diagnostic::from));
+        if let Err(e) = result {
+            diagnostics.push(Diagnostic::from(&e));
+        }
+    }
+    let path = source_path.display().to_string();
+    for diagnostic in diagnostics.iter() {
+        match error_format {
+            ErrorFormat::Human => print!("{}", diagnostic.render(&path, &source)),
+            ErrorFormat::Json => println!("{}", diagnostic.to_json(&path)),
+        }
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity() == Severity::Error)
+        .count();
+    if errors > 0 {
+        let stage = if parsing_errors.is_empty() {
+            Stage::Compile
+        } else {
+            Stage::Parse
+        };
+        return Err(ProblemsFound {
+            errors,
+            stage: Some(stage),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn format_file(source_path: &Path, write: bool, check: bool) -> Result<(), Box<dyn Error>> {
+    let source = read_source_from_file(source_path)?;
+    let lexer = Lexer::new(&source);
+    let mut parser = BaubleParser::new(lexer);
+    let ast = parser.parse_program()?;
+    let formatted = format_program(&ast);
+    let dropped = dropped_comments(&source, &formatted);
+    if (write || check) && dropped > 0 {
+        return Err(CommentsDropped(source_path.to_path_buf(), dropped).into());
+    }
+    if check {
+        if formatted != source {
+            return Err(NotFormatted(source_path.to_path_buf()).into());
+        }
+    } else if write {
+        std::fs::write(source_path, formatted)?;
+    } else {
+        print!("{}", formatted);
+    }
+    Ok(())
+}
+
+fn repl(error_format: ErrorFormat) -> Result<(), Box<dyn Error>> {
+    let mut vm = Vm::default();
+    let mut source = String::new();
+    prompt("> ")?;
+    for line in stdin().lock().lines() {
+        source.push_str(&line?);
+        source.push('\n');
+        if is_incomplete(&source) {
+            prompt("... ")?;
+            continue;
+        }
+        match evaluate(&mut vm, &source) {
+            Ok(ValueType::Nil) => {}
+            Ok(value) => println!("{}", value.as_string()),
+            Err(e) => report_error(&e, REPL_SOURCE_NAME, error_format),
+        }
+        source.clear();
+        prompt("> ")?;
+    }
+    Ok(())
+}
+
+fn prompt(text: &str) -> Result<(), std::io::Error> {
+    print!("{}", text);
+    stdout().flush()
+}
+
+/// Checks whether the input has unclosed brackets, so the statement continues on the next line.
+fn is_incomplete(source: &str) -> bool {
+    let depth: isize = Lexer::new(source)
+        .map(|token| match token.kind() {
+            Token::LeftCurly | Token::LeftParen | Token::LeftSquare => 1,
+            Token::RightCurly | Token::RightParen | Token::RightSquare => -1,
+            _ => 0,
+        })
+        .sum();
+    depth > 0
+}
+
+/// Runs the source in the virtual machine, keeping globals defined by previous inputs.
+fn evaluate(vm: &mut Vm, source: &str) -> Result<ValueType, BaubleError> {
+    match vm.eval(source) {
+        // Allow omitting the semicolon after the expression.
+        Err(BaubleError::Parsing(e)) => {
+            let terminated = format!("{};", source.trim_end());
+            match vm.eval(&terminated) {
+                Err(BaubleError::Parsing(_)) => Err(e.into()),
+                result => result,
+            }
+        }
+        result => result,
+    }
+}
+
+struct Variant {
+    level: OptLevel,
+    op_count: usize,
+    assembly: String,
+    output: Result<String, String>,
+    elapsed: Duration,
+}
+
+fn compare_file(source_path: &Path) -> Result<(), Box<dyn Error>> {
+    let source = read_source_from_file(source_path)?;
+    let lexer = Lexer::new(&source);
+    let mut parser = BaubleParser::new(lexer);
+    let ast = parser.parse_program()?;
+
+    let mut variants = vec![];
+    for level in OptLevel::ALL {
+        let mut compiler = Compiler::with_opt_level(level);
+        let chunk = Rc::new(compiler.compile(ast.clone())?);
+        let mut assembly = vec![];
+        disassemble(&chunk, &mut assembly)?;
+
+        let out = Rc::new(RefCell::new(vec![]));
+        let mut vm = Vm::with_io(out.clone());
+        let start = Instant::now();
+        let result = vm.load_and_run(chunk.clone());
+        let elapsed = start.elapsed();
+        let output = String::from_utf8_lossy(&out.borrow()).to_string();
+
+        variants.push(Variant {
+            level,
+            op_count: count_ops(&chunk),
+            assembly: String::from_utf8(assembly)?,
+            output: result.map(|_| output).map_err(|e| e.to_string()),
+            elapsed,
+        });
+    }
+
+    println!("{:<8}{:>8}{:>14}", "level", "ops", "time");
+    for variant in variants.iter() {
+        println!(
+            "{:<8}{:>8}{:>14}",
+            variant.level.name(),
+            variant.op_count,
+            format!("{:?}", variant.elapsed)
+        );
+    }
+
+    let baseline = &variants[0];
+    for variant in variants.iter().skip(1) {
+        println!();
+        println!(
+            "--- {}\n+++ {}",
+            baseline.level.name(),
+            variant.level.name()
+        );
+        for line in diff_lines(&baseline.assembly, &variant.assembly) {
+            println!("{}", line);
+        }
+        if variant.output != baseline.output {
+            println!(
+                "output of `{}` differs from `{}`",
+                variant.level.name(),
+                baseline.level.name()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Runs every test file in a fresh virtual machine and prints the summary.
+/// Exits with a non-zero code when any test fails.
+fn test_files(path: &Path, coverage: bool) -> Result<(), Box<dyn Error>> {
+    let tests = if path.is_dir() {
+        find_tests(path)?
+    } else {
+        vec![path.to_path_buf()]
+    };
+    let start = Instant::now();
+    let mut failures = vec![];
+    let mut coverages = vec![];
+    for test in tests.iter() {
+        let name = test.display().to_string();
+        let source = read_source_from_file(test)?;
+        let test_start = Instant::now();
+        let (output, result, test_coverage) = run_test(&source, coverage);
+        let elapsed = test_start.elapsed();
+        coverages.extend(test_coverage.map(|test_coverage| (name.clone(), test_coverage)));
+        match result {
+            Ok(()) => println!("test {} ... ok ({:?})", name, elapsed),
+            Err(e) => {
+                println!("test {} ... FAILED ({:?})", name, elapsed);
+                let mut report = Diagnostic::from(&e).render(&name, &source);
+                report.push_str(&output);
+                failures.push((name, report));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (name, report) in failures.iter() {
+            println!("\n---- {} ----\n{}", name, report.trim_end());
+        }
+    }
+    if coverage {
+        println!("\ncoverage:");
+        for (name, test_coverage) in coverages.iter() {
+            print_coverage(name, test_coverage);
+        }
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed; finished in {:?}",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        tests.len() - failures.len(),
+        failures.len(),
+        start.elapsed()
+    );
+    if !failures.is_empty() {
+        return Err(TestsFailed(failures.len()).into());
+    }
+    Ok(())
+}
+
+/// Finds the test files in the directory and its subdirectories, sorted by path.
+fn find_tests(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut tests = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            tests.extend(find_tests(&path)?);
+        } else if path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().ends_with(TEST_SUFFIX))
+        {
+            tests.push(path);
+        }
+    }
+    tests.sort();
+    Ok(tests)
+}
+
+/// Runs the test source, returning what it printed and how it finished.
+fn run_test(source: &str, coverage: bool) -> (String, Result<(), BaubleError>, Option<Coverage>) {
+    let out = Rc::new(RefCell::new(vec![]));
+    let mut vm = Vm::with_io(out.clone());
+    if coverage {
+        vm = vm.with_coverage();
+    }
+    let result = BaubleParser::new(Lexer::new(source))
+        .parse_program()
+        .map_err(BaubleError::from)
+        .and_then(|ast| Ok(Compiler::default().compile(ast)?))
+        .and_then(|chunk| {
+            vm.load_and_run(Rc::new(chunk))?;
+            Ok(())
+        });
+    let output = String::from_utf8_lossy(&out.borrow()).to_string();
+    (output, result, vm.coverage().cloned())
+}
+
+/// Prints the share of the covered lines of the file and the lines never run.
+fn print_coverage(name: &str, coverage: &Coverage) {
+    let uncovered: Vec<String> = coverage
+        .uncovered_lines()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    if uncovered.is_empty() {
+        println!("{} ... {:.1}%", name, coverage.percent());
+    } else {
+        println!(
+            "{} ... {:.1}%, uncovered lines: {}",
+            name,
+            coverage.percent(),
+            uncovered.join(", ")
+        );
+    }
+}
+
+/// Counts operations of the chunk, including the operations of the functions it defines.
+fn count_ops(chunk: &Chunk) -> usize {
+    let nested: usize = chunk
+        .constants()
+        .map(|constant| match constant {
+            ValueType::Function(function) => count_ops(function.chunk()),
+            _ => 0,
+        })
+        .sum();
+    chunk.ops().count() + nested
+}
+
+/// Line-based diff of two texts, with unchanged lines prefixed by a space.
+fn diff_lines(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // Lengths of the longest common subsequences of the line suffixes.
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            diff.push(format!(" {}", old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            diff.push(format!("-{}", old[i]));
+            i += 1;
+        } else {
+            diff.push(format!("+{}", new[j]));
+            j += 1;
+        }
+    }
+    diff.extend(old[i..].iter().map(|line| format!("-{}", line)));
+    diff.extend(new[j..].iter().map(|line| format!("+{}", line)));
+    diff
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn verify_cli() {
-        use clap::CommandFactory;
         Args::command().debug_assert()
     }
+
+    #[test]
+    fn run_script_without_command() {
+        let args = Args::try_parse_from(["bauble", "script.bbl", "-v", "input.txt"]).unwrap();
+
+        let Commands::Run {
+            source_path, args, ..
+        } = args.command_to_run()
+        else {
+            panic!("run command expected");
+        };
+        assert_eq!(source_path, PathBuf::from("script.bbl"));
+        assert_eq!(args, vec!["-v", "input.txt"]);
+    }
+
+    #[test]
+    fn program_arguments_follow_the_source_path() {
+        let args =
+            Args::try_parse_from(["bauble", "run", "main.bbl", "--verbose", "input.txt"]).unwrap();
+
+        let Commands::Run {
+            source_path, args, ..
+        } = args.command_to_run()
+        else {
+            panic!("run command expected");
+        };
+        assert_eq!(source_path, PathBuf::from("main.bbl"));
+        assert_eq!(args, vec!["--verbose", "input.txt"]);
+    }
+
+    #[test]
+    fn exit_codes_tell_the_failed_stage() {
+        let code = |source: &str| failure_code(&brainterpreter::interpret(source).unwrap_err());
+
+        assert_eq!(code("print 1 +;"), 3);
+        assert_eq!(code("break;"), 4);
+        assert_eq!(code("print 1 + true;"), 5);
+        assert_eq!(failure_code(&std::io::Error::other("closed")), 1);
+    }
+
+    #[test]
+    fn ast_as_json() {
+        use brainterpreter::ast::Program;
+
+        let ast = BaubleParser::new(Lexer::new("print 1 + 2;"))
+            .parse_program()
+            .unwrap();
+
+        let json = serde_json::to_value(&ast).unwrap();
+        assert_eq!(
+            json["statements"][0]["Located"][1]["Print"]["BinaryOperation"][0],
+            "Add"
+        );
+        let parsed: Program = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, ast);
+    }
+
+    #[test]
+    fn configure_lint_rules() {
+        let args = Args::try_parse_from([
+            "bauble",
+            "lint",
+            "main.bbl",
+            "--allow",
+            "empty-block",
+            "--deny",
+            "unused-parameter",
+            "--deny",
+            "shadowed-variable",
+        ])
+        .unwrap();
+
+        let Commands::Lint { allow, deny, .. } = args.command_to_run() else {
+            panic!("lint command expected");
+        };
+        assert_eq!(allow, vec![Rule::EmptyBlock]);
+        assert_eq!(deny, vec![Rule::UnusedParameter, Rule::ShadowedVariable]);
+        assert!(Args::try_parse_from(["bauble", "lint", "main.bbl", "--warn", "typo"]).is_err());
+    }
+
+    #[test]
+    fn lint_errors_fail_the_command() {
+        let path = std::env::temp_dir().join(format!("bauble-lint-{}.bbl", std::process::id()));
+        std::fs::write(&path, "let a = 1;\nif (a = 2) { print a; }\n").unwrap();
+
+        let denied = lint_file(&path, &LintConfig::default(), ErrorFormat::Json);
+        let mut config = LintConfig::default();
+        config.set(Rule::AssignmentInCondition, Level::Warn);
+        let warned = lint_file(&path, &config, ErrorFormat::Json);
+        std::fs::remove_file(&path).unwrap();
+
+        let error = denied.unwrap_err();
+        assert!(error.is::<ProblemsFound>());
+        assert_eq!(failure_code(error.as_ref()), 1);
+        assert!(warned.is_ok());
+    }
+
+    #[test]
+    fn check_fails_with_the_stage_of_the_errors() {
+        let path = std::env::temp_dir().join(format!("bauble-check-{}.bbl", std::process::id()));
+        let check = |source: &str| {
+            std::fs::write(&path, source).unwrap();
+            check_file(&path, ErrorFormat::Json)
+        };
+
+        let parse = check("print 1 +;\nbreak;\n").unwrap_err();
+        let compile = check("let a = 1;\nbreak;\n").unwrap_err();
+        let valid = check("print   1;\n");
+        let unformatted = format_file(&path, false, true);
+        std::fs::write(&path, "// keep me\nprint   1;\n").unwrap();
+        let commented = format_file(&path, true, false);
+        let kept = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(failure_code(parse.as_ref()), 3);
+        assert_eq!(failure_code(compile.as_ref()), 4);
+        assert!(valid.is_ok());
+        let unformatted = unformatted.unwrap_err();
+        assert!(unformatted.is::<NotFormatted>());
+        assert_eq!(failure_code(unformatted.as_ref()), 1);
+        assert!(commented.unwrap_err().is::<CommentsDropped>());
+        assert_eq!(kept, "// keep me\nprint   1;\n");
+    }
+
+    #[test]
+    fn evaluate_keeps_globals() {
+        let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![])));
+
+        evaluate(&mut vm, "let a = 40;").unwrap();
+        assert!(evaluate(&mut vm, "a + unknown;").is_err());
+        let value = evaluate(&mut vm, "a + 2").unwrap();
+
+        assert_eq!(value, ValueType::Int(42));
+    }
+
+    #[test]
+    fn incomplete_input() {
+        assert!(is_incomplete("fun f() {\n"));
+        assert!(is_incomplete("print (1 +\n"));
+        assert!(!is_incomplete("fun f() {}\n"));
+        assert!(!is_incomplete("print \"{\";\n"));
+    }
+
+    #[test]
+    fn find_test_files() {
+        let dir = std::env::temp_dir().join(format!("bauble-tests-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        for file in [
+            "b_test.bbl",
+            "a_test.bbl",
+            "helper.bbl",
+            "nested/c_test.bbl",
+        ] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+
+        let tests = find_tests(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            tests.unwrap(),
+            vec![
+                dir.join("a_test.bbl"),
+                dir.join("b_test.bbl"),
+                dir.join("nested/c_test.bbl")
+            ]
+        );
+    }
+
+    #[test]
+    fn collect_test_coverage() {
+        let (_, result, coverage) = run_test("let a = 1;\nif (a > 2) {\n  print a;\n}", true);
+
+        assert!(result.is_ok());
+        assert_eq!(coverage.unwrap().uncovered_lines(), vec![3]);
+    }
+
+    #[test]
+    fn fail_tests_on_errors() {
+        let (output, result, _) = run_test("print 1; assert(1 > 2, \"order\"); print 2;", false);
+
+        assert_eq!(output, "1\n");
+        assert_eq!(result.unwrap_err().code(), "assertion-failed");
+        assert!(run_test("print 1 + true;", false).1.is_err());
+        assert!(run_test("let = 1;", false).1.is_err());
+        assert!(run_test("assert(true);", false).1.is_ok());
+    }
+
+    #[test]
+    fn diff_changed_lines() {
+        let diff = diff_lines("a\nb\nc", "a\nx\nc\nd");
+
+        assert_eq!(diff, vec![" a", "-b", "+x", " c", "+d"]);
+    }
 }
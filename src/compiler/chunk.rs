@@ -1,42 +1,195 @@
 //! Helps to build executable chunks.
+//!
+//! Besides the compiler, tools generating Bauble programs can use the builder directly.
+//! It tracks how deep the operations fill the stack and resolves the jumps to [Label]s
+//! when it builds the chunk.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use std::cell::RefCell;
+//! # use std::rc::Rc;
+//! # use brainterpreter::compiler::chunk::ChunkBuilder;
+//! # use brainterpreter::vm::opcode::Op;
+//! # use brainterpreter::vm::Vm;
+//! // Counts down from 3 to 1.
+//! let mut builder = ChunkBuilder::default();
+//! let (start, end) = (builder.create_label(), builder.create_label());
+//! builder.add_op(Op::ConstInt(3));
+//! builder.bind_label(start).unwrap();
+//! builder.add_op(Op::ConstInt(0));
+//! builder.add_op(Op::LoadLocal(0));
+//! builder.add_op(Op::Gt);
+//! builder.jump_to_label(Op::JumpIfFalse, end);
+//! builder.add_op(Op::LoadLocal(0));
+//! builder.add_op(Op::Print);
+//! builder.add_op(Op::ConstInt(1));
+//! builder.add_op(Op::LoadLocal(0));
+//! builder.add_op(Op::Sub);
+//! builder.add_op(Op::StoreLocal(0));
+//! builder.add_op(Op::Pop);
+//! builder.jump_to_label(Op::Jump, start);
+//! builder.bind_label(end).unwrap();
+//! builder.add_op(Op::Return);
+//! assert_eq!(builder.max_stack_depth(), 3);
+//!
+//! let out = Rc::new(RefCell::new(vec![]));
+//! let mut vm = Vm::with_io(out.clone());
+//! vm.run_chunk(&builder.build().unwrap()).unwrap();
+//! assert_eq!(out.borrow().as_slice(), b"3\n2\n1\n");
+//! ```
+
+use std::rc::Rc;
 
 use log::trace;
 
 use crate::{
-    value::ValueType,
+    compiler::CompileError,
+    source::Position,
+    value::{Function, ValueType},
     vm::{exec::Chunk, opcode::Op},
 };
 
+/// Place in the code the jumps go to, which may be added after the jumps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
 /// Gradually builds executable chunks.
 #[derive(Debug, Clone, Default)]
 pub struct ChunkBuilder {
     constants: Vec<ValueType>,
     ops: Vec<Op>,
+    positions: Vec<Position>,
+    position: Position,
+    /// Values on the stack after the last operation, counting from the start of the chunk.
+    depth: usize,
+    max_depth: usize,
+    /// Addresses of the labels, `None` until they are bound.
+    labels: Vec<Option<usize>>,
+    /// Stack depth the jumps to the labels arrive with, used to continue counting after the label.
+    label_depths: Vec<Option<usize>>,
+    /// Jumps waiting for their labels to resolve their offsets.
+    label_jumps: Vec<(usize, Label)>,
 }
 
 impl ChunkBuilder {
     /// Adds new operation to the program.
     /// Returns the address of the op in the program.
     pub fn add_op(&mut self, op: Op) -> usize {
+        self.add_op_at(op, self.position)
+    }
+
+    /// Adds new operation compiled from the source code at the position.
+    /// Returns the address of the op in the program.
+    pub fn add_op_at(&mut self, op: Op, position: Position) -> usize {
+        let (pops, pushes) = op.stack_effect();
+        self.depth = self.depth.saturating_sub(pops) + pushes;
+        self.max_depth = self.max_depth.max(self.depth);
         self.ops.push(op);
+        self.positions.push(position);
         self.ops.len() - 1
     }
 
-    /// Returns the address of a last op that was added to the chunk.
-    pub fn last_op_address(&self) -> usize {
-        self.ops.len() - 1
+    /// Adds the operation pushing the constant on the stack.
+    /// Returns the address of the op in the program.
+    pub fn emit_constant(&mut self, value: ValueType) -> usize {
+        let index = self.add_constant(value);
+        self.add_op(Op::Const(index))
+    }
+
+    /// Adds the operation pushing the function on the stack,
+    /// creating a closure when the function captures variables.
+    /// Returns the address of the op in the program.
+    pub fn emit_function(&mut self, function: Function) -> usize {
+        let is_closure = !function.captures().is_empty();
+        let index = self.add_constant(ValueType::Function(Rc::new(function)));
+        if is_closure {
+            self.add_op(Op::Closure(index))
+        } else {
+            self.add_op(Op::Const(index))
+        }
+    }
+
+    /// Number of values on the stack after the operations added so far.
+    ///
+    /// It counts from the start of the chunk, i.e. from the locals of a function.
+    /// Jumps are followed through the labels, while the manually patched jumps are not.
+    pub fn stack_depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Largest number of values the operations added so far keep on the stack, see [ChunkBuilder::stack_depth].
+    pub fn max_stack_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Creates a label to jump to before it is placed in the code with [ChunkBuilder::bind_label].
+    pub fn create_label(&mut self) -> Label {
+        self.labels.push(None);
+        self.label_depths.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Places the label before the operation added next.
+    ///
+    /// Fails when the label is already placed.
+    pub fn bind_label(&mut self, label: Label) -> Result<(), CompileError> {
+        match self.labels.get_mut(label.0) {
+            Some(address @ None) => *address = Some(self.ops.len()),
+            _ => return Err(CompileError::LabelBoundTwice(label.0)),
+        }
+        // The code before an unconditional jump does not fall through, so the jumps tell the depth.
+        if let Some(Some(depth)) = self.label_depths.get(label.0) {
+            self.depth = *depth;
+        }
+        Ok(())
+    }
+
+    /// Adds the jump of the kind, e.g. [Op::JumpIfFalse], to the label.
+    /// The offset of the jump is resolved when the chunk is built.
+    /// Returns the address of the op in the program.
+    pub fn jump_to_label(&mut self, jump: fn(i32) -> Op, label: Label) -> usize {
+        let address = self.add_op(jump(0));
+        let depth = match self.ops[address] {
+            // The handler starts with the thrown value, and the iteration ends with its index.
+            Op::PushHandler(_) => self.depth + 1,
+            Op::IterNext(_) => self.depth - 1,
+            _ => self.depth,
+        };
+        if let Some(label_depth @ None) = self.label_depths.get_mut(label.0) {
+            *label_depth = Some(depth);
+        }
+        self.label_jumps.push((address, label));
+        address
+    }
+
+    /// Sets the source position of the operations added next.
+    pub fn set_position(&mut self, position: Position) {
+        self.position = position;
+    }
+
+    /// Returns the address of a last op that was added to the chunk, if there is one.
+    pub fn last_op_address(&self) -> Option<usize> {
+        self.ops.len().checked_sub(1)
+    }
+
+    /// Returns the address the next added op will have.
+    pub fn next_op_address(&self) -> usize {
+        self.ops.len()
     }
 
     /// Adds constant to constants table.
     /// If the constant is already in the table, the method does not add it again and instead
     /// returns the index of existing constant.
+    /// Functions are equal when their names are, so every function gets its own entry.
     pub fn add_constant(&mut self, value: ValueType) -> usize {
-        if let Some((i, v)) = self
+        let existing = self
             .constants
             .iter()
             .enumerate()
-            .find(|(_, v)| *v == &value)
-        {
+            .filter(|_| !matches!(value, ValueType::Function(_)))
+            .find(|(_, v)| *v == &value);
+        if let Some((i, v)) = existing {
             trace!("found constant {:?} on index {}", v, i);
             i
         } else {
@@ -46,30 +199,66 @@ impl ChunkBuilder {
     }
 
     /// Sets target address to previously added jump instruction.
-    pub fn patch_jump(&mut self, address: usize, offset: i32) {
-        if let Op::JumpIfFalse(_) = self.ops[address] {
-            self.ops[address] = Op::JumpIfFalse(offset);
-        } else if let Op::Jump(_) = self.ops[address] {
-            self.ops[address] = Op::Jump(offset);
-        } else {
-            panic!("Invalid jump address");
+    ///
+    /// Fails when there is no jump instruction at the address.
+    pub fn patch_jump(&mut self, address: usize, offset: i32) -> Result<(), CompileError> {
+        match self.ops.get_mut(address) {
+            Some(
+                Op::JumpIfFalse(o)
+                | Op::JumpIfTrue(o)
+                | Op::Jump(o)
+                | Op::PushHandler(o)
+                | Op::IterNext(o),
+            ) => {
+                *o = offset;
+                Ok(())
+            }
+            _ => Err(CompileError::InvalidJump(address)),
         }
     }
 
     /// Directs jump instruction at jump_address to the target_address.
-    pub fn patch_jump_to(&mut self, jump_address: usize, target_address: usize) {
+    pub fn patch_jump_to(
+        &mut self,
+        jump_address: usize,
+        target_address: usize,
+    ) -> Result<(), CompileError> {
         let offset = target_address as i32 - jump_address as i32;
-        self.patch_jump(jump_address, offset);
+        self.patch_jump(jump_address, offset)
+    }
+
+    /// Directs jump instruction at jump_address to continue execution from the target_address.
+    pub fn patch_jump_to_address(
+        &mut self,
+        jump_address: usize,
+        target_address: usize,
+    ) -> Result<(), CompileError> {
+        let offset = target_address as i32 - jump_address as i32 - 1;
+        self.patch_jump(jump_address, offset)
     }
 
     /// Directs jump instruction at jump_address to the last instruction.
-    pub fn patch_jump_to_last(&mut self, jump_address: usize) {
-        self.patch_jump_to(jump_address, self.last_op_address());
+    pub fn patch_jump_to_last(&mut self, jump_address: usize) -> Result<(), CompileError> {
+        let last_address = self
+            .last_op_address()
+            .ok_or(CompileError::InvalidJump(jump_address))?;
+        self.patch_jump_to(jump_address, last_address)
     }
 
-    /// Produces a [Chunk] from the builder.
-    pub fn build(self) -> Chunk {
-        Chunk::new(self.ops, self.constants)
+    /// Produces a [Chunk] from the builder, directing the jumps to their labels.
+    ///
+    /// Fails when a jump goes to a label that is not placed in the code.
+    pub fn build(mut self) -> Result<Chunk, CompileError> {
+        for (address, label) in std::mem::take(&mut self.label_jumps) {
+            let target = self
+                .labels
+                .get(label.0)
+                .copied()
+                .flatten()
+                .ok_or(CompileError::UnboundLabel(label.0))?;
+            self.patch_jump_to_address(address, target)?;
+        }
+        Ok(Chunk::try_new(self.ops, self.constants)?.with_positions(self.positions))
     }
 }
 
@@ -85,6 +274,21 @@ mod tests {
         chunk_builder
     }
 
+    #[test]
+    fn keep_positions_of_operations() {
+        let mut chunk_builder = ChunkBuilder::default();
+        chunk_builder.set_position(Position::new(1, 5));
+        chunk_builder.add_op(Op::Nil);
+        chunk_builder.add_op_at(Op::Print, Position::new(2, 3));
+        chunk_builder.add_op(Op::Return);
+
+        let chunk = chunk_builder.build().unwrap();
+
+        assert_eq!(chunk.position(0), Some(Position::new(1, 5)));
+        assert_eq!(chunk.position(1), Some(Position::new(2, 3)));
+        assert_eq!(chunk.position(2), Some(Position::new(1, 5)));
+    }
+
     #[test]
     fn patch_conditional_jump() {
         let mut chunk_builder = ChunkBuilder::default();
@@ -92,11 +296,11 @@ mod tests {
         chunk_builder.add_op(Op::ConstFloat(4.0));
         chunk_builder.add_op(Op::Cmp);
         let jump_address = chunk_builder.add_op(Op::JumpIfFalse(0));
-        chunk_builder.patch_jump(jump_address, -2);
+        chunk_builder.patch_jump(jump_address, -2).unwrap();
 
-        let chunk = chunk_builder.build();
+        let chunk = chunk_builder.build().unwrap();
 
-        assert_eq!(chunk.op(jump_address), Some(&Op::JumpIfFalse(-2)));
+        assert_eq!(chunk.op(jump_address), Some(Op::JumpIfFalse(-2)));
     }
 
     #[test]
@@ -105,20 +309,30 @@ mod tests {
             chunk_builder_from_ops([Op::ConstFloat(3.0), Op::ConstFloat(4.0), Op::Cmp]);
         let jump_address = chunk_builder.add_op(Op::Jump(0));
 
-        chunk_builder.patch_jump(jump_address, -1);
+        chunk_builder.patch_jump(jump_address, -1).unwrap();
 
-        let chunk = chunk_builder.build();
-        assert_eq!(chunk.op(jump_address), Some(&Op::Jump(-1)));
+        let chunk = chunk_builder.build().unwrap();
+        assert_eq!(chunk.op(jump_address), Some(Op::Jump(-1)));
     }
 
     #[test]
-    #[should_panic]
     fn patch_jump_invalid_operation() {
         let mut chunk_builder =
             chunk_builder_from_ops([Op::ConstFloat(3.0), Op::ConstFloat(4.0), Op::Cmp]);
         let jump_address = chunk_builder.add_op(Op::ConstFloat(0.0));
 
-        chunk_builder.patch_jump(jump_address, -1);
+        assert!(matches!(
+            chunk_builder.patch_jump(jump_address, -1),
+            Err(CompileError::InvalidJump(3))
+        ));
+        assert!(matches!(
+            chunk_builder.patch_jump_to_last(10),
+            Err(CompileError::InvalidJump(10))
+        ));
+        assert!(matches!(
+            ChunkBuilder::default().patch_jump_to_last(0),
+            Err(CompileError::InvalidJump(0))
+        ));
     }
 
     #[test]
@@ -129,10 +343,42 @@ mod tests {
         chunk_builder.add_op(Op::Cmp);
         let jump_address = chunk_builder.add_op(Op::Jump(0));
 
-        chunk_builder.patch_jump_to(jump_address, target_address);
+        chunk_builder
+            .patch_jump_to(jump_address, target_address)
+            .unwrap();
+
+        let chunk = chunk_builder.build().unwrap();
+        assert_eq!(chunk.op(jump_address), Some(Op::Jump(-3)));
+    }
+
+    #[test]
+    fn jump_to_first_address() {
+        let mut chunk_builder = ChunkBuilder::default();
+        let target_address = chunk_builder.next_op_address();
+        chunk_builder.add_op(Op::ConstBool(true));
+        let jump_address = chunk_builder.add_op(Op::Jump(0));
+
+        chunk_builder
+            .patch_jump_to_address(jump_address, target_address)
+            .unwrap();
+
+        let chunk = chunk_builder.build().unwrap();
+        assert_eq!(chunk.op(jump_address), Some(Op::Jump(-2)));
+    }
+
+    #[test]
+    fn record_op_positions() {
+        let mut chunk_builder = ChunkBuilder::default();
+        chunk_builder.set_position(Position::new(1, 1));
+        chunk_builder.add_op(Op::ConstFloat(1.0));
+        chunk_builder.set_position(Position::new(2, 5));
+        chunk_builder.add_op(Op::Print);
+
+        let chunk = chunk_builder.build().unwrap();
 
-        let chunk = chunk_builder.build();
-        assert_eq!(chunk.op(jump_address), Some(&Op::Jump(-3)));
+        assert_eq!(chunk.position(0), Some(Position::new(1, 1)));
+        assert_eq!(chunk.position(1), Some(Position::new(2, 5)));
+        assert_eq!(chunk.position(2), None);
     }
 
     #[test]
@@ -151,4 +397,56 @@ mod tests {
             "constant pool put different constants in the same entry"
         );
     }
+
+    #[test]
+    fn resolve_jumps_to_labels() {
+        let mut chunk_builder = ChunkBuilder::default();
+        let start = chunk_builder.create_label();
+        let otherwise = chunk_builder.create_label();
+        let end = chunk_builder.create_label();
+        chunk_builder.bind_label(start).unwrap();
+        chunk_builder.add_op(Op::ConstBool(true));
+        chunk_builder.jump_to_label(Op::JumpIfFalse, otherwise);
+        chunk_builder.add_op(Op::ConstInt(1));
+        chunk_builder.jump_to_label(Op::Jump, end);
+        chunk_builder.bind_label(otherwise).unwrap();
+        chunk_builder.add_op(Op::ConstInt(2));
+        chunk_builder.bind_label(end).unwrap();
+        chunk_builder.add_op(Op::Pop);
+        chunk_builder.jump_to_label(Op::Jump, start);
+
+        // Only one of the branches pushes its value.
+        assert_eq!(chunk_builder.stack_depth(), 0);
+        assert_eq!(chunk_builder.max_stack_depth(), 1);
+        let chunk = chunk_builder.build().unwrap();
+        assert_eq!(
+            chunk.ops().collect::<Vec<_>>(),
+            vec![
+                Op::ConstBool(true),
+                Op::JumpIfFalse(2),
+                Op::ConstInt(1),
+                Op::Jump(1),
+                Op::ConstInt(2),
+                Op::Pop,
+                Op::Jump(-7),
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_misplaced_labels() {
+        let mut chunk_builder = ChunkBuilder::default();
+        let label = chunk_builder.create_label();
+        chunk_builder.jump_to_label(Op::Jump, label);
+
+        assert!(matches!(
+            chunk_builder.clone().build(),
+            Err(CompileError::UnboundLabel(0))
+        ));
+        chunk_builder.bind_label(label).unwrap();
+        assert!(matches!(
+            chunk_builder.bind_label(label),
+            Err(CompileError::LabelBoundTwice(0))
+        ));
+    }
 }
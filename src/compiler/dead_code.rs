@@ -0,0 +1,314 @@
+//! Dead code elimination of the AST.
+//!
+//! Runs after constant folding, so the conditions computed from literals are known.
+//! Drops the branches of `if` statements that are never taken, the `while` loops that never run,
+//! and the statements following `return`, `throw`, `break`, or `continue` in the same block.
+//! Only `true` and `false` are taken as constant conditions, as other values fail the strict conditions.
+//! Every piece of the dropped code is reported as a warning.
+
+use crate::ast::{Expression, Program, Statement};
+use crate::compiler::diagnostics::{CompileWarning, WarningKind};
+use crate::source::Span;
+
+/// Drops the code of the program that never runs, adding the warnings about it.
+pub fn eliminate_dead_code(program: Program, warnings: &mut Vec<CompileWarning>) -> Program {
+    let mut eliminator = Eliminator {
+        warnings,
+        span: None,
+    };
+    let mut statements = program.statements().to_vec();
+    statements.retain_mut(|statement| eliminator.statement(statement));
+    Program::new(statements)
+}
+
+struct Eliminator<'a> {
+    warnings: &'a mut Vec<CompileWarning>,
+    /// Span of the innermost statement being rewritten.
+    span: Option<Span>,
+}
+
+impl Eliminator<'_> {
+    /// Drops the dead code of the statement, returning `false` when none of it is left.
+    fn statement(&mut self, statement: &mut Statement) -> bool {
+        match statement {
+            Statement::Located(span, inner) => {
+                let outer = self.span.replace(*span);
+                let live = self.statement(inner);
+                self.span = outer;
+                live
+            }
+            Statement::If(Expression::BooleanLiteral(condition), then, otherwise) => {
+                let then = std::mem::replace(then.as_mut(), Statement::Block(vec![]));
+                let otherwise = otherwise.take().map(|otherwise| *otherwise);
+                let (taken, dead) = if *condition {
+                    (Some(then), otherwise)
+                } else {
+                    (otherwise, Some(then))
+                };
+                if dead.is_some() {
+                    self.warn(WarningKind::DeadBranch);
+                }
+                let Some(mut taken) = taken else {
+                    return false;
+                };
+                if !self.statement(&mut taken) {
+                    return false;
+                }
+                // Branches other than blocks declare their variables in the enclosing scope,
+                // so they stay under the condition instead of becoming blocks.
+                *statement = match taken {
+                    Statement::Block(_) => taken,
+                    taken => Statement::if_statement(Expression::BooleanLiteral(true), taken),
+                };
+                true
+            }
+            Statement::While(Expression::BooleanLiteral(false), _) => {
+                self.warn(WarningKind::ConstantFalseLoop);
+                false
+            }
+            Statement::Block(statements) => {
+                self.statements(statements);
+                true
+            }
+            Statement::Function { body, .. } => {
+                self.branch(body);
+                true
+            }
+            Statement::If(condition, then, otherwise) => {
+                self.expression(condition);
+                self.branch(then);
+                if let Some(otherwise) = otherwise {
+                    self.branch(otherwise);
+                }
+                true
+            }
+            Statement::While(condition, body) | Statement::DoWhile(body, condition) => {
+                self.expression(condition);
+                self.branch(body);
+                true
+            }
+            Statement::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                if let Some(initializer) = initializer {
+                    self.branch(initializer);
+                }
+                condition
+                    .iter_mut()
+                    .chain(increment.iter_mut())
+                    .for_each(|expr| self.expression(expr));
+                self.branch(body);
+                true
+            }
+            Statement::ForIn {
+                collection, body, ..
+            } => {
+                self.expression(collection);
+                self.branch(body);
+                true
+            }
+            Statement::Try { body, handler, .. } => {
+                self.branch(body);
+                self.branch(handler);
+                true
+            }
+            Statement::Switch {
+                subject,
+                cases,
+                default,
+            } => {
+                self.expression(subject);
+                for (value, body) in cases {
+                    self.expression(value);
+                    if !self.statement(body) {
+                        *body = Statement::Block(vec![]);
+                    }
+                }
+                if let Some(default) = default {
+                    self.branch(default);
+                }
+                true
+            }
+            Statement::Assert { condition, message } => {
+                self.expression(condition);
+                if let Some(message) = message {
+                    self.expression(message);
+                }
+                true
+            }
+            Statement::Expression(expr)
+            | Statement::DefineVariable(_, expr)
+            | Statement::DefineVariables(_, expr)
+            | Statement::DefineConstant(_, expr)
+            | Statement::Print(expr)
+            | Statement::Return(expr)
+            | Statement::Throw(expr) => {
+                self.expression(expr);
+                true
+            }
+            Statement::DeclareVariable(_) | Statement::Break | Statement::Continue => true,
+        }
+    }
+
+    /// Drops the dead code of the statement nested in another one, leaving an empty block if none is left.
+    fn branch(&mut self, statement: &mut Statement) {
+        if !self.statement(statement) {
+            *statement = Statement::Block(vec![]);
+        }
+    }
+
+    /// Drops the dead statements of a block, including the ones following a jump out of it.
+    fn statements(&mut self, statements: &mut Vec<Statement>) {
+        let mut rest = std::mem::take(statements).into_iter();
+        for mut statement in rest.by_ref() {
+            let jumps = matches!(
+                statement.inner(),
+                Statement::Return(_) | Statement::Throw(_) | Statement::Break | Statement::Continue
+            );
+            if self.statement(&mut statement) {
+                statements.push(statement);
+            }
+            if jumps {
+                break;
+            }
+        }
+        if let Some(unreachable) = rest.next() {
+            // One warning covers the rest of the block.
+            self.warnings.push(CompileWarning::new(
+                WarningKind::UnreachableCode,
+                unreachable.span(),
+            ));
+        }
+    }
+
+    /// Drops the dead code of the functions and blocks the expression defines.
+    fn expression(&mut self, expression: &mut Expression) {
+        match expression {
+            Expression::Lambda(_, body) => self.branch(body),
+            Expression::Block { statements, value } => {
+                self.statements(statements);
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            Expression::UnaryOperation(_, operand) => self.expression(operand),
+            Expression::Index {
+                array: lhs,
+                index: rhs,
+            }
+            | Expression::Assign {
+                target: lhs,
+                value: rhs,
+            }
+            | Expression::Array {
+                initial: lhs,
+                size: rhs,
+            }
+            | Expression::BinaryOperation(_, lhs, rhs)
+            | Expression::Range {
+                start: lhs,
+                end: rhs,
+            } => {
+                self.expression(lhs);
+                self.expression(rhs);
+            }
+            Expression::Tuple(items) => items.iter_mut().for_each(|item| self.expression(item)),
+            Expression::Map(entries) => entries.iter_mut().for_each(|(key, value)| {
+                self.expression(key);
+                self.expression(value);
+            }),
+            Expression::Call(callee, args) => {
+                self.expression(callee);
+                args.iter_mut().for_each(|arg| self.expression(arg));
+            }
+            Expression::Conditional {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.expression(condition);
+                self.expression(then);
+                self.expression(otherwise);
+            }
+            Expression::Nil
+            | Expression::NumberLiteral(_)
+            | Expression::IntegerLiteral(_)
+            | Expression::BooleanLiteral(_)
+            | Expression::StringLiteral(_)
+            | Expression::Variable(_)
+            | Expression::Update { .. } => {}
+        }
+    }
+
+    fn warn(&mut self, kind: WarningKind) {
+        self.warnings.push(CompileWarning::new(kind, self.span));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fmt::format_program;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    /// Eliminates the dead code of the source, returning the code left and the warnings.
+    fn eliminate(source: &str) -> (String, Vec<String>) {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let mut warnings = vec![];
+        let program = eliminate_dead_code(program, &mut warnings);
+        let warnings = warnings
+            .iter()
+            .map(|warning| format!("{} at {}", warning, warning.span().unwrap()))
+            .collect();
+        (format_program(&program), warnings)
+    }
+
+    #[test]
+    fn drop_branches_never_taken() {
+        let (code, warnings) = eliminate(
+            "if (false) { print 1; }
+if (true) { print 2; } else { print 3; }
+if (false) print 4; else print 5;
+while (false) { print 6; }",
+        );
+
+        assert_eq!(code, "{\n    print 2;\n}\nif (true)\n    print 5;\n");
+        assert_eq!(
+            warnings,
+            vec![
+                "branch is never taken at [1:1-2]",
+                "branch is never taken at [2:1-2]",
+                "branch is never taken at [3:1-2]",
+                "loop condition is always false at [4:1-5]",
+            ]
+        );
+    }
+
+    #[test]
+    fn drop_statements_after_jumps() {
+        let (code, warnings) = eliminate(
+            "fun f(a) {
+    if (a) { throw a; print a; }
+    return a;
+    print \"never\";
+    a = 1;
+}",
+        );
+
+        assert_eq!(
+            code,
+            "fun f(a) {\n    if (a) {\n        throw a;\n    }\n    return a;\n}\n"
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                "unreachable code at [2:23-27]",
+                "unreachable code at [4:5-9]"
+            ]
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Warnings about suspicious code found while compiling the program.
+//!
+//! Unlike [CompileError](super::CompileError)s, warnings do not stop the compilation.
+
+use std::fmt::Display;
+
+use crate::source::Span;
+
+/// Kinds of suspicious code the compiler warns about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WarningKind {
+    /// Local variable is declared but never read.
+    UnusedLocal(String),
+    /// Statement follows `return`, `throw`, `break`, or `continue` in the same block.
+    UnreachableCode,
+    /// Condition of `while` is always false, so the body never runs.
+    ConstantFalseLoop,
+    /// Condition of `if` is always the same, so one of the branches never runs.
+    DeadBranch,
+}
+
+/// Warning reported by the compiler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileWarning {
+    kind: WarningKind,
+    span: Option<Span>,
+}
+
+impl CompileWarning {
+    pub fn new(kind: WarningKind, span: Option<Span>) -> Self {
+        CompileWarning { kind, span }
+    }
+
+    /// Stable identifier of the warning kind.
+    pub fn code(&self) -> &'static str {
+        match self.kind {
+            WarningKind::UnusedLocal(_) => "unused-local",
+            WarningKind::UnreachableCode => "unreachable-code",
+            WarningKind::ConstantFalseLoop => "constant-false-loop",
+            WarningKind::DeadBranch => "dead-branch",
+        }
+    }
+
+    pub fn kind(&self) -> &WarningKind {
+        &self.kind
+    }
+
+    /// Range of the source code with the statement the warning is about, if it is known.
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+}
+
+impl Display for CompileWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            WarningKind::UnusedLocal(name) => write!(f, "local variable {} is never read", name),
+            WarningKind::UnreachableCode => write!(f, "unreachable code"),
+            WarningKind::ConstantFalseLoop => write!(f, "loop condition is always false"),
+            WarningKind::DeadBranch => write!(f, "branch is never taken"),
+        }
+    }
+}
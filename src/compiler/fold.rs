@@ -0,0 +1,359 @@
+//! Constant folding of the AST.
+//!
+//! Replaces operations on literals with their results, so the virtual machine
+//! does not compute the same values on every run.
+//! Operations that fail at runtime (e.g., adding a number to a string) are left intact.
+
+use crate::ast::{BinaryOperator, Expression, Program, Statement, UnaryOperator};
+
+/// Folds constant expressions of the whole program.
+pub fn fold_constants(program: Program) -> Program {
+    Program::new(program.statements().iter().map(fold_statement).collect())
+}
+
+fn fold_statement(statement: &Statement) -> Statement {
+    match statement {
+        Statement::Expression(expr) => Statement::Expression(fold_expression(expr)),
+        Statement::Print(expr) => Statement::Print(fold_expression(expr)),
+        Statement::Return(expr) => Statement::Return(fold_expression(expr)),
+        Statement::DeclareVariable(name) => Statement::DeclareVariable(name.clone()),
+        Statement::Break => Statement::Break,
+        Statement::Continue => Statement::Continue,
+        Statement::DefineVariable(name, value) => {
+            Statement::DefineVariable(name.clone(), fold_expression(value))
+        }
+        Statement::DefineVariables(names, value) => {
+            Statement::DefineVariables(names.clone(), fold_expression(value))
+        }
+        Statement::DefineConstant(name, value) => {
+            Statement::DefineConstant(name.clone(), fold_expression(value))
+        }
+        Statement::Function {
+            name,
+            params,
+            body,
+            doc,
+        } => Statement::Function {
+            name: name.clone(),
+            params: params.clone(),
+            body: Box::new(fold_statement(body)),
+            doc: doc.clone(),
+        },
+        Statement::Block(statements) => {
+            Statement::Block(statements.iter().map(fold_statement).collect())
+        }
+        Statement::If(condition, then, otherwise) => Statement::If(
+            fold_expression(condition),
+            Box::new(fold_statement(then)),
+            otherwise
+                .as_ref()
+                .map(|otherwise| Box::new(fold_statement(otherwise))),
+        ),
+        Statement::While(condition, body) => {
+            Statement::While(fold_expression(condition), Box::new(fold_statement(body)))
+        }
+        Statement::DoWhile(body, condition) => {
+            Statement::DoWhile(Box::new(fold_statement(body)), fold_expression(condition))
+        }
+        Statement::For {
+            initializer,
+            condition,
+            increment,
+            body,
+        } => Statement::For {
+            initializer: initializer
+                .as_ref()
+                .map(|initializer| Box::new(fold_statement(initializer))),
+            condition: condition.as_ref().map(fold_expression),
+            increment: increment.as_ref().map(fold_expression),
+            body: Box::new(fold_statement(body)),
+        },
+        Statement::ForIn {
+            variable,
+            collection,
+            body,
+        } => Statement::ForIn {
+            variable: variable.clone(),
+            collection: fold_expression(collection),
+            body: Box::new(fold_statement(body)),
+        },
+        Statement::Throw(expression) => Statement::Throw(fold_expression(expression)),
+        Statement::Try {
+            body,
+            variable,
+            handler,
+        } => Statement::Try {
+            body: Box::new(fold_statement(body)),
+            variable: variable.clone(),
+            handler: Box::new(fold_statement(handler)),
+        },
+        Statement::Switch {
+            subject,
+            cases,
+            default,
+        } => Statement::Switch {
+            subject: fold_expression(subject),
+            cases: cases
+                .iter()
+                .map(|(value, body)| (fold_expression(value), fold_statement(body)))
+                .collect(),
+            default: default
+                .as_ref()
+                .map(|default| Box::new(fold_statement(default))),
+        },
+        Statement::Assert { condition, message } => Statement::Assert {
+            condition: fold_expression(condition),
+            message: message.as_ref().map(fold_expression),
+        },
+        Statement::Located(span, statement) => Statement::located(*span, fold_statement(statement)),
+    }
+}
+
+pub(super) fn fold_expression(expression: &Expression) -> Expression {
+    match expression {
+        Expression::BinaryOperation(op, lhs, rhs) => {
+            let lhs = fold_expression(lhs);
+            let rhs = fold_expression(rhs);
+            fold_binary(op, &lhs, &rhs).unwrap_or_else(|| Expression::binary(op.clone(), lhs, rhs))
+        }
+        Expression::UnaryOperation(op, operand) => {
+            let operand = fold_expression(operand);
+            fold_unary(op, &operand).unwrap_or_else(|| Expression::unary(op.clone(), operand))
+        }
+        Expression::Index { array, index } => Expression::Index {
+            array: Box::new(fold_expression(array)),
+            index: Box::new(fold_expression(index)),
+        },
+        Expression::Assign { target, value } => Expression::Assign {
+            target: Box::new(fold_expression(target)),
+            value: Box::new(fold_expression(value)),
+        },
+        Expression::Array { initial, size } => Expression::Array {
+            initial: Box::new(fold_expression(initial)),
+            size: Box::new(fold_expression(size)),
+        },
+        Expression::Map(entries) => Expression::Map(
+            entries
+                .iter()
+                .map(|(key, value)| (fold_expression(key), fold_expression(value)))
+                .collect(),
+        ),
+        Expression::Lambda(params, body) => {
+            Expression::Lambda(params.clone(), Box::new(fold_statement(body)))
+        }
+        Expression::Call(callee, args) => Expression::Call(
+            Box::new(fold_expression(callee)),
+            args.iter().map(fold_expression).collect(),
+        ),
+        Expression::Conditional {
+            condition,
+            then,
+            otherwise,
+        } => Expression::Conditional {
+            condition: Box::new(fold_expression(condition)),
+            then: Box::new(fold_expression(then)),
+            otherwise: Box::new(fold_expression(otherwise)),
+        },
+        Expression::Tuple(elements) => {
+            Expression::Tuple(elements.iter().map(fold_expression).collect())
+        }
+        Expression::Range { start, end } => Expression::Range {
+            start: Box::new(fold_expression(start)),
+            end: Box::new(fold_expression(end)),
+        },
+        Expression::Block { statements, value } if statements.is_empty() => match value {
+            Some(value) => fold_expression(value),
+            None => Expression::Nil,
+        },
+        Expression::Block { statements, value } => Expression::Block {
+            statements: statements.iter().map(fold_statement).collect(),
+            value: value.as_ref().map(|value| Box::new(fold_expression(value))),
+        },
+        expression => expression.clone(),
+    }
+}
+
+fn fold_binary(op: &BinaryOperator, lhs: &Expression, rhs: &Expression) -> Option<Expression> {
+    use Expression::{BooleanLiteral, IntegerLiteral, NumberLiteral, StringLiteral};
+
+    let folded = match (op, lhs, rhs) {
+        // Overflowing integers are left for the virtual machine to report.
+        (BinaryOperator::Add, IntegerLiteral(a), IntegerLiteral(b)) => {
+            IntegerLiteral(a.checked_add(*b)?)
+        }
+        (BinaryOperator::Sub, IntegerLiteral(a), IntegerLiteral(b)) => {
+            IntegerLiteral(a.checked_sub(*b)?)
+        }
+        (BinaryOperator::Mul, IntegerLiteral(a), IntegerLiteral(b)) => {
+            IntegerLiteral(a.checked_mul(*b)?)
+        }
+        (BinaryOperator::Div, IntegerLiteral(a), IntegerLiteral(b)) => {
+            NumberLiteral(*a as f64 / *b as f64)
+        }
+        (BinaryOperator::Equal, IntegerLiteral(a), IntegerLiteral(b)) => BooleanLiteral(a == b),
+        (BinaryOperator::NotEqual, IntegerLiteral(a), IntegerLiteral(b)) => BooleanLiteral(a != b),
+        (BinaryOperator::Less, IntegerLiteral(a), IntegerLiteral(b)) => BooleanLiteral(a < b),
+        (BinaryOperator::Greater, IntegerLiteral(a), IntegerLiteral(b)) => BooleanLiteral(a > b),
+        (BinaryOperator::LessOrEqual, IntegerLiteral(a), IntegerLiteral(b)) => {
+            BooleanLiteral(a <= b)
+        }
+        (BinaryOperator::GreaterOrEqual, IntegerLiteral(a), IntegerLiteral(b)) => {
+            BooleanLiteral(a >= b)
+        }
+        (BinaryOperator::Add, NumberLiteral(a), NumberLiteral(b)) => NumberLiteral(a + b),
+        (BinaryOperator::Sub, NumberLiteral(a), NumberLiteral(b)) => NumberLiteral(a - b),
+        (BinaryOperator::Mul, NumberLiteral(a), NumberLiteral(b)) => NumberLiteral(a * b),
+        (BinaryOperator::Div, NumberLiteral(a), NumberLiteral(b)) => NumberLiteral(a / b),
+        (BinaryOperator::Pow, NumberLiteral(a), NumberLiteral(b)) => NumberLiteral(a.powf(*b)),
+        (BinaryOperator::Add, StringLiteral(a), StringLiteral(b)) => {
+            StringLiteral(format!("{}{}", a, b))
+        }
+        (BinaryOperator::Equal, NumberLiteral(a), NumberLiteral(b)) => BooleanLiteral(a == b),
+        (BinaryOperator::Equal, BooleanLiteral(a), BooleanLiteral(b)) => BooleanLiteral(a == b),
+        (BinaryOperator::Equal, StringLiteral(a), StringLiteral(b)) => BooleanLiteral(a == b),
+        (BinaryOperator::NotEqual, NumberLiteral(a), NumberLiteral(b)) => BooleanLiteral(a != b),
+        (BinaryOperator::NotEqual, BooleanLiteral(a), BooleanLiteral(b)) => BooleanLiteral(a != b),
+        (BinaryOperator::NotEqual, StringLiteral(a), StringLiteral(b)) => BooleanLiteral(a != b),
+        // The compiler emits `<` and `>` as negated `>=` and `<=`, so they are true for NaN.
+        (BinaryOperator::Less, NumberLiteral(a), NumberLiteral(b)) => {
+            BooleanLiteral(a < b || a.is_nan() || b.is_nan())
+        }
+        (BinaryOperator::Greater, NumberLiteral(a), NumberLiteral(b)) => {
+            BooleanLiteral(a > b || a.is_nan() || b.is_nan())
+        }
+        (BinaryOperator::LessOrEqual, NumberLiteral(a), NumberLiteral(b)) => BooleanLiteral(a <= b),
+        // The right operand is only evaluated when needed, so it is kept as is or dropped.
+        (BinaryOperator::And, BooleanLiteral(false), _) => BooleanLiteral(false),
+        (BinaryOperator::And, BooleanLiteral(true), rhs) => rhs.clone(),
+        (BinaryOperator::Or, BooleanLiteral(true), _) => BooleanLiteral(true),
+        (BinaryOperator::Or, BooleanLiteral(false), rhs) => rhs.clone(),
+        (BinaryOperator::GreaterOrEqual, NumberLiteral(a), NumberLiteral(b)) => {
+            BooleanLiteral(a >= b)
+        }
+        _ => return None,
+    };
+    Some(folded)
+}
+
+fn fold_unary(op: &UnaryOperator, operand: &Expression) -> Option<Expression> {
+    match (op, operand) {
+        (UnaryOperator::Negate, Expression::NumberLiteral(n)) => Some(Expression::number(-n)),
+        (UnaryOperator::Negate, Expression::IntegerLiteral(n)) => {
+            Some(Expression::integer(n.checked_neg()?))
+        }
+        (UnaryOperator::Not, Expression::BooleanLiteral(b)) => Some(Expression::BooleanLiteral(!b)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fold(expression: Expression) -> Expression {
+        fold_expression(&expression)
+    }
+
+    #[test]
+    fn fold_arithmetic() {
+        let expression = Expression::binary(
+            BinaryOperator::Add,
+            Expression::number(1),
+            Expression::binary(
+                BinaryOperator::Mul,
+                Expression::number(2),
+                Expression::unary(UnaryOperator::Negate, Expression::number(3)),
+            ),
+        );
+
+        assert_eq!(fold(expression), Expression::number(-5));
+    }
+
+    #[test]
+    fn fold_comparison() {
+        let expression = Expression::unary(
+            UnaryOperator::Not,
+            Expression::binary(
+                BinaryOperator::Less,
+                Expression::number(1),
+                Expression::number(2),
+            ),
+        );
+
+        assert_eq!(fold(expression), Expression::BooleanLiteral(false));
+    }
+
+    #[test]
+    fn fold_logical_operations() {
+        let expression = Expression::binary(
+            BinaryOperator::Or,
+            Expression::BooleanLiteral(false),
+            Expression::binary(
+                BinaryOperator::And,
+                Expression::BooleanLiteral(false),
+                Expression::variable("a"),
+            ),
+        );
+
+        assert_eq!(fold(expression), Expression::BooleanLiteral(false));
+    }
+
+    #[test]
+    fn keep_operations_with_variables() {
+        let expression = Expression::binary(
+            BinaryOperator::Add,
+            Expression::variable("a"),
+            Expression::binary(
+                BinaryOperator::Add,
+                Expression::number(1),
+                Expression::number(2),
+            ),
+        );
+
+        assert_eq!(
+            fold(expression),
+            Expression::binary(
+                BinaryOperator::Add,
+                Expression::variable("a"),
+                Expression::number(3)
+            )
+        );
+    }
+
+    #[test]
+    fn keep_operations_failing_at_runtime() {
+        let expression = Expression::binary(
+            BinaryOperator::Add,
+            Expression::number(1),
+            Expression::StringLiteral("a".to_string()),
+        );
+
+        assert_eq!(fold(expression.clone()), expression);
+    }
+
+    #[test]
+    fn fold_inside_statements() {
+        let program = Program::new(vec![Statement::while_loop(
+            Expression::binary(
+                BinaryOperator::Equal,
+                Expression::number(1),
+                Expression::number(2),
+            ),
+            Statement::print(Expression::binary(
+                BinaryOperator::Add,
+                Expression::StringLiteral("a".to_string()),
+                Expression::StringLiteral("b".to_string()),
+            )),
+        )]);
+
+        let folded = fold_constants(program);
+
+        assert_eq!(
+            folded.statements(),
+            &[Statement::while_loop(
+                Expression::BooleanLiteral(false),
+                Statement::print(Expression::StringLiteral("ab".to_string()))
+            )]
+        );
+    }
+}
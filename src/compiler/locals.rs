@@ -1,9 +1,17 @@
+use crate::compiler::CompileError;
+use crate::source::Span;
+
 /// Represents a local variable in the current scope
 #[derive(Debug, Clone)]
 pub struct Local {
     name: String,
     depth: usize,
     initialized: bool,
+    captured: bool,
+    used: bool,
+    span: Option<Span>,
+    /// Position of the variable on the stack of the call frame.
+    slot: usize,
 }
 
 /// Contains local variables
@@ -13,6 +21,26 @@ pub struct Locals {
     depth: usize,
 }
 
+impl Local {
+    pub fn is_captured(&self) -> bool {
+        self.captured
+    }
+
+    /// Checks if the variable is read or captured by a closure.
+    pub fn is_used(&self) -> bool {
+        self.used || self.captured
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Range of the source code with the declaration of the variable.
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+}
+
 impl Locals {
     /// Find the index of a local variable by name.
     pub fn resolve_local(&self, name: &str) -> Option<usize> {
@@ -35,11 +63,22 @@ impl Locals {
         self.check_local_on_depth(name, self.depth)
     }
 
-    pub fn add_local(&mut self, name: &str) -> Local {
+    /// Adds the local declared at the span of the source code.
+    /// It takes the stack slot next to the last local.
+    pub fn add_local(&mut self, name: &str, span: Option<Span>) -> Local {
+        self.add_local_in_slot(name, span, self.next_slot())
+    }
+
+    /// Adds the local in the stack slot, leaving the slots below it for temporary values.
+    pub fn add_local_in_slot(&mut self, name: &str, span: Option<Span>, slot: usize) -> Local {
         let local = Local {
             name: name.to_string(),
             depth: self.depth,
             initialized: false,
+            captured: false,
+            used: false,
+            span,
+            slot,
         };
         self.locals.push(local.clone());
         local
@@ -49,30 +88,68 @@ impl Locals {
         self.depth += 1;
     }
 
-    pub fn end_scope(&mut self) -> usize {
+    /// Ends the scope and returns its locals, starting from the last declared.
+    pub fn end_scope(&mut self) -> Vec<Local> {
         let locals_in_scope = self
             .locals
             .iter()
             .rev()
             .take_while(|local| local.depth == self.depth)
             .count();
+        let mut locals = vec![];
         for _ in 0..locals_in_scope {
-            self.locals.pop();
+            locals.extend(self.locals.pop());
         }
         self.depth -= 1;
-        locals_in_scope
+        locals
+    }
+
+    /// Returns locals of the scopes deeper than the depth, starting from the last declared.
+    /// The locals stay in scope.
+    pub fn locals_deeper_than(&self, depth: usize) -> impl Iterator<Item = &Local> {
+        self.locals
+            .iter()
+            .rev()
+            .take_while(move |local| local.depth > depth)
+    }
+
+    /// Marks the local as captured by a closure.
+    pub fn capture(&mut self, index: usize) {
+        self.locals[index].captured = true;
+    }
+
+    /// Returns the stack slot right above the last local.
+    pub fn next_slot(&self) -> usize {
+        self.locals.last().map_or(0, |local| local.slot + 1)
+    }
+
+    /// Returns the stack slot of the local.
+    pub fn slot(&self, index: usize) -> usize {
+        self.locals[index].slot
     }
 
-    pub fn initialize_last_local(&mut self) {
-        self.locals.last_mut().unwrap().initialized = true;
+    /// Marks the local as read by the program.
+    pub fn mark_used(&mut self, index: usize) {
+        self.locals[index].used = true;
+    }
+
+    /// Marks the last declared local as initialized, so the program can read it.
+    pub fn initialize_last_local(&mut self) -> Result<(), CompileError> {
+        let local = self.locals.last_mut().ok_or(CompileError::NoLocal)?;
+        local.initialized = true;
+        Ok(())
     }
 
     pub fn depth(&self) -> usize {
         self.depth
     }
 
-    pub fn last_index(&self) -> usize {
-        &self.locals.len() - 1
+    /// Returns the index of the last declared local.
+    pub fn last_index(&self) -> Result<usize, CompileError> {
+        self.locals
+            .len()
+            .checked_sub(1)
+            .ok_or(CompileError::NoLocal)
     }
 }
 
@@ -84,20 +161,45 @@ mod tests {
     fn add_local() {
         let mut locals = Locals::default();
         locals.begin_scope();
-        let local = locals.add_local("a");
+        let local = locals.add_local("a", None);
         assert_eq!(local.depth, 1);
 
         locals.begin_scope();
-        let local = locals.add_local("b");
+        let local = locals.add_local("b", None);
         assert_eq!(local.depth, 2);
 
         locals.end_scope();
-        let local = locals.add_local("c");
+        let local = locals.add_local("c", None);
         assert_eq!(local.depth, 1);
 
         locals.end_scope();
     }
 
+    #[test]
+    fn reject_initializing_missing_local() {
+        let mut locals = Locals::default();
+
+        assert!(matches!(
+            locals.initialize_last_local(),
+            Err(CompileError::NoLocal)
+        ));
+        assert!(matches!(locals.last_index(), Err(CompileError::NoLocal)));
+    }
+
+    #[test]
+    fn leave_slots_for_temporary_values() {
+        let mut locals = Locals::default();
+        locals.begin_scope();
+        locals.add_local("a", None);
+        locals.add_local_in_slot("b", None, 3);
+        locals.add_local("c", None);
+
+        assert_eq!(locals.slot(0), 0);
+        assert_eq!(locals.slot(1), 3);
+        assert_eq!(locals.slot(2), 4);
+        assert_eq!(locals.next_slot(), 5);
+    }
+
     #[test]
     fn begin_scope() {
         let mut locals = Locals::default();
@@ -113,25 +215,42 @@ mod tests {
         let mut locals = Locals::default();
         locals.begin_scope(); // outer scope
         locals.begin_scope(); // inner scope
-        locals.add_local("a");
-        locals.add_local("b");
-        let locals_in_scope = locals.end_scope();
+        locals.add_local("a", None);
+        locals.add_local("b", None);
+        let locals_in_scope = locals.end_scope().len();
         assert_eq!(locals_in_scope, 2, "inner scope had 2 variables");
         assert_eq!(locals.depth, 1, "inner scope ended");
-        let locals_in_scope = locals.end_scope();
+        let locals_in_scope = locals.end_scope().len();
         assert_eq!(locals_in_scope, 0, "outer scope had no variables");
         assert_eq!(locals.depth, 0, "outer scope ended");
     }
 
+    #[test]
+    fn end_scope_with_captured_locals() {
+        let mut locals = Locals::default();
+        locals.begin_scope();
+        locals.add_local("a", None);
+        locals.add_local("b", None);
+        locals.capture(0);
+
+        let captured: Vec<bool> = locals.end_scope().iter().map(Local::is_captured).collect();
+
+        assert_eq!(
+            captured,
+            vec![false, true],
+            "last declared local goes first"
+        );
+    }
+
     #[test]
     fn resolve_locals() {
         let mut locals = Locals::default();
         locals.begin_scope();
-        locals.add_local("a");
-        locals.initialize_last_local();
+        locals.add_local("a", None);
+        locals.initialize_last_local().unwrap();
         locals.begin_scope();
-        locals.add_local("b");
-        locals.initialize_last_local();
+        locals.add_local("b", None);
+        locals.initialize_last_local().unwrap();
 
         assert_eq!(locals.resolve_local("a"), Some(0));
         assert_eq!(locals.resolve_local("b"), Some(1));
@@ -150,11 +269,11 @@ mod tests {
     fn check_local_on_depth_level() {
         let mut locals = Locals::default();
         locals.begin_scope();
-        locals.add_local("a");
-        locals.initialize_last_local();
+        locals.add_local("a", None);
+        locals.initialize_last_local().unwrap();
         locals.begin_scope();
-        locals.add_local("b");
-        locals.initialize_last_local();
+        locals.add_local("b", None);
+        locals.initialize_last_local().unwrap();
 
         assert!(
             locals.check_local_on_depth("a", 1),
@@ -1,26 +1,119 @@
 //! Compiles AST into virtual machine instructions
 use log::trace;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::rc::Rc;
 use thiserror::Error;
 
-use locals::Locals;
+use diagnostics::{CompileWarning, WarningKind};
+use locals::{Local, Locals};
 
-use crate::ast::{BinaryOperator, Expression, Program, Statement, UnaryOperator};
-use crate::value::{Function, ValueType};
+use crate::ast::visit::Visitor;
+use crate::ast::{BinaryOperator, Expression, Program, Statement, UnaryOperator, UpdateOperator};
+use crate::source::Span;
+use crate::value::{Capture, Function, Interner};
+use crate::vm::bytecode::BytecodeError;
 use crate::vm::exec::Chunk;
+use crate::vm::globals::GlobalSlots;
 use crate::vm::opcode::Op;
 
-use self::chunk::ChunkBuilder;
+use self::chunk::{ChunkBuilder, Label};
 
 pub mod chunk;
+mod dead_code;
+pub mod diagnostics;
+mod fold;
 mod locals;
+pub mod optimize;
 
 type CompilationResult = Result<(), CompileError>;
 
+/// Name of the anonymous functions.
+const LAMBDA_NAME: &str = "lambda";
+/// Name of the hidden local variable holding the subject of a `switch`.
+/// It is not a valid identifier, so programs cannot refer to it.
+const SWITCH_SUBJECT: &str = "$switch";
+/// Names of the hidden local variables with the elements and the position of a `for-in` loop.
+const ITER_ELEMENTS: &str = "$elements";
+const ITER_INDEX: &str = "$index";
+/// Name of the hidden local variable with the end of the range a `for-in` loop counts to.
+const ITER_END: &str = "$end";
+/// Name of the hidden local variable taking the value of a block expression.
+const BLOCK_VALUE: &str = "$block";
+
 #[derive(Debug, Clone, Default)]
 pub struct Compiler {
     chunk: ChunkBuilder,
     locals: Locals,
+    opt_level: OptLevel,
+    /// Compiler of the function this function is declared in.
+    enclosing: Option<Box<Compiler>>,
+    captures: Vec<Capture>,
+    /// Loops enclosing the statement being compiled, the innermost is the last.
+    loops: Vec<Loop>,
+    /// Number of `try` blocks of the function enclosing the statement being compiled.
+    tries: usize,
+    /// Number of values the enclosing expressions keep on the stack above the locals.
+    temporaries: usize,
+    /// Span of the innermost statement being compiled.
+    span: Option<Span>,
+    warnings: Vec<CompileWarning>,
+    /// Constants declared by the script, with the values known at compile time.
+    consts: HashMap<String, Option<Expression>>,
+    /// Functions declared at the top level of the script, with their number of parameters.
+    functions: HashMap<String, usize>,
+    /// Number of stores to each global, telling the functions that are never replaced.
+    global_stores: HashMap<String, usize>,
+    /// Calls of globals, checked against the declared functions once the whole script is compiled.
+    calls: Vec<Call>,
+    /// Strings of the constant pools, shared by the chunks of all the functions.
+    strings: Interner,
+    /// Slots of the globals of the machine running the code, if it is known.
+    /// Without them the globals are addressed by name.
+    global_slots: Option<Rc<RefCell<GlobalSlots>>>,
+}
+
+/// Call of a global by name, waiting for the arity check.
+#[derive(Debug, Clone)]
+struct Call {
+    name: String,
+    arguments: usize,
+    span: Option<Span>,
+}
+
+/// Jumps of `break` and `continue` statements waiting for the loop addresses.
+#[derive(Debug, Clone, Default)]
+struct Loop {
+    /// Scope depth of the loop; the locals of deeper scopes are dropped on `break` and `continue`.
+    depth: usize,
+    /// Number of `try` blocks around the loop; the handlers of the inner ones are removed on jumps.
+    tries: usize,
+    break_jumps: Vec<usize>,
+    continue_jumps: Vec<usize>,
+}
+
+/// How much effort the compiler puts into optimizing the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel {
+    /// Compiles the program as written.
+    #[default]
+    None,
+    /// Folds constant expressions and drops the code that never runs.
+    Basic,
+    /// Also applies peephole optimizations to the compiled code.
+    Full,
+}
+
+impl OptLevel {
+    pub const ALL: [OptLevel; 3] = [OptLevel::None, OptLevel::Basic, OptLevel::Full];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OptLevel::None => "none",
+            OptLevel::Basic => "basic",
+            OptLevel::Full => "full",
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Error)]
@@ -31,115 +124,1115 @@ pub enum CompileError {
     VariableAlreadyDeclared(String),
     #[error("unsupported assignment target: {context}")]
     UnsupportedAssignmentTarget { context: String },
+    #[error("`{0}` outside of a loop")]
+    OutsideLoop(&'static str),
+    #[error("cannot assign to constant {0}")]
+    AssignToConst(String),
+    #[error("constant {0} is not declared at the top level")]
+    NestedConst(String),
+    #[error("function {name} expects {expected} arguments but got {found}")]
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    /// The syntax tree makes the compiler patch an instruction that is not a jump.
+    #[error("no jump instruction at address {0}")]
+    InvalidJump(usize),
+    /// The syntax tree makes the compiler initialize a local variable that is not declared.
+    #[error("no local variable is declared")]
+    NoLocal,
+    /// A jump goes to the label that is never placed in the code.
+    #[error("label {0} is not bound")]
+    UnboundLabel(usize),
+    /// The label is placed in the code more than once.
+    #[error("label {0} is bound more than once")]
+    LabelBoundTwice(usize),
+    /// An operand of the compiled code, like the index of a constant, does not fit 32 bits.
+    #[error("{0}")]
+    ChunkEncoding(#[from] BytecodeError),
+    /// Error of the statement at the span of the source code.
+    #[error("{source} at {span}")]
+    Located {
+        span: Span,
+        source: Box<CompileError>,
+    },
+}
+
+impl CompileError {
+    /// Stable identifier of the error kind.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompileError::Unknown => "compile-error",
+            CompileError::VariableAlreadyDeclared(_) => "variable-already-declared",
+            CompileError::UnsupportedAssignmentTarget { .. } => "unsupported-assignment-target",
+            CompileError::OutsideLoop(_) => "outside-loop",
+            CompileError::AssignToConst(_) => "assign-to-const",
+            CompileError::NestedConst(_) => "nested-const",
+            CompileError::ArityMismatch { .. } => "arity-mismatch",
+            CompileError::InvalidJump(_) => "invalid-jump",
+            CompileError::NoLocal => "no-local",
+            CompileError::UnboundLabel(_) => "unbound-label",
+            CompileError::LabelBoundTwice(_) => "label-bound-twice",
+            CompileError::ChunkEncoding(_) => "chunk-encoding",
+            CompileError::Located { source, .. } => source.code(),
+        }
+    }
+
+    /// Range of the source code with the statement that failed to compile, if it is known.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            CompileError::Located { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+
+    /// The error without its location.
+    pub fn kind(&self) -> &CompileError {
+        match self {
+            CompileError::Located { source, .. } => source.kind(),
+            error => error,
+        }
+    }
+
+    /// Annotates the error with the span of the statement, unless it is located already.
+    fn at(self, span: Span) -> Self {
+        match self {
+            CompileError::Located { .. } => self,
+            error => CompileError::Located {
+                span,
+                source: Box::new(error),
+            },
+        }
+    }
 }
 
 impl Compiler {
+    pub fn with_opt_level(opt_level: OptLevel) -> Self {
+        Compiler {
+            opt_level,
+            ..Default::default()
+        }
+    }
+
+    /// Addresses the globals by the slots of the table, shared with the machine running the code.
+    pub fn with_global_slots(self, global_slots: Rc<RefCell<GlobalSlots>>) -> Self {
+        Compiler {
+            global_slots: Some(global_slots),
+            ..self
+        }
+    }
+
+    /// Compiles the program into an executable chunk.
+    ///
+    /// The value of the last top-level expression statement becomes the result of the program.
     pub fn compile(&mut self, program: Program) -> Result<Chunk, CompileError> {
+        let mut warnings = vec![];
+        let program = if self.opt_level >= OptLevel::Basic {
+            dead_code::eliminate_dead_code(fold::fold_constants(program), &mut warnings)
+        } else {
+            program
+        };
         // TODO: this delegation approach is weird. Get rid of it.
-        let script_compiler = Compiler::default();
-        let chunk_builder = script_compiler.compile_part(program)?;
-        Ok(chunk_builder.build())
+        let mut script_compiler = Compiler::with_opt_level(self.opt_level);
+        script_compiler.strings = std::mem::take(&mut self.strings);
+        script_compiler.global_slots = self.global_slots.clone();
+        script_compiler.warnings = warnings;
+        let result = script_compiler.compile_script(program);
+        self.warnings = script_compiler.warnings;
+        self.strings = script_compiler.strings;
+        result?;
+        self.build(script_compiler.chunk)
+    }
+
+    /// Warnings about suspicious code found by the last compilation.
+    pub fn warnings(&self) -> &[CompileWarning] {
+        &self.warnings
     }
 
-    fn compile_part(mut self, program: Program) -> Result<ChunkBuilder, CompileError> {
-        for statement in program.statements() {
-            self.statement(statement)?;
+    fn build(&self, chunk_builder: ChunkBuilder) -> Result<Chunk, CompileError> {
+        let chunk = chunk_builder.build()?;
+        if self.opt_level >= OptLevel::Full {
+            Ok(optimize::optimize(&chunk)?)
+        } else {
+            Ok(chunk)
         }
-        Ok(self.chunk)
     }
 
-    fn statement(&mut self, ast: &Statement) -> CompilationResult {
-        trace!("Compiling statement: {:?}", ast);
-        match ast {
-            Statement::Expression(expr) => self.expression_statement(expr),
-            Statement::Print(expr) => self.print_statement(expr),
-            Statement::DeclareVariable(name) => self.declare_variable(name),
-            Statement::DefineVariable(name, value) => self.define_variable(name, value),
-            Statement::Block(statements) => self.block_statement(statements),
-            Statement::If(condition, then, otherwise) => {
-                self.if_statement(condition, then, otherwise)
+    fn compile_script(&mut self, program: Program) -> CompilationResult {
+        let statements = program.statements();
+        for (i, statement) in statements.iter().enumerate() {
+            let span = statement.span();
+            if let Some(span) = span {
+                self.chunk.set_position(span.end());
             }
-            Statement::While(condition, body) => self.while_statement(condition, body),
-            Statement::Function(name, params, body) => {
-                self.function_declaration(name, params, body)
+            match statement.inner() {
+                Statement::Expression(expr) if i == statements.len() - 1 => {
+                    self.visit_expression(expr).map_err(|e| match span {
+                        Some(span) => e.at(span),
+                        None => e,
+                    })?;
+                    self.chunk.add_op(Op::Return);
+                }
+                _ => self.visit_statement(statement)?,
             }
-            Statement::Return(expr) => self.return_statement(expr),
         }
+        self.check_arities()
+    }
+
+    /// Checks the calls of the functions the script declares once and never replaces.
+    fn check_arities(&self) -> CompilationResult {
+        for call in self.calls.iter() {
+            let Some(&expected) = self.functions.get(&call.name) else {
+                continue;
+            };
+            if expected == call.arguments || self.global_stores.get(&call.name) != Some(&1) {
+                continue;
+            }
+            let error = CompileError::ArityMismatch {
+                name: call.name.clone(),
+                expected,
+                found: call.arguments,
+            };
+            return Err(match call.span {
+                Some(span) => error.at(span),
+                None => error,
+            });
+        }
+        Ok(())
     }
 
-    fn block_statement(&mut self, statements: &Vec<Statement>) -> Result<(), CompileError> {
+    fn block_statement(&mut self, statements: &[Statement]) -> Result<(), CompileError> {
         self.block(statements)?;
         Ok(())
     }
 
     fn print_statement(&mut self, expr: &Expression) -> Result<(), CompileError> {
-        self.expression(expr)?;
+        self.visit_expression(expr)?;
         self.chunk.add_op(Op::Print);
         Ok(())
     }
 
     fn assign_variable(&mut self, name: &str, value: &Expression) -> Result<(), CompileError> {
-        self.expression(value)?;
+        self.visit_expression(value)?;
+        self.assign_variable_from_stack(name)
+    }
+
+    fn assign_variable_from_stack(&mut self, name: &str) -> Result<(), CompileError> {
         if self.locals.depth() > 0 {
             if let Some(local) = self.locals.resolve_local(name) {
-                self.chunk.add_op(Op::StoreLocal(local));
+                self.chunk.add_op(Op::StoreLocal(self.locals.slot(local)));
                 return Ok(());
             }
         }
-        self.store_global(name);
-        Ok(())
+        if let Some(upvalue) = self.resolve_upvalue(name) {
+            self.chunk.add_op(Op::StoreUpvalue(upvalue));
+            return Ok(());
+        }
+        self.store_global(name)
     }
 
-    fn assign_variable_from_stack(&mut self, name: &str) -> Result<(), CompileError> {
+    fn declare_variable(&mut self, name: &str) -> CompilationResult {
         if self.locals.depth() > 0 {
-            if let Some(local) = self.locals.resolve_local(name) {
-                self.chunk.add_op(Op::StoreLocal(local));
-                return Ok(());
+            if self.locals.check_local(name) {
+                return Err(CompileError::VariableAlreadyDeclared(name.to_string()));
             }
+            self.chunk.add_op(Op::Nil);
+            self.locals.add_local(name, self.span);
+            self.locals.initialize_last_local()?;
+            return Ok(());
         }
-        self.store_global(name);
+        self.chunk.add_op(Op::Nil);
+        self.store_global(name)?;
+        self.chunk.add_op(Op::Pop);
         Ok(())
     }
 
-    fn declare_variable(&mut self, name: &str) -> CompilationResult {
+    fn define_variable(&mut self, name: &str, value: &Expression) -> CompilationResult {
         if self.locals.depth() > 0 {
             if self.locals.check_local(name) {
                 return Err(CompileError::VariableAlreadyDeclared(name.to_string()));
             }
-            self.locals.add_local(name);
-            self.locals.initialize_last_local();
+            // The local is added after its value, so locals of block expressions in the value go below it.
+            self.visit_expression(value)?;
+            self.locals.add_local(name, self.span);
+            self.locals.initialize_last_local()?;
+            self.chunk
+                .add_op(Op::StoreLocal(self.locals.slot(self.locals.last_index()?)));
+            return Ok(());
+        }
+
+        self.visit_expression(value)?;
+        self.store_global(name)?;
+        self.chunk.add_op(Op::Pop);
+        Ok(())
+    }
+
+    /// Unpacks the tuple value into the variables in order.
+    /// The virtual machine checks that the tuple has a value for every variable.
+    fn define_variables(&mut self, names: &[String], value: &Expression) -> CompilationResult {
+        self.visit_expression(value)?;
+        self.chunk.add_op(Op::Unpack(names.len()));
+        if self.locals.depth() > 0 {
+            // The unpacked values are already in the slots of the new locals.
+            for name in names {
+                if self.locals.check_local(name) {
+                    return Err(CompileError::VariableAlreadyDeclared(name.to_string()));
+                }
+                self.locals.add_local(name, self.span);
+                self.locals.initialize_last_local()?;
+            }
             return Ok(());
         }
+        for name in names.iter().rev() {
+            self.store_global(name)?;
+            self.chunk.add_op(Op::Pop);
+        }
+        Ok(())
+    }
+
+    /// Defines the global that cannot be assigned later.
+    /// Uses of the constant with a literal value are replaced with the value.
+    fn define_constant(&mut self, name: &str, value: &Expression) -> CompilationResult {
+        if self.enclosing.is_some() || self.locals.depth() > 0 {
+            return Err(CompileError::NestedConst(name.to_string()));
+        }
+        self.visit_expression(value)?;
+        self.store_global(name)?;
+        self.chunk.add_op(Op::Pop);
+        let value = match fold::fold_expression(value) {
+            value @ (Expression::Nil
+            | Expression::NumberLiteral(_)
+            | Expression::IntegerLiteral(_)
+            | Expression::BooleanLiteral(_)
+            | Expression::StringLiteral(_)) => Some(value),
+            _ => None,
+        };
+        self.consts.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Finds the constant declared by the script.
+    fn constant(&self, name: &str) -> Option<&Option<Expression>> {
+        match self.consts.get(name) {
+            Some(constant) => Some(constant),
+            None => self.enclosing.as_ref()?.constant(name),
+        }
+    }
+
+    fn expression_statement(&mut self, expr: &Expression) -> CompilationResult {
+        self.visit_expression(expr)?;
+        self.chunk.add_op(Op::Pop);
+        Ok(())
+    }
+
+    fn initialize_array(&mut self, initial: &Expression, size: &Expression) -> CompilationResult {
+        self.visit_expression(size)?;
+        self.operand(initial, 1)?;
+        self.chunk.add_op(Op::Array);
+
+        Ok(())
+    }
+
+    /// Changes the element of the array or map in place, leaving the assigned value on the stack.
+    /// Strings are replaced by their changed copies, so the variables holding them get the copies back.
+    fn assign_index(
+        &mut self,
+        array: &Expression,
+        index: &Expression,
+        value: &Expression,
+    ) -> CompilationResult {
+        self.visit_expression(array)?;
+        self.operand(index, 1)?;
+        self.operand(value, 2)?;
+        match array {
+            Expression::Variable(name) if !self.is_constant(name) => {
+                self.chunk.add_op(Op::ReplaceIndex);
+                self.assign_variable_from_stack(name)?;
+                self.chunk.add_op(Op::Pop);
+            }
+            // Constants keep their values, but the elements of their arrays still change in place.
+            _ => {
+                self.chunk.add_op(Op::StoreIndex);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the name refers to a constant of the script rather than a variable hiding it.
+    fn is_constant(&mut self, name: &str) -> bool {
+        self.constant(name).is_some()
+            && self.locals.resolve_local(name).is_none()
+            && self.resolve_upvalue(name).is_none()
+    }
+
+    /// Compiles the operand while the values of the previous operands wait on the stack.
+    fn operand(&mut self, expression: &Expression, waiting: usize) -> CompilationResult {
+        self.temporaries += waiting;
+        let result = self.visit_expression(expression);
+        self.temporaries -= waiting;
+        result
+    }
+
+    /// Runs the statements in a new scope and leaves the value of the final expression on the stack.
+    /// The value goes to the slot of a hidden local declared first, so the other locals are dropped from above it.
+    /// Loops outside of the block cannot be left from it, as their jumps do not drop the waiting operands.
+    fn block_expression(
+        &mut self,
+        statements: &[Statement],
+        value: &Option<Box<Expression>>,
+    ) -> CompilationResult {
+        let temporaries = std::mem::take(&mut self.temporaries);
+        let loops = std::mem::take(&mut self.loops);
+        self.begin_scope();
+        let slot = self.locals.next_slot() + temporaries;
         self.chunk.add_op(Op::Nil);
-        self.store_global(name);
+        self.locals.add_local_in_slot(BLOCK_VALUE, None, slot);
+        self.locals.initialize_last_local()?;
+        self.locals.mark_used(self.locals.last_index()?);
+        let result = self.block_value(statements, value);
+        self.temporaries = temporaries;
+        self.loops = loops;
+        result?;
+        self.chunk.add_op(Op::StoreLocal(slot));
+        self.chunk.add_op(Op::Pop);
+        let mut locals = self.locals.end_scope();
+        // The hidden local is the first declared, so it is the last one returned.
+        locals.pop();
+        self.warn_unused(&locals);
+        for local in locals {
+            if local.is_captured() {
+                self.chunk.add_op(Op::CloseUpvalue);
+            } else {
+                self.chunk.add_op(Op::Pop);
+            }
+        }
+        Ok(())
+    }
+
+    fn block_value(
+        &mut self,
+        statements: &[Statement],
+        value: &Option<Box<Expression>>,
+    ) -> CompilationResult {
+        self.statements(statements)?;
+        match value {
+            Some(value) => self.visit_expression(value)?,
+            None => {
+                self.chunk.add_op(Op::Nil);
+            }
+        }
+        Ok(())
+    }
+
+    fn conditional(
+        &mut self,
+        condition: &Expression,
+        then: &Expression,
+        otherwise: &Expression,
+    ) -> CompilationResult {
+        let (otherwise_label, end) = (self.chunk.create_label(), self.chunk.create_label());
+        self.visit_expression(condition)?;
+        self.chunk.jump_to_label(Op::JumpIfFalse, otherwise_label);
+        self.visit_expression(then)?;
+        self.chunk.jump_to_label(Op::Jump, end);
+        self.chunk.bind_label(otherwise_label)?;
+        self.visit_expression(otherwise)?;
+        self.chunk.bind_label(end)
+    }
+
+    fn and(&mut self, lhs: &Expression, rhs: &Expression) -> CompilationResult {
+        let (is_false, end) = (self.chunk.create_label(), self.chunk.create_label());
+        self.visit_expression(lhs)?;
+        self.chunk.jump_to_label(Op::JumpIfFalse, is_false);
+        self.visit_expression(rhs)?;
+        self.chunk.jump_to_label(Op::Jump, end);
+        self.chunk.bind_label(is_false)?;
+        self.chunk.add_op(Op::ConstBool(false));
+        self.chunk.bind_label(end)
+    }
+
+    fn or(&mut self, lhs: &Expression, rhs: &Expression) -> CompilationResult {
+        let (is_false, end) = (self.chunk.create_label(), self.chunk.create_label());
+        self.visit_expression(lhs)?;
+        self.chunk.jump_to_label(Op::JumpIfFalse, is_false);
+        self.chunk.add_op(Op::ConstBool(true));
+        self.chunk.jump_to_label(Op::Jump, end);
+        self.chunk.bind_label(is_false)?;
+        self.visit_expression(rhs)?;
+        self.chunk.bind_label(end)
+    }
+
+    fn function_body(&mut self, params: &[String], body: &Statement) -> CompilationResult {
+        self.begin_scope();
+        for param in params {
+            self.declare_used_variable(param)?;
+        }
+        self.visit_statement(body)
+    }
+
+    /// Adds or subtracts one from the variable, leaving the new or the old value on the stack.
+    fn update_variable(
+        &mut self,
+        operator: &UpdateOperator,
+        name: &str,
+        prefix: bool,
+    ) -> CompilationResult {
+        if !prefix {
+            self.load_variable(name);
+        }
+        self.chunk.add_op(Op::ConstInt(1));
+        self.load_variable(name);
+        match operator {
+            UpdateOperator::Increment => self.chunk.add_op(Op::Add),
+            UpdateOperator::Decrement => self.chunk.add_op(Op::Sub),
+        };
+        self.assign_variable_from_stack(name)?;
+        if !prefix {
+            self.chunk.add_op(Op::Pop);
+        }
+        Ok(())
+    }
+
+    /// Declares the variable the program is not required to read, like a parameter.
+    /// The value of the variable must be on the stack already.
+    fn declare_used_variable(&mut self, name: &str) -> CompilationResult {
+        if self.locals.check_local(name) {
+            return Err(CompileError::VariableAlreadyDeclared(name.to_string()));
+        }
+        self.locals.add_local(name, self.span);
+        self.locals.initialize_last_local()?;
+        self.locals.mark_used(self.locals.last_index()?);
+        Ok(())
+    }
+
+    fn load_variable(&mut self, name: &str) {
+        if let Some(local) = self.locals.resolve_local(name) {
+            self.locals.mark_used(local);
+            self.chunk.add_op(Op::LoadLocal(self.locals.slot(local)));
+            return;
+        }
+        if let Some(upvalue) = self.resolve_upvalue(name) {
+            self.chunk.add_op(Op::LoadUpvalue(upvalue));
+            return;
+        }
+        if let Some(Some(value)) = self.constant(name).cloned() {
+            // Literals compile without errors.
+            let _ = self.visit_expression(&value);
+            return;
+        }
+        self.load_global(name);
+    }
+
+    /// Finds the variable in the enclosing functions and captures it.
+    fn resolve_upvalue(&mut self, name: &str) -> Option<usize> {
+        let enclosing = self.enclosing.as_mut()?;
+        if let Some(local) = enclosing.locals.resolve_local(name) {
+            enclosing.locals.capture(local);
+            let slot = enclosing.locals.slot(local);
+            return Some(self.add_capture(Capture::Local(slot)));
+        }
+        let upvalue = enclosing.resolve_upvalue(name)?;
+        Some(self.add_capture(Capture::Upvalue(upvalue)))
+    }
+
+    fn add_capture(&mut self, capture: Capture) -> usize {
+        if let Some(index) = self.captures.iter().position(|c| *c == capture) {
+            return index;
+        }
+        self.captures.push(capture);
+        self.captures.len() - 1
+    }
+
+    fn block(&mut self, statements: &[Statement]) -> CompilationResult {
+        self.begin_scope();
+        self.statements(statements)?;
+        self.end_scope();
+        Ok(())
+    }
+
+    /// Compiles the statements of a block, warning about the ones that never run.
+    fn statements(&mut self, statements: &[Statement]) -> CompilationResult {
+        let mut reachable = true;
+        for statement in statements {
+            if !reachable {
+                self.warn(WarningKind::UnreachableCode, statement.span());
+                // One warning covers the rest of the block.
+                reachable = true;
+            }
+            self.visit_statement(statement)?;
+            if matches!(
+                statement.inner(),
+                Statement::Return(_) | Statement::Throw(_) | Statement::Break | Statement::Continue
+            ) {
+                reachable = false;
+            }
+        }
+        Ok(())
+    }
+
+    fn begin_scope(&mut self) {
+        self.locals.begin_scope();
+    }
+
+    fn end_scope(&mut self) {
+        let locals = self.locals.end_scope();
+        self.warn_unused(&locals);
+        for local in locals {
+            if local.is_captured() {
+                self.chunk.add_op(Op::CloseUpvalue);
+            } else {
+                self.chunk.add_op(Op::Pop);
+            }
+        }
+    }
+
+    fn if_statement(
+        &mut self,
+        condition: &Expression,
+        then: &Statement,
+        otherwise: &Option<Box<Statement>>,
+    ) -> CompilationResult {
+        let (otherwise_label, end) = (self.chunk.create_label(), self.chunk.create_label());
+        self.visit_expression(condition)?;
+        self.chunk.jump_to_label(Op::JumpIfFalse, otherwise_label);
+        self.visit_statement(then)?;
+        if let Some(otherwise) = otherwise {
+            self.chunk.jump_to_label(Op::Jump, end);
+            self.chunk.bind_label(otherwise_label)?;
+            self.visit_statement(otherwise)?;
+        } else {
+            self.chunk.bind_label(otherwise_label)?;
+        }
+        self.chunk.bind_label(end)
+    }
+
+    /// Runs the body under the exception handler.
+    /// The thrown value becomes the local variable of the handler block.
+    fn try_statement(
+        &mut self,
+        body: &Statement,
+        variable: &str,
+        handler: &Statement,
+    ) -> CompilationResult {
+        let handler_jump = self.chunk.add_op(Op::PushHandler(0));
+        self.tries += 1;
+        let result = self.visit_statement(body);
+        self.tries -= 1;
+        result?;
+        self.chunk.add_op(Op::PopHandler);
+        let end_jump = self.chunk.add_op(Op::Jump(0));
+        self.chunk.patch_jump_to_last(handler_jump)?;
+        self.begin_scope();
+        self.declare_used_variable(variable)?;
+        self.visit_statement(handler)?;
+        self.end_scope();
+        self.chunk.patch_jump_to_last(end_jump)?;
+        Ok(())
+    }
+
+    /// Compares the subject with the case values in order and runs the body of the first match.
+    /// The subject lives in a local variable, so `break` and `continue` in the cases drop it.
+    fn switch_statement(
+        &mut self,
+        subject: &Expression,
+        cases: &[(Expression, Statement)],
+        default: &Option<Box<Statement>>,
+    ) -> CompilationResult {
+        self.begin_scope();
+        self.define_variable(SWITCH_SUBJECT, subject)?;
+        self.locals.mark_used(self.locals.last_index()?);
+        let mut end_jumps = vec![];
+        for (value, body) in cases {
+            self.load_variable(SWITCH_SUBJECT);
+            self.operand(value, 1)?;
+            self.chunk.add_op(Op::Match);
+            let next_jump = self.chunk.add_op(Op::JumpIfFalse(0));
+            self.visit_statement(body)?;
+            end_jumps.push(self.chunk.add_op(Op::Jump(0)));
+            self.chunk.patch_jump_to_last(next_jump)?;
+        }
+        if let Some(default) = default {
+            self.visit_statement(default)?;
+        }
+        for jump in end_jumps {
+            self.chunk.patch_jump_to_last(jump)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    fn while_statement(&mut self, condition: &Expression, body: &Statement) -> CompilationResult {
+        if matches!(
+            fold::fold_expression(condition),
+            Expression::BooleanLiteral(false) | Expression::Nil
+        ) {
+            self.warn(WarningKind::ConstantFalseLoop, self.span);
+        }
+        // The condition follows the body, so every iteration takes a single jump back.
+        let (body_start, condition_label) = (self.chunk.create_label(), self.chunk.create_label());
+        self.chunk.jump_to_label(Op::Jump, condition_label);
+        self.chunk.bind_label(body_start)?;
+        self.loop_body(body)?;
+        let condition_start = self.chunk.next_op_address();
+        self.chunk.bind_label(condition_label)?;
+        self.loop_condition(condition, body_start)?;
+        self.end_loop(condition_start)?;
+        Ok(())
+    }
+
+    fn do_while_statement(
+        &mut self,
+        body: &Statement,
+        condition: &Expression,
+    ) -> CompilationResult {
+        let body_start = self.chunk.create_label();
+        self.chunk.bind_label(body_start)?;
+        self.loop_body(body)?;
+        // `continue` skips the rest of the body to the condition.
+        let condition_start = self.chunk.next_op_address();
+        self.loop_condition(condition, body_start)?;
+        self.end_loop(condition_start)?;
+        Ok(())
+    }
+
+    /// Compiles the condition jumping back to the start of the loop body while it holds.
+    fn loop_condition(&mut self, condition: &Expression, body_start: Label) -> CompilationResult {
+        // The condition belongs to the loop statement, not to the last statement of the body.
+        if let Some(span) = self.span {
+            self.chunk.set_position(span.end());
+        }
+        self.visit_expression(condition)?;
+        self.chunk.jump_to_label(Op::JumpIfTrue, body_start);
+        Ok(())
+    }
+
+    fn for_statement(
+        &mut self,
+        initializer: &Option<Box<Statement>>,
+        condition: &Option<Expression>,
+        increment: &Option<Expression>,
+        body: &Statement,
+    ) -> CompilationResult {
+        // The loop variable is only visible inside the loop.
+        self.begin_scope();
+        if let Some(initializer) = initializer {
+            self.visit_statement(initializer)?;
+        }
+        let (body_start, condition_label) = (self.chunk.create_label(), self.chunk.create_label());
+        if condition.is_some() {
+            self.chunk.jump_to_label(Op::Jump, condition_label);
+        }
+        self.chunk.bind_label(body_start)?;
+        self.loop_body(body)?;
+        let increment_start = self.chunk.next_op_address();
+        if let Some(increment) = increment {
+            self.visit_expression(increment)?;
+            self.chunk.add_op(Op::Pop);
+        }
+        match condition {
+            Some(condition) => {
+                self.chunk.bind_label(condition_label)?;
+                self.loop_condition(condition, body_start)?;
+            }
+            None => {
+                self.chunk.jump_to_label(Op::Jump, body_start);
+            }
+        }
+        self.end_loop(increment_start)?;
+        self.end_scope();
         Ok(())
     }
 
-    fn define_variable(&mut self, name: &str, value: &Expression) -> CompilationResult {
-        if self.locals.depth() > 0 {
-            if self.locals.check_local(name) {
-                return Err(CompileError::VariableAlreadyDeclared(name.to_string()));
-            }
-            self.locals.add_local(name);
-            self.expression(value)?;
-            self.locals.initialize_last_local();
-            self.chunk.add_op(Op::StoreLocal(self.locals.last_index()));
+    /// Compiles `for (variable in collection)` into the iteration operations.
+    /// The elements and the index stay on the stack as hidden locals while the loop runs.
+    fn for_in_statement(
+        &mut self,
+        variable: &str,
+        collection: &Expression,
+        body: &Statement,
+    ) -> CompilationResult {
+        if let Expression::Range { start, end } = collection {
+            return self.for_range_statement(variable, start, end, body);
+        }
+        self.begin_scope();
+        self.visit_expression(collection)?;
+        self.chunk.add_op(Op::IterInit);
+        for name in [ITER_ELEMENTS, ITER_INDEX] {
+            self.declare_used_variable(name)?;
+        }
+        let loop_start = self.chunk.next_op_address();
+        let exit_jump = self.chunk.add_op(Op::IterNext(0));
+        self.iteration_body(variable, body)?;
+        let loop_jump = self.chunk.add_op(Op::Jump(0));
+        self.chunk.patch_jump_to_address(loop_jump, loop_start)?;
+        self.chunk.patch_jump_to_last(exit_jump)?;
+        self.end_loop(loop_start)?;
+        self.end_scope();
+        Ok(())
+    }
+
+    /// Compiles `for (variable in start..end)` into a counter loop without creating the range.
+    fn for_range_statement(
+        &mut self,
+        variable: &str,
+        start: &Expression,
+        end: &Expression,
+        body: &Statement,
+    ) -> CompilationResult {
+        self.begin_scope();
+        self.visit_expression(start)?;
+        self.declare_used_variable(ITER_INDEX)?;
+        self.visit_expression(end)?;
+        self.declare_used_variable(ITER_END)?;
+        let loop_start = self.chunk.next_op_address();
+        // Binary operations take the first operand from the top of the stack.
+        self.load_variable(ITER_END);
+        self.load_variable(ITER_INDEX);
+        self.chunk.add_op(Op::Lt);
+        let exit_jump = self.chunk.add_op(Op::JumpIfFalse(0));
+        self.load_variable(ITER_INDEX);
+        self.iteration_body(variable, body)?;
+        let increment_start = self.chunk.next_op_address();
+        self.load_variable(ITER_INDEX);
+        self.chunk.add_op(Op::Increment);
+        self.assign_variable_from_stack(ITER_INDEX)?;
+        self.chunk.add_op(Op::Pop);
+        let loop_jump = self.chunk.add_op(Op::Jump(0));
+        self.chunk.patch_jump_to_address(loop_jump, loop_start)?;
+        self.chunk.patch_jump_to_last(exit_jump)?;
+        self.end_loop(increment_start)?;
+        self.end_scope();
+        Ok(())
+    }
+
+    /// Compiles the body of a `for-in` loop with the element on top of the stack as the loop variable.
+    /// The loop variable is declared deeper than the loop, so `break` and `continue` drop it.
+    fn iteration_body(&mut self, variable: &str, body: &Statement) -> CompilationResult {
+        self.loops.push(Loop {
+            depth: self.locals.depth(),
+            tries: self.tries,
+            ..Default::default()
+        });
+        self.begin_scope();
+        self.locals.add_local(variable, self.span);
+        self.locals.initialize_last_local()?;
+        let result = self.visit_statement(body);
+        if result.is_err() {
+            self.loops.pop();
+            return result;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    /// Compiles the loop body, collecting its `break` and `continue` jumps.
+    fn loop_body(&mut self, body: &Statement) -> CompilationResult {
+        self.loops.push(Loop {
+            depth: self.locals.depth(),
+            tries: self.tries,
+            ..Default::default()
+        });
+        let result = self.visit_statement(body);
+        if result.is_err() {
+            self.loops.pop();
+        }
+        result
+    }
+
+    /// Directs `continue` jumps of the innermost loop to the address and `break` jumps past the loop.
+    fn end_loop(&mut self, continue_address: usize) -> CompilationResult {
+        let Some(current) = self.loops.pop() else {
+            return Ok(());
+        };
+        for jump in current.continue_jumps {
+            self.chunk.patch_jump_to_address(jump, continue_address)?;
+        }
+        for jump in current.break_jumps {
+            self.chunk.patch_jump_to_last(jump)?;
+        }
+        Ok(())
+    }
+
+    fn break_statement(&mut self) -> CompilationResult {
+        let jump = self.loop_jump("break")?;
+        if let Some(current) = self.loops.last_mut() {
+            current.break_jumps.push(jump);
+        }
+        Ok(())
+    }
+
+    fn continue_statement(&mut self) -> CompilationResult {
+        let jump = self.loop_jump("continue")?;
+        if let Some(current) = self.loops.last_mut() {
+            current.continue_jumps.push(jump);
+        }
+        Ok(())
+    }
+
+    /// Drops the locals and the exception handlers of the loop body and adds a jump to be patched later.
+    fn loop_jump(&mut self, statement: &'static str) -> Result<usize, CompileError> {
+        let (depth, tries) = self
+            .loops
+            .last()
+            .map(|current| (current.depth, current.tries))
+            .ok_or(CompileError::OutsideLoop(statement))?;
+        for _ in tries..self.tries {
+            self.chunk.add_op(Op::PopHandler);
+        }
+        let drops: Vec<Op> = self
+            .locals
+            .locals_deeper_than(depth)
+            .map(|local| {
+                if local.is_captured() {
+                    Op::CloseUpvalue
+                } else {
+                    Op::Pop
+                }
+            })
+            .collect();
+        for op in drops {
+            self.chunk.add_op(op);
+        }
+        Ok(self.chunk.add_op(Op::Jump(0)))
+    }
+
+    fn function_declaration(
+        &mut self,
+        name: &str,
+        params: &[String],
+        body: &Statement,
+    ) -> CompilationResult {
+        if self.locals.depth() > 0 {
+            if self.locals.check_local(name) {
+                return Err(CompileError::VariableAlreadyDeclared(name.to_string()));
+            }
+            // The local is added before the body, so the function can call itself.
+            self.chunk.add_op(Op::Nil);
+            self.locals.add_local(name, self.span);
+            self.locals.initialize_last_local()?;
+            let slot = self.locals.slot(self.locals.last_index()?);
+            self.function(name, params, body)?;
+            self.chunk.add_op(Op::StoreLocal(slot));
+            self.chunk.add_op(Op::Pop);
+            return Ok(());
+        }
+        self.function(name, params, body)?;
+        self.store_global(name)?;
+        self.chunk.add_op(Op::Pop);
+        if self.enclosing.is_none() {
+            self.functions.insert(name.to_string(), params.len());
+        }
+        Ok(())
+    }
+
+    /// Compiles the function and puts it on the stack.
+    fn function(&mut self, name: &str, params: &[String], body: &Statement) -> CompilationResult {
+        // The function compiler owns the enclosing one while compiling the body,
+        // so it can resolve variables of the enclosing function.
+        let mut function_compiler = Compiler {
+            opt_level: self.opt_level,
+            strings: std::mem::take(&mut self.strings),
+            global_slots: self.global_slots.clone(),
+            enclosing: Some(Box::new(std::mem::take(self))),
+            ..Default::default()
+        };
+        let result = function_compiler.function_body(params, body);
+        *self = *function_compiler.enclosing.take().unwrap_or_default();
+        self.strings = std::mem::take(&mut function_compiler.strings);
+        // The locals of the function body are dropped by the return, not by the end of the scope.
+        let locals: Vec<Local> = function_compiler
+            .locals
+            .locals_deeper_than(0)
+            .cloned()
+            .collect();
+        function_compiler.warn_unused(&locals);
+        self.warnings.append(&mut function_compiler.warnings);
+        self.calls.append(&mut function_compiler.calls);
+        for (name, stores) in function_compiler.global_stores {
+            *self.global_stores.entry(name).or_default() += stores;
+        }
+        result?;
+
+        let mut chunk_builder = function_compiler.chunk;
+        chunk_builder.add_op(Op::Nil);
+        chunk_builder.add_op(Op::Return);
+        let chunk = Rc::new(self.build(chunk_builder)?);
+        let captures = function_compiler.captures;
+        let function = Function::closure(name.to_string(), chunk, params.len(), captures);
+        self.chunk.emit_function(function);
+        Ok(())
+    }
+
+    fn call(&mut self, callee: &Expression, args: &[Expression]) -> CompilationResult {
+        self.callee_and_arguments(callee, args)?;
+        self.chunk.add_op(Op::Call(args.len()));
+        Ok(())
+    }
+
+    fn callee_and_arguments(
+        &mut self,
+        callee: &Expression,
+        args: &[Expression],
+    ) -> CompilationResult {
+        if let Expression::Variable(name) = callee {
+            // Only the calls of globals can reach the top-level declarations checked for arity.
+            if self.locals.resolve_local(name).is_none() && self.resolve_upvalue(name).is_none() {
+                self.calls.push(Call {
+                    name: name.to_string(),
+                    arguments: args.len(),
+                    span: self.span,
+                });
+            }
+        }
+        self.visit_expression(callee)?;
+        self.arguments(args)
+    }
+
+    /// Compiles the arguments above the callee, which waits on the stack for the call.
+    fn arguments(&mut self, args: &[Expression]) -> CompilationResult {
+        for (i, arg) in args.iter().enumerate() {
+            self.operand(arg, i + 1)?;
+        }
+        Ok(())
+    }
+
+    fn return_statement(&mut self, expression: &Expression) -> CompilationResult {
+        match expression {
+            // The script keeps its frame, so only the calls from functions reuse the frame.
+            // The calls inside `try` keep the frame with the exception handler.
+            Expression::Call(callee, args) if self.enclosing.is_some() && self.tries == 0 => {
+                self.callee_and_arguments(callee, args)?;
+                self.chunk.add_op(Op::TailCall(args.len()));
+            }
+            _ => {
+                self.visit_expression(expression)?;
+                self.chunk.add_op(Op::Return);
+            }
+        }
+        Ok(())
+    }
+
+    fn warn(&mut self, kind: WarningKind, span: Option<Span>) {
+        self.warnings.push(CompileWarning::new(kind, span));
+    }
+
+    /// Warns about the locals that are never read, in the order of declaration.
+    fn warn_unused(&mut self, locals: &[Local]) {
+        for local in locals.iter().rev().filter(|local| !local.is_used()) {
+            self.warn(
+                WarningKind::UnusedLocal(local.name().to_string()),
+                local.span(),
+            );
+        }
+    }
+
+    fn load_global(&mut self, name: &str) {
+        if let Some(slot) = self.global_slot(name) {
+            self.chunk.add_op(Op::LoadGlobalSlot(slot));
+            return;
+        }
+        let const_idx = self.chunk.add_constant(self.strings.string(name));
+        self.chunk.add_op(Op::LoadGlobal(const_idx));
+    }
+
+    fn store_global(&mut self, name: &str) -> CompilationResult {
+        if self.constant(name).is_some() {
+            return Err(CompileError::AssignToConst(name.to_string()));
+        }
+        *self.global_stores.entry(name.to_string()).or_default() += 1;
+        if let Some(slot) = self.global_slot(name) {
+            self.chunk.add_op(Op::StoreGlobalSlot(slot));
             return Ok(());
         }
-
-        self.expression(value)?;
-        self.store_global(name);
-        self.chunk.add_op(Op::Pop);
+        let const_idx = self.chunk.add_constant(self.strings.string(name));
+        self.chunk.add_op(Op::StoreGlobal(const_idx));
         Ok(())
     }
 
-    fn expression_statement(&mut self, expr: &Expression) -> CompilationResult {
-        self.expression(expr)?;
-        self.chunk.add_op(Op::Pop);
-        Ok(())
+    /// Slot of the global in the table of the machine, if the compiler has one.
+    fn global_slot(&mut self, name: &str) -> Option<usize> {
+        let name = self.strings.intern(name);
+        let global_slots = self.global_slots.as_ref()?;
+        let slot = global_slots.borrow_mut().slot(&name);
+        Some(slot)
+    }
+}
+
+/// The compiler visits the nodes of the tree in the order of the instructions it emits for them,
+/// so it dispatches on the nodes itself instead of walking them in the order of the source code.
+impl Visitor for Compiler {
+    type Error = CompileError;
+
+    fn visit_statement(&mut self, ast: &Statement) -> CompilationResult {
+        trace!("Compiling statement: {:?}", ast);
+        match ast {
+            Statement::Expression(expr) => self.expression_statement(expr),
+            Statement::Print(expr) => self.print_statement(expr),
+            Statement::DeclareVariable(name) => self.declare_variable(name),
+            Statement::DefineVariable(name, value) => self.define_variable(name, value),
+            Statement::DefineVariables(names, value) => self.define_variables(names, value),
+            Statement::DefineConstant(name, value) => self.define_constant(name, value),
+            Statement::Block(statements) => self.block_statement(statements),
+            Statement::If(condition, then, otherwise) => {
+                self.if_statement(condition, then, otherwise)
+            }
+            Statement::While(condition, body) => self.while_statement(condition, body),
+            Statement::DoWhile(body, condition) => self.do_while_statement(body, condition),
+            Statement::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => self.for_statement(initializer, condition, increment, body),
+            Statement::ForIn {
+                variable,
+                collection,
+                body,
+            } => self.for_in_statement(variable, collection, body),
+            Statement::Function {
+                name, params, body, ..
+            } => self.function_declaration(name, params, body),
+            Statement::Break => self.break_statement(),
+            Statement::Continue => self.continue_statement(),
+            Statement::Return(expr) => self.return_statement(expr),
+            Statement::Throw(expr) => {
+                self.visit_expression(expr)?;
+                self.chunk.add_op(Op::Throw);
+                Ok(())
+            }
+            Statement::Try {
+                body,
+                variable,
+                handler,
+            } => self.try_statement(body, variable, handler),
+            Statement::Switch {
+                subject,
+                cases,
+                default,
+            } => self.switch_statement(subject, cases, default),
+            Statement::Assert { condition, message } => {
+                self.visit_expression(condition)?;
+                match message {
+                    Some(message) => self.operand(message, 1)?,
+                    None => {
+                        self.chunk.add_op(Op::Nil);
+                    }
+                }
+                self.chunk.add_op(Op::Assert);
+                Ok(())
+            }
+            Statement::Located(span, statement) => {
+                self.chunk.set_position(span.end());
+                let enclosing_span = self.span.replace(*span);
+                let result = self.visit_statement(statement).map_err(|e| e.at(*span));
+                self.span = enclosing_span;
+                result
+            }
+        }
     }
 
-    fn expression(&mut self, ast: &Expression) -> CompilationResult {
+    fn visit_expression(&mut self, ast: &Expression) -> CompilationResult {
         match ast {
             Expression::Nil => {
                 self.chunk.add_op(Op::Nil);
@@ -147,29 +1240,57 @@ impl Compiler {
             Expression::NumberLiteral(n) => {
                 self.chunk.add_op(Op::ConstFloat(*n));
             }
+            Expression::IntegerLiteral(n) => {
+                self.chunk.add_op(Op::ConstInt(*n));
+            }
             Expression::StringLiteral(s) => {
-                let n = self
-                    .chunk
-                    .add_constant(ValueType::Text(Box::new(s.clone())));
+                let text = self.strings.string(s);
+                let n = self.chunk.add_constant(text);
                 self.chunk.add_op(Op::Const(n));
             }
             Expression::BooleanLiteral(b) => {
                 self.chunk.add_op(Op::ConstBool(*b));
             }
-            Expression::AssignVariable(name, expr) => {
-                self.assign_variable(name, expr)?;
+            Expression::Assign { target, value } => match target.as_ref() {
+                Expression::Variable(name) => self.assign_variable(name, value)?,
+                Expression::Index { array, index } => self.assign_index(array, index, value)?,
+                target => {
+                    return Err(CompileError::UnsupportedAssignmentTarget {
+                        context: crate::fmt::format_expression(target),
+                    })
+                }
+            },
+            Expression::Array { initial, size } => self.initialize_array(initial, size)?,
+            Expression::Map(entries) => {
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    self.operand(key, 2 * i)?;
+                    self.operand(value, 2 * i + 1)?;
+                }
+                self.chunk.add_op(Op::Map(entries.len()));
+            }
+            Expression::Tuple(elements) => {
+                for (i, element) in elements.iter().enumerate() {
+                    self.operand(element, i)?;
+                }
+                self.chunk.add_op(Op::Tuple(elements.len()));
             }
-            Expression::AssignIndexVariable {
+            Expression::Lambda(params, body) => self.function(LAMBDA_NAME, params, body)?,
+            Expression::Block { statements, value } => self.block_expression(statements, value)?,
+            Expression::Update {
+                operator,
                 variable,
-                index,
-                value,
-            } => {
-                self.assign_index_variable(variable, index, value)?;
-            }
-            Expression::Array { initial, size } => self.initialize_array(initial, size)?,
+                prefix,
+            } => self.update_variable(operator, variable, *prefix)?,
+            Expression::Conditional {
+                condition,
+                then,
+                otherwise,
+            } => self.conditional(condition, then, otherwise)?,
+            Expression::BinaryOperation(BinaryOperator::And, a, b) => self.and(a, b)?,
+            Expression::BinaryOperation(BinaryOperator::Or, a, b) => self.or(a, b)?,
             Expression::BinaryOperation(op, a, b) => {
-                self.expression(b)?;
-                self.expression(a)?;
+                self.visit_expression(b)?;
+                self.operand(a, 1)?;
                 match op {
                     BinaryOperator::Add => {
                         self.chunk.add_op(Op::Add);
@@ -183,6 +1304,9 @@ impl Compiler {
                     BinaryOperator::Div => {
                         self.chunk.add_op(Op::Div);
                     }
+                    BinaryOperator::Pow => {
+                        self.chunk.add_op(Op::Pow);
+                    }
                     BinaryOperator::Equal => {
                         self.chunk.add_op(Op::Cmp);
                     }
@@ -204,185 +1328,75 @@ impl Compiler {
                     BinaryOperator::GreaterOrEqual => {
                         self.chunk.add_op(Op::Ge);
                     }
+                    BinaryOperator::And | BinaryOperator::Or => {
+                        unreachable!("logical operators are compiled with short-circuit jumps")
+                    }
                 }
             }
             Expression::Variable(name) => self.load_variable(name),
-            Expression::FunctionCall(name, args) => self.function_call(name, args)?,
+            Expression::Call(callee, args) => self.call(callee, args)?,
             Expression::UnaryOperation(UnaryOperator::Negate, lhs) => {
-                self.expression(lhs)?;
-                self.chunk.add_op(Op::ConstFloat(0.0));
+                self.visit_expression(lhs)?;
+                self.chunk.add_op(Op::ConstInt(0));
                 self.chunk.add_op(Op::Sub);
             }
             Expression::UnaryOperation(UnaryOperator::Not, lhs) => {
-                self.expression(lhs)?;
+                self.visit_expression(lhs)?;
                 self.chunk.add_op(Op::Not);
             }
             Expression::Index { array, index } => {
-                self.expression(index)?;
-                self.expression(array)?;
+                self.visit_expression(index)?;
+                self.operand(array, 1)?;
                 self.chunk.add_op(Op::LoadIndex);
             }
+            Expression::Range { start, end } => {
+                self.visit_expression(start)?;
+                self.operand(end, 1)?;
+                self.chunk.add_op(Op::Range);
+            }
         }
         Ok(())
     }
-
-    fn initialize_array(&mut self, initial: &Expression, size: &Expression) -> CompilationResult {
-        self.expression(size)?;
-        self.expression(initial)?;
-        self.chunk.add_op(Op::Array);
-
-        Ok(())
-    }
-
-    fn assign_index_variable(
-        &mut self,
-        variable: &str,
-        index: &Expression,
-        value: &Expression,
-    ) -> CompilationResult {
-        self.expression(index)?;
-        self.load_variable(variable);
-        self.expression(value)?;
-        self.chunk.add_op(Op::StoreIndex);
-        self.assign_variable_from_stack(variable)?;
-        Ok(())
-    }
-
-    fn load_variable(&mut self, name: &str) {
-        if let Some(local) = self.locals.resolve_local(name) {
-            self.chunk.add_op(Op::LoadLocal(local));
-            return;
-        }
-        self.load_global(name);
-    }
-
-    fn block(&mut self, statements: &Vec<Statement>) -> CompilationResult {
-        self.begin_scope();
-        for statement in statements {
-            self.statement(statement)?;
-        }
-        self.end_scope();
-        Ok(())
-    }
-
-    fn begin_scope(&mut self) {
-        self.locals.begin_scope();
-    }
-
-    fn end_scope(&mut self) {
-        let locals_in_scope = self.locals.end_scope();
-        for _ in 0..locals_in_scope {
-            self.chunk.add_op(Op::Pop);
-        }
-    }
-
-    fn if_statement(
-        &mut self,
-        condition: &Expression,
-        then: &Statement,
-        otherwise: &Option<Box<Statement>>,
-    ) -> CompilationResult {
-        self.expression(condition)?;
-        let then_jump = self.chunk.add_op(Op::JumpIfFalse(0));
-        self.statement(then)?;
-
-        if let Some(otherwise) = otherwise {
-            let else_jump = self.chunk.add_op(Op::Jump(0));
-            let jump_offset = self.chunk.last_op_address() - then_jump;
-            self.chunk.patch_jump(then_jump, jump_offset as i32);
-            self.statement(otherwise)?;
-            self.chunk.patch_jump_to_last(else_jump);
-        } else {
-            self.chunk.patch_jump_to_last(then_jump);
-        }
-        Ok(())
-    }
-
-    fn while_statement(&mut self, condition: &Expression, body: &Statement) -> CompilationResult {
-        let loop_start = self.chunk.last_op_address();
-        self.expression(condition)?;
-        let exit_jump = self.chunk.add_op(Op::JumpIfFalse(0));
-        self.statement(body)?;
-        let loop_jump = self.chunk.add_op(Op::Jump(0));
-        self.chunk.patch_jump_to(loop_jump, loop_start);
-        self.chunk.patch_jump_to_last(exit_jump);
-        Ok(())
-    }
-
-    fn function_declaration(
-        &mut self,
-        name: &str,
-        params: &Vec<String>,
-        body: &Statement,
-    ) -> CompilationResult {
-        let mut function_compiler = Compiler::default();
-        function_compiler.begin_scope();
-        for param in params {
-            function_compiler.declare_variable(param)?;
-        }
-        let function_program = Program::new(vec![body.clone()]);
-        let mut chunk_builder = function_compiler.compile_part(function_program)?;
-        chunk_builder.add_op(Op::Nil);
-        chunk_builder.add_op(Op::Return);
-        let chunk = Rc::new(chunk_builder.build());
-        let function = Function::new(name.to_string(), Rc::clone(&chunk), params.len());
-        let n = self
-            .chunk
-            .add_constant(ValueType::Function(Box::new(function)));
-        self.chunk.add_op(Op::Const(n));
-        self.store_global(name);
-        self.chunk.add_op(Op::Pop);
-        Ok(())
-    }
-
-    fn function_call(&mut self, name: &str, args: &Vec<Expression>) -> CompilationResult {
-        self.load_global(name);
-        for arg in args {
-            self.expression(arg)?;
-        }
-        self.chunk.add_op(Op::Call(args.len()));
-        Ok(())
-    }
-
-    fn return_statement(&mut self, expression: &Expression) -> CompilationResult {
-        self.expression(expression)?;
-        self.chunk.add_op(Op::Return);
-        Ok(())
-    }
-
-    fn load_global(&mut self, name: &str) {
-        let const_idx = self.chunk.add_constant(ValueType::string(name));
-        self.chunk.add_op(Op::LoadGlobal(const_idx));
-    }
-
-    fn store_global(&mut self, name: &str) {
-        let const_idx = self.chunk.add_constant(ValueType::string(name));
-        self.chunk.add_op(Op::StoreGlobal(const_idx));
-    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::value::ValueType;
 
     #[test]
     fn assign_global_variable() {
-        let assign = Statement::Expression(Expression::AssignVariable(
-            "a".to_string(),
-            Box::new(Expression::number(42)),
+        let assign = Statement::Expression(Expression::assign(
+            Expression::variable("a"),
+            Expression::number(42),
         ));
         let program = Program::new(vec![assign]);
         let mut compiler = Compiler::default();
 
         let chunk = compiler.compile(program).unwrap();
-        let ops: Vec<&Op> = chunk.ops().collect();
+        let ops: Vec<Op> = chunk.ops().collect();
 
         assert_eq!(
             ops,
-            vec![&Op::ConstFloat(42.0), &Op::StoreGlobal(0), &Op::Pop]
+            vec![Op::ConstFloat(42.0), Op::StoreGlobal(0), Op::Return]
         );
     }
 
+    #[test]
+    fn reject_unsupported_assignment_targets() {
+        let assign = Statement::expression(Expression::assign(
+            Expression::call(Expression::variable("f"), vec![]),
+            Expression::number(1),
+        ));
+
+        let error = Compiler::default()
+            .compile(Program::new(vec![assign]))
+            .unwrap_err();
+
+        assert_eq!(error.to_string(), "unsupported assignment target: f()");
+        assert_eq!(error.code(), "unsupported-assignment-target");
+    }
+
     #[test]
     fn compile_number_literal() {
         let number = Statement::expression(Expression::number(42.0));
@@ -390,7 +1404,7 @@ mod tests {
 
         let chunk = compiler.compile(Program::new(vec![number])).unwrap();
 
-        assert_eq!(chunk.op(0), Some(&Op::ConstFloat(42.0)));
+        assert_eq!(chunk.op(0), Some(Op::ConstFloat(42.0)));
     }
 
     #[test]
@@ -405,9 +1419,44 @@ mod tests {
 
         let chunk: Chunk = compiler.compile(Program::new(vec![add_statement])).unwrap();
 
-        assert_eq!(chunk.op(0), Some(&Op::ConstFloat(8.5)));
-        assert_eq!(chunk.op(1), Some(&Op::ConstFloat(3.0)));
-        assert_eq!(chunk.op(2), Some(&Op::Add));
+        assert_eq!(chunk.op(0), Some(Op::ConstFloat(8.5)));
+        assert_eq!(chunk.op(1), Some(Op::ConstFloat(3.0)));
+        assert_eq!(chunk.op(2), Some(Op::Add));
+    }
+
+    #[test]
+    fn fold_constants_on_basic_level() {
+        let add_statement = Statement::expression(Expression::binary(
+            BinaryOperator::Add,
+            Expression::number(3.0),
+            Expression::number(8.5),
+        ));
+        let mut compiler = Compiler::with_opt_level(OptLevel::Basic);
+
+        let chunk = compiler.compile(Program::new(vec![add_statement])).unwrap();
+        let ops: Vec<Op> = chunk.ops().collect();
+
+        assert_eq!(ops, vec![Op::ConstFloat(11.5), Op::Return]);
+    }
+
+    #[test]
+    fn optimize_on_full_level() {
+        let compare_statement = Statement::expression(Expression::binary(
+            BinaryOperator::NotEqual,
+            Expression::Variable("a".to_string()),
+            Expression::number(8.5),
+        ));
+        let mut compiler = Compiler::with_opt_level(OptLevel::Full);
+
+        let chunk = compiler
+            .compile(Program::new(vec![compare_statement]))
+            .unwrap();
+        let ops: Vec<Op> = chunk.ops().collect();
+
+        assert_eq!(
+            ops,
+            vec![Op::ConstFloat(8.5), Op::LoadGlobal(0), Op::Ne, Op::Return]
+        );
     }
 
     #[test]
@@ -421,7 +1470,7 @@ mod tests {
 
         let program = compiler.compile(Program::new(vec![block])).unwrap();
 
-        let opcodes: Vec<Op> = program.ops().cloned().collect();
+        let opcodes: Vec<Op> = program.ops().collect();
         assert_eq!(
             opcodes,
             vec![
@@ -435,6 +1484,139 @@ mod tests {
         );
     }
 
+    #[test]
+    fn compile_captured_locals() {
+        let block = Statement::Block(vec![
+            Statement::DefineVariable("a".to_string(), Expression::number(1.0)),
+            Statement::function("get", &[], Statement::Return(Expression::variable("a"))),
+        ]);
+        let mut compiler = Compiler::default();
+
+        let program = compiler.compile(Program::new(vec![block])).unwrap();
+
+        let opcodes: Vec<Op> = program.ops().collect();
+        assert_eq!(
+            opcodes,
+            vec![
+                Op::ConstFloat(1.0),
+                Op::StoreLocal(0),
+                Op::Nil,
+                Op::Closure(0),
+                Op::StoreLocal(1),
+                Op::Pop,
+                Op::Pop,
+                Op::CloseUpvalue,
+            ]
+        );
+        let Some(ValueType::Function(function)) = program.constant(0) else {
+            panic!("function constant expected");
+        };
+        assert_eq!(function.captures(), &[Capture::Local(0)]);
+        let function_ops: Vec<Op> = function.chunk().ops().collect();
+        assert_eq!(function_ops[0], Op::LoadUpvalue(0));
+    }
+
+    #[test]
+    fn share_strings_between_chunks() {
+        let name = || Expression::StringLiteral("name".to_string());
+        let function = Statement::function("get", &[], Statement::Return(name()));
+        let global = Statement::DefineVariable("name".to_string(), name());
+        let mut compiler = Compiler::default();
+
+        let program = compiler
+            .compile(Program::new(vec![function, global]))
+            .unwrap();
+
+        let texts = |chunk: &Chunk| -> Vec<Rc<String>> {
+            chunk
+                .constants()
+                .filter_map(|constant| match constant {
+                    ValueType::Text(text) => Some(text.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+        let Some(ValueType::Function(function)) = program.constant(0) else {
+            panic!("function constant expected");
+        };
+        let literal = texts(function.chunk());
+        let global = texts(&program);
+        let global = global.iter().find(|text| text.as_str() == "name").unwrap();
+        assert!(Rc::ptr_eq(&literal[0], global));
+    }
+
+    #[test]
+    fn compile_loops_with_condition_after_body() {
+        let body = Statement::Print(Expression::integer(1));
+        let condition = Expression::BooleanLiteral(true);
+        let mut compiler = Compiler::default();
+
+        let program = compiler
+            .compile(Program::new(vec![
+                Statement::while_loop(condition.clone(), body.clone()),
+                Statement::do_while_loop(body, condition),
+            ]))
+            .unwrap();
+
+        let opcodes: Vec<Op> = program.ops().collect();
+        assert_eq!(
+            opcodes,
+            vec![
+                Op::Jump(2),
+                Op::ConstInt(1),
+                Op::Print,
+                Op::ConstBool(true),
+                Op::JumpIfTrue(-4),
+                Op::ConstInt(1),
+                Op::Print,
+                Op::ConstBool(true),
+                Op::JumpIfTrue(-4),
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_tail_calls() {
+        let call = Expression::call(Expression::variable("f"), vec![Expression::number(1.0)]);
+        let function = Statement::function("f", &["n"], Statement::Return(call.clone()));
+        let mut compiler = Compiler::default();
+
+        let program = compiler
+            .compile(Program::new(vec![function, Statement::Return(call)]))
+            .unwrap();
+
+        let opcodes: Vec<Op> = program.ops().collect();
+        assert_eq!(opcodes[opcodes.len() - 2..], [Op::Call(1), Op::Return]);
+        let Some(ValueType::Function(function)) = program.constant(0) else {
+            panic!("function constant expected");
+        };
+        let function_ops: Vec<Op> = function.chunk().ops().collect();
+        assert_eq!(
+            function_ops,
+            vec![
+                Op::LoadGlobal(0),
+                Op::ConstFloat(1.0),
+                Op::TailCall(1),
+                Op::Nil,
+                Op::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn locate_errors_at_innermost_statement() {
+        let outer = Span::from(crate::source::Position::new(1, 1));
+        let inner = Span::from(crate::source::Position::new(2, 5));
+        let body = Statement::Block(vec![Statement::located(inner, Statement::Break)]);
+        let program = Program::new(vec![Statement::located(outer, body)]);
+
+        let error = Compiler::default().compile(program).unwrap_err();
+
+        assert_eq!(error.span(), Some(inner));
+        assert_eq!(error.kind(), &CompileError::OutsideLoop("break"));
+        assert_eq!(error.to_string(), "`break` outside of a loop at [2:5]");
+    }
+
     #[test]
     fn shadow_initialization() {
         let global = Statement::DefineVariable("a".to_string(), Expression::number(1.0));
@@ -444,7 +1626,7 @@ mod tests {
         let mut compiler = Compiler::default();
 
         let chunk = compiler.compile(Program::new(vec![global, block])).unwrap();
-        let opcodes: Vec<Op> = chunk.ops().cloned().collect();
+        let opcodes: Vec<Op> = chunk.ops().collect();
 
         assert_eq!(
             opcodes,
@@ -458,4 +1640,144 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn inline_constants() {
+        let constant = Statement::DefineConstant(
+            "N".to_string(),
+            Expression::binary(
+                BinaryOperator::Mul,
+                Expression::number(2),
+                Expression::number(3),
+            ),
+        );
+        let print = Statement::print(Expression::variable("N"));
+        let mut compiler = Compiler::default();
+
+        let chunk = compiler
+            .compile(Program::new(vec![constant, print]))
+            .unwrap();
+        let ops: Vec<Op> = chunk.ops().collect();
+
+        assert_eq!(
+            ops,
+            vec![
+                Op::ConstFloat(3.0),
+                Op::ConstFloat(2.0),
+                Op::Mul,
+                Op::StoreGlobal(0),
+                Op::Pop,
+                Op::ConstFloat(6.0),
+                Op::Print,
+            ]
+        );
+    }
+
+    #[test]
+    fn check_arity_of_declared_functions() {
+        let compile = |source: &str| {
+            let program = crate::parser::Parser::new(crate::lexer::Lexer::new(source))
+                .parse_program()
+                .unwrap();
+            Compiler::default().compile(program)
+        };
+
+        let error =
+            compile("fun twice() { return add(1); }\nfun add(a, b) { return a + b; }").unwrap_err();
+        assert_eq!(
+            error.kind(),
+            &CompileError::ArityMismatch {
+                name: "add".to_string(),
+                expected: 2,
+                found: 1
+            }
+        );
+        assert_eq!(error.span().map(|span| span.start().line()), Some(1));
+        assert!(
+            compile("fun add(a, b) { return a + b; }\nadd = fun (a) { return a; };\nadd(1);")
+                .is_ok()
+        );
+        assert!(compile("fun add(a, b) { return a + b; }\nprint add(1, 2);").is_ok());
+        assert!(compile("fun f(a, b) { return a + b; }\nfun apply(f, x) { return f(x); }").is_ok());
+    }
+
+    #[test]
+    fn compile_calls_of_parameters() {
+        let call = Expression::call(Expression::variable("f"), vec![Expression::variable("x")]);
+        let apply = Statement::function("apply", &["f", "x"], Statement::expression(call));
+        let mut compiler = Compiler::default();
+
+        let program = compiler.compile(Program::new(vec![apply])).unwrap();
+
+        let Some(ValueType::Function(function)) = program.constant(0) else {
+            panic!("function constant expected");
+        };
+        let function_ops: Vec<Op> = function.chunk().ops().collect();
+        assert_eq!(
+            function_ops[..3],
+            [Op::LoadLocal(0), Op::LoadLocal(1), Op::Call(1)]
+        );
+    }
+
+    #[test]
+    fn reject_assignments_to_constants() {
+        let constant = || Statement::DefineConstant("N".to_string(), Expression::number(1));
+        let assign = Statement::expression(Expression::assign(
+            Expression::variable("N"),
+            Expression::number(2),
+        ));
+        let redefine = Statement::DefineVariable("N".to_string(), Expression::number(2));
+        let nested = Statement::Block(vec![constant()]);
+
+        for program in [
+            vec![constant(), assign],
+            vec![constant(), redefine],
+            vec![constant(), constant()],
+        ] {
+            let error = Compiler::default()
+                .compile(Program::new(program))
+                .unwrap_err();
+            assert_eq!(error, CompileError::AssignToConst("N".to_string()));
+        }
+        let error = Compiler::default()
+            .compile(Program::new(vec![nested]))
+            .unwrap_err();
+        assert_eq!(error, CompileError::NestedConst("N".to_string()));
+    }
+
+    #[test]
+    fn warn_about_suspicious_code() {
+        let source = r#"
+fun f(unused_param) {
+    let a = 1;
+    let b = 2;
+    while (1 > 2) {
+        print b;
+    }
+    return b;
+    print "never";
+}
+try { throw 1; } catch (e) {}
+"#;
+        let program = crate::parser::Parser::new(crate::lexer::Lexer::new(source))
+            .parse_program()
+            .unwrap();
+        let mut compiler = Compiler::default();
+
+        compiler.compile(program).unwrap();
+        let warnings: Vec<String> = compiler
+            .warnings()
+            .iter()
+            .map(|warning| format!("{} at {}", warning, warning.span().unwrap()))
+            .collect();
+
+        assert_eq!(
+            warnings,
+            vec![
+                "loop condition is always false at [5:5-9]",
+                "unreachable code at [9:5-9]",
+                "local variable a is never read at [3:5-7]",
+            ]
+        );
+    }
 }
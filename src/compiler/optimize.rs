@@ -0,0 +1,243 @@
+//! Peephole optimization of the compiled chunks.
+//!
+//! Rewrites short sequences of operations into cheaper ones:
+//! - jumps landing on unconditional jumps go straight to the final address;
+//! - values pushed on the stack and popped right away are not pushed at all;
+//! - comparisons followed by [Op::Not] become a single negated comparison;
+//! - [Op::Not] followed by [Op::JumpIfFalse] becomes [Op::JumpIfTrue].
+//!
+//! Sequences that other jumps land in the middle of are left intact.
+
+use std::collections::HashSet;
+
+use crate::vm::bytecode::BytecodeError;
+use crate::vm::exec::Chunk;
+use crate::vm::opcode::Op;
+
+/// Applies peephole optimizations to the code of the chunk.
+///
+/// The chunks of the functions in the constant pool are not optimized.
+pub fn optimize(chunk: &Chunk) -> Result<Chunk, BytecodeError> {
+    let mut ops: Vec<Op> = chunk.ops().collect();
+    let mut positions: Vec<_> = chunk.positions().copied().collect();
+    thread_jumps(&mut ops);
+    while let Some(removed) = rewrite(&mut ops) {
+        remove_ops(&mut ops, &removed);
+        let mut address = 0;
+        positions.retain(|_| {
+            address += 1;
+            !removed.contains(&(address - 1))
+        });
+    }
+    Ok(Chunk::try_new(ops, chunk.constants().cloned())?.with_positions(positions))
+}
+
+/// Returns the address execution continues from after the jump.
+fn destination(ops: &[Op], address: usize) -> Option<usize> {
+    match ops[address] {
+        Op::Jump(offset)
+        | Op::JumpIfFalse(offset)
+        | Op::JumpIfTrue(offset)
+        | Op::PushHandler(offset)
+        | Op::IterNext(offset) => (address + 1).checked_add_signed(offset as isize),
+        _ => None,
+    }
+}
+
+fn thread_jumps(ops: &mut [Op]) {
+    for address in 0..ops.len() {
+        if !matches!(
+            ops[address],
+            Op::Jump(_) | Op::JumpIfFalse(_) | Op::JumpIfTrue(_)
+        ) {
+            continue;
+        }
+        let Some(mut target) = destination(ops, address) else {
+            continue;
+        };
+        // Jumps may form a cycle, so follow them no more times than there are operations.
+        for _ in 0..ops.len() {
+            match ops.get(target) {
+                Some(Op::Jump(_)) if target != address => match destination(ops, target) {
+                    Some(next) => target = next,
+                    None => break,
+                },
+                _ => break,
+            }
+        }
+        let offset = target as i32 - address as i32 - 1;
+        match &mut ops[address] {
+            Op::Jump(o) | Op::JumpIfFalse(o) | Op::JumpIfTrue(o) => *o = offset,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Rewrites fusable pairs of operations in place.
+/// Returns the addresses of the operations to remove, if anything changed.
+fn rewrite(ops: &mut [Op]) -> Option<HashSet<usize>> {
+    let targets: HashSet<usize> = (0..ops.len())
+        .filter_map(|address| destination(ops, address))
+        .collect();
+    let mut removed = HashSet::new();
+    let mut address = 0;
+    while address + 1 < ops.len() {
+        if targets.contains(&(address + 1)) {
+            address += 1;
+            continue;
+        }
+        match (ops[address], ops[address + 1]) {
+            (
+                Op::Const(_)
+                | Op::ConstFloat(_)
+                | Op::ConstInt(_)
+                | Op::ConstBool(_)
+                | Op::Nil
+                | Op::LoadLocal(_)
+                | Op::LoadUpvalue(_),
+                Op::Pop,
+            ) => {
+                removed.insert(address);
+                removed.insert(address + 1);
+            }
+            (Op::Cmp | Op::Ge | Op::Le, Op::Not) => {
+                ops[address] = match ops[address] {
+                    Op::Cmp => Op::Ne,
+                    Op::Ge => Op::Lt,
+                    _ => Op::Gt,
+                };
+                removed.insert(address + 1);
+            }
+            (Op::Not, Op::JumpIfFalse(offset)) => {
+                ops[address + 1] = Op::JumpIfTrue(offset);
+                removed.insert(address);
+            }
+            _ => {
+                address += 1;
+                continue;
+            }
+        }
+        address += 2;
+    }
+    (!removed.is_empty()).then_some(removed)
+}
+
+/// Removes the operations and moves the jumps, so they land on the same operations.
+/// A jump to a removed operation lands on the next operation left.
+fn remove_ops(ops: &mut Vec<Op>, removed: &HashSet<usize>) {
+    let mut addresses = Vec::with_capacity(ops.len() + 1);
+    let mut next: usize = 0;
+    for address in 0..=ops.len() {
+        addresses.push(next);
+        if !removed.contains(&address) {
+            next += 1;
+        }
+    }
+    let mut optimized = Vec::with_capacity(ops.len());
+    for (address, op) in ops.iter().enumerate() {
+        if removed.contains(&address) {
+            continue;
+        }
+        let op = match (op, destination(ops, address)) {
+            (
+                Op::Jump(_)
+                | Op::JumpIfFalse(_)
+                | Op::JumpIfTrue(_)
+                | Op::PushHandler(_)
+                | Op::IterNext(_),
+                Some(target),
+            ) => {
+                let offset = addresses[target] as i32 - addresses[address] as i32 - 1;
+                match op {
+                    Op::Jump(_) => Op::Jump(offset),
+                    Op::JumpIfFalse(_) => Op::JumpIfFalse(offset),
+                    Op::JumpIfTrue(_) => Op::JumpIfTrue(offset),
+                    Op::IterNext(_) => Op::IterNext(offset),
+                    _ => Op::PushHandler(offset),
+                }
+            }
+            (op, _) => *op,
+        };
+        optimized.push(op);
+    }
+    *ops = optimized;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn optimize_ops(ops: impl IntoIterator<Item = Op>) -> Vec<Op> {
+        let chunk = optimize(&Chunk::try_new(ops, []).unwrap()).unwrap();
+        chunk.ops().collect()
+    }
+
+    #[test]
+    fn thread_jumps_to_jumps() {
+        let ops = optimize_ops([
+            Op::ConstBool(true),
+            Op::JumpIfFalse(0),
+            Op::Jump(1),
+            Op::Print,
+            Op::Jump(-5),
+        ]);
+
+        assert_eq!(ops[1], Op::JumpIfFalse(-2));
+        assert_eq!(ops[2], Op::Jump(-3));
+    }
+
+    #[test]
+    fn remove_popped_values() {
+        let ops = optimize_ops([Op::Nil, Op::Nil, Op::ConstFloat(1.0), Op::Pop, Op::Pop]);
+
+        assert_eq!(ops, vec![Op::Nil]);
+    }
+
+    #[test]
+    fn fuse_negated_comparisons() {
+        let ops = optimize_ops([
+            Op::ConstFloat(1.0),
+            Op::ConstFloat(2.0),
+            Op::Cmp,
+            Op::Not,
+            Op::JumpIfFalse(2),
+            Op::ConstFloat(3.0),
+            Op::Pop,
+            Op::Nil,
+        ]);
+
+        assert_eq!(
+            ops,
+            vec![
+                Op::ConstFloat(1.0),
+                Op::ConstFloat(2.0),
+                Op::Ne,
+                Op::JumpIfFalse(0),
+                Op::Nil
+            ]
+        );
+    }
+
+    #[test]
+    fn fuse_negated_jumps() {
+        let ops = optimize_ops([
+            Op::ConstBool(true),
+            Op::Not,
+            Op::JumpIfFalse(1),
+            Op::Print,
+            Op::Nil,
+        ]);
+
+        assert_eq!(
+            ops,
+            vec![Op::ConstBool(true), Op::JumpIfTrue(1), Op::Print, Op::Nil]
+        );
+    }
+
+    #[test]
+    fn keep_jump_targets() {
+        let ops = optimize_ops([Op::Nil, Op::JumpIfFalse(1), Op::Nil, Op::Pop]);
+
+        assert_eq!(ops, vec![Op::Nil, Op::JumpIfFalse(1), Op::Nil, Op::Pop]);
+    }
+}
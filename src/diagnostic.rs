@@ -0,0 +1,286 @@
+//! Diagnostic messages reported by the tools working with the source code.
+
+use std::fmt::{Display, Write};
+
+use crate::compiler::diagnostics::CompileWarning;
+use crate::compiler::CompileError;
+use crate::parser::ParsingError;
+use crate::source::{Position, Span};
+use crate::vm::VmRuntimeError;
+use crate::BaubleError;
+
+/// Severity of the reported diagnostic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A message about a problem found in the source code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    code: String,
+    severity: Severity,
+    message: String,
+    span: Option<Span>,
+}
+
+impl Diagnostic {
+    pub fn new(code: &str, severity: Severity, message: impl Into<String>) -> Self {
+        Diagnostic {
+            code: code.to_string(),
+            severity,
+            message: message.into(),
+            span: None,
+        }
+    }
+
+    pub fn at(mut self, span: impl Into<Span>) -> Self {
+        self.span = Some(span.into());
+        self
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn position(&self) -> Option<Position> {
+        self.span.map(|span| span.start())
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// Renders the diagnostic followed by the source line it points to, with the span underlined.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use brainterpreter::diagnostic::{Diagnostic, Severity};
+    /// # use brainterpreter::source::{Position, Span};
+    /// let span = Span::new(Position::new(1, 5), Position::new(1, 7));
+    /// let diagnostic = Diagnostic::new("unknown", Severity::Error, "unknown name").at(span);
+    /// assert_eq!(
+    ///     diagnostic.render("a.bbl", "let foo = 1;"),
+    ///     "a.bbl: error[unknown]: unknown name at [1:5-7]\n1 | let foo = 1;\n  |     ^^^\n"
+    /// );
+    /// ```
+    pub fn render(&self, file: &str, source: &str) -> String {
+        let mut rendered = format!("{}: {}\n", file, self);
+        let Some(span) = self.span else {
+            return rendered;
+        };
+        let start = span.start();
+        let Some(line) = start
+            .line()
+            .checked_sub(1)
+            .and_then(|index| source.lines().nth(index))
+        else {
+            return rendered;
+        };
+        let first = start.column().max(1);
+        let last = if span.end().line() == start.line() {
+            span.end().column()
+        } else {
+            line.chars().count()
+        }
+        .max(first);
+        let number = start.line().to_string();
+        writeln!(rendered, "{} | {}", number, line).unwrap();
+        writeln!(
+            rendered,
+            "{} | {}{}",
+            " ".repeat(number.len()),
+            " ".repeat(first - 1),
+            "^".repeat(last - first + 1)
+        )
+        .unwrap();
+        rendered
+    }
+
+    /// Renders the diagnostic as a single line JSON object.
+    ///
+    /// The `range` field is `null` when the position of the problem is unknown.
+    pub fn to_json(&self, file: &str) -> String {
+        let mut json = String::new();
+        write!(
+            json,
+            r#"{{"file":{},"code":{},"severity":{},"message":{},"range":"#,
+            json_string(file),
+            json_string(&self.code),
+            json_string(&self.severity.to_string()),
+            json_string(&self.message)
+        )
+        .unwrap();
+        match self.span {
+            Some(span) => write!(
+                json,
+                r#"{{"start":{},"end":{}}}}}"#,
+                json_position(span.start()),
+                json_position(span.end())
+            )
+            .unwrap(),
+            None => json.push_str("null}"),
+        }
+        json
+    }
+}
+
+fn json_position(position: Position) -> String {
+    format!(
+        r#"{{"line":{},"column":{}}}"#,
+        position.line(),
+        position.column()
+    )
+}
+
+pub(crate) fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+impl From<&ParsingError> for Diagnostic {
+    fn from(error: &ParsingError) -> Self {
+        Diagnostic::new(error.code(), Severity::Error, error.to_string()).at(error.span())
+    }
+}
+
+impl From<&CompileError> for Diagnostic {
+    fn from(error: &CompileError) -> Self {
+        let diagnostic = Diagnostic::new(error.code(), Severity::Error, error.kind().to_string());
+        match error.span() {
+            Some(span) => diagnostic.at(span),
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<&CompileWarning> for Diagnostic {
+    fn from(warning: &CompileWarning) -> Self {
+        let diagnostic = Diagnostic::new(warning.code(), Severity::Warning, warning.to_string());
+        match warning.span() {
+            Some(span) => diagnostic.at(span),
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<&BaubleError> for Diagnostic {
+    fn from(error: &BaubleError) -> Self {
+        match error {
+            BaubleError::Io(_) => Diagnostic::new(error.code(), Severity::Error, error.to_string()),
+            BaubleError::Parsing(e) => e.into(),
+            BaubleError::Compile(e) => e.into(),
+            BaubleError::Runtime(e) => e.into(),
+        }
+    }
+}
+
+impl From<&VmRuntimeError> for Diagnostic {
+    fn from(error: &VmRuntimeError) -> Self {
+        let diagnostic = Diagnostic::new(error.code(), Severity::Error, error.kind().to_string());
+        match error.position() {
+            Some(position) => diagnostic.at(position),
+            None => diagnostic,
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)?;
+        if let Some(span) = self.span {
+            write!(f, " at {}", span)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_diagnostic() {
+        let diagnostic = Diagnostic::new("empty-block", Severity::Warning, "block is empty");
+        assert_eq!(
+            diagnostic.to_string(),
+            "warning[empty-block]: block is empty"
+        );
+
+        let diagnostic = diagnostic.at(Position::new(3, 4));
+        assert_eq!(
+            diagnostic.to_string(),
+            "warning[empty-block]: block is empty at [3:4]"
+        );
+    }
+
+    #[test]
+    fn json_diagnostic() {
+        let diagnostic = Diagnostic::new("unexpected-token", Severity::Error, "unexpected \"}\"\n")
+            .at(Position::new(2, 7));
+
+        assert_eq!(
+            diagnostic.to_json("dir\\a.bbl"),
+            r#"{"file":"dir\\a.bbl","code":"unexpected-token","severity":"error","message":"unexpected \"}\"\n","range":{"start":{"line":2,"column":7},"end":{"line":2,"column":7}}}"#
+        );
+    }
+
+    #[test]
+    fn render_source_line() {
+        let source = "let a = 1;\nprint a +\n  2;";
+        let span = Span::new(Position::new(2, 7), Position::new(3, 2));
+        let diagnostic = Diagnostic::new("broken", Severity::Error, "broken sum").at(span);
+
+        assert_eq!(
+            diagnostic.render("a.bbl", source),
+            "a.bbl: error[broken]: broken sum at [2:7-3:2]\n2 | print a +\n  |       ^^^\n"
+        );
+        let unknown = Diagnostic::new("broken", Severity::Error, "broken").at(Position::new(9, 1));
+        assert_eq!(
+            unknown.render("a.bbl", source),
+            "a.bbl: error[broken]: broken at [9:1]\n"
+        );
+    }
+
+    #[test]
+    fn json_diagnostic_without_position() {
+        let diagnostic = Diagnostic::from(&VmRuntimeError::OutOfFuel);
+
+        assert_eq!(
+            diagnostic.to_json("a.bbl"),
+            r#"{"file":"a.bbl","code":"out-of-fuel","severity":"error","message":"execution ran out of fuel","range":null}"#
+        );
+    }
+}
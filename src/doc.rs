@@ -0,0 +1,179 @@
+//! Generates documentation from `///` comments of the source code.
+
+use std::fmt::Write;
+
+use crate::ast::{Program, Statement};
+
+/// Output format of the generated documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DocFormat {
+    #[default]
+    Markdown,
+    Html,
+}
+
+/// Documentation of a single declared function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDoc {
+    name: String,
+    params: Vec<String>,
+    doc: Option<String>,
+}
+
+impl FunctionDoc {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn params(&self) -> &[String] {
+        &self.params
+    }
+
+    pub fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+
+    /// Function signature as it appears in the source, e.g. `add(a, b)`.
+    pub fn signature(&self) -> String {
+        format!("{}({})", self.name, self.params.join(", "))
+    }
+}
+
+/// Collects documentation of the top-level functions of the program.
+pub fn collect(program: &Program) -> Vec<FunctionDoc> {
+    program
+        .statements()
+        .iter()
+        .filter_map(|statement| match statement.inner() {
+            Statement::Function {
+                name, params, doc, ..
+            } => Some(FunctionDoc {
+                name: name.clone(),
+                params: params.clone(),
+                doc: doc.clone(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Renders documentation of the program in the requested format.
+pub fn generate(program: &Program, title: &str, format: DocFormat) -> String {
+    let docs = collect(program);
+    match format {
+        DocFormat::Markdown => render_markdown(title, &docs),
+        DocFormat::Html => render_html(title, &docs),
+    }
+}
+
+/// Renders function documentation as a Markdown document.
+pub fn render_markdown(title: &str, docs: &[FunctionDoc]) -> String {
+    let mut out = String::new();
+    writeln!(out, "# {}", title).unwrap();
+    for function in docs {
+        writeln!(out).unwrap();
+        writeln!(out, "## `{}`", function.signature()).unwrap();
+        if let Some(doc) = function.doc() {
+            writeln!(out).unwrap();
+            writeln!(out, "{}", doc).unwrap();
+        }
+        if !function.params.is_empty() {
+            let params: Vec<String> = function.params.iter().map(|p| format!("`{}`", p)).collect();
+            writeln!(out).unwrap();
+            writeln!(out, "**Parameters:** {}", params.join(", ")).unwrap();
+        }
+    }
+    out
+}
+
+/// Renders function documentation as a standalone HTML page.
+pub fn render_html(title: &str, docs: &[FunctionDoc]) -> String {
+    let mut out = String::new();
+    writeln!(out, "<!DOCTYPE html>").unwrap();
+    writeln!(out, "<html>").unwrap();
+    writeln!(out, "<head><title>{}</title></head>", escape_html(title)).unwrap();
+    writeln!(out, "<body>").unwrap();
+    writeln!(out, "<h1>{}</h1>", escape_html(title)).unwrap();
+    for function in docs {
+        writeln!(
+            out,
+            "<h2><code>{}</code></h2>",
+            escape_html(&function.signature())
+        )
+        .unwrap();
+        if let Some(doc) = function.doc() {
+            writeln!(out, "<p>{}</p>", escape_html(doc).replace('\n', "<br>")).unwrap();
+        }
+        if !function.params.is_empty() {
+            writeln!(out, "<ul>").unwrap();
+            for param in function.params.iter() {
+                writeln!(out, "<li><code>{}</code></li>", escape_html(param)).unwrap();
+            }
+            writeln!(out, "</ul>").unwrap();
+        }
+    }
+    writeln!(out, "</body>").unwrap();
+    writeln!(out, "</html>").unwrap();
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program() -> Program {
+        Program::new(vec![
+            Statement::documented_function(
+                "add",
+                &["a", "b"],
+                Statement::Block(vec![]),
+                "Adds two numbers.",
+            ),
+            Statement::function("noop", &[], Statement::Block(vec![])),
+        ])
+    }
+
+    #[test]
+    fn collect_top_level_functions() {
+        let docs = collect(&program());
+
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].signature(), "add(a, b)");
+        assert_eq!(docs[0].doc(), Some("Adds two numbers."));
+        assert_eq!(docs[1].signature(), "noop()");
+        assert_eq!(docs[1].doc(), None);
+    }
+
+    #[test]
+    fn markdown_output() {
+        let out = generate(&program(), "math", DocFormat::Markdown);
+
+        assert_eq!(
+            out,
+            "# math\n\n## `add(a, b)`\n\nAdds two numbers.\n\n**Parameters:** `a`, `b`\n\n## `noop()`\n"
+        );
+    }
+
+    #[test]
+    fn html_output_is_escaped() {
+        let program = Program::new(vec![Statement::documented_function(
+            "lt",
+            &["a"],
+            Statement::Block(vec![]),
+            "Checks a < 1",
+        )]);
+
+        let out = generate(&program, "math", DocFormat::Html);
+
+        assert!(out.contains("<h2><code>lt(a)</code></h2>"));
+        assert!(out.contains("<p>Checks a &lt; 1</p>"));
+        assert!(out.contains("<li><code>a</code></li>"));
+    }
+}
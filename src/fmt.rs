@@ -0,0 +1,654 @@
+//! Formats the source code by printing the syntax tree in the canonical style.
+//!
+//! Ordinary comments are not a part of the syntax tree, so only `///` documentation of functions
+//! survives formatting. [dropped_comments] tells whether formatting loses any of them.
+
+use std::fmt::Write;
+
+use crate::ast::{BinaryOperator, Expression, Program, Statement, UnaryOperator, UpdateOperator};
+use crate::lexer::token::Token;
+use crate::lexer::Lexer;
+
+const INDENT: &str = "    ";
+
+/// Binding strength of expressions, matching the precedence of the parser.
+const ASSIGNMENT: u8 = 1;
+const CONDITIONAL: u8 = 2;
+const OR: u8 = 3;
+const AND: u8 = 5;
+const EQUALITY: u8 = 7;
+const COMPARISON: u8 = 9;
+const RANGE: u8 = 10;
+const TERM: u8 = 11;
+const FACTOR: u8 = 13;
+const UNARY: u8 = 15;
+const POWER: u8 = 16;
+const POSTFIX: u8 = 17;
+const PRIMARY: u8 = 19;
+
+/// Prints the program as formatted source code.
+///
+/// # Examples
+///
+/// ```rust
+/// # use brainterpreter::fmt::format_program;
+/// # use brainterpreter::lexer::Lexer;
+/// # use brainterpreter::parser::Parser;
+/// let program = Parser::new(Lexer::new("if(a>1){print a;}else if(a<0){print -a;}"))
+///     .parse_program()
+///     .unwrap();
+///
+/// assert_eq!(
+///     format_program(&program),
+///     "if (a > 1) {\n    print a;\n} else if (a < 0) {\n    print -a;\n}\n"
+/// );
+/// ```
+pub fn format_program(program: &Program) -> String {
+    let mut formatter = Formatter::default();
+    formatter.statements(program.statements());
+    formatter.out
+}
+
+/// Counts the comments of the source that its formatted form does not have.
+///
+/// Ordinary comments never survive formatting, and `///` documentation survives only before functions.
+pub fn dropped_comments(source: &str, formatted: &str) -> usize {
+    let (comments, docs) = count_comments(source);
+    let (_, formatted_docs) = count_comments(formatted);
+    comments + docs.saturating_sub(formatted_docs)
+}
+
+/// Counts the ordinary comments and the `///` documentation lines of the source.
+fn count_comments(source: &str) -> (usize, usize) {
+    let mut lexer = Lexer::new(source);
+    let docs = lexer
+        .by_ref()
+        .filter(|token| matches!(token.kind(), Token::DocComment(_)))
+        .count();
+    (lexer.comments(), docs)
+}
+
+/// Formats the expression on one line, e.g. to show it in the messages.
+pub(crate) fn format_expression(expression: &Expression) -> String {
+    let mut formatter = Formatter::default();
+    formatter.expression(expression, 0);
+    formatter.out
+}
+
+#[derive(Debug, Default)]
+struct Formatter {
+    out: String,
+    indent: usize,
+}
+
+impl Formatter {
+    /// Prints statements on separate lines, keeping function declarations apart with blank lines.
+    fn statements(&mut self, statements: &[Statement]) {
+        let mut previous_function = false;
+        for (i, statement) in statements.iter().enumerate() {
+            let function = matches!(statement.inner(), Statement::Function { .. });
+            if i > 0 && (function || previous_function) {
+                self.out.push('\n');
+            }
+            self.line_start();
+            self.statement(statement);
+            self.out.push('\n');
+            previous_function = function;
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Expression(expression) => {
+                self.expression(expression, 0);
+                self.out.push(';');
+            }
+            Statement::DeclareVariable(name) => write!(self.out, "let {};", name).unwrap(),
+            Statement::DefineVariable(name, value) => {
+                write!(self.out, "let {} = ", name).unwrap();
+                self.expression(value, 0);
+                self.out.push(';');
+            }
+            Statement::DefineVariables(names, value) => {
+                write!(self.out, "let ({}) = ", names.join(", ")).unwrap();
+                self.expression(value, 0);
+                self.out.push(';');
+            }
+            Statement::DefineConstant(name, value) => {
+                write!(self.out, "const {} = ", name).unwrap();
+                self.expression(value, 0);
+                self.out.push(';');
+            }
+            Statement::Function {
+                name,
+                params,
+                body,
+                doc,
+            } => {
+                if let Some(doc) = doc {
+                    for line in doc.lines() {
+                        writeln!(self.out, "/// {}", line).unwrap();
+                        self.line_start();
+                    }
+                }
+                write!(self.out, "fun {}({}) ", name, params.join(", ")).unwrap();
+                self.statement(body);
+            }
+            Statement::Print(expression) => {
+                self.out.push_str("print ");
+                let start = self.out.len();
+                self.expression(expression, 0);
+                // A parenthesis right after `print` starts the arguments of the print function,
+                // so the value is passed to it instead.
+                if self.out[start..].starts_with('(') {
+                    self.out.replace_range(start - 1..start, "(");
+                    self.out.push(')');
+                }
+                self.out.push(';');
+            }
+            Statement::Block(statements) if statements.is_empty() => self.out.push_str("{}"),
+            Statement::Block(statements) => {
+                self.out.push_str("{\n");
+                self.indent += 1;
+                self.statements(statements);
+                self.indent -= 1;
+                self.line_start();
+                self.out.push('}');
+            }
+            Statement::If(condition, then_branch, else_branch) => {
+                self.out.push_str("if (");
+                self.expression(condition, 0);
+                self.out.push(')');
+                self.body(then_branch);
+                if let Some(else_branch) = else_branch {
+                    if is_block(then_branch) {
+                        self.out.push(' ');
+                    } else {
+                        self.out.push('\n');
+                        self.line_start();
+                    }
+                    self.out.push_str("else");
+                    // `else if` chains stay on the same level instead of nesting deeper.
+                    if let Statement::If(..) = else_branch.inner() {
+                        self.out.push(' ');
+                        self.statement(else_branch);
+                    } else {
+                        self.body(else_branch);
+                    }
+                }
+            }
+            Statement::While(condition, body) => {
+                self.out.push_str("while (");
+                self.expression(condition, 0);
+                self.out.push(')');
+                self.body(body);
+            }
+            Statement::DoWhile(body, condition) => {
+                self.out.push_str("do");
+                self.body(body);
+                if is_block(body) {
+                    self.out.push(' ');
+                } else {
+                    self.out.push('\n');
+                    self.line_start();
+                }
+                self.out.push_str("while (");
+                self.expression(condition, 0);
+                self.out.push_str(");");
+            }
+            Statement::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                self.out.push_str("for (");
+                match initializer {
+                    Some(initializer) => self.statement(initializer),
+                    None => self.out.push(';'),
+                }
+                if let Some(condition) = condition {
+                    self.out.push(' ');
+                    self.expression(condition, 0);
+                }
+                self.out.push(';');
+                if let Some(increment) = increment {
+                    self.out.push(' ');
+                    self.expression(increment, 0);
+                }
+                self.out.push(')');
+                self.body(body);
+            }
+            Statement::ForIn {
+                variable,
+                collection,
+                body,
+            } => {
+                write!(self.out, "for ({} in ", variable).unwrap();
+                self.expression(collection, 0);
+                self.out.push(')');
+                self.body(body);
+            }
+            Statement::Break => self.out.push_str("break;"),
+            Statement::Continue => self.out.push_str("continue;"),
+            Statement::Return(expression) => {
+                self.out.push_str("return ");
+                self.expression(expression, 0);
+                self.out.push(';');
+            }
+            Statement::Throw(expression) => {
+                self.out.push_str("throw ");
+                self.expression(expression, 0);
+                self.out.push(';');
+            }
+            Statement::Try {
+                body,
+                variable,
+                handler,
+            } => {
+                self.out.push_str("try ");
+                self.statement(body);
+                write!(self.out, " catch ({}) ", variable).unwrap();
+                self.statement(handler);
+            }
+            Statement::Switch {
+                subject,
+                cases,
+                default,
+            } => {
+                self.out.push_str("switch (");
+                self.expression(subject, 0);
+                self.out.push_str(") {\n");
+                self.indent += 1;
+                for (value, body) in cases {
+                    self.line_start();
+                    self.out.push_str("case ");
+                    self.expression(value, 0);
+                    self.out.push_str(":\n");
+                    self.case_body(body);
+                }
+                if let Some(default) = default {
+                    self.line_start();
+                    self.out.push_str("default:\n");
+                    self.case_body(default);
+                }
+                self.indent -= 1;
+                self.line_start();
+                self.out.push('}');
+            }
+            Statement::Assert { condition, message } => {
+                self.out.push_str("assert(");
+                self.expression(condition, 0);
+                if let Some(message) = message {
+                    self.out.push_str(", ");
+                    self.expression(message, 0);
+                }
+                self.out.push_str(");");
+            }
+            Statement::Located(_, statement) => self.statement(statement),
+        }
+    }
+
+    /// Prints the statements of a switch case one level deeper than the case label.
+    fn case_body(&mut self, body: &Statement) {
+        self.indent += 1;
+        match body.inner() {
+            Statement::Block(statements) => self.statements(statements),
+            statement => {
+                self.line_start();
+                self.statement(statement);
+                self.out.push('\n');
+            }
+        }
+        self.indent -= 1;
+    }
+
+    /// Prints the body of a compound statement, putting a single statement on its own line.
+    fn body(&mut self, body: &Statement) {
+        if is_block(body) {
+            self.out.push(' ');
+            self.statement(body);
+        } else {
+            self.out.push('\n');
+            self.indent += 1;
+            self.line_start();
+            self.statement(body);
+            self.indent -= 1;
+        }
+    }
+
+    /// Prints the expression, wrapping it in parentheses when it binds weaker than required.
+    fn expression(&mut self, expression: &Expression, min_binding: u8) {
+        let parenthesize = binding(expression) < min_binding;
+        if parenthesize {
+            self.out.push('(');
+        }
+        match expression {
+            Expression::Nil => self.out.push_str("nil"),
+            // Whole floats keep the fraction, so they are not read back as integers.
+            Expression::NumberLiteral(n) if n.fract() == 0.0 => {
+                write!(self.out, "{:.1}", n).unwrap()
+            }
+            Expression::NumberLiteral(n) => write!(self.out, "{}", n).unwrap(),
+            Expression::IntegerLiteral(n) => write!(self.out, "{}", n).unwrap(),
+            Expression::BooleanLiteral(b) => write!(self.out, "{}", b).unwrap(),
+            Expression::StringLiteral(s) => write!(self.out, "\"{}\"", s).unwrap(),
+            Expression::Index { array, index } => {
+                self.expression(array, POSTFIX);
+                self.out.push('[');
+                self.expression(index, 0);
+                self.out.push(']');
+            }
+            Expression::Variable(name) => self.out.push_str(name),
+            Expression::Assign { target, value } => {
+                self.expression(target, POSTFIX);
+                self.out.push_str(" = ");
+                self.expression(value, ASSIGNMENT + 1);
+            }
+            Expression::Array { initial, size } => {
+                self.out.push('[');
+                self.expression(initial, 0);
+                self.out.push_str("; ");
+                self.expression(size, 0);
+                self.out.push(']');
+            }
+            Expression::Tuple(elements) => {
+                self.out.push('(');
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expression(element, 0);
+                }
+                self.out.push(')');
+            }
+            Expression::Map(entries) => {
+                self.out.push('{');
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expression(key, 0);
+                    self.out.push_str(": ");
+                    self.expression(value, 0);
+                }
+                self.out.push('}');
+            }
+            Expression::Lambda(params, body) => {
+                write!(self.out, "fun ({}) ", params.join(", ")).unwrap();
+                self.statement(body);
+            }
+            Expression::Block { statements, value } => {
+                self.out.push_str("{\n");
+                self.indent += 1;
+                self.statements(statements);
+                if let Some(value) = value {
+                    self.line_start();
+                    self.expression(value, 0);
+                    self.out.push('\n');
+                }
+                self.indent -= 1;
+                self.line_start();
+                self.out.push('}');
+            }
+            Expression::Update {
+                operator,
+                variable,
+                prefix,
+            } => {
+                let operator = match operator {
+                    UpdateOperator::Increment => "++",
+                    UpdateOperator::Decrement => "--",
+                };
+                if *prefix {
+                    write!(self.out, "{}{}", operator, variable).unwrap();
+                } else {
+                    write!(self.out, "{}{}", variable, operator).unwrap();
+                }
+            }
+            Expression::Call(callee, args) => {
+                self.expression(callee, POSTFIX);
+                self.out.push('(');
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expression(arg, 0);
+                }
+                self.out.push(')');
+            }
+            Expression::BinaryOperation(BinaryOperator::Pow, lhs, rhs) => {
+                self.expression(lhs, POWER + 1);
+                self.out.push_str(" ** ");
+                self.expression(rhs, UNARY);
+            }
+            Expression::BinaryOperation(operator, lhs, rhs) => {
+                let binding = binding(expression);
+                self.expression(lhs, binding);
+                write!(self.out, " {} ", binary_operator(operator)).unwrap();
+                self.expression(rhs, binding + 1);
+            }
+            Expression::Range { start, end } => {
+                self.expression(start, RANGE);
+                self.out.push_str("..");
+                self.expression(end, RANGE + 1);
+            }
+            Expression::Conditional {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.expression(condition, CONDITIONAL + 1);
+                self.out.push_str(" ? ");
+                self.expression(then, 0);
+                self.out.push_str(" : ");
+                self.expression(otherwise, CONDITIONAL);
+            }
+            Expression::UnaryOperation(operator, operand) => {
+                let operator = match operator {
+                    UnaryOperator::Not => "!",
+                    UnaryOperator::Negate => "-",
+                };
+                self.out.push_str(operator);
+                // `- -a` must not turn into the `--a` decrement.
+                let start = self.out.len();
+                self.expression(operand, UNARY);
+                if operator == "-" && self.out[start..].starts_with('-') {
+                    self.out.insert(start, ' ');
+                }
+            }
+        }
+        if parenthesize {
+            self.out.push(')');
+        }
+    }
+
+    fn line_start(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+}
+
+fn is_block(statement: &Statement) -> bool {
+    matches!(statement.inner(), Statement::Block(_))
+}
+
+fn binding(expression: &Expression) -> u8 {
+    match expression {
+        Expression::Assign { .. } => ASSIGNMENT,
+        Expression::Conditional { .. } => CONDITIONAL,
+        Expression::Range { .. } => RANGE,
+        Expression::BinaryOperation(operator, ..) => match operator {
+            BinaryOperator::Or => OR,
+            BinaryOperator::And => AND,
+            BinaryOperator::Equal | BinaryOperator::NotEqual => EQUALITY,
+            BinaryOperator::Less
+            | BinaryOperator::Greater
+            | BinaryOperator::LessOrEqual
+            | BinaryOperator::GreaterOrEqual => COMPARISON,
+            BinaryOperator::Add | BinaryOperator::Sub => TERM,
+            BinaryOperator::Mul | BinaryOperator::Div => FACTOR,
+            BinaryOperator::Pow => POWER,
+        },
+        Expression::UnaryOperation(..) => UNARY,
+        Expression::Update { prefix: true, .. } => UNARY,
+        Expression::Update { prefix: false, .. }
+        | Expression::Index { .. }
+        | Expression::Call(..) => POSTFIX,
+        // A negative number is printed with the minus sign like a negation.
+        Expression::NumberLiteral(n) if n.is_sign_negative() => UNARY,
+        Expression::IntegerLiteral(n) if *n < 0 => UNARY,
+        _ => PRIMARY,
+    }
+}
+
+fn binary_operator(operator: &BinaryOperator) -> &'static str {
+    match operator {
+        BinaryOperator::Add => "+",
+        BinaryOperator::Sub => "-",
+        BinaryOperator::Mul => "*",
+        BinaryOperator::Div => "/",
+        BinaryOperator::Pow => "**",
+        BinaryOperator::Equal => "==",
+        BinaryOperator::NotEqual => "!=",
+        BinaryOperator::Less => "<",
+        BinaryOperator::Greater => ">",
+        BinaryOperator::LessOrEqual => "<=",
+        BinaryOperator::GreaterOrEqual => ">=",
+        BinaryOperator::And => "&&",
+        BinaryOperator::Or => "||",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn format(source: &str) -> String {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        format_program(&program)
+    }
+
+    #[test]
+    fn format_statements() {
+        let source = r#"/// Adds numbers
+fun add(a,b){return a+b;}
+let x=add(1,2)*3;let y;
+while(x>0)x--;
+do{x++;}while(x<3);
+do x--;while(x>0);
+for(let i=0;i<3;i++){if(i==1){continue;}print i;}
+for(;;){break;}
+for(c in "abc")print c;
+{}"#;
+
+        assert_eq!(
+            format(source),
+            r#"/// Adds numbers
+fun add(a, b) {
+    return a + b;
+}
+
+let x = add(1, 2) * 3;
+let y;
+while (x > 0)
+    x--;
+do {
+    x++;
+} while (x < 3);
+do
+    x--;
+while (x > 0);
+for (let i = 0; i < 3; i++) {
+    if (i == 1) {
+        continue;
+    }
+    print i;
+}
+for (;;) {
+    break;
+}
+for (c in "abc")
+    print c;
+{}
+"#
+        );
+    }
+
+    #[test]
+    fn format_else_if_chains() {
+        let source = "if (a) print 1; else if (b) { print 2; } else { print 3; }";
+
+        assert_eq!(
+            format(source),
+            "if (a)\n    print 1;\nelse if (b) {\n    print 2;\n} else {\n    print 3;\n}\n"
+        );
+    }
+
+    #[test]
+    fn keep_required_parentheses() {
+        let source = "x = (a + b) * -(c - d) - (e - f); y = - -z; m = {\"k\": [0; n]}; f = fun (v) { return !(v && w); }; t = (a ? b : c) ? d : e ? f : g;";
+
+        assert_eq!(
+            format(source),
+            "x = (a + b) * -(c - d) - (e - f);\ny = - -z;\nm = {\"k\": [0; n]};\nf = fun (v) {\n    return !(v && w);\n};\nt = (a ? b : c) ? d : e ? f : g;\n"
+        );
+    }
+
+    #[test]
+    fn print_values_starting_with_parentheses() {
+        let program = Program::new(vec![Statement::print(Expression::binary(
+            BinaryOperator::Mul,
+            Expression::binary(
+                BinaryOperator::Add,
+                Expression::integer(1),
+                Expression::integer(2),
+            ),
+            Expression::integer(3),
+        ))]);
+
+        assert_eq!(format_program(&program), "print((1 + 2) * 3);\n");
+        assert_eq!(format("print((1 + 2) * 3);"), "print((1 + 2) * 3);\n");
+    }
+
+    #[test]
+    fn format_switch_statements() {
+        let source = "switch(x){case 1:print 1;case 2:default:print 3;print 4;}";
+
+        assert_eq!(
+            format(source),
+            "switch (x) {\n    case 1:\n        print 1;\n    case 2:\n    default:\n        print 3;\n        print 4;\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_block_expressions() {
+        let source = "let x = {let a = f(); print a; a * 2};";
+
+        assert_eq!(
+            format(source),
+            "let x = {\n    let a = f();\n    print a;\n    a * 2\n};\n"
+        );
+    }
+
+    #[test]
+    fn count_dropped_comments() {
+        let dropped = |source: &str| dropped_comments(source, &format(source));
+
+        assert_eq!(dropped("/// Adds one.\nfun inc(a) { return a + 1; }"), 0);
+        assert_eq!(dropped("// note\nprint 1; /* inline */"), 2);
+        assert_eq!(dropped("/// not a function\nprint 1;"), 1);
+    }
+
+    #[test]
+    fn format_is_stable() {
+        let source = include_str!("../tests/brainfuck.bbl");
+
+        let formatted = format(source);
+
+        assert_eq!(format(&formatted), formatted);
+    }
+}
@@ -0,0 +1,63 @@
+//! Entry points for fuzzing the compiler and the virtual machine.
+//!
+//! The AST types implement [arbitrary::Arbitrary] when the `arbitrary` feature is enabled,
+//! so fuzzers can generate well-formed programs instead of random source text.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::rc::Rc;
+
+use crate::ast::Program;
+use crate::compiler::Compiler;
+use crate::vm::Vm;
+
+/// Maximum number of instructions a fuzzed program may execute.
+pub const FUZZ_FUEL: usize = 10_000;
+
+/// Compiles and runs the program with limited fuel and discarded output.
+///
+/// Compilation and runtime errors are expected for random programs and are returned as is.
+/// Any panic inside is a bug in the interpreter.
+pub fn fuzz_compile_and_run(program: Program) -> Result<(), Box<dyn Error>> {
+    let mut compiler = Compiler::default();
+    let chunk = compiler.compile(program)?;
+    let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![]))).with_fuel(FUZZ_FUEL);
+    vm.load_and_run(Rc::new(chunk))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    use super::*;
+    use crate::ast::{Expression, Statement};
+    use crate::vm::VmRuntimeError;
+
+    #[test]
+    fn run_arbitrary_programs() {
+        let data: Vec<u8> = (0..=255).cycle().take(4096).collect();
+        for offset in 0..64 {
+            let mut input = Unstructured::new(&data[offset..]);
+            if let Ok(program) = Program::arbitrary(&mut input) {
+                let _ = fuzz_compile_and_run(program);
+            }
+        }
+    }
+
+    #[test]
+    fn infinite_loop_runs_out_of_fuel() {
+        let program = Program::new(vec![Statement::while_loop(
+            Expression::BooleanLiteral(true),
+            Statement::Block(vec![]),
+        )]);
+
+        let result = fuzz_compile_and_run(program);
+
+        let error = result.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<VmRuntimeError>(),
+            Some(VmRuntimeError::OutOfFuel)
+        ));
+    }
+}
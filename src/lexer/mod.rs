@@ -1,10 +1,13 @@
 //! Lexer for the language tokens
 
+use std::collections::VecDeque;
+use std::io::Read;
+
 use log::error;
 
 use token::Token;
 
-use crate::source::Position;
+use crate::source::{CharSource, Position, ReadSource, Span};
 
 pub mod token;
 
@@ -12,26 +15,59 @@ pub mod token;
 #[derive(Debug, Clone, PartialEq)]
 pub struct SourceToken {
     kind: Token,
-    source: Position,
+    span: Span,
+    len: usize,
 }
 
+/// Splits the source code into tokens.
+///
+/// Reads the source one character at a time, so it never needs the whole program in memory.
 #[derive(Debug)]
-pub struct Lexer<'a> {
-    source: &'a str,
-    start: usize,
-    pos: usize,
+pub struct Lexer<S> {
+    source: S,
+    lookahead: VecDeque<char>,
+    lexeme: String,
+    error: Option<std::io::Error>,
+    start: Position,
     line: usize,
     column: usize,
+    comments: usize,
 }
 
-impl<'a> Lexer<'a> {
+impl<'a> Lexer<std::str::Chars<'a>> {
     pub fn new(source: &'a str) -> Self {
+        Lexer::from_source(source.chars())
+    }
+}
+
+impl<R: Read> Lexer<ReadSource<R>> {
+    /// Creates a lexer decoding UTF-8 source code from a reader as tokens are requested.
+    pub fn from_reader(reader: R) -> Self {
+        Lexer::from_source(ReadSource::new(reader))
+    }
+}
+
+impl<S: CharSource> Lexer<S> {
+    pub fn from_source(source: S) -> Self {
         Lexer {
             source,
-            pos: 0,
-            start: 0,
+            lookahead: VecDeque::new(),
+            lexeme: String::new(),
+            error: None,
+            start: Position::new(1, 1),
             line: 1,
             column: 1,
+            comments: 0,
+        }
+    }
+
+    /// Numbers the lines and columns from the position, e.g. to lex a part of a bigger source.
+    pub fn starting_at(self, position: Position) -> Self {
+        Lexer {
+            start: position,
+            line: position.line(),
+            column: position.column(),
+            ..self
         }
     }
 
@@ -40,30 +76,79 @@ impl<'a> Lexer<'a> {
         while maybe_token.is_none() {
             maybe_token = self.advance_token();
         }
-        maybe_token.unwrap()
+        let mut token = maybe_token.unwrap();
+        // Spans only tell the length of tokens on a single line, while the lexeme covers them all.
+        token.len = self.lexeme.chars().count();
+        token
+    }
+
+    /// Number of the `//` and `/* */` comments and `#!` lines skipped so far.
+    /// Unlike `///` documentation, they do not become tokens.
+    pub fn comments(&self) -> usize {
+        self.comments
+    }
+
+    /// Takes the error that stopped reading the source, if any.
+    pub fn take_error(&mut self) -> Option<std::io::Error> {
+        self.error.take()
     }
 
     fn advance_token(&mut self) -> Option<SourceToken> {
+        // Scripts start with the `#!` line telling the system to run them with the interpreter.
+        if self.line == 1
+            && self.column == 1
+            && self.peek(0) == Some('#')
+            && self.peek(1) == Some('!')
+        {
+            self.comments += 1;
+            self.skip_line();
+        }
         self.skip_whitespace();
+        self.lexeme.clear();
         if self.at_end() {
-            return Some(Token::EndOfFile.with_position(self.src_pos()));
+            if let Some(e) = self.source.take_error() {
+                error!("failed to read the source: {}", e);
+                self.error = Some(e);
+                return Some(Token::Error.with_position(self.current_position()));
+            }
+            return Some(Token::EndOfFile.with_position(self.current_position()));
         }
-        self.start = self.pos;
+        self.start = Position::new(self.line, self.column);
         let c = self.advance().expect("character exhausted prematurely");
         match c {
-            '+' => Some(Token::Plus.with_position(self.src_pos())),
-            '-' => Some(Token::Minus.with_position(self.src_pos())),
-            '*' => Some(Token::Star.with_position(self.src_pos())),
+            '+' => {
+                if self.advance_if('+') {
+                    Some(Token::PlusPlus.with_position(self.src_pos()))
+                } else {
+                    Some(Token::Plus.with_position(self.src_pos()))
+                }
+            }
+            '-' => {
+                if self.advance_if('-') {
+                    Some(Token::MinusMinus.with_position(self.src_pos()))
+                } else {
+                    Some(Token::Minus.with_position(self.src_pos()))
+                }
+            }
+            '*' => {
+                if self.advance_if('*') {
+                    Some(Token::StarStar.with_position(self.src_pos()))
+                } else {
+                    Some(Token::Star.with_position(self.src_pos()))
+                }
+            }
             '/' => {
                 if let Some('/') = self.peek(0) {
                     self.advance();
-                    while let Some(c) = self.peek(0) {
-                        if c == '\n' {
-                            break;
-                        }
-                        self.advance();
+                    if self.peek(0) == Some('/') && self.peek(1) != Some('/') {
+                        return Some(self.doc_comment());
                     }
+                    self.comments += 1;
+                    self.skip_line();
                     None
+                } else if self.advance_if('*') {
+                    self.comments += 1;
+                    self.block_comment()
                 } else {
                     Some(Token::Slash.with_position(self.src_pos()))
                 }
@@ -102,10 +187,36 @@ impl<'a> Lexer<'a> {
                     Some(Token::Greater.with_position(self.src_pos()))
                 }
             }
+            '&' => {
+                if self.advance_if('&') {
+                    Some(Token::AmpAmp.with_position(self.src_pos()))
+                } else {
+                    error!("unknown token: {}", c);
+                    Some(Token::Error.with_position(self.src_pos()))
+                }
+            }
+            '|' => {
+                if self.advance_if('|') {
+                    Some(Token::PipePipe.with_position(self.src_pos()))
+                } else {
+                    error!("unknown token: {}", c);
+                    Some(Token::Error.with_position(self.src_pos()))
+                }
+            }
+            '.' => {
+                if self.advance_if('.') {
+                    Some(Token::DotDot.with_position(self.src_pos()))
+                } else {
+                    error!("unknown token: {}", c);
+                    Some(Token::Error.with_position(self.src_pos()))
+                }
+            }
             ';' => Some(Token::Semicolon.with_position(self.src_pos())),
+            ':' => Some(Token::Colon.with_position(self.src_pos())),
+            '?' => Some(Token::Question.with_position(self.src_pos())),
             ',' => Some(Token::Comma.with_position(self.src_pos())),
             '0'..='9' => Some(self.number()),
-            'a'..='z' | 'A'..='Z' | '_' => Some(self.identifier()),
+            c if c.is_alphabetic() || c == '_' => Some(self.identifier()),
             '"' => Some(self.string_literal()),
             _ => {
                 error!("unknown token: {}", c);
@@ -114,6 +225,57 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    fn doc_comment(&mut self) -> SourceToken {
+        self.advance();
+        let mut text = String::new();
+        while let Some(c) = self.peek(0) {
+            if c == '\n' {
+                break;
+            }
+            text.push(c);
+            self.advance();
+        }
+        Token::DocComment(text.trim().to_string()).with_position(self.src_pos())
+    }
+
+    /// Skips the comment up to the matching `*/`, counting the nested comments.
+    /// Returns the error token spanning the rest of the source when the comment is not closed.
+    fn block_comment(&mut self) -> Option<SourceToken> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.peek(0) {
+                None => {
+                    error!("unterminated block comment at {}", self.start);
+                    return Some(Token::UnterminatedComment.with_position(self.src_pos()));
+                }
+                Some('*') if self.peek(1) == Some('/') => {
+                    self.advance();
+                    depth -= 1;
+                }
+                Some('/') if self.peek(1) == Some('*') => {
+                    self.advance();
+                    depth += 1;
+                }
+                Some('\n') => {
+                    self.line += 1;
+                    self.column = 0;
+                }
+                Some(_) => {}
+            }
+            self.advance();
+        }
+        None
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.peek(0) {
+            if c == '\n' {
+                break;
+            }
+            self.advance();
+        }
+    }
+
     fn number(&mut self) -> SourceToken {
         while let Some(c) = self.peek(0) {
             if !c.is_ascii_digit() {
@@ -122,7 +284,8 @@ impl<'a> Lexer<'a> {
             self.advance();
         }
 
-        if let Some('.') = self.peek(0) {
+        // The dot of `1..2` starts the range operator instead of the fraction.
+        if self.peek(0) == Some('.') && self.peek(1) != Some('.') {
             self.advance();
             while let Some(c) = self.peek(0) {
                 if !c.is_ascii_digit() {
@@ -130,9 +293,11 @@ impl<'a> Lexer<'a> {
                 }
                 self.advance();
             }
+        } else if let Ok(value) = self.lexeme.parse() {
+            // Integers too large for 64 bits are read as floats.
+            return Token::Integer(value).with_position(self.src_pos());
         }
-        let number_literal = &self.source[self.start..self.pos];
-        let value: f64 = number_literal.parse().expect("must be a correct number");
+        let value: f64 = self.lexeme.parse().expect("must be a correct number");
         Token::Number(value).with_position(self.src_pos())
     }
 
@@ -141,39 +306,60 @@ impl<'a> Lexer<'a> {
             if c == '"' {
                 break;
             }
+            if c == '\n' {
+                self.line += 1;
+                self.column = 0;
+            }
             self.advance();
         }
         self.advance();
-        let string_literal = &self.source[(self.start + 1)..(self.pos - 1)];
+        let string_literal = self.lexeme[1..]
+            .strip_suffix('"')
+            .unwrap_or(&self.lexeme[1..]);
         Token::StringLiteral(string_literal.to_string()).with_position(self.src_pos())
     }
 
     fn identifier(&mut self) -> SourceToken {
         while let Some(c) = self.peek(0) {
-            if !c.is_ascii_alphanumeric() && c != '_' {
+            if !c.is_alphanumeric() && c != '_' {
                 break;
             }
             self.advance();
         }
-        let identifier = &self.source[self.start..self.pos];
-        match identifier {
+        match self.lexeme.as_str() {
             "print" => Token::Print.with_position(self.src_pos()),
             "let" => Token::Let.with_position(self.src_pos()),
+            "const" => Token::Const.with_position(self.src_pos()),
             "true" => Token::True.with_position(self.src_pos()),
             "false" => Token::False.with_position(self.src_pos()),
             "if" => Token::If.with_position(self.src_pos()),
             "else" => Token::Else.with_position(self.src_pos()),
             "while" => Token::While.with_position(self.src_pos()),
+            "do" => Token::Do.with_position(self.src_pos()),
+            "for" => Token::For.with_position(self.src_pos()),
+            "in" => Token::In.with_position(self.src_pos()),
+            "break" => Token::Break.with_position(self.src_pos()),
+            "continue" => Token::Continue.with_position(self.src_pos()),
             "fun" => Token::Fun.with_position(self.src_pos()),
             "return" => Token::Return.with_position(self.src_pos()),
             "nil" => Token::Nil.with_position(self.src_pos()),
-            _ => Token::Identifier(identifier.to_string()).with_position(self.src_pos()),
+            "try" => Token::Try.with_position(self.src_pos()),
+            "catch" => Token::Catch.with_position(self.src_pos()),
+            "throw" => Token::Throw.with_position(self.src_pos()),
+            "switch" => Token::Switch.with_position(self.src_pos()),
+            "case" => Token::Case.with_position(self.src_pos()),
+            "default" => Token::Default.with_position(self.src_pos()),
+            "assert" => Token::Assert.with_position(self.src_pos()),
+            identifier => Token::Identifier(identifier.to_string()).with_position(self.src_pos()),
         }
     }
 
     fn advance(&mut self) -> Option<char> {
-        let c = self.source.chars().nth(self.pos);
-        self.pos += 1;
+        let c = self
+            .lookahead
+            .pop_front()
+            .or_else(|| self.source.next_char());
+        self.lexeme.extend(c);
         self.column += 1;
         c
     }
@@ -187,17 +373,21 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn peek(&self, offset: usize) -> Option<char> {
-        self.source.chars().nth(self.pos + offset)
+    fn peek(&mut self, offset: usize) -> Option<char> {
+        while self.lookahead.len() <= offset {
+            let c = self.source.next_char()?;
+            self.lookahead.push_back(c);
+        }
+        self.lookahead.get(offset).copied()
     }
 
-    fn at_end(&self) -> bool {
-        self.pos >= self.source.len()
+    fn at_end(&mut self) -> bool {
+        self.peek(0).is_none()
     }
 
     fn skip_whitespace(&mut self) {
         while let Some(c) = self.peek(0) {
-            if !c.is_ascii_whitespace() {
+            if !c.is_whitespace() {
                 break;
             }
             if c == '\n' {
@@ -208,12 +398,18 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn src_pos(&self) -> Position {
+    /// Position of the last consumed character.
+    fn current_position(&self) -> Position {
         Position::new(self.line, self.column - 1)
     }
+
+    /// Span of the token consumed since the start of the current token.
+    fn src_pos(&self) -> Span {
+        Span::new(self.start, self.current_position())
+    }
 }
 
-impl<'a> Iterator for Lexer<'a> {
+impl<S: CharSource> Iterator for Lexer<S> {
     type Item = SourceToken;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -229,18 +425,22 @@ impl<'a> Iterator for Lexer<'a> {
 
 impl From<Token> for SourceToken {
     fn from(token: Token) -> Self {
-        SourceToken {
-            kind: token,
-            source: Position::default(),
-        }
+        SourceToken::new(token, Span::default())
     }
 }
 
 impl SourceToken {
-    pub fn new(token: Token, source: Position) -> Self {
+    pub fn new(token: Token, span: impl Into<Span>) -> Self {
+        let span = span.into();
+        let len = if span.start().line() == span.end().line() {
+            (span.end().column() + 1).saturating_sub(span.start().column())
+        } else {
+            0
+        };
         SourceToken {
             kind: token,
-            source,
+            span,
+            len,
         }
     }
 
@@ -248,8 +448,24 @@ impl SourceToken {
         &self.kind
     }
 
-    pub fn source(&self) -> &Position {
-        &self.source
+    /// Position of the first character of the token.
+    pub fn source(&self) -> Position {
+        self.span.start()
+    }
+
+    /// Number of characters the token takes in the source code.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the token takes no characters, like the end of the file.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Range of the source code the token was read from.
+    pub fn span(&self) -> Span {
+        self.span
     }
 }
 
@@ -277,10 +493,50 @@ mod tests {
         assert_eq!(lexer.next_token(), Token::EndOfFile);
     }
 
+    #[test]
+    fn logical_operators() {
+        let mut lexer = Lexer::new("&& || &");
+        assert_eq!(lexer.next_token(), Token::AmpAmp);
+        assert_eq!(lexer.next_token(), Token::PipePipe);
+        assert_eq!(lexer.next_token(), Token::Error);
+        assert_eq!(lexer.next_token(), Token::EndOfFile);
+    }
+
+    #[test]
+    fn power_operator() {
+        let mut lexer = Lexer::new("** * ***");
+        assert_eq!(lexer.next_token(), Token::StarStar);
+        assert_eq!(lexer.next_token(), Token::Star);
+        assert_eq!(lexer.next_token(), Token::StarStar);
+        assert_eq!(lexer.next_token(), Token::Star);
+        assert_eq!(lexer.next_token(), Token::EndOfFile);
+    }
+
+    #[test]
+    fn update_operators() {
+        let mut lexer = Lexer::new("++ + -- -");
+        assert_eq!(lexer.next_token(), Token::PlusPlus);
+        assert_eq!(lexer.next_token(), Token::Plus);
+        assert_eq!(lexer.next_token(), Token::MinusMinus);
+        assert_eq!(lexer.next_token(), Token::Minus);
+        assert_eq!(lexer.next_token(), Token::EndOfFile);
+    }
+
+    #[test]
+    fn range_operator() {
+        let mut lexer = Lexer::new("0..1.5 . 2.");
+        assert_eq!(lexer.next_token(), Token::Integer(0));
+        assert_eq!(lexer.next_token(), Token::DotDot);
+        assert_eq!(lexer.next_token(), Token::Number(1.5));
+        assert_eq!(lexer.next_token(), Token::Error);
+        assert_eq!(lexer.next_token(), Token::Number(2.0));
+        assert_eq!(lexer.next_token(), Token::EndOfFile);
+    }
+
     #[test]
     fn integer() {
         let mut lexer = Lexer::new("42");
-        assert_eq!(lexer.next_token(), Token::Number(42.0));
+        assert_eq!(lexer.next_token(), Token::Integer(42));
         assert_eq!(lexer.next_token(), Token::EndOfFile);
     }
 
@@ -293,19 +549,19 @@ mod tests {
     #[test]
     fn arithmetic_expressions() {
         let mut lexer = Lexer::new("42 + 8 / 2");
-        assert_eq!(lexer.next_token(), Token::Number(42.0));
+        assert_eq!(lexer.next_token(), Token::Integer(42));
         assert_eq!(lexer.next_token(), Token::Plus);
-        assert_eq!(lexer.next_token(), Token::Number(8.0));
+        assert_eq!(lexer.next_token(), Token::Integer(8));
         assert_eq!(lexer.next_token(), Token::Slash);
-        assert_eq!(lexer.next_token(), Token::Number(2.0));
+        assert_eq!(lexer.next_token(), Token::Integer(2));
     }
 
     #[test]
     fn inline_comment() {
         let mut lexer = Lexer::new("42 + 7 // this is a comment");
-        assert_eq!(lexer.next_token(), Token::Number(42.0));
+        assert_eq!(lexer.next_token(), Token::Integer(42));
         assert_eq!(lexer.next_token(), Token::Plus);
-        assert_eq!(lexer.next_token(), Token::Number(7.0));
+        assert_eq!(lexer.next_token(), Token::Integer(7));
         assert_eq!(lexer.next_token(), Token::EndOfFile);
     }
 
@@ -315,9 +571,90 @@ mod tests {
             "// comment
             42 + 7",
         );
-        assert_eq!(lexer.next_token(), Token::Number(42.0));
+        assert_eq!(lexer.next_token(), Token::Integer(42));
         assert_eq!(lexer.next_token(), Token::Plus);
-        assert_eq!(lexer.next_token(), Token::Number(7.0));
+        assert_eq!(lexer.next_token(), Token::Integer(7));
+        assert_eq!(lexer.next_token(), Token::EndOfFile);
+    }
+
+    #[test]
+    fn nested_block_comments() {
+        let tokens: Vec<(Token, Position)> = Lexer::new("1 /* a\n/* b */ * c */ * 2\n/**/3")
+            .map(|t| (t.kind().clone(), t.source()))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (Token::Integer(1), Position::new(1, 1)),
+                (Token::Star, Position::new(2, 16)),
+                (Token::Integer(2), Position::new(2, 18)),
+                (Token::Integer(3), Position::new(3, 5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let mut lexer = Lexer::new("print 1;\n  /* a /* b */\n");
+        assert_eq!(lexer.next_token(), Token::Print);
+        assert_eq!(lexer.next_token(), Token::Integer(1));
+        assert_eq!(lexer.next_token(), Token::Semicolon);
+        let token = lexer.next_token();
+        assert_eq!(token, Token::UnterminatedComment);
+        assert_eq!(token.source(), Position::new(2, 3));
+        assert_eq!(lexer.next_token(), Token::EndOfFile);
+    }
+
+    #[test]
+    fn count_lines_of_multiline_strings() {
+        let tokens: Vec<SourceToken> = Lexer::new("\"a\nb\" 1").collect();
+
+        assert_eq!(tokens[0].kind(), &Token::StringLiteral("a\nb".to_string()));
+        assert_eq!(tokens[1].source(), Position::new(2, 4));
+    }
+
+    #[test]
+    fn skip_shebang_line() {
+        let tokens: Vec<(Token, Position)> = Lexer::new("#!/usr/bin/env bauble run\nprint 1;")
+            .map(|t| (t.kind().clone(), t.source()))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (Token::Print, Position::new(2, 1)),
+                (Token::Integer(1), Position::new(2, 7)),
+                (Token::Semicolon, Position::new(2, 8)),
+            ]
+        );
+        let kinds: Vec<Token> = Lexer::new("print 1;\n#!")
+            .map(|t| t.kind().clone())
+            .collect();
+        assert_eq!(kinds[3], Token::Error);
+    }
+
+    #[test]
+    fn count_skipped_comments() {
+        let mut lexer =
+            Lexer::new("#!/usr/bin/env bauble\n// note\nprint /* a /* b */ */ 1;\n/// doc");
+
+        let tokens = lexer.by_ref().count();
+
+        assert_eq!(tokens, 4);
+        assert_eq!(lexer.comments(), 3);
+    }
+
+    #[test]
+    fn doc_comment() {
+        let mut lexer = Lexer::new(
+            "/// Adds numbers
+            //// not a doc comment
+            fun",
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Token::DocComment("Adds numbers".to_string())
+        );
+        assert_eq!(lexer.next_token(), Token::Fun);
         assert_eq!(lexer.next_token(), Token::EndOfFile);
     }
 
@@ -325,7 +662,7 @@ mod tests {
     fn print_statement() {
         let mut lexer = Lexer::new("print 42");
         assert_eq!(lexer.next_token(), Token::Print);
-        assert_eq!(lexer.next_token(), Token::Number(42.0));
+        assert_eq!(lexer.next_token(), Token::Integer(42));
         assert_eq!(lexer.next_token(), Token::EndOfFile);
     }
 
@@ -342,7 +679,7 @@ mod tests {
         assert_eq!(lexer.next_token(), Token::Let);
         assert_eq!(lexer.next_token(), Token::Identifier("foo".to_string()));
         assert_eq!(lexer.next_token(), Token::Equal);
-        assert_eq!(lexer.next_token(), Token::Number(42.0));
+        assert_eq!(lexer.next_token(), Token::Integer(42));
         assert_eq!(lexer.next_token(), Token::Semicolon);
         assert_eq!(lexer.next_token(), Token::EndOfFile);
     }
@@ -358,4 +695,94 @@ mod tests {
         assert_eq!(lexer.next_token(), Token::Less);
         assert_eq!(lexer.next_token(), Token::LessEqual);
     }
+
+    #[test]
+    fn token_spans() {
+        let spans: Vec<Span> = Lexer::new("let foo ==\n  \"a b\"")
+            .map(|t| t.span())
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                Span::new(Position::new(1, 1), Position::new(1, 3)),
+                Span::new(Position::new(1, 5), Position::new(1, 7)),
+                Span::new(Position::new(1, 9), Position::new(1, 10)),
+                Span::new(Position::new(2, 3), Position::new(2, 7)),
+            ]
+        );
+    }
+
+    #[test]
+    fn token_starts_and_lengths() {
+        let tokens: Vec<(Position, usize)> = Lexer::new("a == counter\n\"x\ny\"")
+            .map(|t| (t.source(), t.len()))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (Position::new(1, 1), 1),
+                (Position::new(1, 3), 2),
+                (Position::new(1, 6), 7),
+                (Position::new(2, 1), 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn non_ascii_source() {
+        let tokens: Vec<SourceToken> = Lexer::new("let ціна =\u{a0}\"привіт\";").collect();
+        let kinds: Vec<&Token> = tokens.iter().map(SourceToken::kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                &Token::Let,
+                &Token::Identifier("ціна".to_string()),
+                &Token::Equal,
+                &Token::StringLiteral("привіт".to_string()),
+                &Token::Semicolon,
+            ]
+        );
+        assert_eq!(
+            tokens[1].span(),
+            Span::new(Position::new(1, 5), Position::new(1, 8))
+        );
+        assert_eq!(
+            tokens[3].span(),
+            Span::new(Position::new(1, 12), Position::new(1, 19))
+        );
+    }
+
+    #[test]
+    fn read_tokens_from_stream() {
+        let source: &[u8] = "let s = \"héllo\";\nprint s;".as_bytes();
+        let tokens: Vec<Token> = Lexer::from_reader(source).map(|t| t.kind).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Let,
+                Token::Identifier("s".to_string()),
+                Token::Equal,
+                Token::StringLiteral("héllo".to_string()),
+                Token::Semicolon,
+                Token::Print,
+                Token::Identifier("s".to_string()),
+                Token::Semicolon,
+            ]
+        );
+    }
+
+    #[test]
+    fn report_read_failure() {
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("connection reset"))
+            }
+        }
+
+        let mut lexer = Lexer::from_reader(Failing);
+        assert_eq!(lexer.next_token(), Token::Error);
+        assert_eq!(lexer.next_token(), Token::EndOfFile);
+        assert_eq!(lexer.take_error().unwrap().to_string(), "connection reset");
+    }
 }
@@ -1,14 +1,17 @@
 use std::fmt::{Display, Formatter};
 
 use crate::lexer::SourceToken;
-use crate::source::Position;
+use crate::source::{Position, Span};
 
 /// Lexical token
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Plus,
+    PlusPlus,
     Minus,
+    MinusMinus,
     Star,
+    StarStar,
     Slash,
     DoubleQuote,
     LeftParen,
@@ -25,28 +28,52 @@ pub enum Token {
     Greater,
     LessEqual,
     GreaterEqual,
+    AmpAmp,
+    PipePipe,
+    DotDot,
     Semicolon,
+    Colon,
+    Question,
     Comma,
     Number(f64),
+    /// Number written without the fraction.
+    Integer(i64),
     True,
     False,
     Print,
     If,
     Else,
     While,
+    Do,
+    For,
+    In,
+    Break,
+    Continue,
     Let,
+    Const,
     Fun,
     Return,
+    Try,
+    Catch,
+    Throw,
+    Switch,
+    Case,
+    Default,
+    Assert,
     Nil,
     Identifier(String),
     StringLiteral(String),
+    /// Documentation comment starting with `///`
+    DocComment(String),
     EndOfFile,
+    /// Block comment `/* ... */` missing its closing `*/` before the end of the source.
+    UnterminatedComment,
     Error,
 }
 
 impl Token {
-    pub fn with_position(self, pos: Position) -> SourceToken {
-        SourceToken::new(self, pos)
+    pub fn with_position(self, span: impl Into<Span>) -> SourceToken {
+        SourceToken::new(self, span)
     }
 
     pub fn with_line(self, line: usize) -> SourceToken {
@@ -58,8 +85,11 @@ impl Display for Token {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Token::Plus => write!(f, "+"),
+            Token::PlusPlus => write!(f, "++"),
             Token::Minus => write!(f, "-"),
+            Token::MinusMinus => write!(f, "--"),
             Token::Star => write!(f, "*"),
+            Token::StarStar => write!(f, "**"),
             Token::Slash => write!(f, "/"),
             Token::DoubleQuote => write!(f, "\""),
             Token::LeftParen => write!(f, "("),
@@ -76,22 +106,43 @@ impl Display for Token {
             Token::Greater => write!(f, ">"),
             Token::LessEqual => write!(f, "<="),
             Token::GreaterEqual => write!(f, ">="),
+            Token::AmpAmp => write!(f, "&&"),
+            Token::PipePipe => write!(f, "||"),
+            Token::DotDot => write!(f, ".."),
             Token::Semicolon => write!(f, ";"),
+            Token::Colon => write!(f, ":"),
+            Token::Question => write!(f, "?"),
             Token::Comma => write!(f, ","),
             Token::Number(n) => write!(f, "{}", n),
+            Token::Integer(n) => write!(f, "{}", n),
             Token::True => write!(f, "true"),
             Token::False => write!(f, "false"),
             Token::Print => write!(f, "print"),
             Token::If => write!(f, "if"),
             Token::Else => write!(f, "else"),
             Token::While => write!(f, "while"),
+            Token::Do => write!(f, "do"),
+            Token::For => write!(f, "for"),
+            Token::In => write!(f, "in"),
+            Token::Break => write!(f, "break"),
+            Token::Continue => write!(f, "continue"),
             Token::Let => write!(f, "let"),
+            Token::Const => write!(f, "const"),
             Token::Fun => write!(f, "fun"),
             Token::Return => write!(f, "return"),
+            Token::Try => write!(f, "try"),
+            Token::Catch => write!(f, "catch"),
+            Token::Throw => write!(f, "throw"),
+            Token::Switch => write!(f, "switch"),
+            Token::Case => write!(f, "case"),
+            Token::Default => write!(f, "default"),
+            Token::Assert => write!(f, "assert"),
             Token::Nil => write!(f, "nil"),
             Token::Identifier(name) => write!(f, "{}", name),
             Token::StringLiteral(s) => write!(f, "{}", s),
+            Token::DocComment(s) => write!(f, "/// {}", s),
             Token::EndOfFile => write!(f, "EOF"),
+            Token::UnterminatedComment => write!(f, "/*"),
             Token::Error => write!(f, "Error"),
         }
     }
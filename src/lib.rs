@@ -1,31 +1,274 @@
 //! Interpreter for Bauble programming language
-use std::error::Error;
+//!
+//! The `interpret*` functions run the source code in one call.
+//! Applications going through the stages one by one use the types re-exported here:
+//! [Lexer] and [Parser] turn the source into a [Program], [Compiler] turns it into a [Chunk],
+//! and [Vm] runs it, producing [ValueType]s. Every stage fails with its own error,
+//! and [BaubleError] covers all of them.
+//!
+//! ```rust
+//! # use std::rc::Rc;
+//! # use brainterpreter::{Compiler, Lexer, Parser, ValueType, Vm};
+//! let program = Parser::new(Lexer::new("let a = 2; a * 21;")).parse_program().unwrap();
+//! let chunk = Compiler::default().compile(program).unwrap();
+//! let mut vm = Vm::default();
+//!
+//! assert_eq!(vm.load_and_run(Rc::new(chunk)).unwrap(), ValueType::Int(42));
+//! ```
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
 use std::rc::Rc;
 
-use vm::Vm;
+use thiserror::Error;
 
-use crate::compiler::Compiler;
-use crate::lexer::Lexer;
-use crate::parser::Parser;
+pub use crate::ast::Program;
+pub use crate::compiler::{CompileError, Compiler};
+pub use crate::lexer::Lexer;
+pub use crate::parser::{Parser, ParsingError};
+use crate::source::CharSource;
+use crate::source::{Position, Span};
+pub use crate::value::ValueType;
+pub use crate::vm::exec::Chunk;
+pub use crate::vm::{TraceFrame, Vm, VmRuntimeError};
 
 pub mod ast;
 pub mod compiler;
+pub mod diagnostic;
+pub mod doc;
+pub mod fmt;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
 pub mod lexer;
+pub mod lint;
 pub mod log;
 pub mod parser;
 pub mod source;
 pub mod value;
 pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+/// Stage of running a Bauble program an error occurred at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading the source code.
+    Read,
+    /// Parsing the source code.
+    Parse,
+    /// Compiling the program to bytecode.
+    Compile,
+    /// Running the bytecode.
+    Run,
+}
+
+/// Error of any stage of running a Bauble program.
+#[derive(Debug, Error)]
+pub enum BaubleError {
+    #[error("failed to read the source: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Parsing(#[from] ParsingError),
+    #[error(transparent)]
+    Compile(#[from] CompileError),
+    #[error(transparent)]
+    Runtime(#[from] VmRuntimeError),
+}
+
+impl BaubleError {
+    /// Stage of running the program the error occurred at.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use brainterpreter::{interpret, Stage};
+    /// assert_eq!(interpret("print 1 +;").unwrap_err().stage(), Stage::Parse);
+    /// assert_eq!(interpret("print 1 + true;").unwrap_err().stage(), Stage::Run);
+    /// ```
+    pub fn stage(&self) -> Stage {
+        match self {
+            BaubleError::Io(_) => Stage::Read,
+            BaubleError::Parsing(_) => Stage::Parse,
+            BaubleError::Compile(_) => Stage::Compile,
+            BaubleError::Runtime(_) => Stage::Run,
+        }
+    }
+
+    /// Stable identifier of the error kind, e.g. `unexpected-token` or `type-mismatch`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use brainterpreter::interpret;
+    /// let error = interpret("print 1 + true;").unwrap_err();
+    /// assert_eq!(error.code(), "type-mismatch");
+    /// assert_eq!(error.position().map(|p| p.line()), Some(1));
+    /// ```
+    pub fn code(&self) -> &'static str {
+        match self {
+            BaubleError::Io(_) => "io-error",
+            BaubleError::Parsing(e) => e.code(),
+            BaubleError::Compile(e) => e.code(),
+            BaubleError::Runtime(e) => e.code(),
+        }
+    }
+
+    /// Position in the source code the error occurred at, if it is known.
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            BaubleError::Parsing(e) => Some(e.position()),
+            BaubleError::Compile(e) => e.span().map(|span| span.start()),
+            BaubleError::Runtime(e) => e.position(),
+            BaubleError::Io(_) => None,
+        }
+    }
+
+    /// Range of the source code the error occurred at, if it is known.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            BaubleError::Parsing(e) => Some(e.span()),
+            BaubleError::Compile(e) => e.span(),
+            BaubleError::Runtime(e) => e.position().map(Span::from),
+            BaubleError::Io(_) => None,
+        }
+    }
+
+    /// Address of the failed instruction for the runtime errors.
+    pub fn address(&self) -> Option<usize> {
+        match self {
+            BaubleError::Runtime(e) => e.address(),
+            _ => None,
+        }
+    }
+
+    /// Calls active when a runtime error occurred, starting from the innermost one.
+    pub fn backtrace(&self) -> &[TraceFrame] {
+        match self {
+            BaubleError::Runtime(e) => e.backtrace(),
+            _ => &[],
+        }
+    }
+
+    /// Code the program asked to exit with by calling the `exit` function.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            BaubleError::Runtime(e) => e.exit_code(),
+            _ => None,
+        }
+    }
+}
 
 /// Shortcut function to interpret the source code.
-pub fn interpret(source: &str) -> Result<(), Box<dyn Error>> {
-    let lexer = Lexer::new(source);
-    let mut parser = Parser::new(lexer);
-    let ast = parser.parse_program()?;
-    let mut compiler = Compiler::default();
-    let chunk = compiler.compile(ast)?;
-    let mut vm = Vm::default();
+///
+/// Returns the value of the last expression statement of the program,
+/// or the value of the top-level `return`.
+///
+/// # Examples
+///
+/// ```rust
+/// # use brainterpreter::interpret;
+/// # use brainterpreter::value::ValueType;
+/// let value = interpret("let a = 2; a * 21;").unwrap();
+/// assert_eq!(value, ValueType::Int(42));
+/// ```
+pub fn interpret(source: &str) -> Result<ValueType, BaubleError> {
+    Vm::default().eval(source)
+}
+
+/// Reads the source file and interprets it.
+pub fn interpret_file(path: impl AsRef<Path>) -> Result<ValueType, BaubleError> {
+    interpret_reader(File::open(path)?)
+}
+
+/// Interprets the source code read from a stream, e.g. the standard input.
+///
+/// The source is lexed as it is read, without loading the whole program in memory first.
+///
+/// # Examples
+///
+/// ```rust
+/// # use brainterpreter::interpret_reader;
+/// # use brainterpreter::value::ValueType;
+/// let value = interpret_reader("let a = 2; a * 21;".as_bytes()).unwrap();
+/// assert_eq!(value, ValueType::Int(42));
+/// ```
+pub fn interpret_reader(reader: impl Read) -> Result<ValueType, BaubleError> {
+    interpret_reader_with(reader, &mut Vm::default())
+}
+
+/// Interprets the source code read from a stream on the configured virtual machine.
+///
+/// # Examples
+///
+/// ```rust
+/// # use brainterpreter::interpret_reader_with;
+/// # use brainterpreter::value::ValueType;
+/// # use brainterpreter::vm::Vm;
+/// let mut vm = Vm::default().with_args(["--verbose"]);
+/// let value = interpret_reader_with("args()[0];".as_bytes(), &mut vm).unwrap();
+/// assert_eq!(value, ValueType::string("--verbose"));
+/// ```
+pub fn interpret_reader_with(reader: impl Read, vm: &mut Vm) -> Result<ValueType, BaubleError> {
+    let chunk = compile_lexer(Lexer::from_reader(reader), vm.compiler())?;
+    let value = vm.load_and_run(Rc::new(chunk))?;
+    Ok(value)
+}
+
+/// Interprets the source code and returns everything it prints.
+///
+/// # Examples
+///
+/// ```rust
+/// # use brainterpreter::interpret_to_string;
+/// let out = interpret_to_string(r#"print "Hello, " + "World!";"#).unwrap();
+/// assert_eq!(out, "Hello, World!\n");
+/// ```
+pub fn interpret_to_string(source: &str) -> Result<String, BaubleError> {
+    let out = Rc::new(RefCell::new(vec![]));
+    let mut vm = Vm::with_io(out.clone());
+    let chunk = compile_source(source, vm.compiler())?;
     vm.load_and_run(Rc::new(chunk))?;
+    let out = String::from_utf8_lossy(&out.borrow()).to_string();
+    Ok(out)
+}
+
+/// Parses the source code into the syntax tree, the first stage of running a program.
+///
+/// Tools like formatters and analyzers can work with the tree without compiling it.
+///
+/// # Examples
+///
+/// ```rust
+/// # use brainterpreter::{compile, parse};
+/// # use brainterpreter::vm::Vm;
+/// # use brainterpreter::value::ValueType;
+/// let program = parse("let a = 2; a * 21;")?;
+/// let chunk = compile(program)?;
+/// let value = Vm::default().run_chunk(&chunk)?;
+/// assert_eq!(value, ValueType::Int(42));
+/// # Ok::<(), brainterpreter::BaubleError>(())
+/// ```
+pub fn parse(source: &str) -> Result<Program, ParsingError> {
+    Parser::new(Lexer::new(source)).parse_program()
+}
+
+/// Compiles the syntax tree into the chunk the virtual machine runs.
+pub fn compile(program: Program) -> Result<Chunk, CompileError> {
+    Compiler::default().compile(program)
+}
+
+pub(crate) fn compile_source(source: &str, compiler: Compiler) -> Result<Chunk, BaubleError> {
+    compile_lexer(Lexer::new(source), compiler)
+}
 
-    Ok(())
+fn compile_lexer<S: CharSource>(
+    mut lexer: Lexer<S>,
+    mut compiler: Compiler,
+) -> Result<Chunk, BaubleError> {
+    let ast = Parser::new(&mut lexer).parse_program();
+    if let Some(e) = lexer.take_error() {
+        return Err(e.into());
+    }
+    Ok(compiler.compile(ast?)?)
 }
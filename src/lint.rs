@@ -0,0 +1,610 @@
+//! Static checks of the program for suspicious code.
+//!
+//! The linter visits the AST and reports [Diagnostic]s for code that is valid but likely wrong.
+//! Every rule can be disabled or have its severity changed via [LintConfig].
+
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+
+use crate::ast::visit::{walk_expression, walk_statement, Visitor};
+use crate::ast::{BinaryOperator, Expression, Program, Statement};
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::source::Span;
+use crate::vm::native::std_lib;
+
+/// Checks supported by the linter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// Local variable is declared but never read.
+    UnusedVariable,
+    /// Variable, parameter, or function hides a built-in function.
+    ShadowedBuiltin,
+    /// Block has no statements.
+    EmptyBlock,
+    /// Condition of `if` or `while` does not depend on any variable.
+    ConstantCondition,
+    /// Condition of `if` or `while` is an assignment.
+    AssignmentInCondition,
+    /// Local variable or parameter hides a variable of an enclosing scope.
+    ShadowedVariable,
+    /// Value is compared with `true` or `false` instead of being used as the condition itself.
+    BooleanComparison,
+    /// Function parameter is never read.
+    UnusedParameter,
+}
+
+/// What the linter does when a rule is violated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// Rule levels used by the linter.
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+    levels: HashMap<Rule, Level>,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 8] = [
+        Rule::UnusedVariable,
+        Rule::ShadowedBuiltin,
+        Rule::EmptyBlock,
+        Rule::ConstantCondition,
+        Rule::AssignmentInCondition,
+        Rule::ShadowedVariable,
+        Rule::BooleanComparison,
+        Rule::UnusedParameter,
+    ];
+
+    /// Stable identifier of the rule.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Rule::UnusedVariable => "unused-variable",
+            Rule::ShadowedBuiltin => "shadowed-builtin",
+            Rule::EmptyBlock => "empty-block",
+            Rule::ConstantCondition => "constant-condition",
+            Rule::AssignmentInCondition => "assignment-in-condition",
+            Rule::ShadowedVariable => "shadowed-variable",
+            Rule::BooleanComparison => "bool-comparison",
+            Rule::UnusedParameter => "unused-parameter",
+        }
+    }
+
+    /// Finds the rule by its identifier.
+    pub fn from_id(id: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|rule| rule.id() == id)
+    }
+
+    fn default_level(&self) -> Level {
+        match self {
+            Rule::AssignmentInCondition => Level::Deny,
+            _ => Level::Warn,
+        }
+    }
+}
+
+impl LintConfig {
+    /// Sets the level of the rule.
+    pub fn set(&mut self, rule: Rule, level: Level) -> &mut Self {
+        self.levels.insert(rule, level);
+        self
+    }
+
+    pub fn level(&self, rule: Rule) -> Level {
+        self.levels
+            .get(&rule)
+            .copied()
+            .unwrap_or_else(|| rule.default_level())
+    }
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            levels: Rule::ALL
+                .into_iter()
+                .map(|rule| (rule, rule.default_level()))
+                .collect(),
+        }
+    }
+}
+
+/// Checks the program and returns the found problems.
+pub fn lint(program: &Program, config: &LintConfig) -> Vec<Diagnostic> {
+    let mut linter = Linter::new(config);
+    let Ok(()) = linter.visit_program(program);
+    linter.diagnostics
+}
+
+struct LocalUse {
+    name: String,
+    position: Span,
+    used: bool,
+    /// Parameters report [Rule::UnusedParameter] instead of [Rule::UnusedVariable].
+    parameter: bool,
+}
+
+struct Linter<'a> {
+    config: &'a LintConfig,
+    builtins: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+    /// Globals declared so far, which the locals declared later may shadow.
+    globals: HashSet<String>,
+    scopes: Vec<Vec<LocalUse>>,
+    position: Span,
+}
+
+impl<'a> Linter<'a> {
+    fn new(config: &'a LintConfig) -> Self {
+        Linter {
+            config,
+            builtins: std_lib().iter().map(|f| f.name().to_string()).collect(),
+            diagnostics: vec![],
+            globals: HashSet::new(),
+            scopes: vec![],
+            position: Span::default(),
+        }
+    }
+
+    fn report(&mut self, rule: Rule, message: String) {
+        let severity = match self.config.level(rule) {
+            Level::Allow => return,
+            Level::Warn => Severity::Warning,
+            Level::Deny => Severity::Error,
+        };
+        let diagnostic = Diagnostic::new(rule.id(), severity, message).at(self.position);
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn function(&mut self, params: &[String], body: &Statement) -> Result<(), Infallible> {
+        let position = self.position;
+        self.scopes.push(vec![]);
+        for param in params {
+            self.check_builtin_shadowing(param);
+            self.check_variable_shadowing(param);
+            let param = LocalUse {
+                name: param.clone(),
+                position,
+                used: false,
+                parameter: true,
+            };
+            if let Some(scope) = self.scopes.last_mut() {
+                scope.push(param);
+            }
+        }
+        self.function_body(body)?;
+        self.end_scope();
+        Ok(())
+    }
+
+    fn function_body(&mut self, body: &Statement) -> Result<(), Infallible> {
+        // An empty function body is a legitimate no-op, so only its statements are checked.
+        match body.inner() {
+            Statement::Block(statements) => self.block(statements),
+            statement => self.visit_statement(statement),
+        }
+    }
+
+    fn block(&mut self, statements: &[Statement]) -> Result<(), Infallible> {
+        self.scopes.push(vec![]);
+        for statement in statements {
+            self.visit_statement(statement)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap_or_default();
+        for local in scope.into_iter().filter(|local| !local.used) {
+            let current = std::mem::replace(&mut self.position, local.position);
+            if local.parameter {
+                self.report(
+                    Rule::UnusedParameter,
+                    format!("parameter `{}` is never used", local.name),
+                );
+            } else {
+                self.report(
+                    Rule::UnusedVariable,
+                    format!("variable `{}` is never used", local.name),
+                );
+            }
+            self.position = current;
+        }
+    }
+
+    fn declare(&mut self, name: &str) {
+        self.check_builtin_shadowing(name);
+        self.check_variable_shadowing(name);
+        let position = self.position;
+        match self.scopes.last_mut() {
+            Some(scope) => scope.push(LocalUse {
+                name: name.to_string(),
+                position,
+                used: false,
+                parameter: false,
+            }),
+            None => {
+                self.globals.insert(name.to_string());
+            }
+        }
+    }
+
+    fn check_builtin_shadowing(&mut self, name: &str) {
+        if self.builtins.iter().any(|builtin| builtin == name) {
+            self.report(
+                Rule::ShadowedBuiltin,
+                format!("`{}` shadows a built-in function", name),
+            );
+        }
+    }
+
+    /// Reports the local hiding a variable of the enclosing scopes.
+    /// Redeclaring a global at the top level replaces it, so it is not reported.
+    fn check_variable_shadowing(&mut self, name: &str) {
+        let Some((_, enclosing)) = self.scopes.split_last() else {
+            return;
+        };
+        let shadowed = enclosing.iter().flatten().any(|local| local.name == name)
+            || self.globals.contains(name);
+        if shadowed {
+            self.report(
+                Rule::ShadowedVariable,
+                format!("`{}` shadows a variable of an enclosing scope", name),
+            );
+        }
+    }
+
+    fn use_variable(&mut self, name: &str) {
+        let local = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .flat_map(|scope| scope.iter_mut().rev())
+            .find(|local| local.name == name);
+        if let Some(local) = local {
+            local.used = true;
+        }
+    }
+
+    fn condition(&mut self, condition: &Expression, statement: &str) -> Result<(), Infallible> {
+        match condition {
+            Expression::Assign { .. } => self.report(
+                Rule::AssignmentInCondition,
+                format!("assignment used as a condition of `{}`", statement),
+            ),
+            c if is_constant(c) => self.report(
+                Rule::ConstantCondition,
+                format!("condition of `{}` is always the same", statement),
+            ),
+            _ => {}
+        }
+        self.visit_expression(condition)
+    }
+}
+
+impl Visitor for Linter<'_> {
+    type Error = Infallible;
+
+    fn visit_statement(&mut self, statement: &Statement) -> Result<(), Infallible> {
+        match statement {
+            Statement::Located(position, statement) => {
+                self.position = *position;
+                self.visit_statement(statement)?;
+            }
+            Statement::Try {
+                body,
+                variable,
+                handler,
+            } => {
+                self.visit_statement(body)?;
+                self.scopes.push(vec![LocalUse {
+                    name: variable.clone(),
+                    position: self.position,
+                    used: true,
+                    parameter: false,
+                }]);
+                self.visit_statement(handler)?;
+                self.end_scope();
+            }
+            Statement::Switch {
+                subject,
+                cases,
+                default,
+            } => {
+                self.visit_expression(subject)?;
+                for (value, body) in cases {
+                    self.visit_expression(value)?;
+                    self.function_body(body)?;
+                }
+                if let Some(default) = default {
+                    self.function_body(default)?;
+                }
+            }
+            Statement::DeclareVariable(name) => self.declare(name),
+            Statement::DefineVariable(name, value) | Statement::DefineConstant(name, value) => {
+                self.visit_expression(value)?;
+                self.declare(name);
+            }
+            Statement::DefineVariables(names, value) => {
+                self.visit_expression(value)?;
+                for name in names {
+                    self.declare(name);
+                }
+            }
+            Statement::Function {
+                name, params, body, ..
+            } => {
+                self.check_builtin_shadowing(name);
+                if self.scopes.is_empty() {
+                    self.globals.insert(name.clone());
+                }
+                self.function(params, body)?;
+            }
+            Statement::Block(statements) => {
+                if statements.is_empty() {
+                    self.report(Rule::EmptyBlock, "block is empty".to_string());
+                }
+                self.block(statements)?;
+            }
+            Statement::If(condition, then, otherwise) => {
+                self.condition(condition, "if")?;
+                self.visit_statement(then)?;
+                if let Some(otherwise) = otherwise {
+                    self.visit_statement(otherwise)?;
+                }
+            }
+            Statement::While(condition, body) => {
+                self.condition(condition, "while")?;
+                self.visit_statement(body)?;
+            }
+            Statement::DoWhile(body, condition) => {
+                self.visit_statement(body)?;
+                self.condition(condition, "do-while")?;
+            }
+            Statement::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                self.scopes.push(vec![]);
+                if let Some(initializer) = initializer {
+                    self.visit_statement(initializer)?;
+                }
+                if let Some(condition) = condition {
+                    self.condition(condition, "for")?;
+                }
+                if let Some(increment) = increment {
+                    self.visit_expression(increment)?;
+                }
+                self.visit_statement(body)?;
+                self.end_scope();
+            }
+            Statement::ForIn {
+                variable,
+                collection,
+                body,
+            } => {
+                self.visit_expression(collection)?;
+                self.scopes.push(vec![]);
+                self.declare(variable);
+                self.visit_statement(body)?;
+                self.end_scope();
+            }
+            _ => walk_statement(self, statement)?,
+        }
+        Ok(())
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) -> Result<(), Infallible> {
+        match expression {
+            Expression::Variable(name) | Expression::Update { variable: name, .. } => {
+                self.use_variable(name)
+            }
+            Expression::Block { statements, value } => {
+                self.scopes.push(vec![]);
+                for statement in statements {
+                    self.visit_statement(statement)?;
+                }
+                if let Some(value) = value {
+                    self.visit_expression(value)?;
+                }
+                self.end_scope();
+            }
+            Expression::Lambda(params, body) => {
+                // The lambda body is checked at the position of the enclosing statement.
+                let position = self.position;
+                self.function(params, body)?;
+                self.position = position;
+            }
+            // Assigning to a variable is not a use of it, unlike assigning to an element.
+            Expression::Assign { target, value } if matches!(**target, Expression::Variable(_)) => {
+                self.visit_expression(value)?
+            }
+            Expression::BinaryOperation(
+                op @ (BinaryOperator::Equal | BinaryOperator::NotEqual),
+                lhs,
+                rhs,
+            ) => {
+                if let Some(value) = boolean_operand(lhs, rhs) {
+                    let operator = if *op == BinaryOperator::Equal {
+                        "=="
+                    } else {
+                        "!="
+                    };
+                    self.report(
+                        Rule::BooleanComparison,
+                        format!("comparison `{} {}` can be simplified", operator, value),
+                    );
+                }
+                walk_expression(self, expression)?;
+            }
+            Expression::Conditional {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.condition(condition, "?")?;
+                self.visit_expression(then)?;
+                self.visit_expression(otherwise)?;
+            }
+            _ => walk_expression(self, expression)?,
+        }
+        Ok(())
+    }
+}
+
+/// Value of the boolean literal on either side of the comparison.
+fn boolean_operand(lhs: &Expression, rhs: &Expression) -> Option<bool> {
+    match (lhs, rhs) {
+        (Expression::BooleanLiteral(value), _) | (_, Expression::BooleanLiteral(value)) => {
+            Some(*value)
+        }
+        _ => None,
+    }
+}
+
+fn is_constant(expression: &Expression) -> bool {
+    match expression {
+        Expression::Nil
+        | Expression::NumberLiteral(_)
+        | Expression::IntegerLiteral(_)
+        | Expression::BooleanLiteral(_)
+        | Expression::StringLiteral(_) => true,
+        Expression::BinaryOperation(_, lhs, rhs) => is_constant(lhs) && is_constant(rhs),
+        Expression::UnaryOperation(_, operand) => is_constant(operand),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn lint_source(source: &str) -> Vec<Diagnostic> {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        lint(&program, &LintConfig::default())
+    }
+
+    fn codes(diagnostics: &[Diagnostic]) -> Vec<&str> {
+        diagnostics.iter().map(|d| d.code()).collect()
+    }
+
+    #[test]
+    fn unused_local_variable() {
+        let diagnostics = lint_source(
+            "let global = 1;
+            {
+                let used = 1;
+                let unused = 2;
+                print used;
+            }",
+        );
+
+        assert_eq!(codes(&diagnostics), vec!["unused-variable"]);
+        assert_eq!(diagnostics[0].message(), "variable `unused` is never used");
+        assert_eq!(diagnostics[0].position().map(|p| p.line()), Some(4));
+    }
+
+    #[test]
+    fn shadowed_builtin() {
+        let diagnostics = lint_source("fun f(len) { return len; }");
+
+        assert_eq!(codes(&diagnostics), vec!["shadowed-builtin"]);
+    }
+
+    #[test]
+    fn empty_block() {
+        let diagnostics = lint_source("fun noop() {} let a = 1; if (a > 0) {}");
+
+        assert_eq!(codes(&diagnostics), vec!["empty-block"]);
+    }
+
+    #[test]
+    fn constant_condition() {
+        let diagnostics = lint_source("while (1 < 2) { print 1; }");
+
+        assert_eq!(codes(&diagnostics), vec!["constant-condition"]);
+    }
+
+    #[test]
+    fn assignment_in_condition() {
+        let diagnostics = lint_source("let a; if (a = true) { print a; }");
+
+        assert_eq!(codes(&diagnostics), vec!["assignment-in-condition"]);
+        assert_eq!(diagnostics[0].severity(), Severity::Error);
+    }
+
+    #[test]
+    fn shadowed_variable() {
+        let diagnostics = lint_source(
+            "let a = 1;
+            let a = 2;
+            fun f(a) {
+                for (let i = 0; i < a; i++) {
+                    let i = 2;
+                    print i;
+                }
+            }",
+        );
+
+        assert_eq!(
+            codes(&diagnostics),
+            vec!["shadowed-variable", "shadowed-variable"]
+        );
+        assert_eq!(diagnostics[0].position().map(|p| p.line()), Some(3));
+        assert_eq!(diagnostics[1].position().map(|p| p.line()), Some(5));
+    }
+
+    #[test]
+    fn boolean_comparison() {
+        let diagnostics =
+            lint_source("let a = 1; print a == true; print false != a; print a == 1;");
+
+        assert_eq!(
+            codes(&diagnostics),
+            vec!["bool-comparison", "bool-comparison"]
+        );
+        assert_eq!(
+            diagnostics[1].message(),
+            "comparison `!= false` can be simplified"
+        );
+    }
+
+    #[test]
+    fn unused_parameter() {
+        let diagnostics = lint_source(
+            "fun f(a, b) { return a; }
+            let g = fun(x) { return 0; };",
+        );
+
+        assert_eq!(
+            codes(&diagnostics),
+            vec!["unused-parameter", "unused-parameter"]
+        );
+        assert_eq!(diagnostics[0].message(), "parameter `b` is never used");
+        assert_eq!(diagnostics[1].position().map(|p| p.line()), Some(2));
+    }
+
+    #[test]
+    fn allowed_rules_are_not_reported() {
+        let program = Parser::new(Lexer::new("if (true) {}"))
+            .parse_program()
+            .unwrap();
+        let mut config = LintConfig::default();
+        config
+            .set(Rule::EmptyBlock, Level::Allow)
+            .set(Rule::ConstantCondition, Level::Deny);
+
+        let diagnostics = lint(&program, &config);
+
+        assert_eq!(codes(&diagnostics), vec!["constant-condition"]);
+        assert_eq!(diagnostics[0].severity(), Severity::Error);
+    }
+}
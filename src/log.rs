@@ -2,21 +2,26 @@
 use log::debug;
 
 use crate::vm::exec::Chunk;
-use crate::vm::trace::VmStepTrace;
+use crate::vm::trace::{TraceEvent, VmStepTrace};
 use crate::vm::VmStack;
 
+/// Writes the execution events to the debug log.
 #[derive(Debug, Default)]
 pub struct LoggingTracer;
 
 impl VmStepTrace for LoggingTracer {
-    fn trace_before(&self, ip: usize, chunk: &Chunk, _stack: &VmStack) {
-        debug!("{}", "=".repeat(16));
-        self.print_instructions_window(ip, chunk, 5);
-        // self.print_stack(stack, "before");
-    }
-
-    fn trace_after(&self, _ip: usize, _chunk: &Chunk, stack: &VmStack) {
-        self.print_stack(stack, "after");
+    fn trace(&mut self, event: TraceEvent) {
+        match event {
+            TraceEvent::Instruction { address, chunk, .. } => {
+                debug!("{}", "=".repeat(16));
+                self.print_instructions_window(address, chunk, 5);
+            }
+            TraceEvent::CallEntered { function, depth } => {
+                debug!("= call {} at depth {}", function, depth);
+            }
+            TraceEvent::CallReturned { depth } => debug!("= return to depth {}", depth),
+            TraceEvent::Stack(stack) => self.print_stack(stack, "after"),
+        }
     }
 }
 
@@ -3,24 +3,36 @@ use log::trace;
 use crate::lexer::token::Token;
 use crate::lexer::SourceToken;
 use crate::parser::{Parser, ParsingError};
-use crate::source::Position;
+use crate::source::{Position, Span};
 
 impl<T> Parser<T>
 where
     T: Iterator<Item = SourceToken>,
 {
     pub fn advance(&mut self) -> Token {
-        self.tokens
-            .next()
-            .map(|t| t.kind().clone())
-            .unwrap_or(Token::EndOfFile)
+        self.skip_doc_comments();
+        match self.tokens.next() {
+            Some(token) => {
+                self.previous = token.span();
+                token.kind().clone()
+            }
+            None => Token::EndOfFile,
+        }
     }
 
     pub fn advance_if(&mut self, token: Token) -> bool {
-        self.tokens.next_if(|t| t.kind() == &token).is_some()
+        self.skip_doc_comments();
+        match self.tokens.next_if(|t| t.kind() == &token) {
+            Some(token) => {
+                self.previous = token.span();
+                true
+            }
+            None => false,
+        }
     }
 
     pub fn peek(&mut self) -> &Token {
+        self.skip_doc_comments();
         self.tokens
             .peek()
             .map(|t| t.kind())
@@ -34,15 +46,85 @@ where
         if &token == expected {
             return Ok(());
         }
+        if token == Token::UnterminatedComment {
+            return Err(ParsingError::UnterminatedComment(self.previous_span()));
+        }
         Err(ParsingError::MissingToken {
-            position: self.last_position(),
+            span: self.previous_span(),
             expected: expected.clone(),
             actual: token,
         })
     }
 
     pub fn last_position(&mut self) -> Position {
-        self.tokens.peek().map(|t| *t.source()).unwrap_or_default()
+        self.last_span().start()
+    }
+
+    /// Span of the next token to parse.
+    pub fn last_span(&mut self) -> Span {
+        self.skip_doc_comments();
+        self.tokens.peek().map(|t| t.span()).unwrap_or_default()
+    }
+
+    /// Span of the token parsed last.
+    pub fn previous_span(&self) -> Span {
+        self.previous
+    }
+
+    /// Skips tokens until the start of the statement following the failed one.
+    ///
+    /// The statement is over after the semicolon or before a keyword starting a new statement.
+    pub fn synchronize(&mut self, failed_at: Position) {
+        // The statement failed on its first token, so skip it to make progress.
+        if self.last_position() == failed_at {
+            self.advance();
+        }
+        loop {
+            match self.peek() {
+                Token::EndOfFile
+                | Token::Let
+                | Token::Const
+                | Token::Fun
+                | Token::If
+                | Token::While
+                | Token::Do
+                | Token::For
+                | Token::Return
+                | Token::Try
+                | Token::Switch
+                | Token::Assert
+                | Token::Throw
+                | Token::Print
+                | Token::Break
+                | Token::Continue => return,
+                Token::Semicolon => {
+                    self.advance();
+                    return;
+                }
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    /// Returns documentation collected since the last call and clears it.
+    pub fn take_doc_comments(&mut self) -> Option<String> {
+        if self.doc_comments.is_empty() {
+            return None;
+        }
+        let doc = self.doc_comments.join("\n");
+        self.doc_comments.clear();
+        Some(doc)
+    }
+
+    /// Documentation comments are not a part of the grammar.
+    /// The parser collects them aside so declarations can pick them up.
+    fn skip_doc_comments(&mut self) {
+        while let Some(Token::DocComment(text)) = self.tokens.peek().map(|t| t.kind()) {
+            self.doc_comments.push(text.clone());
+            self.tokens.next();
+        }
     }
 }
 
@@ -55,9 +137,9 @@ mod tests {
     fn parser_advance() {
         let lexer = Lexer::new("1 + 2");
         let mut parser = Parser::new(lexer);
-        assert_eq!(parser.advance(), Token::Number(1.0));
+        assert_eq!(parser.advance(), Token::Integer(1));
         assert_eq!(parser.advance(), Token::Plus);
-        assert_eq!(parser.advance(), Token::Number(2.0));
+        assert_eq!(parser.advance(), Token::Integer(2));
         assert_eq!(parser.advance(), Token::EndOfFile);
     }
 
@@ -88,7 +170,7 @@ mod tests {
         assert_eq!(
             result,
             Err(ParsingError::MissingToken {
-                position: Position::default(),
+                span: Span::default(),
                 expected: Token::Minus,
                 actual: Token::Plus,
             })
@@ -106,6 +188,18 @@ mod tests {
         assert_eq!(parser.peek(), &Token::Number(2.0));
     }
 
+    #[test]
+    fn skip_doc_comments() {
+        let lexer = Lexer::new("/// first\n/// second\n1");
+        let mut parser = Parser::new(lexer);
+        assert_eq!(parser.advance(), Token::Integer(1));
+        assert_eq!(
+            parser.take_doc_comments(),
+            Some("first\nsecond".to_string())
+        );
+        assert_eq!(parser.take_doc_comments(), None);
+    }
+
     #[test]
     fn advance_if_no_match() {
         let tokens = vec![
@@ -1,6 +1,6 @@
 use log::trace;
 
-use crate::ast::{BinaryOperator, Expression, UnaryOperator};
+use crate::ast::{BinaryOperator, Expression, Statement, UnaryOperator, UpdateOperator};
 use crate::lexer::token::Token;
 use crate::lexer::SourceToken;
 use crate::parser::{Parser, ParsingError};
@@ -21,15 +21,23 @@ where
         trace!("Parsing expression (token: {:?})", token);
         let mut lhs = match token {
             Token::Number(n) => Expression::number(n),
+            Token::Integer(n) => Expression::integer(n),
             Token::Nil => Expression::Nil,
             Token::True => Expression::BooleanLiteral(true),
             Token::False => Expression::BooleanLiteral(false),
             Token::StringLiteral(s) => Expression::StringLiteral(s),
             Token::Minus | Token::Bang => self.unary_operation(&token)?,
+            Token::PlusPlus | Token::MinusMinus => self.prefix_update(&token)?,
             Token::Identifier(name) => Expression::Variable(name),
+            Token::Print => Expression::Variable("print".to_string()),
             Token::LeftParen => self.grouping()?,
             Token::LeftSquare => self.array_initialisation()?,
-            t => return Err(ParsingError::UnexpectedToken(t, self.last_position())),
+            Token::LeftCurly => self.map_or_block()?,
+            Token::Fun => self.lambda()?,
+            Token::UnterminatedComment => {
+                return Err(ParsingError::UnterminatedComment(self.previous_span()))
+            }
+            t => return Err(ParsingError::UnexpectedToken(t, self.previous_span())),
         };
 
         loop {
@@ -45,6 +53,11 @@ where
                     lhs = self.call(lhs)?;
                     continue;
                 }
+                if let Some(operator) = self.update_operator() {
+                    self.advance();
+                    lhs = self.update(operator, lhs, false)?;
+                    continue;
+                }
             }
 
             if let Some((left_binding, right_binding)) = self.infix_binding() {
@@ -55,6 +68,14 @@ where
                     lhs = self.assignment(lhs, right_binding)?;
                     continue;
                 }
+                if self.advance_if(Token::Question) {
+                    lhs = self.conditional(lhs, right_binding)?;
+                    continue;
+                }
+                if self.advance_if(Token::DotDot) {
+                    lhs = self.range(lhs, right_binding)?;
+                    continue;
+                }
                 lhs = self.binary_operation(lhs, right_binding)?;
                 continue;
             }
@@ -65,55 +86,111 @@ where
         Ok(lhs)
     }
 
+    /// Parses the expression in parentheses, or the tuple if there are several separated by commas.
     fn grouping(&mut self) -> ParsingResult {
         let expr = self.expression_bp(0)?;
+        if self.peek() != &Token::Comma {
+            self.consume(&Token::RightParen)?;
+            return Ok(expr);
+        }
+        let mut elements = vec![expr];
+        while self.advance_if(Token::Comma) {
+            elements.push(self.expression_bp(0)?);
+        }
         self.consume(&Token::RightParen)?;
-        Ok(expr)
+        Ok(Expression::Tuple(elements))
     }
 
     fn unary_operation(&mut self, token: &Token) -> ParsingResult {
         let binding = self
             .prefix_binding(token)
-            .ok_or(ParsingError::UnknownOperation(self.last_position()))?;
+            .ok_or(ParsingError::UnknownOperation(self.last_span()))?;
         let rhs = self.expression_bp(binding)?;
         let operator = match token {
             Token::Minus => UnaryOperator::Negate,
             Token::Bang => UnaryOperator::Not,
-            _ => return Err(ParsingError::UnknownOperation(self.last_position())),
+            _ => return Err(ParsingError::UnknownOperation(self.last_span())),
         };
         Ok(Expression::unary(operator, rhs))
     }
 
+    fn prefix_update(&mut self, token: &Token) -> ParsingResult {
+        let binding = self
+            .prefix_binding(token)
+            .ok_or(ParsingError::UnknownOperation(self.last_span()))?;
+        let operand = self.expression_bp(binding)?;
+        let operator = match token {
+            Token::PlusPlus => UpdateOperator::Increment,
+            Token::MinusMinus => UpdateOperator::Decrement,
+            _ => return Err(ParsingError::UnknownOperation(self.last_span())),
+        };
+        self.update(operator, operand, true)
+    }
+
+    fn update(
+        &mut self,
+        operator: UpdateOperator,
+        operand: Expression,
+        prefix: bool,
+    ) -> ParsingResult {
+        match operand {
+            Expression::Variable(variable) => Ok(Expression::Update {
+                operator,
+                variable,
+                prefix,
+            }),
+            _ => Err(ParsingError::InvalidAssignment(self.last_span())),
+        }
+    }
+
+    fn update_operator(&mut self) -> Option<UpdateOperator> {
+        match self.peek() {
+            Token::PlusPlus => Some(UpdateOperator::Increment),
+            Token::MinusMinus => Some(UpdateOperator::Decrement),
+            _ => None,
+        }
+    }
+
     fn binary_operation(&mut self, lhs: Expression, right_binding: u8) -> ParsingResult {
         let op = self
             .binary_operator()
-            .ok_or_else(|| ParsingError::Unknown(self.last_position()))?;
+            .ok_or_else(|| ParsingError::Unknown(self.last_span()))?;
         self.advance();
         let rhs = self
             .expression_bp(right_binding)
-            .map_err(|_| ParsingError::MissingOperand(self.last_position()))?;
+            .map_err(|_| ParsingError::MissingOperand(self.previous_span()))?;
 
         Ok(Expression::binary(op, lhs, rhs))
     }
 
-    fn assignment(&mut self, lhs: Expression, right_binding: u8) -> ParsingResult {
-        if let Expression::Variable(name) = lhs {
-            let rhs = self.expression_bp(right_binding)?;
-            return Ok(Expression::AssignVariable(name, Box::new(rhs)));
-        }
+    fn range(&mut self, start: Expression, right_binding: u8) -> ParsingResult {
+        let end = self
+            .expression_bp(right_binding)
+            .map_err(|_| ParsingError::MissingOperand(self.previous_span()))?;
+        Ok(Expression::Range {
+            start: Box::new(start),
+            end: Box::new(end),
+        })
+    }
 
-        if let Expression::Index { array, index } = lhs {
-            if let Expression::Variable(name) = *array {
-                let rhs = self.expression_bp(right_binding)?;
-                return Ok(Expression::AssignIndexVariable {
-                    variable: name,
-                    index,
-                    value: Box::new(rhs),
-                });
-            }
+    /// Parses the value assigned to the variable or to the element of an array or map.
+    fn assignment(&mut self, lhs: Expression, right_binding: u8) -> ParsingResult {
+        if !matches!(lhs, Expression::Variable(_) | Expression::Index { .. }) {
+            return Err(ParsingError::InvalidAssignment(self.last_span()));
         }
+        let rhs = self.expression_bp(right_binding)?;
+        Ok(Expression::assign(lhs, rhs))
+    }
 
-        Err(ParsingError::InvalidAssignment(self.last_position()))
+    fn conditional(&mut self, condition: Expression, right_binding: u8) -> ParsingResult {
+        let then = self.expression_bp(0)?;
+        self.consume(&Token::Colon)?;
+        let otherwise = self.expression_bp(right_binding)?;
+        Ok(Expression::Conditional {
+            condition: Box::new(condition),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+        })
     }
 
     fn index(&mut self, lhs: Expression) -> ParsingResult {
@@ -125,6 +202,74 @@ where
         })
     }
 
+    /// Parses a map literal or a block expression, telling them apart by the first element.
+    fn map_or_block(&mut self) -> ParsingResult {
+        if self.peek() == &Token::RightCurly {
+            self.advance();
+            return Ok(Expression::Map(vec![]));
+        }
+        if starts_statement(self.peek()) {
+            return self.block_expression(vec![]);
+        }
+        let span = self.last_span();
+        let first = self.expression_bp(0)?;
+        if self.advance_if(Token::Colon) {
+            return self.map_literal(first);
+        }
+        if self.advance_if(Token::RightCurly) {
+            return Ok(Expression::Block {
+                statements: vec![],
+                value: Some(Box::new(first)),
+            });
+        }
+        self.consume(&Token::Semicolon)?;
+        self.block_expression(vec![Statement::located(span, Statement::Expression(first))])
+    }
+
+    /// Parses the map entries after the first key.
+    fn map_literal(&mut self, first_key: Expression) -> ParsingResult {
+        trace!("Parsing map literal");
+        let value = self.expression_bp(0)?;
+        let mut entries = vec![(first_key, value)];
+        while self.advance_if(Token::Comma) && self.peek() != &Token::RightCurly {
+            let key = self.expression_bp(0)?;
+            self.consume(&Token::Colon)?;
+            let value = self.expression_bp(0)?;
+            entries.push((key, value));
+        }
+        self.consume(&Token::RightCurly)?;
+        Ok(Expression::Map(entries))
+    }
+
+    /// Parses the rest of the block expression.
+    /// An expression not followed by a semicolon must be the last one, it gives the value of the block.
+    fn block_expression(&mut self, mut statements: Vec<Statement>) -> ParsingResult {
+        trace!("Parsing block expression");
+        let mut value = None;
+        while !matches!(self.peek(), Token::RightCurly | Token::EndOfFile) {
+            if starts_statement(self.peek()) {
+                statements.push(self.located_statement()?);
+                continue;
+            }
+            let span = self.last_span();
+            let expression = self.expression_bp(0)?;
+            if self.peek() == &Token::RightCurly {
+                value = Some(Box::new(expression));
+                break;
+            }
+            self.consume(&Token::Semicolon)?;
+            statements.push(Statement::located(span, Statement::Expression(expression)));
+        }
+        self.consume(&Token::RightCurly)?;
+        Ok(Expression::Block { statements, value })
+    }
+
+    fn lambda(&mut self) -> ParsingResult {
+        trace!("Parsing anonymous function");
+        let (params, body) = self.function_parameters_and_body()?;
+        Ok(Expression::Lambda(params, Box::new(body)))
+    }
+
     fn array_initialisation(&mut self) -> ParsingResult {
         let initial = self.expression_bp(0)?;
         self.consume(&Token::Semicolon)?;
@@ -138,17 +283,24 @@ where
 
     fn call(&mut self, lhs: Expression) -> ParsingResult {
         match lhs {
-            Expression::Variable(name) => self.function_call(&name),
-            _ => Err(ParsingError::InvalidCall(self.last_position())),
+            Expression::Nil
+            | Expression::NumberLiteral(_)
+            | Expression::IntegerLiteral(_)
+            | Expression::BooleanLiteral(_)
+            | Expression::StringLiteral(_) => Err(ParsingError::InvalidCall(self.last_span())),
+            callee => {
+                trace!("Parsing call expression");
+                Ok(Expression::call(callee, self.arguments()?))
+            }
         }
     }
 
-    fn function_call(&mut self, name: &str) -> ParsingResult {
-        trace!("Parsing function call expression (name: {})", name);
+    /// Parses the arguments of a call up to the closing parenthesis.
+    pub(super) fn arguments(&mut self) -> Result<Vec<Expression>, ParsingError> {
         let mut arguments = vec![];
         if let Token::RightParen = self.peek() {
             self.consume(&Token::RightParen)?;
-            return Ok(Expression::FunctionCall(name.to_string(), arguments));
+            return Ok(arguments);
         }
         loop {
             let expr = self.expression_bp(0)?;
@@ -159,12 +311,12 @@ where
                 Token::RightParen => break,
                 _ => {
                     return Err(ParsingError::MissingClosingParentheses(
-                        self.last_position(),
+                        self.previous_span(),
                     ))
                 }
             }
         }
-        Ok(Expression::FunctionCall(name.to_string(), arguments))
+        Ok(arguments)
     }
 
     fn binary_operator(&mut self) -> Option<BinaryOperator> {
@@ -173,12 +325,15 @@ where
             Token::Minus => Some(BinaryOperator::Sub),
             Token::Star => Some(BinaryOperator::Mul),
             Token::Slash => Some(BinaryOperator::Div),
+            Token::StarStar => Some(BinaryOperator::Pow),
             Token::EqualEqual => Some(BinaryOperator::Equal),
             Token::BangEqual => Some(BinaryOperator::NotEqual),
             Token::Less => Some(BinaryOperator::Less),
             Token::LessEqual => Some(BinaryOperator::LessOrEqual),
             Token::Greater => Some(BinaryOperator::Greater),
             Token::GreaterEqual => Some(BinaryOperator::GreaterOrEqual),
+            Token::AmpAmp => Some(BinaryOperator::And),
+            Token::PipePipe => Some(BinaryOperator::Or),
             _ => None,
         }
     }
@@ -187,10 +342,15 @@ where
         match self.peek() {
             Token::Plus | Token::Minus => Precedence::Term.infix_binding(),
             Token::Star | Token::Slash => Precedence::Factor.infix_binding(),
+            Token::StarStar => Precedence::Power.infix_binding(),
             Token::EqualEqual | Token::BangEqual => Precedence::Equality.infix_binding(),
+            Token::DotDot => Precedence::Range.infix_binding(),
             Token::Less | Token::LessEqual => Precedence::Comparison.infix_binding(),
             Token::Greater | Token::GreaterEqual => Precedence::Comparison.infix_binding(),
             Token::Equal => Precedence::Assignment.infix_binding(),
+            Token::Question => Precedence::Conditional.infix_binding(),
+            Token::PipePipe => Precedence::Or.infix_binding(),
+            Token::AmpAmp => Precedence::And.infix_binding(),
             _ => None,
         }
     }
@@ -199,6 +359,7 @@ where
         match self.peek() {
             Token::LeftSquare => Precedence::Index.postfix_binding(),
             Token::LeftParen => Precedence::Call.postfix_binding(),
+            Token::PlusPlus | Token::MinusMinus => Precedence::Call.postfix_binding(),
             _ => None,
         }
     }
@@ -206,18 +367,48 @@ where
     fn prefix_binding(&self, token: &Token) -> Option<u8> {
         match token {
             Token::Minus | Token::Bang => Precedence::Unary.prefix_binding(),
+            Token::PlusPlus | Token::MinusMinus => Precedence::Unary.prefix_binding(),
             _ => None,
         }
     }
 }
 
+/// Checks if the token starts a statement that is not an expression statement.
+fn starts_statement(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Print
+            | Token::Let
+            | Token::Const
+            | Token::Fun
+            | Token::If
+            | Token::While
+            | Token::Do
+            | Token::For
+            | Token::Break
+            | Token::Continue
+            | Token::Return
+            | Token::Throw
+            | Token::Try
+            | Token::Switch
+            | Token::Assert
+            | Token::LeftCurly
+            | Token::DocComment(_)
+    )
+}
+
 enum Precedence {
     Assignment,
+    Conditional,
+    Or,
+    And,
     Equality,
     Comparison,
+    Range,
     Term,
     Factor,
     Unary,
+    Power,
     Call,
     Index,
 }
@@ -227,13 +418,16 @@ impl Precedence {
         match self {
             // Precedence::None => 0,
             Precedence::Assignment => 1,
-            // Precedence::Or => 3,
-            // Precedence::And => 5,
+            Precedence::Conditional => 2,
+            Precedence::Or => 3,
+            Precedence::And => 5,
             Precedence::Equality => 7,
             Precedence::Comparison => 9,
+            Precedence::Range => 10,
             Precedence::Term => 11,
             Precedence::Factor => 13,
             Precedence::Unary => 15,
+            Precedence::Power => 16,
             Precedence::Call => 17,
             Precedence::Index => 19,
         }
@@ -242,6 +436,10 @@ impl Precedence {
     fn infix_binding(&self) -> Option<(u8, u8)> {
         match self {
             Precedence::Unary | Precedence::Index => None,
+            // Right associative and binds tighter than the prefix operators: `-a ** b ** c` is `-(a ** (b ** c))`.
+            Precedence::Power => Some((self.base_binding(), self.base_binding() - 1)),
+            // Right associative: `a ? b : c ? d : e` is `a ? b : (c ? d : e)`.
+            Precedence::Conditional => Some((self.base_binding(), self.base_binding())),
             p => Some((p.base_binding(), p.base_binding() + 1)),
         }
     }
@@ -263,7 +461,7 @@ impl Precedence {
 
 #[cfg(test)]
 mod tests {
-    use crate::ast::Expression;
+    use crate::ast::{Expression, Statement};
     use crate::lexer::Lexer;
     use crate::parser::Parser;
 
@@ -275,7 +473,132 @@ mod tests {
         let expr = parser.expression().unwrap();
         assert_eq!(
             expr,
-            Expression::unary(UnaryOperator::Negate, Expression::number(1))
+            Expression::unary(UnaryOperator::Negate, Expression::integer(1))
+        );
+    }
+
+    #[test]
+    fn update_operations() {
+        let mut parser = Parser::new(Lexer::new("-i++ + --j"));
+        let expr = parser.expression().unwrap();
+        assert_eq!(
+            expr,
+            Expression::binary(
+                BinaryOperator::Add,
+                Expression::unary(
+                    UnaryOperator::Negate,
+                    Expression::update(UpdateOperator::Increment, "i", false)
+                ),
+                Expression::update(UpdateOperator::Decrement, "j", true)
+            )
+        );
+    }
+
+    #[test]
+    fn assignment_requires_variable_or_element() {
+        for source in ["f() = 1", "a + b = 1", "(a, b) = 1"] {
+            let mut parser = Parser::new(Lexer::new(source));
+            assert!(
+                matches!(parser.expression(), Err(ParsingError::InvalidAssignment(_))),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn update_requires_variable() {
+        let mut parser = Parser::new(Lexer::new("++1"));
+        assert!(matches!(
+            parser.expression(),
+            Err(ParsingError::InvalidAssignment(_))
+        ));
+    }
+
+    #[test]
+    fn map_literal() {
+        let mut parser = Parser::new(Lexer::new(r#"{ "a": 1, "b": 2, }"#));
+        let expr = parser.expression().unwrap();
+        assert_eq!(
+            expr,
+            Expression::Map(vec![
+                (
+                    Expression::StringLiteral("a".to_string()),
+                    Expression::integer(1)
+                ),
+                (
+                    Expression::StringLiteral("b".to_string()),
+                    Expression::integer(2)
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn range_binds_weaker_than_terms() {
+        let mut parser = Parser::new(Lexer::new("a + 1..b * 2 < c"));
+
+        assert_eq!(
+            parser.expression().unwrap(),
+            Expression::binary(
+                BinaryOperator::Less,
+                Expression::Range {
+                    start: Box::new(Expression::binary(
+                        BinaryOperator::Add,
+                        Expression::variable("a"),
+                        Expression::integer(1)
+                    )),
+                    end: Box::new(Expression::binary(
+                        BinaryOperator::Mul,
+                        Expression::variable("b"),
+                        Expression::integer(2)
+                    )),
+                },
+                Expression::variable("c")
+            )
+        );
+    }
+
+    #[test]
+    fn block_expression() {
+        let mut parser = Parser::new(Lexer::new("{ let a = 1; a; a * 2 }"));
+        let Expression::Block { statements, value } = parser.expression().unwrap() else {
+            panic!("expected a block expression");
+        };
+
+        let statements: Vec<_> = statements.iter().map(Statement::inner).collect();
+        assert_eq!(
+            statements,
+            vec![
+                &Statement::DefineVariable("a".to_string(), Expression::integer(1)),
+                &Statement::Expression(Expression::Variable("a".to_string())),
+            ]
+        );
+        assert_eq!(
+            value,
+            Some(Box::new(Expression::BinaryOperation(
+                BinaryOperator::Mul,
+                Box::new(Expression::Variable("a".to_string())),
+                Box::new(Expression::integer(2))
+            )))
+        );
+        let mut parser = Parser::new(Lexer::new("{ print 1; }"));
+        assert!(matches!(
+            parser.expression().unwrap(),
+            Expression::Block { value: None, .. }
+        ));
+    }
+
+    #[test]
+    fn lambda() {
+        let mut parser = Parser::new(Lexer::new("fun (a, b) { }"));
+        let expr = parser.expression().unwrap();
+        assert_eq!(
+            expr,
+            Expression::Lambda(
+                vec!["a".to_string(), "b".to_string()],
+                Box::new(Statement::Block(vec![]))
+            )
         );
     }
 
@@ -287,8 +610,30 @@ mod tests {
             expr,
             Expression::binary(
                 BinaryOperator::Add,
-                Expression::number(1),
-                Expression::number(2)
+                Expression::integer(1),
+                Expression::integer(2)
+            )
+        );
+    }
+
+    #[test]
+    fn logical_operation_priorities() {
+        let mut parser = Parser::new(Lexer::new("a || b && c == d"));
+        let expr = parser.expression().unwrap();
+        assert_eq!(
+            expr,
+            Expression::binary(
+                BinaryOperator::Or,
+                Expression::variable("a"),
+                Expression::binary(
+                    BinaryOperator::And,
+                    Expression::variable("b"),
+                    Expression::binary(
+                        BinaryOperator::Equal,
+                        Expression::variable("c"),
+                        Expression::variable("d")
+                    )
+                )
             )
         );
     }
@@ -301,16 +646,66 @@ mod tests {
             expr,
             Expression::binary(
                 BinaryOperator::Add,
-                Expression::number(1),
+                Expression::integer(1),
                 Expression::binary(
                     BinaryOperator::Mul,
-                    Expression::unary(UnaryOperator::Negate, Expression::number(2)),
-                    Expression::number(3)
+                    Expression::unary(UnaryOperator::Negate, Expression::integer(2)),
+                    Expression::integer(3)
                 )
             )
         );
     }
 
+    #[test]
+    fn power_priority() {
+        let mut parser = Parser::new(Lexer::new("-2 ** 3 ** 2 * 4"));
+        let expr = parser.expression().unwrap();
+        assert_eq!(
+            expr,
+            Expression::binary(
+                BinaryOperator::Mul,
+                Expression::unary(
+                    UnaryOperator::Negate,
+                    Expression::binary(
+                        BinaryOperator::Pow,
+                        Expression::integer(2),
+                        Expression::binary(
+                            BinaryOperator::Pow,
+                            Expression::integer(3),
+                            Expression::integer(2)
+                        )
+                    )
+                ),
+                Expression::integer(4)
+            )
+        );
+    }
+
+    #[test]
+    fn conditional_priority() {
+        let mut parser = Parser::new(Lexer::new("x = a || b ? 1 : c ? 2 : 3"));
+        let expr = parser.expression().unwrap();
+        assert_eq!(
+            expr,
+            Expression::assign(
+                Expression::variable("x"),
+                Expression::Conditional {
+                    condition: Box::new(Expression::binary(
+                        BinaryOperator::Or,
+                        Expression::variable("a"),
+                        Expression::variable("b")
+                    )),
+                    then: Box::new(Expression::integer(1)),
+                    otherwise: Box::new(Expression::Conditional {
+                        condition: Box::new(Expression::variable("c")),
+                        then: Box::new(Expression::integer(2)),
+                        otherwise: Box::new(Expression::integer(3)),
+                    }),
+                }
+            )
+        );
+    }
+
     #[test]
     fn grouping() {
         let mut parser = Parser::new(Lexer::new("(1 + 2) * 3"));
@@ -321,10 +716,10 @@ mod tests {
                 BinaryOperator::Mul,
                 Expression::binary(
                     BinaryOperator::Add,
-                    Expression::number(1),
-                    Expression::number(2)
+                    Expression::integer(1),
+                    Expression::integer(2)
                 ),
-                Expression::number(3)
+                Expression::integer(3)
             )
         );
     }
@@ -335,7 +730,7 @@ mod tests {
         let expr = parser.expression().unwrap();
         assert_eq!(
             expr,
-            Expression::AssignVariable("a".to_string(), Box::new(Expression::number(1)))
+            Expression::assign(Expression::variable("a"), Expression::integer(1))
         );
     }
 
@@ -347,7 +742,7 @@ mod tests {
             expr,
             Expression::Index {
                 array: Box::new(Expression::variable("a")),
-                index: Box::new(Expression::number(1))
+                index: Box::new(Expression::integer(1))
             }
         );
     }
@@ -358,11 +753,32 @@ mod tests {
         let expr = parser.expression().unwrap();
         assert_eq!(
             expr,
-            Expression::AssignIndexVariable {
-                variable: "a".to_string(),
-                index: Box::new(Expression::number(1)),
-                value: Box::new(Expression::number(2))
-            }
+            Expression::assign(
+                Expression::Index {
+                    array: Box::new(Expression::variable("a")),
+                    index: Box::new(Expression::integer(1))
+                },
+                Expression::integer(2)
+            )
+        );
+    }
+
+    #[test]
+    fn indexed_assignment_to_expression() {
+        let mut parser = Parser::new(Lexer::new("f()[0][1] = 2"));
+        let expr = parser.expression().unwrap();
+        assert_eq!(
+            expr,
+            Expression::assign(
+                Expression::Index {
+                    array: Box::new(Expression::Index {
+                        array: Box::new(Expression::call(Expression::variable("f"), vec![])),
+                        index: Box::new(Expression::integer(0))
+                    }),
+                    index: Box::new(Expression::integer(1))
+                },
+                Expression::integer(2)
+            )
         );
     }
 
@@ -370,7 +786,7 @@ mod tests {
     fn function_call() {
         let mut parser = Parser::new(Lexer::new("foo()"));
         let expr = parser.expression().unwrap();
-        assert_eq!(expr, Expression::FunctionCall("foo".to_string(), vec![]));
+        assert_eq!(expr, Expression::call(Expression::variable("foo"), vec![]));
     }
 
     #[test]
@@ -379,13 +795,38 @@ mod tests {
         let expr = parser.expression().unwrap();
         assert_eq!(
             expr,
-            Expression::FunctionCall(
-                "foo".to_string(),
-                vec![Expression::number(1), Expression::number(2)]
+            Expression::call(
+                Expression::variable("foo"),
+                vec![Expression::integer(1), Expression::integer(2)]
             )
         );
     }
 
+    #[test]
+    fn call_expressions() {
+        let mut parser = Parser::new(Lexer::new("handlers[0](1)()"));
+        let expr = parser.expression().unwrap();
+        assert_eq!(
+            expr,
+            Expression::Call(
+                Box::new(Expression::Call(
+                    Box::new(Expression::Index {
+                        array: Box::new(Expression::Variable("handlers".to_string())),
+                        index: Box::new(Expression::integer(0)),
+                    }),
+                    vec![Expression::integer(1)]
+                )),
+                vec![]
+            )
+        );
+
+        let mut parser = Parser::new(Lexer::new("1(2)"));
+        assert!(matches!(
+            parser.expression(),
+            Err(ParsingError::InvalidCall(_))
+        ));
+    }
+
     #[test]
     fn array_initialisation() {
         let mut parser = Parser::new(Lexer::new("[1; 5]"));
@@ -393,8 +834,8 @@ mod tests {
         assert_eq!(
             expr,
             Expression::Array {
-                size: Box::new(Expression::number(5)),
-                initial: Box::new(Expression::number(1))
+                size: Box::new(Expression::integer(5)),
+                initial: Box::new(Expression::integer(1))
             }
         );
     }
@@ -0,0 +1,451 @@
+//! Parsing of the source code changed by an editor.
+//!
+//! Editors change a few characters at a time, so most top-level statements stay as they were.
+//! The incremental parser keeps the parsed statements and parses again only the ones the change may affect.
+
+use crate::ast::{Expression, Program, Statement};
+use crate::lexer::token::Token;
+use crate::lexer::Lexer;
+use crate::parser::{Parser, ParsingError};
+use crate::source::{Position, Span};
+
+/// Parser keeping the top-level statements of the source code between edits.
+///
+/// # Examples
+///
+/// ```rust
+/// # use brainterpreter::parser::incremental::IncrementalParser;
+/// # use brainterpreter::source::Position;
+/// let mut parser = IncrementalParser::new("let a = 1;\nprint a;\nprint a + 1;");
+/// let reparsed = parser.edit(Position::new(2, 7), Position::new(2, 8), "2");
+///
+/// assert_eq!(parser.source(), "let a = 1;\nprint 2;\nprint a + 1;");
+/// assert_eq!(reparsed, 2);
+/// assert_eq!(parser.program().statements().len(), 3);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct IncrementalParser {
+    source: String,
+    items: Vec<Item>,
+}
+
+/// Top-level statement, or the error found in place of it.
+///
+/// The item starts where the previous one ends, so the comments before the statement belong to it.
+#[derive(Debug, Clone)]
+struct Item {
+    /// Position right after the last token of the statement.
+    end: Position,
+    statement: Result<Statement, ParsingError>,
+    /// Documentation comments collected by the parser wait for the next statement,
+    /// so the parsing cannot start over after the item.
+    doc_pending: bool,
+}
+
+impl IncrementalParser {
+    /// Parses the whole source code.
+    pub fn new(source: impl Into<String>) -> Self {
+        let mut parser = IncrementalParser {
+            source: source.into(),
+            items: vec![],
+        };
+        (parser.items, _) = parser.parse_from(Position::new(1, 1), |_| None);
+        parser
+    }
+
+    /// Source code with all the edits applied.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Statements parsed successfully, like [Parser::parse_program_with_recovery] returns them.
+    pub fn program(&self) -> Program {
+        Program::new(
+            self.items
+                .iter()
+                .filter_map(|item| item.statement.as_ref().ok())
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Errors found in place of the broken statements.
+    pub fn errors(&self) -> Vec<ParsingError> {
+        self.items
+            .iter()
+            .filter_map(|item| item.statement.as_ref().err())
+            .cloned()
+            .collect()
+    }
+
+    /// Replaces the source code from the start up to, but not including, the end position with the text.
+    ///
+    /// The statements before the change are kept, and the ones after it are moved to their new positions
+    /// once the parser gets back to the boundary between them.
+    /// Returns the number of the top-level statements parsed again.
+    pub fn edit(&mut self, start: Position, end: Position, text: &str) -> usize {
+        let range = offset(&self.source, start)..offset(&self.source, end);
+        self.source.replace_range(range, text);
+        let new_end = end_of_text(start, text);
+        let moved = |position: Position| moved(position, end, new_end);
+
+        // The statement before the changed one may go on, e.g. with the `else` branch typed after an `if`.
+        let mut first = self
+            .items
+            .iter()
+            .position(|item| item.end >= start)
+            .unwrap_or(self.items.len())
+            .saturating_sub(1);
+        while first > 0 && self.items[first - 1].doc_pending {
+            first -= 1;
+        }
+        let parse_start = match first {
+            0 => Position::new(1, 1),
+            first => self.items[first - 1].end,
+        };
+        let items = &self.items;
+        // The old items after the change are parsed the same once a new item ends where one of them starts.
+        let mut next = (1..items.len())
+            .find(|&i| items[i - 1].end >= end)
+            .unwrap_or(items.len());
+        let (parsed, resume) = self.parse_from(parse_start, |parsed_end| {
+            while next < items.len() && moved(items[next - 1].end) < parsed_end {
+                next += 1;
+            }
+            let aligned = next < items.len() && moved(items[next - 1].end) == parsed_end;
+            (aligned && !items[next - 1].doc_pending).then_some(next)
+        });
+
+        let reparsed = parsed.len();
+        let kept: Vec<Item> = match resume {
+            Some(resume) => self
+                .items
+                .drain(resume..)
+                .map(|item| item.moved(&moved))
+                .collect(),
+            None => vec![],
+        };
+        self.items.truncate(first);
+        self.items.extend(parsed);
+        self.items.extend(kept);
+        reparsed
+    }
+
+    /// Parses the statements from the position until the resume function tells which old item follows
+    /// the end of the statement parsed last.
+    ///
+    /// The resume function is only asked when no documentation comments wait for the next statement.
+    fn parse_from<F>(&self, start: Position, mut resume: F) -> (Vec<Item>, Option<usize>)
+    where
+        F: FnMut(Position) -> Option<usize>,
+    {
+        let source = &self.source[offset(&self.source, start)..];
+        let mut parser = Parser::new(Lexer::new(source).starting_at(start));
+        let mut items = vec![];
+        while parser.peek() != &Token::EndOfFile {
+            let first = parser.last_position();
+            let statement = match parser.located_statement() {
+                Ok(statement) => Ok(statement),
+                Err(error) => {
+                    parser.synchronize(first);
+                    Err(error)
+                }
+            };
+            let last = parser.previous_span().end();
+            let end = Position::new(last.line(), last.column() + 1);
+            let doc_pending = !parser.doc_comments.is_empty();
+            items.push(Item {
+                end,
+                statement,
+                doc_pending,
+            });
+            if doc_pending {
+                continue;
+            }
+            if let Some(next) = resume(end) {
+                return (items, Some(next));
+            }
+        }
+        (items, None)
+    }
+}
+
+impl Item {
+    fn moved(mut self, moved: &dyn Fn(Position) -> Position) -> Self {
+        self.end = moved(self.end);
+        match &mut self.statement {
+            Ok(statement) => move_statement(statement, moved),
+            Err(error) => move_span(error.span_mut(), moved),
+        }
+        self
+    }
+}
+
+/// Byte offset of the character at the position, or of the end of the line for the columns past it.
+fn offset(source: &str, position: Position) -> usize {
+    let line_start = match position.line() {
+        0 | 1 => 0,
+        line => source
+            .match_indices('\n')
+            .nth(line - 2)
+            .map_or(source.len(), |(i, _)| i + 1),
+    };
+    let line = &source[line_start..];
+    let line_len = line.find('\n').unwrap_or(line.len());
+    let column = line[..line_len]
+        .char_indices()
+        .nth(position.column().saturating_sub(1))
+        .map_or(line_len, |(i, _)| i);
+    line_start + column
+}
+
+/// Position right after the text inserted at the start.
+fn end_of_text(start: Position, text: &str) -> Position {
+    match text.rsplit_once('\n') {
+        Some((before, last)) => Position::new(
+            start.line() + before.matches('\n').count() + 1,
+            last.chars().count() + 1,
+        ),
+        None => Position::new(start.line(), start.column() + text.chars().count()),
+    }
+}
+
+/// Moves the position following the changed source code along with the end of the change.
+///
+/// Positions before the change stay, e.g. the empty span of the error at the end of the source.
+fn moved(position: Position, old_end: Position, new_end: Position) -> Position {
+    if position < old_end {
+        position
+    } else if position.line() == old_end.line() {
+        Position::new(
+            new_end.line(),
+            position.column() - old_end.column() + new_end.column(),
+        )
+    } else {
+        Position::new(
+            position.line() - old_end.line() + new_end.line(),
+            position.column(),
+        )
+    }
+}
+
+fn move_span(span: &mut Span, moved: &dyn Fn(Position) -> Position) {
+    *span = Span::new(moved(span.start()), moved(span.end()));
+}
+
+fn move_statement(statement: &mut Statement, moved: &dyn Fn(Position) -> Position) {
+    match statement {
+        Statement::Located(span, statement) => {
+            move_span(span, moved);
+            move_statement(statement, moved);
+        }
+        Statement::Expression(expr)
+        | Statement::DefineVariable(_, expr)
+        | Statement::DefineVariables(_, expr)
+        | Statement::DefineConstant(_, expr)
+        | Statement::Print(expr)
+        | Statement::Return(expr)
+        | Statement::Throw(expr) => move_expression(expr, moved),
+        Statement::Function { body, .. } => move_statement(body, moved),
+        Statement::Block(statements) => statements
+            .iter_mut()
+            .for_each(|statement| move_statement(statement, moved)),
+        Statement::If(condition, then, otherwise) => {
+            move_expression(condition, moved);
+            move_statement(then, moved);
+            if let Some(otherwise) = otherwise {
+                move_statement(otherwise, moved);
+            }
+        }
+        Statement::While(condition, body) | Statement::DoWhile(body, condition) => {
+            move_expression(condition, moved);
+            move_statement(body, moved);
+        }
+        Statement::For {
+            initializer,
+            condition,
+            increment,
+            body,
+        } => {
+            if let Some(initializer) = initializer {
+                move_statement(initializer, moved);
+            }
+            condition
+                .iter_mut()
+                .chain(increment.iter_mut())
+                .for_each(|expr| move_expression(expr, moved));
+            move_statement(body, moved);
+        }
+        Statement::ForIn {
+            collection, body, ..
+        } => {
+            move_expression(collection, moved);
+            move_statement(body, moved);
+        }
+        Statement::Try { body, handler, .. } => {
+            move_statement(body, moved);
+            move_statement(handler, moved);
+        }
+        Statement::Switch {
+            subject,
+            cases,
+            default,
+        } => {
+            move_expression(subject, moved);
+            for (value, body) in cases {
+                move_expression(value, moved);
+                move_statement(body, moved);
+            }
+            if let Some(default) = default {
+                move_statement(default, moved);
+            }
+        }
+        Statement::Assert { condition, message } => {
+            move_expression(condition, moved);
+            if let Some(message) = message {
+                move_expression(message, moved);
+            }
+        }
+        Statement::DeclareVariable(_) | Statement::Break | Statement::Continue => {}
+    }
+}
+
+/// Moves the statements of the functions and blocks inside the expression.
+fn move_expression(expression: &mut Expression, moved: &dyn Fn(Position) -> Position) {
+    match expression {
+        Expression::Lambda(_, body) => move_statement(body, moved),
+        Expression::Block { statements, value } => {
+            statements
+                .iter_mut()
+                .for_each(|statement| move_statement(statement, moved));
+            if let Some(value) = value {
+                move_expression(value, moved);
+            }
+        }
+        Expression::Index { array, index } => {
+            move_expression(array, moved);
+            move_expression(index, moved);
+        }
+        Expression::UnaryOperation(_, value) => move_expression(value, moved),
+        Expression::Assign { target, value } => {
+            move_expression(target, moved);
+            move_expression(value, moved);
+        }
+        Expression::Array { initial, size } => {
+            move_expression(initial, moved);
+            move_expression(size, moved);
+        }
+        Expression::Tuple(items) => items
+            .iter_mut()
+            .for_each(|item| move_expression(item, moved)),
+        Expression::Map(entries) => {
+            for (key, value) in entries {
+                move_expression(key, moved);
+                move_expression(value, moved);
+            }
+        }
+        Expression::Call(callee, args) => {
+            move_expression(callee, moved);
+            args.iter_mut().for_each(|arg| move_expression(arg, moved));
+        }
+        Expression::BinaryOperation(_, lhs, rhs)
+        | Expression::Range {
+            start: lhs,
+            end: rhs,
+        } => {
+            move_expression(lhs, moved);
+            move_expression(rhs, moved);
+        }
+        Expression::Conditional {
+            condition,
+            then,
+            otherwise,
+        } => {
+            move_expression(condition, moved);
+            move_expression(then, moved);
+            move_expression(otherwise, moved);
+        }
+        Expression::Nil
+        | Expression::NumberLiteral(_)
+        | Expression::IntegerLiteral(_)
+        | Expression::BooleanLiteral(_)
+        | Expression::StringLiteral(_)
+        | Expression::Variable(_)
+        | Expression::Update { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses the edited source from scratch to compare with the incremental result.
+    fn parse_again(parser: &IncrementalParser) -> (Program, Vec<ParsingError>) {
+        Parser::new(Lexer::new(parser.source())).parse_program_with_recovery()
+    }
+
+    #[test]
+    fn reuse_statements_around_the_edit() {
+        let source = "let a = 1;\nfun f(x) {\n    return x * 2;\n}\nprint f(a);\nprint a;\n";
+        let mut parser = IncrementalParser::new(source);
+
+        let reparsed = parser.edit(Position::new(3, 17), Position::new(3, 17), " + 1\n   ");
+
+        assert_eq!(reparsed, 2);
+        assert_eq!(
+            parser.source(),
+            "let a = 1;\nfun f(x) {\n    return x * 2 + 1\n   ;\n}\nprint f(a);\nprint a;\n"
+        );
+        assert_eq!((parser.program(), parser.errors()), parse_again(&parser));
+    }
+
+    #[test]
+    fn extend_the_statement_before_the_edit() {
+        let mut parser = IncrementalParser::new("if (true) print 1;\n\nprint 3;");
+
+        parser.edit(Position::new(2, 1), Position::new(2, 1), "else print 2;");
+
+        assert_eq!(parser.program().statements().len(), 2);
+        assert_eq!((parser.program(), parser.errors()), parse_again(&parser));
+    }
+
+    #[test]
+    fn recover_from_broken_edits() {
+        let mut parser = IncrementalParser::new("let a = 1;\nprint a;\nprint 2;");
+
+        parser.edit(Position::new(2, 7), Position::new(2, 8), "(");
+        assert_eq!(parser.errors().len(), 1);
+        assert_eq!((parser.program(), parser.errors()), parse_again(&parser));
+
+        parser.edit(Position::new(2, 8), Position::new(2, 8), "a)");
+        assert!(parser.errors().is_empty());
+        assert_eq!(parser.source(), "let a = 1;\nprint (a);\nprint 2;");
+        assert_eq!((parser.program(), parser.errors()), parse_again(&parser));
+    }
+
+    #[test]
+    fn keep_documentation_of_the_function_after_a_broken_statement() {
+        let mut parser = IncrementalParser::new("let a = 1; x \n/// doc\n}fun f() {}\nprint 1;");
+
+        parser.edit(Position::new(4, 7), Position::new(4, 8), "2");
+
+        let (program, errors) = parse_again(&parser);
+        assert_eq!(parser.program(), program);
+        assert_eq!(parser.errors(), errors);
+        assert!(matches!(
+            program.statements()[1].inner(),
+            Statement::Function { doc: Some(_), .. }
+        ));
+    }
+
+    #[test]
+    fn open_comment_hides_the_rest_of_the_source() {
+        let mut parser = IncrementalParser::new("print 1;\nprint 2;\nprint 3;");
+
+        let reparsed = parser.edit(Position::new(2, 1), Position::new(2, 1), "/* ");
+
+        assert_eq!(reparsed, 2);
+        assert_eq!(parser.errors()[0].code(), "unterminated-comment");
+        assert_eq!((parser.program(), parser.errors()), parse_again(&parser));
+    }
+}
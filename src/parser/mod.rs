@@ -7,10 +7,11 @@ use thiserror::Error;
 use crate::ast::Program;
 use crate::lexer::token::Token;
 use crate::lexer::SourceToken;
-use crate::source::Position;
+use crate::source::{Position, Span};
 
 mod advance;
 mod expression;
+pub mod incremental;
 mod statement;
 
 #[derive(Debug)]
@@ -19,30 +20,85 @@ where
     T: Iterator<Item = SourceToken>,
 {
     tokens: Peekable<T>,
+    doc_comments: Vec<String>,
+    previous: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Error)]
 pub enum ParsingError {
     #[error("error during parsing at {0}")]
-    Unknown(Position),
+    Unknown(Span),
     #[error("unexpected token `{0}` at {1}")]
-    UnexpectedToken(Token, Position),
-    #[error("{position}: expected `{expected}` but found `{actual}`")]
+    UnexpectedToken(Token, Span),
+    #[error("{span}: expected `{expected}` but found `{actual}`")]
     MissingToken {
-        position: Position,
+        span: Span,
         expected: Token,
         actual: Token,
     },
     #[error("missing operand at {0}")]
-    MissingOperand(Position),
+    MissingOperand(Span),
     #[error("unknown operation at {0}")]
-    UnknownOperation(Position),
+    UnknownOperation(Span),
     #[error("missing closing parentheses at {0}")]
-    MissingClosingParentheses(Position),
+    MissingClosingParentheses(Span),
     #[error("attempting to call uncallable object {0}")]
-    InvalidCall(Position),
+    InvalidCall(Span),
     #[error("attempting to assign to non-assignable object {0}")]
-    InvalidAssignment(Position),
+    InvalidAssignment(Span),
+    #[error("unterminated block comment at {0}")]
+    UnterminatedComment(Span),
+}
+
+impl ParsingError {
+    /// Stable identifier of the error kind.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParsingError::Unknown(_) => "parse-error",
+            ParsingError::UnexpectedToken(..) => "unexpected-token",
+            ParsingError::MissingToken { .. } => "missing-token",
+            ParsingError::MissingOperand(_) => "missing-operand",
+            ParsingError::UnknownOperation(_) => "unknown-operation",
+            ParsingError::MissingClosingParentheses(_) => "missing-closing-parentheses",
+            ParsingError::InvalidCall(_) => "invalid-call",
+            ParsingError::InvalidAssignment(_) => "invalid-assignment",
+            ParsingError::UnterminatedComment(_) => "unterminated-comment",
+        }
+    }
+
+    /// Position of the first character of the token the error occurred at.
+    pub fn position(&self) -> Position {
+        self.span().start()
+    }
+
+    /// Range of the source code with the token the error occurred at.
+    pub fn span(&self) -> Span {
+        match self {
+            ParsingError::Unknown(span)
+            | ParsingError::UnexpectedToken(_, span)
+            | ParsingError::MissingToken { span, .. }
+            | ParsingError::MissingOperand(span)
+            | ParsingError::UnknownOperation(span)
+            | ParsingError::MissingClosingParentheses(span)
+            | ParsingError::InvalidCall(span)
+            | ParsingError::InvalidAssignment(span)
+            | ParsingError::UnterminatedComment(span) => *span,
+        }
+    }
+
+    pub(crate) fn span_mut(&mut self) -> &mut Span {
+        match self {
+            ParsingError::Unknown(span)
+            | ParsingError::UnexpectedToken(_, span)
+            | ParsingError::MissingToken { span, .. }
+            | ParsingError::MissingOperand(span)
+            | ParsingError::UnknownOperation(span)
+            | ParsingError::MissingClosingParentheses(span)
+            | ParsingError::InvalidCall(span)
+            | ParsingError::InvalidAssignment(span)
+            | ParsingError::UnterminatedComment(span) => span,
+        }
+    }
 }
 
 impl<T> Parser<T>
@@ -52,14 +108,66 @@ where
     pub fn new(tokens: T) -> Self {
         Parser {
             tokens: tokens.peekable(),
+            doc_comments: vec![],
+            previous: Span::default(),
         }
     }
 
     pub fn parse_program(&mut self) -> Result<Program, ParsingError> {
         let mut program = Program::default();
-        while self.tokens.peek().is_some() {
-            program.add_statement(self.statement()?);
+        while self.peek() != &Token::EndOfFile {
+            program.add_statement(self.located_statement()?);
         }
         Ok(program)
     }
+
+    /// Parses the program, skipping broken statements to report all errors at once.
+    ///
+    /// The program contains only the statements parsed successfully.
+    pub fn parse_program_with_recovery(&mut self) -> (Program, Vec<ParsingError>) {
+        let mut program = Program::default();
+        let mut errors = vec![];
+        while self.peek() != &Token::EndOfFile {
+            let start = self.last_position();
+            match self.located_statement() {
+                Ok(statement) => program.add_statement(statement),
+                Err(error) => {
+                    errors.push(error);
+                    self.synchronize(start);
+                }
+            }
+        }
+        (program, errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    #[test]
+    fn recover_from_parsing_errors() {
+        let source = "let = 1;\nprint 2;\nprint (3;\nprint 4 4;\nlet a = 5;";
+        let mut parser = Parser::new(Lexer::new(source));
+
+        let (program, errors) = parser.parse_program_with_recovery();
+
+        assert_eq!(program.statements().len(), 2);
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].code(), "missing-token");
+    }
+
+    #[test]
+    fn report_unterminated_comment() {
+        for source in ["print 1;\n/* open", "print 1 /* open"] {
+            let error = Parser::new(Lexer::new(source)).parse_program().unwrap_err();
+
+            assert_eq!(error.code(), "unterminated-comment", "{}", source);
+        }
+        let error = Parser::new(Lexer::new("print 1;\n/* open"))
+            .parse_program()
+            .unwrap_err();
+        assert_eq!(error.position(), Position::new(2, 1));
+    }
 }
@@ -1,6 +1,6 @@
 use log::trace;
 
-use crate::ast::Statement;
+use crate::ast::{Expression, Statement};
 use crate::lexer::token::Token;
 use crate::lexer::SourceToken;
 use crate::parser::{Parser, ParsingError};
@@ -10,34 +10,68 @@ where
     T: Iterator<Item = SourceToken>,
 {
     pub fn statement(&mut self) -> Result<Statement, ParsingError> {
-        if let Token::Identifier(_) = self.peek() {
+        if let Token::Identifier(_) | Token::PlusPlus | Token::MinusMinus = self.peek() {
+            self.take_doc_comments();
             return self.expression_statement();
         }
+        let doc = self.take_doc_comments();
         match self.advance() {
             Token::Print => self.print_statement(),
             Token::LeftCurly => self.block_statement(),
             Token::Let => self.variable_definition(),
-            Token::Fun => self.function_definition(),
+            Token::Const => self.constant_definition(),
+            Token::Fun => self.function_definition(doc),
             Token::If => self.if_statement(),
             Token::While => self.while_statement(),
+            Token::Do => self.do_while_statement(),
+            Token::For => self.for_statement(),
+            Token::Break => {
+                self.consume(&Token::Semicolon)?;
+                Ok(Statement::Break)
+            }
+            Token::Continue => {
+                self.consume(&Token::Semicolon)?;
+                Ok(Statement::Continue)
+            }
+            Token::Throw => {
+                let expr = self.expression()?;
+                self.consume(&Token::Semicolon)?;
+                Ok(Statement::Throw(expr))
+            }
+            Token::Try => self.try_statement(),
+            Token::Switch => self.switch_statement(),
+            Token::Assert => self.assert_statement(),
             Token::Return => {
                 let expr = self.expression()?;
                 self.consume(&Token::Semicolon)?;
                 Ok(Statement::Return(expr))
             }
-            _ => Err(ParsingError::Unknown(self.last_position())),
+            Token::UnterminatedComment => {
+                Err(ParsingError::UnterminatedComment(self.previous_span()))
+            }
+            _ => Err(ParsingError::Unknown(self.previous_span())),
         }
     }
 
+    /// Parses statement and annotates it with the source position.
+    pub fn located_statement(&mut self) -> Result<Statement, ParsingError> {
+        let span = self.last_span();
+        let statement = self.statement()?;
+        Ok(Statement::located(span, statement))
+    }
+
     fn variable_definition(&mut self) -> Result<Statement, ParsingError> {
         trace!("Parsing variable declaration");
+        if self.advance_if(Token::LeftParen) {
+            return self.variables_definition();
+        }
         let token = self.advance();
         trace!("Variable declaration token: {:?}", token);
         let name = match token {
             Token::Identifier(name) => name,
             _ => {
                 return Err(ParsingError::MissingToken {
-                    position: self.last_position(),
+                    span: self.previous_span(),
                     expected: Token::Identifier("identifier".to_string()),
                     actual: token.clone(),
                 })
@@ -54,7 +88,47 @@ where
         def
     }
 
-    fn function_definition(&mut self) -> Result<Statement, ParsingError> {
+    /// Parses the rest of `let (a, b) = value;` after the opening parenthesis.
+    fn variables_definition(&mut self) -> Result<Statement, ParsingError> {
+        let mut names = vec![];
+        loop {
+            let token = self.advance();
+            let Token::Identifier(name) = token else {
+                return Err(ParsingError::MissingToken {
+                    span: self.previous_span(),
+                    expected: Token::Identifier("identifier".to_string()),
+                    actual: token.clone(),
+                });
+            };
+            names.push(name);
+            if !self.advance_if(Token::Comma) {
+                break;
+            }
+        }
+        self.consume(&Token::RightParen)?;
+        self.consume(&Token::Equal)?;
+        let value = self.expression()?;
+        self.consume(&Token::Semicolon)?;
+        Ok(Statement::DefineVariables(names, value))
+    }
+
+    fn constant_definition(&mut self) -> Result<Statement, ParsingError> {
+        trace!("Parsing constant definition");
+        let token = self.advance();
+        let Token::Identifier(name) = token else {
+            return Err(ParsingError::MissingToken {
+                span: self.previous_span(),
+                expected: Token::Identifier("identifier".to_string()),
+                actual: token.clone(),
+            });
+        };
+        self.consume(&Token::Equal)?;
+        let value = self.expression()?;
+        self.consume(&Token::Semicolon)?;
+        Ok(Statement::DefineConstant(name, value))
+    }
+
+    fn function_definition(&mut self, doc: Option<String>) -> Result<Statement, ParsingError> {
         trace!("Parsing function declaration");
         let token = self.advance();
         trace!("Function declaration token: {:?}", token);
@@ -63,11 +137,24 @@ where
             _ => {
                 return Err(ParsingError::UnexpectedToken(
                     token.clone(),
-                    self.last_position(),
+                    self.previous_span(),
                 ))
             }
         };
 
+        let (parameters, body) = self.function_parameters_and_body()?;
+        Ok(Statement::Function {
+            name,
+            params: parameters,
+            body: Box::new(body),
+            doc,
+        })
+    }
+
+    /// Parses the parameter list and the body of a named or anonymous function.
+    pub(super) fn function_parameters_and_body(
+        &mut self,
+    ) -> Result<(Vec<String>, Statement), ParsingError> {
         let mut parameters = vec![];
         self.consume(&Token::LeftParen)?;
         if let Token::Identifier(name) = self.peek() {
@@ -83,7 +170,7 @@ where
         self.consume(&Token::RightParen)?;
         self.consume(&Token::LeftCurly)?;
         let body = self.block_statement()?;
-        Ok(Statement::Function(name, parameters, Box::new(body)))
+        Ok((parameters, body))
     }
 
     fn block_statement(&mut self) -> Result<Statement, ParsingError> {
@@ -96,7 +183,7 @@ where
                 }
                 _ => {}
             }
-            statements.push(self.statement()?);
+            statements.push(self.located_statement()?);
         }
         self.consume(&Token::RightCurly)?;
         Ok(Statement::Block(statements))
@@ -116,6 +203,84 @@ where
         Ok(Statement::If(condition, Box::new(then_branch), else_branch))
     }
 
+    fn try_statement(&mut self) -> Result<Statement, ParsingError> {
+        trace!("Parsing try statement");
+        self.consume(&Token::LeftCurly)?;
+        let body = self.block_statement()?;
+        self.consume(&Token::Catch)?;
+        self.consume(&Token::LeftParen)?;
+        let variable = match self.advance() {
+            Token::Identifier(name) => name,
+            token => {
+                return Err(ParsingError::MissingToken {
+                    span: self.previous_span(),
+                    expected: Token::Identifier("identifier".to_string()),
+                    actual: token,
+                })
+            }
+        };
+        self.consume(&Token::RightParen)?;
+        self.consume(&Token::LeftCurly)?;
+        let handler = self.block_statement()?;
+        Ok(Statement::Try {
+            body: Box::new(body),
+            variable,
+            handler: Box::new(handler),
+        })
+    }
+
+    fn switch_statement(&mut self) -> Result<Statement, ParsingError> {
+        trace!("Parsing switch statement");
+        self.consume(&Token::LeftParen)?;
+        let subject = self.expression()?;
+        self.consume(&Token::RightParen)?;
+        self.consume(&Token::LeftCurly)?;
+        let mut cases = vec![];
+        while self.advance_if(Token::Case) {
+            let value = self.expression()?;
+            self.consume(&Token::Colon)?;
+            cases.push((value, self.case_body()?));
+        }
+        let default = if self.advance_if(Token::Default) {
+            self.consume(&Token::Colon)?;
+            Some(Box::new(self.case_body()?))
+        } else {
+            None
+        };
+        self.consume(&Token::RightCurly)?;
+        Ok(Statement::Switch {
+            subject,
+            cases,
+            default,
+        })
+    }
+
+    fn assert_statement(&mut self) -> Result<Statement, ParsingError> {
+        trace!("Parsing assert statement");
+        self.consume(&Token::LeftParen)?;
+        let condition = self.expression()?;
+        let message = if self.advance_if(Token::Comma) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(&Token::RightParen)?;
+        self.consume(&Token::Semicolon)?;
+        Ok(Statement::Assert { condition, message })
+    }
+
+    /// Parses the statements of a switch case up to the next case or the end of the switch.
+    fn case_body(&mut self) -> Result<Statement, ParsingError> {
+        let mut statements = Vec::new();
+        while !matches!(
+            self.peek(),
+            Token::Case | Token::Default | Token::RightCurly | Token::EndOfFile
+        ) {
+            statements.push(self.located_statement()?);
+        }
+        Ok(Statement::Block(statements))
+    }
+
     fn while_statement(&mut self) -> Result<Statement, ParsingError> {
         trace!("Parsing while statement");
         self.consume(&Token::LeftParen)?;
@@ -125,8 +290,71 @@ where
         Ok(Statement::While(condition, Box::new(body)))
     }
 
+    fn do_while_statement(&mut self) -> Result<Statement, ParsingError> {
+        trace!("Parsing do-while statement");
+        let body = self.statement()?;
+        self.consume(&Token::While)?;
+        self.consume(&Token::LeftParen)?;
+        let condition = self.expression()?;
+        self.consume(&Token::RightParen)?;
+        self.consume(&Token::Semicolon)?;
+        Ok(Statement::DoWhile(Box::new(body), condition))
+    }
+
+    fn for_statement(&mut self) -> Result<Statement, ParsingError> {
+        trace!("Parsing for statement");
+        self.consume(&Token::LeftParen)?;
+        let initializer = if self.advance_if(Token::Semicolon) {
+            None
+        } else if self.advance_if(Token::Let) {
+            Some(self.variable_definition()?)
+        } else {
+            let expression = self.expression()?;
+            if let Expression::Variable(variable) = &expression {
+                if self.advance_if(Token::In) {
+                    return self.for_in_statement(variable.clone());
+                }
+            }
+            self.consume(&Token::Semicolon)?;
+            Some(Statement::Expression(expression))
+        };
+        let condition = if self.peek() == &Token::Semicolon {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(&Token::Semicolon)?;
+        let increment = if self.peek() == &Token::RightParen {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(&Token::RightParen)?;
+        let body = self.statement()?;
+        Ok(Statement::for_loop(initializer, condition, increment, body))
+    }
+
+    /// Parses the rest of the `for (variable in collection) body` loop after the `in` keyword.
+    fn for_in_statement(&mut self, variable: String) -> Result<Statement, ParsingError> {
+        trace!("Parsing for-in statement");
+        let collection = self.expression()?;
+        self.consume(&Token::RightParen)?;
+        let body = self.statement()?;
+        Ok(Statement::ForIn {
+            variable,
+            collection,
+            body: Box::new(body),
+        })
+    }
+
     fn print_statement(&mut self) -> Result<Statement, ParsingError> {
         trace!("Parsing print statement");
+        // A parenthesis right after the keyword starts the arguments of the print function.
+        if self.advance_if(Token::LeftParen) {
+            let call = Expression::call(Expression::variable("print"), self.arguments()?);
+            self.consume(&Token::Semicolon)?;
+            return Ok(Statement::Expression(call));
+        }
         let expr = self.expression()?;
         self.consume(&Token::Semicolon)?;
         Ok(Statement::Print(expr))
@@ -160,9 +388,9 @@ mod tests {
         let statement = parser.statement().unwrap();
         assert_eq!(
             statement,
-            Statement::Expression(Expression::AssignVariable(
-                "a".to_string(),
-                Box::new(Expression::number(1))
+            Statement::Expression(Expression::assign(
+                Expression::variable("a"),
+                Expression::integer(1)
             ))
         );
     }
@@ -173,8 +401,22 @@ mod tests {
         let statement = parser.statement().unwrap();
         assert_eq!(
             statement,
-            Statement::DefineVariable("a".to_string(), Expression::number(1))
+            Statement::DefineVariable("a".to_string(), Expression::integer(1))
+        );
+    }
+
+    #[test]
+    fn variables_definition() {
+        let mut parser = Parser::new(Lexer::new("let (a, b) = (1, 2); let (a, 1) = t;"));
+
+        assert_eq!(
+            parser.statement().unwrap(),
+            Statement::DefineVariables(
+                vec!["a".to_string(), "b".to_string()],
+                Expression::Tuple(vec![Expression::integer(1), Expression::integer(2)])
+            )
         );
+        assert!(parser.statement().is_err());
     }
 
     #[test]
@@ -197,6 +439,101 @@ mod tests {
         );
     }
 
+    #[test]
+    fn documented_function_definition() {
+        let mut parser = Parser::new(Lexer::new(
+            "/// Does nothing.
+            /// Really.
+            fun a() {}",
+        ));
+        let statement = parser.statement().unwrap();
+        assert_eq!(
+            statement,
+            Statement::documented_function(
+                "a",
+                &[],
+                Statement::Block(vec![]),
+                "Does nothing.\nReally."
+            )
+        );
+    }
+
+    #[test]
+    fn try_statement() {
+        let mut parser = Parser::new(Lexer::new("try { throw 1; } catch (e) { }"));
+        let statement = parser.statement().unwrap();
+        let Statement::Try {
+            body,
+            variable,
+            handler,
+        } = statement
+        else {
+            panic!("try statement expected");
+        };
+        assert_eq!(variable, "e");
+        let Statement::Block(body) = *body else {
+            panic!("block expected");
+        };
+        assert_eq!(body[0].inner(), &Statement::Throw(Expression::integer(1)));
+        assert_eq!(*handler, Statement::Block(vec![]));
+    }
+
+    #[test]
+    fn switch_statement() {
+        let mut parser = Parser::new(Lexer::new(
+            "switch (a) { case 1: print 1; print 2; case \"x\": default: print 3; }",
+        ));
+        let statement = parser.statement().unwrap();
+        let Statement::Switch {
+            subject,
+            cases,
+            default,
+        } = statement
+        else {
+            panic!("switch statement expected");
+        };
+        assert_eq!(subject, Expression::variable("a"));
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].0, Expression::integer(1));
+        assert!(matches!(&cases[0].1, Statement::Block(body) if body.len() == 2));
+        assert_eq!(cases[1].1, Statement::Block(vec![]));
+        let Some(default) = default else {
+            panic!("default case expected");
+        };
+        assert!(matches!(*default, Statement::Block(body) if body.len() == 1));
+    }
+
+    #[test]
+    fn constant_definition() {
+        let mut parser = Parser::new(Lexer::new("const MAX = 10; const X;"));
+
+        assert_eq!(
+            parser.statement().unwrap(),
+            Statement::DefineConstant("MAX".to_string(), Expression::integer(10))
+        );
+        assert!(parser.statement().is_err());
+    }
+
+    #[test]
+    fn assert_statement() {
+        let mut parser = Parser::new(Lexer::new("assert(a, \"a is set\"); assert(b);"));
+
+        assert_eq!(
+            parser.statement().unwrap(),
+            Statement::Assert {
+                condition: Expression::variable("a"),
+                message: Some(Expression::StringLiteral("a is set".to_string())),
+            }
+        );
+        assert_eq!(
+            parser.statement().unwrap(),
+            Statement::Assert {
+                condition: Expression::variable("b"),
+                message: None,
+            }
+        );
+    }
+
     #[test]
     fn if_statement() {
         let mut parser = Parser::new(Lexer::new("if (a == 10) { }"));
@@ -207,7 +544,7 @@ mod tests {
                 Expression::binary(
                     BinaryOperator::Equal,
                     Expression::variable("a"),
-                    Expression::number(10)
+                    Expression::integer(10)
                 ),
                 Statement::Block(vec![]),
             )
@@ -224,7 +561,7 @@ mod tests {
                 Expression::binary(
                     BinaryOperator::Equal,
                     Expression::variable("a"),
-                    Expression::number(10)
+                    Expression::integer(10)
                 ),
                 Statement::Block(vec![]),
                 Statement::Block(vec![])
@@ -242,17 +579,122 @@ mod tests {
                 Expression::binary(
                     BinaryOperator::Greater,
                     Expression::variable("i"),
-                    Expression::number(0)
+                    Expression::integer(0)
                 ),
                 Statement::Block(vec![])
             )
         );
     }
 
+    #[test]
+    fn for_in_statement() {
+        let mut parser = Parser::new(Lexer::new("for (x in items) print x; for (x in; ;) {}"));
+
+        assert_eq!(
+            parser.statement().unwrap(),
+            Statement::ForIn {
+                variable: "x".to_string(),
+                collection: Expression::variable("items"),
+                body: Box::new(Statement::Print(Expression::variable("x"))),
+            }
+        );
+        assert!(parser.statement().is_err());
+    }
+
+    #[test]
+    fn for_statement() {
+        let mut parser = Parser::new(Lexer::new("for (let i = 0; i < 3; i = i + 1) { }"));
+        let statement = parser.statement().unwrap();
+        assert_eq!(
+            statement,
+            Statement::for_loop(
+                Some(Statement::DefineVariable(
+                    "i".to_string(),
+                    Expression::integer(0)
+                )),
+                Some(Expression::binary(
+                    BinaryOperator::Less,
+                    Expression::variable("i"),
+                    Expression::integer(3)
+                )),
+                Some(Expression::assign(
+                    Expression::variable("i"),
+                    Expression::binary(
+                        BinaryOperator::Add,
+                        Expression::variable("i"),
+                        Expression::integer(1)
+                    )
+                )),
+                Statement::Block(vec![])
+            )
+        );
+    }
+
+    #[test]
+    fn for_statement_without_clauses() {
+        let mut parser = Parser::new(Lexer::new("for (;;) { }"));
+        let statement = parser.statement().unwrap();
+        assert_eq!(
+            statement,
+            Statement::for_loop(None, None, None, Statement::Block(vec![]))
+        );
+    }
+
+    #[test]
+    fn loop_control_statements() {
+        let mut parser = Parser::new(Lexer::new("while (true) { break; continue; }"));
+        let statement = parser.statement().unwrap();
+        let Statement::While(_, body) = statement else {
+            panic!("while statement expected");
+        };
+        let Statement::Block(statements) = *body else {
+            panic!("block expected");
+        };
+        let statements: Vec<&Statement> = statements.iter().map(Statement::inner).collect();
+        assert_eq!(statements, vec![&Statement::Break, &Statement::Continue]);
+    }
+
+    #[test]
+    fn do_while_statement() {
+        let mut parser = Parser::new(Lexer::new("do x--; while (x > 0);"));
+        let statement = parser.statement().unwrap();
+        let Statement::DoWhile(body, condition) = statement else {
+            panic!("do-while statement expected");
+        };
+        assert!(matches!(body.inner(), Statement::Expression(_)));
+        assert!(matches!(condition, Expression::BinaryOperation(..)));
+
+        let mut parser = Parser::new(Lexer::new("do {} while (true)"));
+        assert!(parser.statement().is_err());
+    }
+
     #[test]
     fn print_statement() {
         let mut parser = Parser::new(Lexer::new("print 1;"));
         let statement = parser.statement().unwrap();
-        assert_eq!(statement, Statement::Print(Expression::number(1)));
+        assert_eq!(statement, Statement::Print(Expression::integer(1)));
+    }
+
+    #[test]
+    fn print_several_values() {
+        let mut parser = Parser::new(Lexer::new("print(1, x);"));
+        let statement = parser.statement().unwrap();
+        assert_eq!(
+            statement,
+            Statement::Expression(Expression::call(
+                Expression::variable("print"),
+                vec![Expression::integer(1), Expression::variable("x")]
+            ))
+        );
+    }
+
+    #[test]
+    fn print_without_values() {
+        let mut parser = Parser::new(Lexer::new("print();"));
+        let statement = parser.statement().unwrap();
+        assert_eq!(
+            statement,
+            Statement::Expression(Expression::call(Expression::variable("print"), vec![]))
+        );
     }
 }
@@ -1,8 +1,14 @@
-//! Keeps track of source file positions in interpreter.
+//! Keeps track of source file positions in interpreter and feeds the source code to the lexer.
 
 use std::fmt::Display;
+use std::io::{self, BufRead, BufReader, Read};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Line and column of a character in the source code, both starting from one.
+///
+/// Positions are ordered as they appear in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Position {
     line: usize,
     column: usize,
@@ -34,6 +40,153 @@ impl Display for Position {
     }
 }
 
+/// Range of the source code between two positions, both inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    start: Position,
+    end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Span { start, end }
+    }
+
+    /// Position of the first character of the span.
+    pub fn start(&self) -> Position {
+        self.start
+    }
+
+    /// Position of the last character of the span.
+    pub fn end(&self) -> Position {
+        self.end
+    }
+}
+
+impl From<Position> for Span {
+    fn from(position: Position) -> Self {
+        Span::new(position, position)
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else if self.start.line == self.end.line {
+            write!(
+                f,
+                "[{}:{}-{}]",
+                self.start.line, self.start.column, self.end.column
+            )
+        } else {
+            write!(
+                f,
+                "[{}:{}-{}:{}]",
+                self.start.line, self.start.column, self.end.line, self.end.column
+            )
+        }
+    }
+}
+
+/// Source code read by the lexer one character at a time.
+///
+/// Lets the lexer work over sources that are not in memory as a whole,
+/// like the standard input, sockets or huge files.
+pub trait CharSource {
+    /// Returns the next character of the source, or `None` when it is exhausted.
+    fn next_char(&mut self) -> Option<char>;
+
+    /// Takes the error that cut the source short, if any.
+    fn take_error(&mut self) -> Option<io::Error> {
+        None
+    }
+}
+
+impl CharSource for std::str::Chars<'_> {
+    fn next_char(&mut self) -> Option<char> {
+        self.next()
+    }
+}
+
+/// Source decoding UTF-8 characters from a reader as the lexer asks for them.
+///
+/// Malformed sequences are replaced with `U+FFFD`.
+/// A read failure ends the source and is kept until taken with [`CharSource::take_error`].
+#[derive(Debug)]
+pub struct ReadSource<R> {
+    reader: BufReader<R>,
+    error: Option<io::Error>,
+    failed: bool,
+}
+
+impl<R: Read> ReadSource<R> {
+    pub fn new(reader: R) -> Self {
+        ReadSource {
+            reader: BufReader::new(reader),
+            error: None,
+            failed: false,
+        }
+    }
+
+    fn peek_byte(&mut self) -> Option<u8> {
+        loop {
+            match self.reader.fill_buf() {
+                Ok(buf) => return buf.first().copied(),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.error = Some(e);
+                    self.failed = true;
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn next_byte(&mut self) -> Option<u8> {
+        let byte = self.peek_byte()?;
+        self.reader.consume(1);
+        Some(byte)
+    }
+}
+
+impl<R: Read> CharSource for ReadSource<R> {
+    fn next_char(&mut self) -> Option<char> {
+        if self.failed {
+            return None;
+        }
+        let first = self.next_byte()?;
+        let len = match first {
+            0x00..=0x7F => return Some(first as char),
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => return Some(char::REPLACEMENT_CHARACTER),
+        };
+        let mut bytes = [first, 0, 0, 0];
+        for byte in bytes.iter_mut().take(len).skip(1) {
+            match self.peek_byte() {
+                Some(b) if b & 0xC0 == 0x80 => {
+                    self.reader.consume(1);
+                    *byte = b;
+                }
+                _ => return Some(char::REPLACEMENT_CHARACTER),
+            }
+        }
+        let c = std::str::from_utf8(&bytes[..len])
+            .ok()
+            .and_then(|s| s.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+        Some(c)
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -45,4 +198,26 @@ mod tests {
         assert_eq!(pos.column(), 2);
         assert_eq!(format!("{}", pos), "[1:2]");
     }
+
+    #[test]
+    fn display_span() {
+        let point = Span::from(Position::new(1, 2));
+        assert_eq!(point.to_string(), "[1:2]");
+        let token = Span::new(Position::new(1, 2), Position::new(1, 4));
+        assert_eq!(token.to_string(), "[1:2-4]");
+        let lines = Span::new(Position::new(1, 2), Position::new(3, 1));
+        assert_eq!(lines.to_string(), "[1:2-3:1]");
+    }
+
+    #[test]
+    fn decode_characters_from_reader() {
+        let bytes: &[u8] = b"a\xC3\xA9\xE2\x82\xAC\xF0\x9F\x98\x80\xC3z\xFF";
+        let mut source = ReadSource::new(bytes);
+        let chars: Vec<char> = std::iter::from_fn(|| source.next_char()).collect();
+        assert_eq!(
+            chars,
+            vec!['a', 'é', '€', '😀', '\u{FFFD}', 'z', '\u{FFFD}']
+        );
+        assert!(source.take_error().is_none());
+    }
 }
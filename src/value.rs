@@ -1,6 +1,7 @@
 //! Different values natively supported by the virtual machine
 
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Display};
 use std::rc::Rc;
 
@@ -9,17 +10,40 @@ use thiserror::Error;
 use crate::vm::exec::Chunk;
 use crate::vm::{Vm, VmRuntimeError};
 
+/// Value the virtual machine operates on.
+///
+/// With the `serde` feature enabled, the values can be serialized, except for the functions,
+/// which fail to serialize.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ValueType {
     Nil,
     Bool(bool),
     Number(f64),
+    /// Whole number, e.g. a literal written without the fraction.
+    Int(i64),
     Address(usize),
-    Text(Box<String>),
-    Function(Box<Function>),
+    /// Immutable string, shared by the copies of the value.
+    Text(Rc<String>),
+    /// Function shared by the values referencing it, so loading and calling it does not copy it.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Function(Rc<Function>),
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Closure(Rc<Closure>),
+    #[cfg_attr(feature = "serde", serde(skip))]
     NativeFunction(Rc<NativeFunction>),
-    Array(Box<Vec<ValueType>>),
+    /// Array shared by all the variables referencing it, so changing its elements changes it for all of them.
     ArrayRef(Rc<RefCell<Vec<ValueType>>>),
+    /// Numbers from the start up to, but not including, the end, counting by one.
+    /// The bounds are boxed, so the value takes 16 bytes like the others.
+    Range(Box<(f64, f64)>),
+    /// Fixed group of values, e.g. the results of a function.
+    Tuple(Box<Vec<ValueType>>),
+    /// Dictionary with string keys, shared by all the variables referencing it.
+    Map(Rc<RefCell<HashMap<String, ValueType>>>),
+    /// Failure reported by a native function, e.g. on the arguments of a wrong type.
+    /// Programs check for it with `is_error` instead of being stopped.
+    Error(Box<String>),
 }
 
 #[derive(Debug, Error)]
@@ -34,6 +58,14 @@ pub enum TypeError {
     UnsupportedArrayType(ValueType),
     #[error("array does not support value of type `{0}`")]
     UnsupportedArrayValueType(ValueType),
+    #[error("elements of {0} cannot be changed")]
+    Immutable(ValueType),
+    #[error("only strings can be map keys. {0} cannot be used as a key")]
+    InvalidKeyType(ValueType),
+    #[error("expected a number but found {0}")]
+    ExpectedNumber(ValueType),
+    #[error("only arrays, ranges, strings, and maps can be iterated. {0} cannot be iterated")]
+    NotIterable(ValueType),
 }
 
 #[derive(Debug, Clone)]
@@ -41,13 +73,51 @@ pub struct Function {
     name: String,
     chunk: Rc<Chunk>,
     arity: usize,
+    captures: Vec<Capture>,
 }
 
+/// Describes where a closure takes a captured variable from when it is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    /// Local variable of the enclosing function.
+    Local(usize),
+    /// Variable the enclosing function captured itself.
+    Upvalue(usize),
+}
+
+/// Variable captured by a closure.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Upvalue {
+    /// The variable is still on the stack at the given slot.
+    Open(usize),
+    /// The variable left the stack, so the closure owns its value.
+    Closed(ValueType),
+}
+
+/// Function together with the variables it captured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Closure {
+    function: Rc<Function>,
+    upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+/// Signature of the host functions callable from the programs.
+///
+/// The function receives the call arguments and returns the result of the call.
+/// Invalid arguments are reported with the [ValueType::Error] result, so the program can handle them.
+/// Returning [VmRuntimeError] stops the program.
+pub type NativeFn = dyn Fn(&mut Vm, &[ValueType]) -> Result<ValueType, VmRuntimeError>;
+
+/// Function implemented by the host application.
 #[derive(Clone)]
 pub struct NativeFunction {
     name: String,
     arity: usize,
-    function: fn(&mut Vm) -> Result<(), VmRuntimeError>,
+    /// Number of the last parameters the calls may omit.
+    optional: usize,
+    /// Whether the calls may pass more arguments than the arity.
+    variadic: bool,
+    function: Rc<NativeFn>,
 }
 
 impl ValueType {
@@ -60,6 +130,9 @@ impl ValueType {
                 }
                 Ok(idx as usize)
             }
+            ValueType::Int(idx) => {
+                usize::try_from(*idx).map_err(|_| TypeError::IncorrectIndex(*idx as f64))
+            }
             _ => Err(TypeError::InvalidIndexType(self.clone())),
         }
     }
@@ -68,11 +141,9 @@ impl ValueType {
         match self {
             ValueType::Text(s) => {
                 let idx = self.index_in_bounds(index.index()?)?;
-                Ok(ValueType::Text(Box::new(
-                    s.chars().nth(idx).unwrap().to_string(),
-                )))
+                Ok(ValueType::string(s.chars().nth(idx).unwrap()))
             }
-            ValueType::Array(arr) => {
+            ValueType::Tuple(arr) => {
                 let idx = self.index_in_bounds(index.index()?)?;
                 Ok(arr[idx].clone())
             }
@@ -80,37 +151,75 @@ impl ValueType {
                 let idx = self.index_in_bounds(index.index()?)?;
                 Ok(arr.borrow()[idx].clone())
             }
+            // Missing keys are read as nil.
+            ValueType::Map(map) => Ok(map
+                .borrow()
+                .get(index.key()?)
+                .cloned()
+                .unwrap_or(ValueType::Nil)),
             _ => Err(TypeError::UnsupportedArrayType(self.clone())),
         }
     }
 
+    /// Changes the element of the array or the entry of the map in place,
+    /// so every value referencing the same array or map sees the change.
+    ///
+    /// Returns the changed value. Strings cannot change, so the result is a new string
+    /// with the character replaced.
     pub fn set(&self, index: &ValueType, value: ValueType) -> Result<ValueType, TypeError> {
         match (self, &value) {
             (ValueType::Text(s), ValueType::Text(v)) => {
                 let idx = self.index_in_bounds(index.index()?)?;
-                let mut s = s.clone();
-                s.replace_range(idx..idx + 1, v);
-                Ok(ValueType::Text(s))
+                // Strings are indexed by characters, so find the bytes of the character to replace.
+                let (start, c) = s.char_indices().nth(idx).unwrap();
+                let mut s = s.to_string();
+                s.replace_range(start..start + c.len_utf8(), v);
+                Ok(ValueType::string(s))
             }
-            (ValueType::Array(arr), v) => {
+            (ValueType::ArrayRef(arr), _) => {
                 let idx = self.index_in_bounds(index.index()?)?;
-                let mut arr = arr.clone();
-                arr[idx] = v.clone();
-                Ok(ValueType::Array(arr))
+                arr.borrow_mut()[idx] = value;
+                Ok(self.clone())
             }
-            (ValueType::ArrayRef(arr), v) => {
-                let idx = self.index_in_bounds(index.index()?)?;
-                arr.borrow_mut()[idx] = v.clone();
+            (ValueType::Map(map), _) => {
+                map.borrow_mut().insert(index.key()?.to_string(), value);
                 Ok(self.clone())
             }
             (ValueType::Text(_), _) => Err(TypeError::UnsupportedArrayValueType(value)),
+            (ValueType::Tuple(_), _) => Err(TypeError::Immutable(self.clone())),
             _ => Err(TypeError::UnsupportedArrayType(self.clone())),
         }
     }
 
+    /// Returns the array or the range of values a `for` loop goes over.
+    /// Arrays and ranges are iterated as they are, strings by characters, and maps by keys in sorted order.
+    pub fn elements(&self) -> Result<ValueType, TypeError> {
+        match self {
+            ValueType::ArrayRef(_) | ValueType::Range(..) => Ok(self.clone()),
+            ValueType::Text(s) => Ok(ValueType::ArrayRef(Rc::new(RefCell::new(
+                s.chars().map(ValueType::string).collect(),
+            )))),
+            ValueType::Map(map) => {
+                let mut keys: Vec<String> = map.borrow().keys().cloned().collect();
+                keys.sort();
+                Ok(ValueType::ArrayRef(Rc::new(RefCell::new(
+                    keys.into_iter().map(ValueType::string).collect(),
+                ))))
+            }
+            _ => Err(TypeError::NotIterable(self.clone())),
+        }
+    }
+
+    fn key(&self) -> Result<&str, TypeError> {
+        match self {
+            ValueType::Text(key) => Ok(key),
+            _ => Err(TypeError::InvalidKeyType(self.clone())),
+        }
+    }
+
     fn index_in_bounds(&self, index: usize) -> Result<usize, TypeError> {
         match self {
-            ValueType::Text(_) | ValueType::Array(_) | ValueType::ArrayRef(_) => {
+            ValueType::Text(_) | ValueType::ArrayRef(_) | ValueType::Tuple(_) => {
                 let len = self.len()?;
                 if index >= len {
                     return Err(TypeError::IndexOutOfBounds { index, size: len });
@@ -121,24 +230,52 @@ impl ValueType {
         }
     }
 
+    /// Name of the value type as the `typeof` function reports it.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ValueType::Nil => "nil",
+            ValueType::Bool(_) => "bool",
+            ValueType::Number(_) => "number",
+            ValueType::Int(_) => "int",
+            ValueType::Address(_) => "address",
+            ValueType::Text(_) => "string",
+            ValueType::Function(_) | ValueType::Closure(_) | ValueType::NativeFunction(_) => {
+                "function"
+            }
+            ValueType::ArrayRef(_) => "array",
+            ValueType::Range(..) => "range",
+            ValueType::Tuple(_) => "tuple",
+            ValueType::Map(_) => "map",
+            ValueType::Error(_) => "error",
+        }
+    }
+
     pub fn as_string(&self) -> String {
         match self {
             ValueType::Nil => "nil".to_string(),
             ValueType::Bool(b) => b.to_string(),
             ValueType::Number(n) => n.to_string(),
+            ValueType::Int(n) => n.to_string(),
             ValueType::Address(a) => a.to_string(),
             ValueType::Text(s) => s.to_string(),
             ValueType::Function(func) => func.name.to_string(),
+            ValueType::Closure(closure) => closure.function.name.to_string(),
             ValueType::NativeFunction(func) => func.name.to_string(),
-            ValueType::Array(_) => "[]".to_string(),
             ValueType::ArrayRef(_) => "&[]".to_string(),
+            ValueType::Range(range) => format!("{}..{}", range.0, range.1),
+            ValueType::Tuple(elements) => {
+                let elements: Vec<String> = elements.iter().map(ValueType::as_string).collect();
+                format!("({})", elements.join(", "))
+            }
+            ValueType::Map(_) => "{}".to_string(),
+            ValueType::Error(message) => message.to_string(),
         }
     }
 
     fn len(&self) -> Result<usize, TypeError> {
         match self {
-            ValueType::Text(s) => Ok(s.len()),
-            ValueType::Array(arr) => Ok(arr.len()),
+            ValueType::Text(s) => Ok(s.chars().count()),
+            ValueType::Tuple(arr) => Ok(arr.len()),
             ValueType::ArrayRef(arr) => Ok(arr.borrow().len()),
             _ => Err(TypeError::UnsupportedArrayType(self.clone())),
         }
@@ -151,8 +288,149 @@ impl ValueType {
     where
         T: Into<String>,
     {
-        ValueType::Text(Box::new(value.into()))
+        ValueType::Text(Rc::new(value.into()))
+    }
+
+    /// Value of the number as a float, integers included.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            ValueType::Number(n) => Some(*n),
+            ValueType::Int(n) => Some(*n as f64),
+            _ => None,
+        }
+    }
+
+    /// Whether conditions treat the value as true.
+    /// Only `nil` and `false` are false, any other value is true, including zero and empty strings.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, ValueType::Nil | ValueType::Bool(false))
     }
+
+    pub fn error<T>(message: T) -> ValueType
+    where
+        T: Into<String>,
+    {
+        ValueType::Error(Box::new(message.into()))
+    }
+
+    /// Compares the values by their contents, looking into arrays, tuples, and maps.
+    ///
+    /// Returns `None` for the values of different types and for the values that cannot be compared, e.g. functions.
+    /// Any value can be compared with nil. Inside of arrays and maps such values are just not equal.
+    pub fn equals(&self, other: &ValueType) -> Option<bool> {
+        equals(self, other, &mut vec![])
+    }
+
+    /// Copies the arrays and maps the value consists of, so changing the copy leaves the value as it is.
+    ///
+    /// Other values are not copied, as they cannot be changed.
+    /// Arrays and maps containing themselves are copied containing their copies.
+    pub fn deep_copy(&self) -> ValueType {
+        deep_copy(self, &mut HashMap::new())
+    }
+}
+
+/// Copies of the shared arrays and maps made so far, by the addresses of the originals.
+type Copies = HashMap<*const (), ValueType>;
+
+fn deep_copy(value: &ValueType, copies: &mut Copies) -> ValueType {
+    match value {
+        ValueType::ArrayRef(array) => {
+            if let Some(copy) = copies.get(&Rc::as_ptr(array).cast()) {
+                return copy.clone();
+            }
+            let copy = Rc::new(RefCell::new(vec![]));
+            copies.insert(Rc::as_ptr(array).cast(), ValueType::ArrayRef(copy.clone()));
+            let elements = array
+                .borrow()
+                .iter()
+                .map(|element| deep_copy(element, copies))
+                .collect();
+            *copy.borrow_mut() = elements;
+            ValueType::ArrayRef(copy)
+        }
+        ValueType::Map(map) => {
+            if let Some(copy) = copies.get(&Rc::as_ptr(map).cast()) {
+                return copy.clone();
+            }
+            let copy = Rc::new(RefCell::new(HashMap::new()));
+            copies.insert(Rc::as_ptr(map).cast(), ValueType::Map(copy.clone()));
+            let entries = map
+                .borrow()
+                .iter()
+                .map(|(key, value)| (key.clone(), deep_copy(value, copies)))
+                .collect();
+            *copy.borrow_mut() = entries;
+            ValueType::Map(copy)
+        }
+        ValueType::Tuple(elements) => ValueType::Tuple(Box::new(
+            elements
+                .iter()
+                .map(|element| deep_copy(element, copies))
+                .collect(),
+        )),
+        value => value.clone(),
+    }
+}
+
+/// Shared arrays and maps being compared, so the ones containing themselves are not compared forever.
+type Comparisons = Vec<(*const (), *const ())>;
+
+fn equals(a: &ValueType, b: &ValueType, comparing: &mut Comparisons) -> Option<bool> {
+    let equal = match (a, b) {
+        (ValueType::Nil, b) => matches!(b, ValueType::Nil),
+        (a, ValueType::Nil) => matches!(a, ValueType::Nil),
+        (ValueType::Number(a), ValueType::Number(b)) => a == b,
+        (ValueType::Int(a), ValueType::Int(b)) => a == b,
+        (ValueType::Int(a), ValueType::Number(b)) | (ValueType::Number(b), ValueType::Int(a)) => {
+            *a as f64 == *b
+        }
+        (ValueType::Bool(a), ValueType::Bool(b)) => a == b,
+        // Interned strings are equal when they are the same string.
+        (ValueType::Text(a), ValueType::Text(b)) => Rc::ptr_eq(a, b) || a == b,
+        (ValueType::Error(a), ValueType::Error(b)) => a == b,
+        (ValueType::Range(a), ValueType::Range(b)) => a == b,
+        (ValueType::Tuple(a), ValueType::Tuple(b)) => all_equal(a, b, comparing),
+        (ValueType::ArrayRef(a), ValueType::ArrayRef(b)) => {
+            shared_equal(Rc::as_ptr(a).cast(), Rc::as_ptr(b).cast(), comparing, |c| {
+                all_equal(&a.borrow(), &b.borrow(), c)
+            })
+        }
+        (ValueType::Map(a), ValueType::Map(b)) => {
+            shared_equal(Rc::as_ptr(a).cast(), Rc::as_ptr(b).cast(), comparing, |c| {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len()
+                    && a.iter().all(|(key, value)| {
+                        b.get(key)
+                            .is_some_and(|other| equals(value, other, c).unwrap_or(false))
+                    })
+            })
+        }
+        _ => return None,
+    };
+    Some(equal)
+}
+
+fn all_equal(a: &[ValueType], b: &[ValueType], comparing: &mut Comparisons) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(a, b)| equals(a, b, comparing).unwrap_or(false))
+}
+
+/// Compares the shared values, taking the pair already being compared as equal.
+/// The pair differs only if some other part of the values differs.
+fn shared_equal<F>(a: *const (), b: *const (), comparing: &mut Comparisons, compare: F) -> bool
+where
+    F: FnOnce(&mut Comparisons) -> bool,
+{
+    if a == b || comparing.contains(&(a, b)) {
+        return true;
+    }
+    comparing.push((a, b));
+    let equal = compare(comparing);
+    comparing.pop();
+    equal
 }
 
 impl Display for ValueType {
@@ -161,53 +439,111 @@ impl Display for ValueType {
             ValueType::Nil => write!(f, "nil"),
             ValueType::Bool(b) => write!(f, "b:{}", b),
             ValueType::Number(n) => write!(f, "f:{}", n),
+            ValueType::Int(n) => write!(f, "i:{}", n),
             ValueType::Address(a) => write!(f, "*:{}", a),
             ValueType::Text(s) => write!(f, "s:{}", s),
             ValueType::Function(func) => write!(f, "fn:{}", func.name),
+            ValueType::Closure(closure) => write!(f, "closure:{}", closure.function.name),
             ValueType::NativeFunction(func) => write!(f, "<native>fn:{}", func.name),
-            ValueType::Array(_) => write!(f, "[]"),
             ValueType::ArrayRef(_) => write!(f, "&[]"),
+            ValueType::Range(range) => write!(f, "{}..{}", range.0, range.1),
+            ValueType::Tuple(elements) => write!(f, "({})", elements.len()),
+            ValueType::Map(_) => write!(f, "{{}}"),
+            ValueType::Error(message) => write!(f, "err:{}", message),
         }
     }
 }
 
 impl Function {
     pub fn new(name: String, chunk: Rc<Chunk>, arity: usize) -> Self {
-        Self { name, chunk, arity }
+        Self {
+            name,
+            chunk,
+            arity,
+            captures: vec![],
+        }
     }
 
-    pub fn script(chunk: Rc<Chunk>) -> Self {
+    /// Creates a function that captures variables of the enclosing function.
+    pub fn closure(name: String, chunk: Rc<Chunk>, arity: usize, captures: Vec<Capture>) -> Self {
         Self {
-            name: "$main$".to_string(),
+            name,
             chunk,
-            arity: 0,
+            arity,
+            captures,
         }
     }
 
+    pub fn script(chunk: Rc<Chunk>) -> Self {
+        Self::new("$main$".to_string(), chunk, 0)
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
 
-    pub fn chunk(&self) -> Rc<Chunk> {
-        self.chunk.clone()
+    pub fn chunk(&self) -> &Rc<Chunk> {
+        &self.chunk
     }
 
     pub fn arity(&self) -> usize {
         self.arity
     }
+
+    /// Variables captured when the closure of the function is created.
+    pub fn captures(&self) -> &[Capture] {
+        &self.captures
+    }
+}
+
+impl Closure {
+    pub fn new(function: Rc<Function>, upvalues: Vec<Rc<RefCell<Upvalue>>>) -> Self {
+        Closure { function, upvalues }
+    }
+
+    pub fn function(&self) -> &Function {
+        &self.function
+    }
+
+    pub fn upvalue(&self, index: usize) -> Option<&Rc<RefCell<Upvalue>>> {
+        self.upvalues.get(index)
+    }
 }
 
 impl NativeFunction {
-    pub fn new(
-        name: &str,
-        arity: usize,
-        function: fn(&mut Vm) -> Result<(), VmRuntimeError>,
-    ) -> Self {
+    pub fn new<F>(name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(&mut Vm, &[ValueType]) -> Result<ValueType, VmRuntimeError> + 'static,
+    {
         Self {
             name: name.to_string(),
             arity,
-            function,
+            optional: 0,
+            variadic: false,
+            function: Rc::new(function),
+        }
+    }
+
+    /// Lets the calls omit the given number of the last parameters.
+    /// The function gets only the arguments passed.
+    pub fn with_optional(mut self, optional: usize) -> Self {
+        self.optional = optional.min(self.arity);
+        self
+    }
+
+    /// Lets the calls pass any number of arguments after the required ones.
+    pub fn variadic(mut self) -> Self {
+        self.variadic = true;
+        self
+    }
+
+    /// Whether the function can be called with the number of arguments.
+    pub fn accepts(&self, count: usize) -> bool {
+        let required = self.arity - self.optional;
+        if self.variadic {
+            return count >= required;
         }
+        (required..=self.arity).contains(&count)
     }
 
     pub fn name(&self) -> &str {
@@ -237,9 +573,47 @@ impl Debug for NativeFunction {
     }
 }
 
+/// Pool of strings, so the equal ones share a single copy.
+///
+/// The compiler interns the string literals and the names of the globals,
+/// so repeated strings take memory once and compare by pointer.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    strings: HashSet<Interned>,
+}
+
+/// String of the pool, looked up by its text.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Interned(Rc<String>);
+
+impl std::borrow::Borrow<str> for Interned {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Interner {
+    /// Returns the shared copy of the string, adding it to the pool when it is new.
+    pub fn intern(&mut self, text: &str) -> Rc<String> {
+        match self.strings.get(text) {
+            Some(interned) => interned.0.clone(),
+            None => {
+                let interned = Rc::new(text.to_string());
+                self.strings.insert(Interned(interned.clone()));
+                interned
+            }
+        }
+    }
+
+    /// String value with the shared copy of the text.
+    pub fn string(&mut self, text: &str) -> ValueType {
+        ValueType::Text(self.intern(text))
+    }
+}
+
 impl NativeFunction {
-    pub fn call(&self, vm: &mut Vm) -> Result<(), VmRuntimeError> {
-        (self.function)(vm)
+    pub fn call(&self, vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+        (self.function)(vm, args)
     }
 }
 
@@ -247,6 +621,47 @@ impl NativeFunction {
 mod tests {
     use super::*;
 
+    #[test]
+    fn compact_values() {
+        assert_eq!(std::mem::size_of::<ValueType>(), 16);
+    }
+
+    #[test]
+    fn intern_strings() {
+        let mut interner = Interner::default();
+        let a = interner.intern("name");
+        let b = interner.intern(&String::from("name"));
+
+        assert!(Rc::ptr_eq(&a, &b));
+        assert!(!Rc::ptr_eq(&a, &interner.intern("other")));
+        assert_eq!(
+            interner.string("name").equals(&ValueType::string("name")),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn compare_contents() {
+        let array = |values: Vec<ValueType>| ValueType::ArrayRef(Rc::new(RefCell::new(values)));
+        let a = array(vec![ValueType::Number(1.0), ValueType::string("x")]);
+        let b = array(vec![ValueType::Number(1.0), ValueType::string("x")]);
+        let c = array(vec![ValueType::Number(1.0), ValueType::Bool(true)]);
+
+        assert_eq!(a.equals(&b), Some(true));
+        assert_eq!(a.equals(&c), Some(false));
+        assert_eq!(a.equals(&ValueType::Nil), Some(false));
+        assert_eq!(a.equals(&ValueType::Number(1.0)), None);
+
+        let (ValueType::ArrayRef(a_values), ValueType::ArrayRef(b_values)) = (&a, &b) else {
+            unreachable!()
+        };
+        a_values.borrow_mut().push(a.clone());
+        b_values.borrow_mut().push(b.clone());
+        assert_eq!(a.equals(&b), Some(true));
+        b_values.borrow_mut()[0] = ValueType::Number(2.0);
+        assert_eq!(a.equals(&b), Some(false));
+    }
+
     #[test]
     fn test_index() {
         let num = ValueType::Number(1.0);
@@ -257,7 +672,7 @@ mod tests {
         let idx = num.index();
         assert!(matches!(idx, Err(TypeError::IncorrectIndex(_))));
 
-        let num = ValueType::Text(Box::new("hello".to_string()));
+        let num = ValueType::string("hello");
         let idx = num.index();
         assert!(matches!(
             idx,
@@ -267,14 +682,14 @@ mod tests {
 
     #[test]
     fn get_string_elements() {
-        let s = ValueType::Text(Box::new("hello".to_string()));
+        let s = ValueType::string("hello");
         let idx = ValueType::Number(0.0);
         let val = s.get(&idx);
-        assert_eq!(val.unwrap(), ValueType::Text(Box::new("h".to_string())));
+        assert_eq!(val.unwrap(), ValueType::string("h"));
 
         let idx = ValueType::Number(1.0);
         let val = s.get(&idx);
-        assert_eq!(val.unwrap(), ValueType::Text(Box::new("e".to_string())));
+        assert_eq!(val.unwrap(), ValueType::string("e"));
 
         let idx = ValueType::Number(16.0);
         let val = s.get(&idx);
@@ -284,19 +699,59 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn index_non_ascii_strings() {
+        let s = ValueType::string("привіт");
+
+        assert_eq!(
+            s.get(&ValueType::Number(5.0)).unwrap(),
+            ValueType::string("т")
+        );
+        assert_eq!(
+            s.set(&ValueType::Number(1.0), ValueType::string("p"))
+                .unwrap(),
+            ValueType::string("пpивіт")
+        );
+        assert!(matches!(
+            s.get(&ValueType::Number(6.0)),
+            Err(TypeError::IndexOutOfBounds { index: 6, size: 6 })
+        ));
+    }
+
+    #[test]
+    fn deep_copy_arrays_containing_themselves() {
+        let inner = ValueType::ArrayRef(Rc::new(RefCell::new(vec![ValueType::Int(1)])));
+        let array = Rc::new(RefCell::new(vec![inner.clone()]));
+        array.borrow_mut().push(ValueType::ArrayRef(array.clone()));
+        let array = ValueType::ArrayRef(array);
+
+        let copy = array.deep_copy();
+        copy.get(&ValueType::Int(0))
+            .unwrap()
+            .set(&ValueType::Int(0), ValueType::Int(2))
+            .unwrap();
+
+        assert_eq!(inner.get(&ValueType::Int(0)).unwrap(), ValueType::Int(1));
+        let ValueType::ArrayRef(copy) = copy else {
+            panic!("array expected");
+        };
+        let ValueType::ArrayRef(itself) = copy.borrow()[1].clone() else {
+            panic!("array expected");
+        };
+        assert!(Rc::ptr_eq(&copy, &itself));
+    }
+
     #[test]
     fn set_string_elements() {
-        let s = ValueType::Text(Box::new("hello".to_string()));
+        let s = ValueType::string("hello");
         let idx = ValueType::Number(0.0);
-        let val = ValueType::Text(Box::new("H".to_string()));
+        let val = ValueType::string("H");
         let new_s = s.set(&idx, val);
-        assert_eq!(
-            new_s.unwrap(),
-            ValueType::Text(Box::new("Hello".to_string()))
-        );
+        assert_eq!(new_s.unwrap(), ValueType::string("Hello"));
+        assert_eq!(s, ValueType::string("hello"));
 
         let idx = ValueType::Number(16.0);
-        let val = ValueType::Text(Box::new("H".to_string()));
+        let val = ValueType::string("H");
         let new_s = s.set(&idx, val);
         assert!(matches!(
             new_s,
@@ -315,9 +770,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn set_array_elements_in_place() {
+        let array = ValueType::ArrayRef(Rc::new(RefCell::new(vec![ValueType::Nil; 2])));
+        let alias = array.clone();
+
+        array.set(&ValueType::Int(1), ValueType::Int(7)).unwrap();
+
+        assert_eq!(alias.get(&ValueType::Int(1)).unwrap(), ValueType::Int(7));
+        assert!(matches!(
+            array.set(&ValueType::Int(2), ValueType::Nil),
+            Err(TypeError::IndexOutOfBounds { index: 2, size: 2 })
+        ));
+    }
+
+    #[test]
+    fn get_and_set_map_entries() {
+        let map = ValueType::Map(Rc::new(RefCell::new(HashMap::new())));
+        let key = ValueType::string("a");
+
+        assert_eq!(map.get(&key).unwrap(), ValueType::Nil);
+
+        map.set(&key, ValueType::Number(1.0)).unwrap();
+        assert_eq!(map.get(&key).unwrap(), ValueType::Number(1.0));
+
+        let idx = ValueType::Number(0.0);
+        assert!(matches!(
+            map.get(&idx),
+            Err(TypeError::InvalidKeyType(ValueType::Number(_)))
+        ));
+    }
+
     #[test]
     fn values_as_string() {
-        let s = ValueType::Text(Box::new("hello".to_string()));
+        let s = ValueType::string("hello");
         assert_eq!(s.as_string(), "hello");
 
         let s = ValueType::Number(10.0);
@@ -332,26 +818,25 @@ mod tests {
         let s = ValueType::Address(10);
         assert_eq!(s.as_string(), "10");
 
-        let s = ValueType::Function(Box::new(Function::new(
+        let s = ValueType::Function(Rc::new(Function::new(
             "test".to_string(),
             Rc::new(Chunk::default()),
             0,
         )));
         assert_eq!(s.as_string(), "test");
 
-        let s = ValueType::NativeFunction(Rc::new(NativeFunction::new("test", 0, |_vm| Ok(()))));
+        let s = ValueType::NativeFunction(Rc::new(NativeFunction::new("test", 0, |_vm, _args| {
+            Ok(ValueType::Nil)
+        })));
         assert_eq!(s.as_string(), "test");
 
-        let s = ValueType::Array(Box::new(vec![ValueType::Number(10.0)]));
-        assert_eq!(s.as_string(), "[]");
-
         let s = ValueType::ArrayRef(Rc::new(RefCell::new(vec![ValueType::Number(10.0)])));
         assert_eq!(s.as_string(), "&[]");
     }
 
     #[test]
     fn display() {
-        let s = ValueType::Text(Box::new("hello".to_string()));
+        let s = ValueType::string("hello");
         assert_eq!(format!("{}", s), "s:hello");
 
         let s = ValueType::Number(10.0);
@@ -366,19 +851,18 @@ mod tests {
         let s = ValueType::Address(10);
         assert_eq!(format!("{}", s), "*:10");
 
-        let s = ValueType::Function(Box::new(Function::new(
+        let s = ValueType::Function(Rc::new(Function::new(
             "test".to_string(),
             Rc::new(Chunk::default()),
             0,
         )));
         assert_eq!(format!("{}", s), "fn:test");
 
-        let s = ValueType::NativeFunction(Rc::new(NativeFunction::new("test", 0, |_vm| Ok(()))));
+        let s = ValueType::NativeFunction(Rc::new(NativeFunction::new("test", 0, |_vm, _args| {
+            Ok(ValueType::Nil)
+        })));
         assert_eq!(format!("{}", s), "<native>fn:test");
 
-        let s = ValueType::Array(Box::new(vec![ValueType::Number(10.0)]));
-        assert_eq!(format!("{}", s), "[]");
-
         let s = ValueType::ArrayRef(Rc::new(RefCell::new(vec![ValueType::Number(10.0)])));
         assert_eq!(format!("{}", s), "&[]");
     }
@@ -0,0 +1,217 @@
+//! Configuration of the virtual machine in one place.
+//!
+//! [Vm::default] covers the common case. The builder sets up everything the host application needs
+//! before the machine registers its globals, e.g. a machine without the standard library.
+
+use std::collections::HashSet;
+
+use crate::log::LoggingTracer;
+use crate::value::{NativeFunction, ValueType};
+use crate::vm::globals::Globals;
+use crate::vm::io::VmIo;
+use crate::vm::native::std_lib;
+use crate::vm::provider::{SeededRandom, SystemClock};
+use crate::vm::trace::VmStepTrace;
+use crate::vm::{Vm, VmRuntimeError, VmStack, MAX_CALL_DEPTH, STACK_SIZE};
+
+/// Builder of the virtual machine, created by [Vm::builder].
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// # use brainterpreter::value::ValueType;
+/// # use brainterpreter::vm::io::VmIo;
+/// # use brainterpreter::vm::Vm;
+/// let out = Rc::new(RefCell::new(vec![]));
+/// let mut vm = Vm::builder()
+///     .io(VmIo::default().with_stdout(out.clone()))
+///     .without_std_lib()
+///     .native("answer", 0, |_vm, _args| Ok(ValueType::Int(42)))
+///     .fuel(1000)
+///     .build();
+/// vm.eval("print answer();").unwrap();
+///
+/// assert_eq!(out.borrow().as_slice(), b"42\n");
+/// assert!(vm.eval("len(\"text\");").is_err());
+/// ```
+pub struct VmBuilder {
+    tracer: Box<dyn VmStepTrace>,
+    io: VmIo,
+    std_lib: bool,
+    natives: Vec<NativeFunction>,
+    stack_limit: usize,
+    fuel: Option<usize>,
+}
+
+impl Default for VmBuilder {
+    fn default() -> Self {
+        VmBuilder {
+            tracer: Box::new(LoggingTracer),
+            io: VmIo::default(),
+            std_lib: true,
+            natives: vec![],
+            stack_limit: STACK_SIZE,
+            fuel: None,
+        }
+    }
+}
+
+impl VmBuilder {
+    /// Sets the tracer receiving the events of the execution, the debug log by default.
+    pub fn tracer(self, tracer: impl VmStepTrace + 'static) -> Self {
+        VmBuilder {
+            tracer: Box::new(tracer),
+            ..self
+        }
+    }
+
+    /// Sets the streams the programs read from and write to, the standard streams by default.
+    pub fn io(self, io: VmIo) -> Self {
+        VmBuilder { io, ..self }
+    }
+
+    /// Leaves out the native functions of the standard library, e.g. to sandbox the programs.
+    ///
+    /// The `print` statement keeps working, as it is a part of the language.
+    pub fn without_std_lib(self) -> Self {
+        VmBuilder {
+            std_lib: false,
+            ..self
+        }
+    }
+
+    /// Defines the native functions, e.g. the groups of the standard library left out by [VmBuilder::without_std_lib].
+    ///
+    /// They replace the functions with the same names defined before.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use brainterpreter::value::ValueType;
+    /// # use brainterpreter::vm::native::{math, strings};
+    /// # use brainterpreter::vm::Vm;
+    /// let mut vm = Vm::builder()
+    ///     .without_std_lib()
+    ///     .natives(math())
+    ///     .natives(strings())
+    ///     .build();
+    ///
+    /// assert_eq!(vm.eval("to_upper(\"a\");").unwrap(), ValueType::string("A"));
+    /// assert!(vm.eval("read_line();").is_err());
+    /// ```
+    pub fn natives(mut self, natives: impl IntoIterator<Item = NativeFunction>) -> Self {
+        self.natives.extend(natives);
+        self
+    }
+
+    /// Defines a global function implemented by the host application, see [Vm::register_native].
+    ///
+    /// It replaces the function of the standard library with the same name.
+    pub fn native<F>(mut self, name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(&mut Vm, &[ValueType]) -> Result<ValueType, VmRuntimeError> + 'static,
+    {
+        self.natives
+            .push(NativeFunction::new(name, arity, function));
+        self
+    }
+
+    /// Limits the number of values on the operand stack, see [Vm::with_stack_limit].
+    pub fn stack_limit(self, stack_limit: usize) -> Self {
+        VmBuilder {
+            stack_limit,
+            ..self
+        }
+    }
+
+    /// Limits the number of instructions the virtual machine executes, see [Vm::with_fuel].
+    pub fn fuel(self, fuel: usize) -> Self {
+        VmBuilder {
+            fuel: Some(fuel),
+            ..self
+        }
+    }
+
+    /// Creates the virtual machine with the native functions defined.
+    pub fn build(self) -> Vm {
+        let mut vm = Vm {
+            stack: VmStack::with_limit(self.stack_limit),
+            frames: Vec::new(),
+            globals: Globals::default(),
+            trace: Some(self.tracer),
+            io: self.io,
+            random: Box::new(SeededRandom::default()),
+            clock: Box::new(SystemClock),
+            args: vec![],
+            env_access: true,
+            exit_allowed: true,
+            fuel: self.fuel,
+            strict_conditions: false,
+            coverage: None,
+            stats: None,
+            max_call_depth: MAX_CALL_DEPTH,
+            breakpoints: HashSet::new(),
+            resume_from_breakpoint: false,
+            open_upvalues: vec![],
+            handler_barrier: 0,
+        };
+        if self.std_lib {
+            std_lib()
+                .into_iter()
+                .for_each(|f| vm.define_native_function(f));
+        }
+        self.natives
+            .into_iter()
+            .for_each(|f| vm.define_native_function(f));
+        vm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::native::{core, math};
+    use crate::BaubleError;
+
+    #[test]
+    fn replace_std_lib_natives() {
+        let mut vm = Vm::builder()
+            .native("len", 1, |_vm, _args| Ok(ValueType::Int(-1)))
+            .build();
+
+        assert_eq!(vm.eval("len(\"text\");").unwrap(), ValueType::Int(-1));
+        assert_eq!(vm.eval("as_int(\"7\");").unwrap(), ValueType::Int(7));
+    }
+
+    #[test]
+    fn build_without_std_lib() {
+        let mut vm = Vm::builder().without_std_lib().stack_limit(16).build();
+
+        assert!(vm.global("len").is_none());
+        let error = vm
+            .eval("fun deep(n) { return deep(n + 1) + n; } deep(0);")
+            .unwrap_err();
+        let BaubleError::Runtime(error) = error else {
+            panic!("runtime error expected");
+        };
+        assert!(matches!(error.kind(), VmRuntimeError::StackOverflow(16)));
+    }
+
+    #[test]
+    fn register_selected_groups() {
+        let mut vm = Vm::builder()
+            .without_std_lib()
+            .natives(core())
+            .natives(math())
+            .native("abs", 1, |_vm, _args| Ok(ValueType::Int(0)))
+            .build();
+
+        assert_eq!(vm.eval("len(\"text\");").unwrap(), ValueType::Int(4));
+        assert_eq!(vm.eval("sqrt(16);").unwrap(), ValueType::Number(4.0));
+        assert_eq!(vm.eval("abs(-1);").unwrap(), ValueType::Int(0));
+        assert!(vm.global("print").is_none());
+        assert!(vm.global("env").is_none());
+    }
+}
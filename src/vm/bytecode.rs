@@ -0,0 +1,545 @@
+//! Binary format of the compiled chunks.
+//!
+//! The format lets the interpreter save a compiled program and run it later without compiling the source again.
+//! The file starts with the `BBC` magic bytes and the format version followed by the main chunk.
+//! Each chunk is a list of constants, a list of operations, and source positions of the operations.
+//! Functions declared in the program are stored as constants along with their own chunks.
+//!
+//! All numbers are stored in little-endian byte order.
+
+use std::rc::Rc;
+
+use thiserror::Error;
+
+use crate::source::Position;
+use crate::value::{Capture, Function, Interner, ValueType};
+use crate::vm::exec::Chunk;
+use crate::vm::opcode::Op;
+
+const MAGIC: &[u8; 3] = b"BBC";
+const VERSION: u8 = 4;
+
+/// Error of reading or writing the bytecode.
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum BytecodeError {
+    #[error("not a bauble bytecode")]
+    InvalidHeader,
+    #[error("unsupported bytecode version {0}")]
+    UnsupportedVersion(u8),
+    #[error("bytecode ended unexpectedly")]
+    UnexpectedEnd,
+    #[error("unknown operation code {0}")]
+    UnknownOp(u8),
+    #[error("unknown constant type {0}")]
+    UnknownConstant(u8),
+    #[error("string is not valid utf-8")]
+    InvalidString,
+    #[error("constant {0} cannot be stored in bytecode")]
+    UnsupportedConstant(String),
+    /// Globals addressed by slot belong to the machine the code was compiled for.
+    #[error("operation {0} cannot be stored in bytecode")]
+    UnsupportedOp(String),
+    #[error("operand of {0} does not fit 32 bits")]
+    OperandOutOfRange(String),
+}
+
+/// Writes the chunk with the format header.
+pub fn write(chunk: &Chunk) -> Result<Vec<u8>, BytecodeError> {
+    let mut writer = Writer::default();
+    writer.bytes.extend_from_slice(MAGIC);
+    writer.u8(VERSION);
+    writer.chunk(chunk)?;
+    Ok(writer.bytes)
+}
+
+/// Reads the chunk written by [write].
+pub fn read(bytes: &[u8]) -> Result<Chunk, BytecodeError> {
+    let mut reader = Reader {
+        bytes,
+        pos: 0,
+        strings: Interner::default(),
+    };
+    if reader
+        .take(MAGIC.len())
+        .map_err(|_| BytecodeError::InvalidHeader)?
+        != MAGIC
+    {
+        return Err(BytecodeError::InvalidHeader);
+    }
+    let version = reader.u8()?;
+    if version != VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+    reader.chunk()
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn chunk(&mut self, chunk: &Chunk) -> Result<(), BytecodeError> {
+        self.usize(chunk.constants_len());
+        for constant in chunk.constants() {
+            self.constant(constant)?;
+        }
+        self.usize(chunk.ops_len());
+        for op in chunk.ops() {
+            self.op(&op)?;
+        }
+        self.usize(chunk.positions().len());
+        for position in chunk.positions() {
+            self.usize(position.line());
+            self.usize(position.column());
+        }
+        Ok(())
+    }
+
+    fn constant(&mut self, constant: &ValueType) -> Result<(), BytecodeError> {
+        match constant {
+            ValueType::Nil => self.u8(0),
+            ValueType::Bool(b) => {
+                self.u8(1);
+                self.bool(*b);
+            }
+            ValueType::Number(n) => {
+                self.u8(2);
+                self.f64(*n);
+            }
+            ValueType::Address(a) => {
+                self.u8(3);
+                self.usize(*a);
+            }
+            ValueType::Int(n) => {
+                self.u8(6);
+                self.i64(*n);
+            }
+            ValueType::Text(s) => {
+                self.u8(4);
+                self.string(s);
+            }
+            ValueType::Function(function) => {
+                self.u8(5);
+                self.string(function.name());
+                self.usize(function.arity());
+                self.usize(function.captures().len());
+                for capture in function.captures() {
+                    match capture {
+                        Capture::Local(idx) => {
+                            self.u8(0);
+                            self.usize(*idx);
+                        }
+                        Capture::Upvalue(idx) => {
+                            self.u8(1);
+                            self.usize(*idx);
+                        }
+                    }
+                }
+                self.chunk(function.chunk())?;
+            }
+            _ => return Err(BytecodeError::UnsupportedConstant(constant.to_string())),
+        }
+        Ok(())
+    }
+
+    fn op(&mut self, op: &Op) -> Result<(), BytecodeError> {
+        match op {
+            Op::Return => self.u8(0),
+            Op::Call(arity) => {
+                self.u8(1);
+                self.usize(*arity);
+            }
+            Op::ConstFloat(n) => {
+                self.u8(2);
+                self.f64(*n);
+            }
+            Op::ConstBool(b) => {
+                self.u8(3);
+                self.bool(*b);
+            }
+            Op::Const(idx) => {
+                self.u8(4);
+                self.usize(*idx);
+            }
+            Op::LoadIndex => self.u8(5),
+            Op::StoreIndex => self.u8(6),
+            Op::ReplaceIndex => self.u8(49),
+            Op::Increment => self.u8(50),
+            Op::Add => self.u8(7),
+            Op::Sub => self.u8(8),
+            Op::Mul => self.u8(9),
+            Op::Div => self.u8(10),
+            Op::Cmp => self.u8(11),
+            Op::Not => self.u8(12),
+            Op::Le => self.u8(13),
+            Op::Ge => self.u8(14),
+            Op::Print => self.u8(15),
+            Op::StoreGlobal(idx) => {
+                self.u8(16);
+                self.usize(*idx);
+            }
+            Op::LoadGlobal(idx) => {
+                self.u8(17);
+                self.usize(*idx);
+            }
+            Op::StoreLocal(idx) => {
+                self.u8(18);
+                self.usize(*idx);
+            }
+            Op::LoadLocal(idx) => {
+                self.u8(19);
+                self.usize(*idx);
+            }
+            Op::StoreUpvalue(idx) => {
+                self.u8(20);
+                self.usize(*idx);
+            }
+            Op::LoadUpvalue(idx) => {
+                self.u8(21);
+                self.usize(*idx);
+            }
+            Op::Closure(idx) => {
+                self.u8(22);
+                self.usize(*idx);
+            }
+            Op::CloseUpvalue => self.u8(23),
+            Op::Pop => self.u8(24),
+            Op::Nil => self.u8(25),
+            Op::Jump(offset) => {
+                self.u8(26);
+                self.i32(*offset);
+            }
+            Op::JumpIfFalse(offset) => {
+                self.u8(27);
+                self.i32(*offset);
+            }
+            Op::JumpIfTrue(offset) => {
+                self.u8(48);
+                self.i32(*offset);
+            }
+            Op::Array => self.u8(28),
+            Op::Map(len) => {
+                self.u8(29);
+                self.usize(*len);
+            }
+            Op::TailCall(arity) => {
+                self.u8(30);
+                self.usize(*arity);
+            }
+            Op::Pow => self.u8(31),
+            Op::PushHandler(offset) => {
+                self.u8(32);
+                self.i32(*offset);
+            }
+            Op::PopHandler => self.u8(33),
+            Op::Throw => self.u8(34),
+            Op::Ne => self.u8(35),
+            Op::Lt => self.u8(36),
+            Op::Gt => self.u8(37),
+            Op::Match => self.u8(38),
+            Op::Assert => self.u8(39),
+            Op::IterInit => self.u8(40),
+            Op::IterNext(offset) => {
+                self.u8(41);
+                self.i32(*offset);
+            }
+            Op::Range => self.u8(42),
+            Op::Tuple(len) => {
+                self.u8(43);
+                self.usize(*len);
+            }
+            Op::Unpack(len) => {
+                self.u8(44);
+                self.usize(*len);
+            }
+            Op::ConstInt(n) => {
+                self.u8(45);
+                self.i64(*n);
+            }
+            Op::StoreGlobalSlot(_) | Op::LoadGlobalSlot(_) => {
+                return Err(BytecodeError::UnsupportedOp(op.to_string()))
+            }
+        }
+        Ok(())
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    fn usize(&mut self, value: usize) {
+        self.bytes.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f64(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn i64(&mut self, value: i64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.usize(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// Strings of the constant pools, shared by all the chunks read.
+    strings: Interner,
+}
+
+impl<'a> Reader<'a> {
+    fn chunk(&mut self) -> Result<Chunk, BytecodeError> {
+        let constants_len = self.usize()?;
+        let mut constants = vec![];
+        for _ in 0..constants_len {
+            constants.push(self.constant()?);
+        }
+        let ops_len = self.usize()?;
+        let mut ops = vec![];
+        for _ in 0..ops_len {
+            ops.push(self.op()?);
+        }
+        let positions_len = self.usize()?;
+        let mut positions = vec![];
+        for _ in 0..positions_len {
+            positions.push(Position::new(self.usize()?, self.usize()?));
+        }
+        Ok(Chunk::try_new(ops, constants)?.with_positions(positions))
+    }
+
+    fn constant(&mut self) -> Result<ValueType, BytecodeError> {
+        let constant = match self.u8()? {
+            0 => ValueType::Nil,
+            1 => ValueType::Bool(self.bool()?),
+            2 => ValueType::Number(self.f64()?),
+            3 => ValueType::Address(self.usize()?),
+            6 => ValueType::Int(self.i64()?),
+            4 => {
+                let text = self.string()?;
+                self.strings.string(&text)
+            }
+            5 => {
+                let name = self.string()?;
+                let arity = self.usize()?;
+                let captures_len = self.usize()?;
+                let mut captures = vec![];
+                for _ in 0..captures_len {
+                    let capture = match self.u8()? {
+                        0 => Capture::Local(self.usize()?),
+                        1 => Capture::Upvalue(self.usize()?),
+                        tag => return Err(BytecodeError::UnknownConstant(tag)),
+                    };
+                    captures.push(capture);
+                }
+                let chunk = Rc::new(self.chunk()?);
+                let function = Function::closure(name, chunk, arity, captures);
+                ValueType::Function(Rc::new(function))
+            }
+            tag => return Err(BytecodeError::UnknownConstant(tag)),
+        };
+        Ok(constant)
+    }
+
+    fn op(&mut self) -> Result<Op, BytecodeError> {
+        let op = match self.u8()? {
+            0 => Op::Return,
+            1 => Op::Call(self.usize()?),
+            2 => Op::ConstFloat(self.f64()?),
+            3 => Op::ConstBool(self.bool()?),
+            4 => Op::Const(self.usize()?),
+            5 => Op::LoadIndex,
+            6 => Op::StoreIndex,
+            7 => Op::Add,
+            8 => Op::Sub,
+            9 => Op::Mul,
+            10 => Op::Div,
+            11 => Op::Cmp,
+            12 => Op::Not,
+            13 => Op::Le,
+            14 => Op::Ge,
+            15 => Op::Print,
+            16 => Op::StoreGlobal(self.usize()?),
+            17 => Op::LoadGlobal(self.usize()?),
+            18 => Op::StoreLocal(self.usize()?),
+            19 => Op::LoadLocal(self.usize()?),
+            20 => Op::StoreUpvalue(self.usize()?),
+            21 => Op::LoadUpvalue(self.usize()?),
+            22 => Op::Closure(self.usize()?),
+            23 => Op::CloseUpvalue,
+            24 => Op::Pop,
+            25 => Op::Nil,
+            26 => Op::Jump(self.i32()?),
+            27 => Op::JumpIfFalse(self.i32()?),
+            48 => Op::JumpIfTrue(self.i32()?),
+            49 => Op::ReplaceIndex,
+            50 => Op::Increment,
+            28 => Op::Array,
+            29 => Op::Map(self.usize()?),
+            30 => Op::TailCall(self.usize()?),
+            31 => Op::Pow,
+            32 => Op::PushHandler(self.i32()?),
+            33 => Op::PopHandler,
+            34 => Op::Throw,
+            35 => Op::Ne,
+            36 => Op::Lt,
+            37 => Op::Gt,
+            38 => Op::Match,
+            39 => Op::Assert,
+            40 => Op::IterInit,
+            41 => Op::IterNext(self.i32()?),
+            42 => Op::Range,
+            43 => Op::Tuple(self.usize()?),
+            44 => Op::Unpack(self.usize()?),
+            45 => Op::ConstInt(self.i64()?),
+            code => return Err(BytecodeError::UnknownOp(code)),
+        };
+        Ok(op)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BytecodeError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(BytecodeError::UnexpectedEnd)?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], BytecodeError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, BytecodeError> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn usize(&mut self) -> Result<usize, BytecodeError> {
+        Ok(u64::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn i32(&mut self) -> Result<i32, BytecodeError> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, BytecodeError> {
+        Ok(f64::from_le_bytes(self.array()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, BytecodeError> {
+        Ok(i64::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<String, BytecodeError> {
+        let len = self.usize()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| BytecodeError::InvalidString)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_and_read_chunk() {
+        let function_chunk = Chunk::try_new([Op::LoadUpvalue(0), Op::Return], []).unwrap();
+        let function = Function::closure(
+            "get".to_string(),
+            Rc::new(function_chunk),
+            0,
+            vec![Capture::Local(0)],
+        );
+        let positions = (1..=5).map(|line| Position::new(line, 3));
+        let chunk = Chunk::try_new(
+            [
+                Op::ConstFloat(1.5),
+                Op::Closure(0),
+                Op::Call(0),
+                Op::JumpIfFalse(-3),
+                Op::Print,
+            ],
+            [
+                ValueType::Function(Rc::new(function)),
+                ValueType::string("name"),
+            ],
+        )
+        .unwrap()
+        .with_positions(positions);
+
+        let bytes = write(&chunk).unwrap();
+        let restored = read(&bytes).unwrap();
+
+        assert!(restored.ops().eq(chunk.ops()));
+        assert!(restored.constants().eq(chunk.constants()));
+        assert!(restored.positions().eq(chunk.positions()));
+        let Some(ValueType::Function(function)) = restored.constant(0) else {
+            panic!("function constant expected");
+        };
+        assert_eq!(function.captures(), &[Capture::Local(0)]);
+        assert!(function.chunk().ops().eq([Op::LoadUpvalue(0), Op::Return]));
+    }
+
+    #[test]
+    fn reject_invalid_bytecode() {
+        assert_eq!(read(b"BAD").unwrap_err(), BytecodeError::InvalidHeader);
+        assert_eq!(
+            read(b"BBC\x07").unwrap_err(),
+            BytecodeError::UnsupportedVersion(7)
+        );
+        assert_eq!(
+            read(b"BBC\x04\x01").unwrap_err(),
+            BytecodeError::UnexpectedEnd
+        );
+
+        let mut bytes = write(&Chunk::try_new([Op::Call(0)], []).unwrap()).unwrap();
+        // The operand of the call is followed only by the number of positions.
+        let operand = bytes.len() - 16;
+        bytes[operand..operand + 8].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        assert_eq!(
+            read(&bytes).unwrap_err(),
+            BytecodeError::OperandOutOfRange("CALL, 1099511627776".to_string())
+        );
+    }
+
+    #[test]
+    fn reject_runtime_constants() {
+        let chunk = Chunk::try_new([], [ValueType::ArrayRef(Default::default())]).unwrap();
+
+        assert!(matches!(
+            write(&chunk),
+            Err(BytecodeError::UnsupportedConstant(_))
+        ));
+    }
+
+    #[test]
+    fn reject_global_slots() {
+        let chunk = Chunk::try_new([Op::LoadGlobalSlot(0)], []).unwrap();
+
+        assert_eq!(
+            write(&chunk),
+            Err(BytecodeError::UnsupportedOp("LD_GS, 0".to_string()))
+        );
+    }
+}
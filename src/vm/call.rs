@@ -1,13 +1,42 @@
+use std::cell::RefCell;
+use std::fmt::Display;
 use std::num::IntErrorKind;
 use std::rc::Rc;
 
+use crate::source::Position;
+use crate::value::{Closure, Upvalue};
+
 use super::{exec::Chunk, opcode::Op};
 
+/// Execution state of a called function.
+///
+/// The frame does not keep the name of its function. The called function stays on the stack
+/// at [CallFrame::stack_top], below its arguments, so backtraces read the name from there
+/// and calls do not copy it.
 #[derive(Debug)]
 pub struct CallFrame {
     ip: usize,
     chunk: Rc<Chunk>,
     stack_top: usize,
+    closure: Option<Rc<Closure>>,
+    handlers: Vec<Handler>,
+}
+
+/// Exception handler installed by a `try` block.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Handler {
+    /// Address of the first instruction of the `catch` block.
+    address: usize,
+    /// Number of values on the stack when the handler was installed.
+    stack_len: usize,
+}
+
+/// Function call active at the moment of a runtime error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    function: Option<String>,
+    address: usize,
+    position: Option<Position>,
 }
 
 impl CallFrame {
@@ -16,10 +45,29 @@ impl CallFrame {
             chunk,
             ip: 0,
             stack_top,
+            closure: None,
+            handlers: vec![],
+        }
+    }
+
+    pub fn with_closure(closure: Rc<Closure>, stack_top: usize) -> Self {
+        CallFrame {
+            chunk: closure.function().chunk().clone(),
+            ip: 0,
+            stack_top,
+            closure: Some(closure),
+            handlers: vec![],
         }
     }
 
-    pub fn advance(&mut self) -> Option<&Op> {
+    /// Variable captured by the closure being executed.
+    pub fn upvalue(&self, index: usize) -> Option<&Rc<RefCell<Upvalue>>> {
+        self.closure
+            .as_ref()
+            .and_then(|closure| closure.upvalue(index))
+    }
+
+    pub fn advance(&mut self) -> Option<Op> {
         let op = self.chunk.op(self.ip);
         self.ip += 1;
         op
@@ -29,6 +77,11 @@ impl CallFrame {
         self.stack_top
     }
 
+    /// Moves the frame to the stack section starting at the given value.
+    pub(crate) fn rebase(&mut self, stack_top: usize) {
+        self.stack_top = stack_top;
+    }
+
     pub fn ip(&self) -> usize {
         self.ip
     }
@@ -46,7 +99,75 @@ impl CallFrame {
         Ok(())
     }
 
-    pub fn chunk(&self) -> &Chunk {
+    pub(crate) fn push_handler(&mut self, handler: Handler) {
+        self.handlers.push(handler);
+    }
+
+    pub(crate) fn pop_handler(&mut self) -> Option<Handler> {
+        self.handlers.pop()
+    }
+
+    pub(crate) fn has_handlers(&self) -> bool {
+        !self.handlers.is_empty()
+    }
+
+    /// Trace of the instruction being executed in the frame of the named function.
+    pub(crate) fn trace(&self, function: Option<&str>) -> TraceFrame {
+        let address = self.ip.saturating_sub(1);
+        TraceFrame {
+            function: function.map(str::to_string),
+            address,
+            position: self.chunk.position(address),
+        }
+    }
+
+    pub fn chunk(&self) -> &Rc<Chunk> {
         &self.chunk
     }
 }
+
+impl Handler {
+    pub(crate) fn new(address: usize, stack_len: usize) -> Self {
+        Handler { address, stack_len }
+    }
+
+    pub(crate) fn address(&self) -> usize {
+        self.address
+    }
+
+    pub(crate) fn stack_len(&self) -> usize {
+        self.stack_len
+    }
+}
+
+impl TraceFrame {
+    /// Name of the function, `None` for the script itself.
+    pub fn function(&self) -> Option<&str> {
+        self.function.as_deref()
+    }
+
+    /// Address of the executed instruction in the function chunk.
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    /// Source position of the executed instruction, if the chunk has it.
+    pub fn position(&self) -> Option<Position> {
+        self.position
+    }
+}
+
+impl Display for TraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at address {}",
+            self.function.as_deref().unwrap_or("script"),
+            self.address
+        )?;
+        if let Some(position) = self.position {
+            write!(f, " {}", position)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+//! Coverage of the source lines by the executed instructions.
+//!
+//! The lines come from the source positions the compiler records for the instructions.
+
+use std::collections::BTreeMap;
+
+use crate::value::ValueType;
+use crate::vm::exec::Chunk;
+
+/// Source lines of the loaded programs with the number of times their instructions ran.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    lines: BTreeMap<usize, usize>,
+}
+
+impl Coverage {
+    /// Number of instructions executed on the line, or `None` when the line has no instructions.
+    pub fn hits(&self, line: usize) -> Option<usize> {
+        self.lines.get(&line).copied()
+    }
+
+    /// Lines with instructions, in order.
+    pub fn lines(&self) -> impl Iterator<Item = usize> + '_ {
+        self.lines.keys().copied()
+    }
+
+    /// Lines with instructions that never ran, in order.
+    pub fn uncovered_lines(&self) -> Vec<usize> {
+        self.lines
+            .iter()
+            .filter(|(_, hits)| **hits == 0)
+            .map(|(line, _)| *line)
+            .collect()
+    }
+
+    /// Share of the lines with instructions that ran, from 0 to 100.
+    pub fn percent(&self) -> f64 {
+        if self.lines.is_empty() {
+            return 100.0;
+        }
+        let covered = self.lines.len() - self.uncovered_lines().len();
+        covered as f64 * 100.0 / self.lines.len() as f64
+    }
+
+    /// Registers the lines of the chunk and the functions it defines, so the lines never run show up.
+    pub(crate) fn add_chunk(&mut self, chunk: &Chunk) {
+        for position in chunk.positions() {
+            // Instructions the compiler made up have no line.
+            if position.line() > 0 {
+                self.lines.entry(position.line()).or_default();
+            }
+        }
+        for constant in chunk.constants() {
+            if let ValueType::Function(function) = constant {
+                self.add_chunk(function.chunk());
+            }
+        }
+    }
+
+    pub(crate) fn hit(&mut self, line: usize) {
+        if line > 0 {
+            *self.lines.entry(line).or_default() += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::vm::Vm;
+
+    #[test]
+    fn uncovered_lines_of_branches() {
+        let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![]))).with_coverage();
+
+        vm.eval(
+            "let a = 1;
+            if (a > 0) {
+                print \"positive\";
+            } else {
+                print \"negative\";
+            }
+            fun unused() {
+                return 0;
+            }",
+        )
+        .unwrap();
+
+        let coverage = vm.coverage().unwrap();
+        assert_eq!(coverage.uncovered_lines(), vec![5, 8]);
+        assert!(coverage.hits(3).is_some_and(|hits| hits > 0));
+        assert_eq!(coverage.hits(4), None);
+        assert!(coverage.percent() < 100.0);
+    }
+}
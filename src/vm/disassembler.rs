@@ -2,17 +2,131 @@
 //!
 //! It's a diagnostic tool to help find issues in compiled code.
 
+use crate::source::Position;
 use crate::value::ValueType;
 use crate::vm::exec::Chunk;
-use crate::vm::opcode::Op;
+use crate::vm::opcode::{Op, Operand};
 use std::io::{Error, Write};
 
+/// Disassembled operation of a chunk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisasmLine {
+    address: usize,
+    op: Op,
+    constant: Option<ValueType>,
+    target: Option<usize>,
+    position: Option<Position>,
+}
+
+/// Width of the instruction column of the assembly annotated with source lines.
+const INSTRUCTION_WIDTH: usize = 32;
+
+impl DisasmLine {
+    /// Position of the operation in the chunk.
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    pub fn op(&self) -> Op {
+        self.op
+    }
+
+    /// Assembly name of the operation.
+    pub fn opcode(&self) -> &'static str {
+        self.op.mnemonic()
+    }
+
+    pub fn operand(&self) -> Option<Operand> {
+        self.op.operand()
+    }
+
+    /// Value from the constant pool the operation refers to.
+    pub fn constant(&self) -> Option<&ValueType> {
+        self.constant.as_ref()
+    }
+
+    /// Address the operation jumps to.
+    pub fn target(&self) -> Option<usize> {
+        self.target
+    }
+
+    /// Position of the source code the operation was compiled from.
+    pub fn position(&self) -> Option<Position> {
+        self.position
+    }
+}
+
 /// Disassemble executable chunk into VM assembly.
 pub fn disassemble(chunk: &Chunk, mut w: impl Write) -> Result<(), Error> {
-    disassemble_function(chunk, "$main$", &mut w)
+    disassemble_function(chunk, "$main$", None, &mut w)
 }
 
-fn disassemble_function(chunk: &Chunk, name: &str, w: &mut impl Write) -> Result<(), Error> {
+/// Disassemble executable chunk into VM assembly,
+/// annotating every instruction with the `# file:line` it was compiled from.
+///
+/// # Examples
+///
+/// ```rust
+/// # use brainterpreter::compiler::Compiler;
+/// # use brainterpreter::lexer::Lexer;
+/// # use brainterpreter::parser::Parser;
+/// # use brainterpreter::vm::disassembler::disassemble_with_source;
+/// let program = Parser::new(Lexer::new("let a = 1;\nprint a;")).parse_program().unwrap();
+/// let chunk = Compiler::default().compile(program).unwrap();
+/// let mut assembly = vec![];
+///
+/// disassemble_with_source(&chunk, "main.bbl", &mut assembly).unwrap();
+///
+/// let assembly = String::from_utf8(assembly).unwrap();
+/// assert!(assembly.contains("\tPRN                             # main.bbl:2\n"));
+/// ```
+pub fn disassemble_with_source(
+    chunk: &Chunk,
+    source_name: &str,
+    mut w: impl Write,
+) -> Result<(), Error> {
+    disassemble_function(chunk, "$main$", Some(source_name), &mut w)
+}
+
+/// Disassemble the code of the chunk into the list of operations with resolved operands.
+///
+/// Functions in the constant pool are not disassembled.
+pub fn disassemble_to_ir(chunk: &Chunk) -> Vec<DisasmLine> {
+    chunk
+        .ops()
+        .enumerate()
+        .map(|(address, op)| {
+            let constant = match op {
+                Op::Const(idx) | Op::Closure(idx) | Op::StoreGlobal(idx) | Op::LoadGlobal(idx) => {
+                    chunk.constant(idx).cloned()
+                }
+                _ => None,
+            };
+            let target = match op {
+                Op::Jump(offset)
+                | Op::JumpIfFalse(offset)
+                | Op::JumpIfTrue(offset)
+                | Op::PushHandler(offset)
+                | Op::IterNext(offset) => address.checked_add_signed(offset as isize),
+                _ => None,
+            };
+            DisasmLine {
+                address,
+                op,
+                constant,
+                target,
+                position: chunk.position(address),
+            }
+        })
+        .collect()
+}
+
+fn disassemble_function(
+    chunk: &Chunk,
+    name: &str,
+    source_name: Option<&str>,
+    w: &mut impl Write,
+) -> Result<(), Error> {
     let mut functions = vec![];
     writeln!(w, "fn:{}:", name)?;
     writeln!(w, "constants:")?;
@@ -23,22 +137,32 @@ fn disassemble_function(chunk: &Chunk, name: &str, w: &mut impl Write) -> Result
         }
     }
     writeln!(w, "code:")?;
-    for (line, op) in chunk.ops().enumerate() {
-        match op {
-            Op::Jump(offset) | Op::JumpIfFalse(offset) => {
-                let address = line.checked_add_signed(*offset as isize).unwrap();
-                writeln!(w, "\t{:04x}\t{} # {:04x}", line, op, address)?;
-            }
-            Op::StoreGlobal(idx) | Op::LoadGlobal(idx) => {
-                let var_name = chunk.constant(*idx).unwrap().as_string();
-                writeln!(w, "\t{:04x}\t{} # {}", line, op, var_name)?;
+    for line in disassemble_to_ir(chunk) {
+        let mut instruction = line.op().to_string();
+        match (line.op(), line.target(), line.constant()) {
+            (_, Some(target), _) => instruction.push_str(&format!(" # {:04x}", target)),
+            (Op::StoreGlobal(_) | Op::LoadGlobal(_), _, Some(name)) => {
+                instruction.push_str(&format!(" # {}", name.as_string()))
             }
-            o => writeln!(w, "\t{:04x}\t{}", line, o)?,
+            _ => {}
+        }
+        write!(w, "\t{:04x}\t", line.address())?;
+        // Operations added before any statement have no line of their own.
+        match (source_name, line.position().filter(|p| p.line() > 0)) {
+            (Some(source_name), Some(position)) => writeln!(
+                w,
+                "{:<width$}# {}:{}",
+                instruction,
+                source_name,
+                position.line(),
+                width = INSTRUCTION_WIDTH
+            )?,
+            _ => writeln!(w, "{}", instruction)?,
         }
     }
     writeln!(w)?;
     for function in functions.iter() {
-        disassemble_function(&function.chunk(), function.name(), w)?;
+        disassemble_function(function.chunk(), function.name(), source_name, w)?;
     }
     Ok(())
 }
@@ -60,7 +184,7 @@ mod tests {
 
     #[test]
     fn disassemble_single_instruction() {
-        let chunk = Chunk::new([Op::Return], []);
+        let chunk = Chunk::try_new([Op::Return], []).unwrap();
 
         let out = test_disassemble(&chunk);
         let mut lines = out.lines();
@@ -70,7 +194,7 @@ mod tests {
 
     #[test]
     fn disassemble_instructions_with_parameters() {
-        let chunk = Chunk::new([Op::ConstFloat(3.42), Op::ConstBool(true)], []);
+        let chunk = Chunk::try_new([Op::ConstFloat(3.42), Op::ConstBool(true)], []).unwrap();
 
         let out = test_disassemble(&chunk);
         let mut lines = out.lines();
@@ -81,7 +205,7 @@ mod tests {
 
     #[test]
     fn disassemble_jump_instructions() {
-        let chunk = Chunk::new(
+        let chunk = Chunk::try_new(
             [
                 Op::ConstFloat(5.0),
                 Op::ConstFloat(1.0),
@@ -89,7 +213,8 @@ mod tests {
                 Op::Jump(-2),
             ],
             [],
-        );
+        )
+        .unwrap();
 
         let out = test_disassemble(&chunk);
         let mut lines = out.lines();
@@ -99,10 +224,7 @@ mod tests {
 
     #[test]
     fn disassemble_string_constants() {
-        let chunk = Chunk::new(
-            [Op::Const(0)],
-            [ValueType::Text(Box::new(String::from("Hello, World!")))],
-        );
+        let chunk = Chunk::try_new([Op::Const(0)], [ValueType::string("Hello, World!")]).unwrap();
 
         let out = test_disassemble(&chunk);
         let mut lines = out.lines();
@@ -112,17 +234,16 @@ mod tests {
 
     #[test]
     fn disassemble_functions() {
-        let function_chunk = Chunk::new(
-            [Op::Const(0), Op::Return],
-            [ValueType::Text(Box::new(String::from("Hello")))],
-        );
-        let function = ValueType::Function(Box::new(Function::new(
+        let function_chunk =
+            Chunk::try_new([Op::Const(0), Op::Return], [ValueType::string("Hello")]).unwrap();
+        let function = ValueType::Function(Rc::new(Function::new(
             "greet".to_string(),
             Rc::new(function_chunk),
             0,
         )));
 
-        let script_chunk = Chunk::new([Op::Const(0), Op::Call(0), Op::Print], [function]);
+        let script_chunk =
+            Chunk::try_new([Op::Const(0), Op::Call(0), Op::Print], [function]).unwrap();
 
         let out = test_disassemble(&script_chunk);
         let mut lines = out.lines();
@@ -132,4 +253,54 @@ mod tests {
         assert_eq!(lines.nth(1), Some("\t0000\tCONST, 0"));
         assert_eq!(lines.next(), Some("\t0001\tRET"));
     }
+
+    #[test]
+    fn disassemble_to_structured_lines() {
+        let chunk = Chunk::try_new(
+            [Op::LoadGlobal(0), Op::JumpIfFalse(1), Op::Nil, Op::Return],
+            [ValueType::string("x")],
+        )
+        .unwrap();
+
+        let lines = disassemble_to_ir(&chunk);
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0].opcode(), "LD_G");
+        assert_eq!(lines[0].operand(), Some(Operand::Index(0)));
+        assert_eq!(
+            lines[0].constant().map(ValueType::as_string),
+            Some("x".to_string())
+        );
+        assert_eq!(lines[1].address(), 1);
+        assert_eq!(lines[1].operand(), Some(Operand::Offset(1)));
+        assert_eq!(lines[1].target(), Some(2));
+        assert_eq!(lines[2].operand(), None);
+        assert_eq!(lines[2].constant(), None);
+    }
+
+    #[test]
+    fn annotate_instructions_with_source_lines() {
+        let chunk = Chunk::try_new([Op::Nil, Op::Jump(-1), Op::Return], [])
+            .unwrap()
+            .with_positions([
+                Position::new(1, 4),
+                Position::new(3, 2),
+                Position::default(),
+            ]);
+        let mut w = vec![];
+
+        disassemble_with_source(&chunk, "a.bbl", &mut w).unwrap();
+        let out = String::from_utf8(w).unwrap();
+        let mut lines = out.lines().skip(3);
+
+        assert_eq!(
+            lines.next(),
+            Some("\t0000\tCONST_NIL                       # a.bbl:1")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("\t0001\tJMP, -1 # 0000                  # a.bbl:3")
+        );
+        assert_eq!(lines.next(), Some("\t0002\tRET"));
+    }
 }
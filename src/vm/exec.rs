@@ -4,8 +4,10 @@
 
 use std::fmt::Display;
 
+use crate::source::Position;
 use crate::value::ValueType;
 
+use super::bytecode::{self, BytecodeError};
 use super::opcode::Op;
 
 /// In-memory representation of the executable VM can run.
@@ -14,9 +16,18 @@ use super::opcode::Op;
 /// - Instructions - a list of VM operations.
 /// - Constant pool - a list of constants necessary for program execution.
 ///
+/// The chunk may also hold the source positions of its operations to report runtime errors.
+///
+/// The operations are stored compactly: a one-byte opcode and a 32-bit inline operand per address.
+/// Floating-point operands live in a separate number table.
+///
 /// The Chunk is generally immutable.
 /// The compiler uses [ChunkBuilder](crate::compiler::chunk::ChunkBuilder) to gradually build executable chunks.
 ///
+/// With the `serde` feature enabled, the chunk is serialized as the list of its operations,
+/// the constants, and the positions.
+/// Chunks defining functions fail to serialize, use [Chunk::serialize] for them.
+///
 /// # Examples
 ///
 /// ## Run a chunk in a virtual machine.
@@ -29,7 +40,7 @@ use super::opcode::Op;
 /// # use brainterpreter::vm::opcode::Op;
 /// # use brainterpreter::vm::Vm;
 /// # fn main() -> Result<(), Box<dyn Error>> {
-/// let answer_chunk = Chunk::new(
+/// let answer_chunk = Chunk::try_new(
 ///     [
 ///         Op::Const(0),
 ///         Op::Const(1),
@@ -39,44 +50,100 @@ use super::opcode::Op;
 ///     [
 ///         ValueType::Number(6.0),
 ///         ValueType::Number(7.0),
-///     ]);
+///     ],
+/// )?;
 /// let mut vm = Vm::default();
 /// vm.load_and_run(Rc::new(answer_chunk))?;
 /// # Ok(())
 /// # }
 /// ```
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "ChunkData", try_from = "ChunkData")
+)]
 pub struct Chunk {
     constants: Vec<ValueType>,
-    ops: Vec<Op>,
+    opcodes: Vec<u8>,
+    operands: Vec<u32>,
+    numbers: Vec<f64>,
+    positions: Vec<Position>,
 }
 
 impl Chunk {
     /// Creates a new chunk from a list of operations and constants.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an operand does not fit 32 bits, see [Chunk::try_new].
+    #[deprecated(note = "use `Chunk::try_new`, which fails instead of panicking on wide operands")]
     pub fn new<I, C>(ops: I, constants: C) -> Self
     where
         I: IntoIterator<Item = Op>,
         C: IntoIterator<Item = ValueType>,
     {
-        Chunk {
-            ops: ops.into_iter().collect(),
+        Chunk::try_new(ops, constants).expect("operand does not fit 32 bits")
+    }
+
+    /// Creates a new chunk from a list of operations and constants,
+    /// failing if an operand of an operation does not fit 32 bits.
+    pub fn try_new<I, C>(ops: I, constants: C) -> Result<Self, BytecodeError>
+    where
+        I: IntoIterator<Item = Op>,
+        C: IntoIterator<Item = ValueType>,
+    {
+        let ops = ops.into_iter();
+        let mut chunk = Chunk {
+            opcodes: Vec::with_capacity(ops.size_hint().0),
+            operands: Vec::with_capacity(ops.size_hint().0),
             constants: constants.into_iter().collect(),
+            ..Default::default()
+        };
+        for op in ops {
+            let (opcode, operand) = op
+                .encode(&mut chunk.numbers)
+                .ok_or_else(|| BytecodeError::OperandOutOfRange(op.to_string()))?;
+            chunk.opcodes.push(opcode);
+            chunk.operands.push(operand);
         }
+        Ok(chunk)
+    }
+
+    /// Sets source positions of the operations.
+    /// The position on index `i` belongs to the operation on address `i`.
+    pub fn with_positions<P>(mut self, positions: P) -> Self
+    where
+        P: IntoIterator<Item = Position>,
+    {
+        self.positions = positions.into_iter().collect();
+        self
+    }
+
+    /// Returns the source position of the operation on address.
+    pub fn position(&self, idx: usize) -> Option<Position> {
+        self.positions.get(idx).copied()
+    }
+
+    /// Return iterator over source positions of the operations.
+    pub fn positions(&self) -> impl ExactSizeIterator<Item = &Position> {
+        self.positions.iter()
     }
 
     /// Returns operation on address.
-    pub fn op(&self, idx: usize) -> Option<&Op> {
-        self.ops.get(idx)
+    pub fn op(&self, idx: usize) -> Option<Op> {
+        let opcode = *self.opcodes.get(idx)?;
+        Op::decode(opcode, *self.operands.get(idx)?, &self.numbers)
     }
 
     /// Count of opcodes in executable chunk.
     pub fn ops_len(&self) -> usize {
-        self.ops.len()
+        self.opcodes.len()
     }
 
-    /// Returns iterator of opcode references.
-    pub fn ops(&self) -> impl ExactSizeIterator<Item = &Op> {
-        self.ops.iter()
+    /// Returns iterator of the operations.
+    pub fn ops(&self) -> impl Iterator<Item = Op> + '_ {
+        (0..self.ops_len()).map_while(|idx| self.op(idx))
     }
 
     /// Get constant from a constants pool by index.
@@ -96,15 +163,117 @@ impl Chunk {
 
     /// Returns true if the chunk has no opcodes.
     pub fn is_empty(&self) -> bool {
-        self.ops.is_empty()
+        self.opcodes.is_empty()
+    }
+
+    /// Writes the chunk, including the chunks of its functions, into the binary bytecode format.
+    ///
+    /// Fails if the constant pool holds values created at runtime, e.g. arrays or closures.
+    pub fn serialize(&self) -> Result<Vec<u8>, BytecodeError> {
+        bytecode::write(self)
+    }
+
+    /// Reads the chunk from the binary bytecode format written by [Chunk::serialize].
+    pub fn deserialize(bytes: &[u8]) -> Result<Chunk, BytecodeError> {
+        bytecode::read(bytes)
+    }
+}
+
+/// Readable form of the chunk for serialization, with the operations decoded.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ChunkData {
+    ops: Vec<Op>,
+    constants: Vec<ValueType>,
+    positions: Vec<Position>,
+}
+
+#[cfg(feature = "serde")]
+impl From<Chunk> for ChunkData {
+    fn from(chunk: Chunk) -> Self {
+        ChunkData {
+            ops: chunk.ops().collect(),
+            constants: chunk.constants,
+            positions: chunk.positions,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<ChunkData> for Chunk {
+    type Error = BytecodeError;
+
+    fn try_from(data: ChunkData) -> Result<Self, Self::Error> {
+        Ok(Chunk::try_new(data.ops, data.constants)?.with_positions(data.positions))
     }
 }
 
 impl Display for Chunk {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for op in self.ops.iter() {
+        for op in self.ops() {
             writeln!(f, "{}", op)?
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_encoded_operations() {
+        let ops = [
+            Op::ConstFloat(-0.25),
+            Op::ConstBool(true),
+            Op::Const(3),
+            Op::JumpIfFalse(-7),
+            Op::PushHandler(12),
+            Op::ConstFloat(1e300),
+            Op::Map(2),
+            Op::Gt,
+        ];
+
+        let chunk = Chunk::try_new(ops, []).unwrap();
+
+        assert_eq!(chunk.ops_len(), ops.len());
+        assert!(chunk.ops().eq(ops));
+        assert_eq!(chunk.op(3), Some(Op::JumpIfFalse(-7)));
+        assert_eq!(chunk.op(8), None);
+    }
+
+    #[test]
+    fn reject_operands_wider_than_32_bits() {
+        let error = Chunk::try_new([Op::Nil, Op::Call(1 << 40)], []).unwrap_err();
+
+        assert!(matches!(error, BytecodeError::OperandOutOfRange(op) if op.starts_with("CALL")));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_chunk_with_decoded_operations() {
+        use std::rc::Rc;
+
+        use crate::value::Function;
+
+        let chunk = Chunk::try_new(
+            [Op::ConstFloat(0.5), Op::Const(0), Op::Add, Op::Print],
+            [ValueType::string("text")],
+        )
+        .unwrap()
+        .with_positions([Position::new(1, 1); 4]);
+
+        let json = serde_json::to_value(&chunk).unwrap();
+        assert_eq!(json["ops"][0]["ConstFloat"], 0.5);
+        assert_eq!(json["constants"][0]["Text"], "text");
+        let parsed: Chunk = serde_json::from_value(json).unwrap();
+        assert!(parsed.ops().eq(chunk.ops()));
+        assert_eq!(parsed.constant(0), chunk.constant(0));
+        assert_eq!(parsed.position(3), Some(Position::new(1, 1)));
+
+        let function = Function::new("f".to_string(), Rc::new(Chunk::default()), 0);
+        let chunk =
+            Chunk::try_new([Op::Const(0)], [ValueType::Function(Rc::new(function))]).unwrap();
+        assert!(serde_json::to_string(&chunk).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+//! Global variables stored in numbered slots.
+//!
+//! The compiler assigns the slots when it compiles the code for the machine running it,
+//! so the code reads and writes the globals by index.
+//! Code compiled on its own, e.g. loaded from the bytecode, finds the slots by the names of the globals.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::value::ValueType;
+
+/// Names of the global variables with their slots, shared by the compiler and the virtual machine.
+#[derive(Debug, Default)]
+pub struct GlobalSlots {
+    slots: HashMap<String, usize>,
+    names: Vec<Rc<String>>,
+}
+
+impl GlobalSlots {
+    /// Slot of the global, assigning the next free one to a new name.
+    pub fn slot(&mut self, name: &Rc<String>) -> usize {
+        match self.slots.get(name.as_str()) {
+            Some(slot) => *slot,
+            None => {
+                self.names.push(name.clone());
+                self.slots.insert(name.to_string(), self.names.len() - 1);
+                self.names.len() - 1
+            }
+        }
+    }
+
+    /// Slot of the global, if it has one.
+    pub fn get(&self, name: &str) -> Option<usize> {
+        self.slots.get(name).copied()
+    }
+
+    /// Name of the global in the slot.
+    pub fn name(&self, slot: usize) -> Option<&Rc<String>> {
+        self.names.get(slot)
+    }
+}
+
+/// Values of the global variables of the virtual machine.
+#[derive(Debug, Default)]
+pub(crate) struct Globals {
+    slots: Rc<RefCell<GlobalSlots>>,
+    /// Values by slot, with `None` for the globals that are not defined yet.
+    values: Vec<Option<ValueType>>,
+}
+
+impl Globals {
+    /// Symbol table for compiling the code addressing the globals by slot.
+    pub(crate) fn slots(&self) -> Rc<RefCell<GlobalSlots>> {
+        self.slots.clone()
+    }
+
+    pub(crate) fn get(&self, slot: usize) -> Option<&ValueType> {
+        self.values.get(slot).and_then(Option::as_ref)
+    }
+
+    pub(crate) fn set(&mut self, slot: usize, value: ValueType) {
+        if slot >= self.values.len() {
+            self.values.resize(slot + 1, None);
+        }
+        self.values[slot] = Some(value);
+    }
+
+    /// Slot of the global with the name, if it has one.
+    pub(crate) fn find(&self, name: &str) -> Option<usize> {
+        self.slots.borrow().get(name)
+    }
+
+    /// Slot of the global with the name, assigning a new one if needed.
+    pub(crate) fn slot(&mut self, name: &Rc<String>) -> usize {
+        self.slots.borrow_mut().slot(name)
+    }
+
+    /// Name of the global in the slot, used to report undefined globals.
+    pub(crate) fn name(&self, slot: usize) -> String {
+        self.slots
+            .borrow()
+            .name(slot)
+            .map_or_else(|| format!("#{}", slot), |name| name.to_string())
+    }
+
+    /// Defined globals with their names.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (Rc<String>, &ValueType)> + '_ {
+        self.values.iter().enumerate().filter_map(|(slot, value)| {
+            let name = self.slots.borrow().name(slot).cloned()?;
+            Some((name, value.as_ref()?))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assign_slots_in_order() {
+        let mut slots = GlobalSlots::default();
+        let a = Rc::new("a".to_string());
+
+        assert_eq!(slots.slot(&a), 0);
+        assert_eq!(slots.slot(&Rc::new("b".to_string())), 1);
+        assert_eq!(slots.slot(&a), 0);
+        assert_eq!(slots.get("b"), Some(1));
+        assert_eq!(slots.get("c"), None);
+        assert_eq!(slots.name(1).map(|name| name.as_str()), Some("b"));
+    }
+}
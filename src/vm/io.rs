@@ -0,0 +1,103 @@
+//! Streams the programs read from and write to.
+//!
+//! The virtual machine uses the standard streams by default.
+//! Embedders replace them to capture the output or to feed the input, e.g. in tests.
+
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::rc::Rc;
+
+/// Input and outputs of the virtual machine.
+///
+/// `print` writes to the standard output, `eprint` to the error output,
+/// and `read_line` reads the standard input.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// # use brainterpreter::vm::io::VmIo;
+/// # use brainterpreter::vm::Vm;
+/// let out = Rc::new(RefCell::new(vec![]));
+/// let err = Rc::new(RefCell::new(vec![]));
+/// let io = VmIo::default()
+///     .with_stdin("Bauble\n".as_bytes())
+///     .with_stdout(out.clone())
+///     .with_stderr(err.clone());
+/// let mut vm = Vm::default().with_streams(io);
+/// vm.eval("print \"Hello, \" + read_line(); eprint(\"done\");").unwrap();
+///
+/// assert_eq!(out.borrow().as_slice(), b"Hello, Bauble\n");
+/// assert_eq!(err.borrow().as_slice(), b"done\n");
+/// ```
+#[derive(Clone)]
+pub struct VmIo {
+    stdin: Rc<RefCell<dyn BufRead>>,
+    stdout: Rc<RefCell<dyn Write>>,
+    stderr: Rc<RefCell<dyn Write>>,
+}
+
+impl VmIo {
+    /// Sets the input, buffering it for reading by lines.
+    pub fn with_stdin(self, stdin: impl Read + 'static) -> Self {
+        VmIo {
+            stdin: Rc::new(RefCell::new(BufReader::new(stdin))),
+            ..self
+        }
+    }
+
+    /// Sets the input shared with the caller.
+    pub fn with_buffered_stdin<T>(self, stdin: Rc<RefCell<T>>) -> Self
+    where
+        T: BufRead + 'static,
+    {
+        VmIo { stdin, ..self }
+    }
+
+    /// Sets the output of the `print` statement and function.
+    pub fn with_stdout<T>(self, stdout: Rc<RefCell<T>>) -> Self
+    where
+        T: Write + 'static,
+    {
+        VmIo { stdout, ..self }
+    }
+
+    /// Sets the output of the `eprint` function.
+    pub fn with_stderr<T>(self, stderr: Rc<RefCell<T>>) -> Self
+    where
+        T: Write + 'static,
+    {
+        VmIo { stderr, ..self }
+    }
+
+    pub(crate) fn stdin(&self) -> &Rc<RefCell<dyn BufRead>> {
+        &self.stdin
+    }
+
+    pub(crate) fn stdout(&self) -> &Rc<RefCell<dyn Write>> {
+        &self.stdout
+    }
+
+    pub(crate) fn stderr(&self) -> &Rc<RefCell<dyn Write>> {
+        &self.stderr
+    }
+}
+
+impl Default for VmIo {
+    /// Standard streams, or no streams on WebAssembly, which has none.
+    fn default() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        return VmIo {
+            stdin: Rc::new(RefCell::new(BufReader::new(std::io::stdin()))),
+            stdout: Rc::new(RefCell::new(std::io::stdout())),
+            stderr: Rc::new(RefCell::new(std::io::stderr())),
+        };
+        #[cfg(target_arch = "wasm32")]
+        return VmIo {
+            stdin: Rc::new(RefCell::new(std::io::empty())),
+            stdout: Rc::new(RefCell::new(std::io::sink())),
+            stderr: Rc::new(RefCell::new(std::io::sink())),
+        };
+    }
+}
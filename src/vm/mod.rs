@@ -1,27 +1,43 @@
 //! Virtual machine for executing bytecode
 
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::io::{stdout, Write};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Write};
 use std::rc::Rc;
 
 use thiserror::Error;
 
-use call::CallFrame;
+use call::Handler;
+pub use call::{CallFrame, TraceFrame};
 
-use crate::log::LoggingTracer;
-use crate::value::{Function, NativeFunction, TypeError, ValueType};
+use crate::compiler::Compiler;
+use crate::source::Position;
+use crate::value::{Capture, Closure, Function, NativeFunction, TypeError, Upvalue, ValueType};
+use crate::vm::builder::VmBuilder;
+use crate::vm::coverage::Coverage;
 use crate::vm::exec::Chunk;
-use crate::vm::native::std_lib;
+use crate::vm::globals::{GlobalSlots, Globals};
+use crate::vm::io::VmIo;
 use crate::vm::opcode::Op;
-use crate::vm::trace::VmStepTrace;
+use crate::vm::provider::{Clock, RandomSource};
+use crate::vm::stats::Stats;
+use crate::vm::trace::{TraceEvent, VmStepTrace};
+use crate::{compile_source, BaubleError};
 
+pub mod builder;
+pub mod bytecode;
 mod call;
+pub mod coverage;
 pub mod disassembler;
 pub mod exec;
-mod native;
+pub mod globals;
+pub mod io;
+pub mod native;
 pub mod opcode;
+pub mod profiler;
+pub mod provider;
 mod stack;
+pub mod stats;
 pub mod trace;
 
 type VmResult = Result<(), VmRuntimeError>;
@@ -33,6 +49,9 @@ pub enum VmRuntimeError {
     Unknown,
     #[error("stack exhausted")]
     StackExhausted,
+    /// The instruction needs the running function, but the machine runs none, e.g. after a stray return.
+    #[error("no function is running to {0}")]
+    NoCallFrame(&'static str),
     #[error("operation is not implemented for operand type")]
     TypeMismatch,
     #[error("variable {0} is not defined")]
@@ -47,28 +66,239 @@ pub enum VmRuntimeError {
     UndefinedConstant(usize),
     #[error("accessing out of bounds value on index {0} with size {1}")]
     OutOfBounds(usize, f64),
-    #[error("error accessing array {0}")]
-    ArrayAccessError(#[from] TypeError),
+    #[error("{0}")]
+    TypeError(#[from] TypeError),
+    #[error("execution ran out of fuel")]
+    OutOfFuel,
+    #[error("undefined captured variable at index {0}")]
+    UndefinedUpvalue(usize),
+    #[error("call stack overflow calling function {function} at depth {depth}")]
+    CallStackOverflow { depth: usize, function: String },
+    #[error("stack overflow: more than {0} values on the stack")]
+    StackOverflow(usize),
+    #[error("uncaught exception: {}", .0.as_string())]
+    Uncaught(ValueType),
+    #[error("assertion failed{}", .0.as_ref().map(|m| format!(": {}", m)).unwrap_or_default())]
+    AssertionFailed(Option<String>),
+    #[error("cannot unpack {actual} values into {expected} variables")]
+    UnpackMismatch { expected: usize, actual: usize },
+    #[error("integer overflow")]
+    IntegerOverflow,
+    #[error("range counter {0} is too large to advance by one")]
+    RangeCounterTooLarge(f64),
+    #[error("array size must be a whole non-negative number. {0} is not a valid size")]
+    InvalidArraySize(ValueType),
+    #[error("array of {0} elements is larger than the limit of {MAX_ARRAY_LEN} elements")]
+    ArrayTooLarge(u128),
+    /// The program called the `exit` function.
+    #[error("program exited with code {0}")]
+    Exit(i32),
+    /// Error of the instruction on the address of the executed chunk.
+    #[error("{source}{}", position.map(|p| format!(" at {}", p)).unwrap_or_default())]
+    Located {
+        address: usize,
+        position: Option<Position>,
+        source: Box<VmRuntimeError>,
+        backtrace: Vec<TraceFrame>,
+    },
+}
+
+impl VmRuntimeError {
+    /// Stable identifier of the error kind.
+    pub fn code(&self) -> &'static str {
+        match self {
+            VmRuntimeError::Unknown => "runtime-error",
+            VmRuntimeError::StackExhausted => "stack-exhausted",
+            VmRuntimeError::NoCallFrame(_) => "no-call-frame",
+            VmRuntimeError::TypeMismatch => "type-mismatch",
+            VmRuntimeError::UndefinedVariable(_) => "undefined-variable",
+            VmRuntimeError::WrongOperation => "wrong-operation",
+            VmRuntimeError::IllegalJump(..) => "illegal-jump",
+            VmRuntimeError::IoError(_) => "io-error",
+            VmRuntimeError::UndefinedConstant(_) => "undefined-constant",
+            VmRuntimeError::OutOfBounds(..) => "out-of-bounds",
+            VmRuntimeError::TypeError(_) => "type-error",
+            VmRuntimeError::OutOfFuel => "out-of-fuel",
+            VmRuntimeError::UndefinedUpvalue(_) => "undefined-upvalue",
+            VmRuntimeError::CallStackOverflow { .. } => "call-stack-overflow",
+            VmRuntimeError::StackOverflow(_) => "stack-overflow",
+            VmRuntimeError::Uncaught(_) => "uncaught-exception",
+            VmRuntimeError::AssertionFailed(_) => "assertion-failed",
+            VmRuntimeError::UnpackMismatch { .. } => "unpack-mismatch",
+            VmRuntimeError::IntegerOverflow => "integer-overflow",
+            VmRuntimeError::RangeCounterTooLarge(_) => "range-counter-too-large",
+            VmRuntimeError::InvalidArraySize(_) => "invalid-array-size",
+            VmRuntimeError::ArrayTooLarge(_) => "array-too-large",
+            VmRuntimeError::Exit(_) => "exit",
+            VmRuntimeError::Located { source, .. } => source.code(),
+        }
+    }
+
+    /// Source position of the operation that failed, if the chunk has it.
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            VmRuntimeError::Located { position, .. } => *position,
+            _ => None,
+        }
+    }
+
+    /// Address of the failed operation in the chunk being executed.
+    pub fn address(&self) -> Option<usize> {
+        match self {
+            VmRuntimeError::Located { address, .. } => Some(*address),
+            _ => None,
+        }
+    }
+
+    /// Calls active when the error occurred, starting from the innermost one.
+    pub fn backtrace(&self) -> &[TraceFrame] {
+        match self {
+            VmRuntimeError::Located { backtrace, .. } => backtrace,
+            _ => &[],
+        }
+    }
+
+    /// Code the program asked to exit with, if the error comes from the `exit` function.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self.kind() {
+            VmRuntimeError::Exit(code) => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// Whether a `try` block of the program can handle the error.
+    /// Running out of fuel, failed assertions, exits, and stack corruption stop the program regardless.
+    fn is_catchable(&self) -> bool {
+        !matches!(
+            self.kind(),
+            VmRuntimeError::OutOfFuel
+                | VmRuntimeError::AssertionFailed(_)
+                | VmRuntimeError::Exit(_)
+                | VmRuntimeError::StackExhausted
+                | VmRuntimeError::NoCallFrame(_)
+        )
+    }
+
+    /// The error without its location.
+    pub fn kind(&self) -> &VmRuntimeError {
+        match self {
+            VmRuntimeError::Located { source, .. } => source.kind(),
+            error => error,
+        }
+    }
+}
+
+/// Result of executing a single instruction with [Vm::step].
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepOutcome {
+    /// The instruction is executed and the program has more instructions to run.
+    Continued,
+    /// The program is finished and returned the value.
+    Finished(ValueType),
+    /// The next instruction has a breakpoint and is not executed yet.
+    /// The following step executes it.
+    Breakpoint,
 }
 
 /// Virtual machine to run programs
 pub struct Vm {
     stack: VmStack,
-    globals: HashMap<String, ValueType>,
+    globals: Globals,
     frames: Vec<CallFrame>,
     trace: Option<Box<dyn VmStepTrace>>,
-    out: Rc<RefCell<dyn Write>>,
+    io: VmIo,
+    random: Box<dyn RandomSource>,
+    clock: Box<dyn Clock>,
+    /// Arguments the `args` function returns.
+    args: Vec<String>,
+    env_access: bool,
+    exit_allowed: bool,
+    fuel: Option<usize>,
+    /// Whether conditions accept only booleans instead of any value.
+    strict_conditions: bool,
+    coverage: Option<Coverage>,
+    stats: Option<Stats>,
+    max_call_depth: usize,
+    breakpoints: HashSet<(*const Chunk, usize)>,
+    resume_from_breakpoint: bool,
+    /// Captured variables still living on the stack.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// Number of frames below the function called by a native, whose exception handlers it cannot reach.
+    handler_barrier: usize,
 }
 
-const STACK_SIZE: usize = 1024 * 1024;
+/// Default limit of the values on the operand stack.
+pub const STACK_SIZE: usize = 1024 * 1024;
+/// Default limit of nested function calls.
+pub const MAX_CALL_DEPTH: usize = 64 * 1024;
+/// Limit of the elements of an array created at once, e.g. by `[nil; n]` or the `matrix` function.
+pub const MAX_ARRAY_LEN: usize = 16 * 1024 * 1024;
 
+/// Operand stack of the virtual machine.
+///
+/// The top value is kept in a separate field, so most instructions work with it
+/// without touching the rest of the stack.
 #[derive(Debug)]
 pub struct VmStack {
-    stack: Vec<ValueType>,
+    slots: Vec<ValueType>,
+    top: Option<ValueType>,
+    /// Maximum number of values on the stack.
+    limit: usize,
 }
 
 impl Vm {
-    pub fn load_and_run(&mut self, chunk: Rc<Chunk>) -> VmResult {
+    /// Compiles and runs the source code, returning the result of the program.
+    ///
+    /// The result is the value of the last expression statement, or the value of the top-level `return`.
+    /// Global variables and native functions of the machine are available to the source,
+    /// and the globals it defines are kept for the following evaluations.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use brainterpreter::value::ValueType;
+    /// # use brainterpreter::vm::Vm;
+    /// let mut vm = Vm::default();
+    /// vm.eval("let rate = 0.2;")?;
+    /// let tax = vm.eval("rate * 100;")?;
+    /// assert_eq!(tax, ValueType::Number(20.0));
+    /// # Ok::<(), brainterpreter::BaubleError>(())
+    /// ```
+    pub fn eval(&mut self, source: &str) -> Result<ValueType, BaubleError> {
+        let chunk = compile_source(source, self.compiler())?;
+        let value = self.load_and_run(Rc::new(chunk))?;
+        Ok(value)
+    }
+
+    /// Runs the chunk to the end and returns the result of the program.
+    pub fn load_and_run(&mut self, chunk: Rc<Chunk>) -> Result<ValueType, VmRuntimeError> {
+        self.load(chunk);
+        loop {
+            if let StepOutcome::Finished(value) = self.step()? {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Runs the copy of the chunk to the end, so the caller keeps the chunk, e.g. to run it again.
+    pub fn run_chunk(&mut self, chunk: &Chunk) -> Result<ValueType, VmRuntimeError> {
+        self.load_and_run(Rc::new(chunk.clone()))
+    }
+
+    /// Prepares the chunk for execution with [Vm::step] without running it.
+    ///
+    /// Loading discards the state of the previously loaded program, e.g., after a runtime error.
+    /// Global variables are kept, so the chunk can use the definitions of the previous programs.
+    pub fn load(&mut self, chunk: Rc<Chunk>) {
+        self.frames.clear();
+        self.stack.clear();
+        self.open_upvalues.clear();
+        self.resume_from_breakpoint = false;
+        self.handler_barrier = 0;
+
+        if let Some(coverage) = &mut self.coverage {
+            coverage.add_chunk(&chunk);
+        }
         let call_frame = CallFrame::new(chunk.clone(), 0);
         self.frames.push(call_frame);
 
@@ -79,77 +309,288 @@ impl Vm {
         // allocation fails.
         // Having a virtual function prevents this issue.
         let virtual_main_function = Function::script(chunk.clone());
-        self.stack
-            .push(ValueType::Function(Box::new(virtual_main_function)));
-        self.execute()?;
-        self.stack.pop()?;
-        Ok(())
+        // The stack was just cleared, so the function fits unless the limit allows no values at all.
+        // Then the first instruction finds the stack exhausted.
+        let _ = self
+            .stack
+            .push(ValueType::Function(Rc::new(virtual_main_function)));
     }
 
-    fn execute(&mut self) -> VmResult {
-        while let Some(op) = self.advance() {
-            let op = op.clone();
-            self.trace_before();
-            match op {
-                Op::Return => self.ret()?,
-                Op::Array => self.initialize_array()?,
-                Op::Call(arity) => self.call(arity)?,
-                Op::Const(n) => {
-                    let value = self.constant(n)?;
-                    self.stack.push(value);
-                }
-                Op::ConstFloat(n) => {
-                    let value = ValueType::Number(n);
-                    self.stack.push(value);
-                }
-                Op::ConstBool(b) => {
-                    let value = ValueType::Bool(b);
-                    self.stack.push(value);
-                }
-                Op::LoadIndex => self.binary_operation(op.clone())?,
-                Op::StoreIndex => self.store_index()?,
-                Op::Pop => {
-                    self.stack.pop()?;
-                }
-                Op::Nil => {
-                    self.stack.push(ValueType::Nil);
-                }
-                Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Ge | Op::Le | Op::Cmp => {
-                    self.binary_operation(op.clone())?
-                }
-                Op::Not => self.not()?,
-                Op::Print => self.print()?,
-                Op::StoreGlobal(idx) => self.store_global(idx)?,
-                Op::LoadGlobal(idx) => self.load_global(idx)?,
-                Op::StoreLocal(offset) => self.store_local(offset)?,
-                Op::LoadLocal(offset) => self.load_local(offset)?,
-                Op::Jump(offset) => self.jump(offset)?,
-                Op::JumpIfFalse(offset) => self.jump_if_false(offset)?,
+    /// Executes a single instruction of the loaded program.
+    ///
+    /// Stepping a machine without a loaded program reports it as finished.
+    pub fn step(&mut self) -> Result<StepOutcome, VmRuntimeError> {
+        if !self.resume_from_breakpoint && self.at_breakpoint() {
+            self.resume_from_breakpoint = true;
+            return Ok(StepOutcome::Breakpoint);
+        }
+        self.resume_from_breakpoint = false;
+
+        let Some(op) = self.advance() else {
+            return self.finish().map(StepOutcome::Finished);
+        };
+        self.burn_fuel()?;
+        let address = self.ip().saturating_sub(1);
+        let position = self
+            .current_chunk()
+            .and_then(|chunk| chunk.position(address));
+        if let (Some(coverage), Some(position)) = (&mut self.coverage, position) {
+            coverage.hit(position.line());
+        }
+        self.trace_instruction(address, op);
+        let result = match self.execute(op) {
+            // Values thrown by the functions natives call keep going to the handlers of the caller.
+            Err(error) if self.has_handlers() => match error.kind() {
+                VmRuntimeError::Uncaught(value) => self.throw(value.clone()),
+                // Failures of the program are thrown to its handler like the thrown values.
+                _ if error.is_catchable() => self.throw(ValueType::error(error.to_string())),
+                _ => Err(error),
+            },
+            result => result,
+        };
+        result.map_err(|error| match error {
+            // Errors of the functions natives call are located where they happened.
+            error @ VmRuntimeError::Located { .. } => error,
+            error => VmRuntimeError::Located {
+                address,
+                position,
+                source: Box::new(error),
+                backtrace: self.backtrace(),
+            },
+        })?;
+        if let Some(stats) = &mut self.stats {
+            stats.executed(self.stack.len());
+        }
+        self.trace_after();
+        Ok(StepOutcome::Continued)
+    }
+
+    fn execute(&mut self, op: Op) -> VmResult {
+        match op {
+            Op::Return => self.ret()?,
+            Op::Array => self.initialize_array()?,
+            Op::Map(len) => self.initialize_map(len)?,
+            Op::Call(arity) => self.call(arity)?,
+            Op::TailCall(arity) => self.tail_call(arity)?,
+            Op::Const(n) => {
+                let value = self.constant(n)?;
+                self.stack.push(value)?;
+            }
+            Op::ConstFloat(n) => {
+                let value = ValueType::Number(n);
+                self.stack.push(value)?;
+            }
+            Op::ConstInt(n) => {
+                let value = ValueType::Int(n);
+                self.stack.push(value)?;
+            }
+            Op::ConstBool(b) => {
+                let value = ValueType::Bool(b);
+                self.stack.push(value)?;
+            }
+            Op::LoadIndex => self.binary_operation(op)?,
+            Op::StoreIndex => self.store_index()?,
+            Op::ReplaceIndex => self.replace_index()?,
+            Op::Pop => {
+                self.stack.pop()?;
+            }
+            Op::Nil => {
+                self.stack.push(ValueType::Nil)?;
+            }
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Pow | Op::Ge | Op::Le | Op::Cmp => {
+                self.binary_operation(op)?
+            }
+            Op::Not => self.not()?,
+            Op::Ne => {
+                self.binary_operation(Op::Cmp)?;
+                self.not()?
+            }
+            Op::Lt => {
+                self.binary_operation(Op::Ge)?;
+                self.not()?
             }
-            self.trace_after()
+            Op::Gt => {
+                self.binary_operation(Op::Le)?;
+                self.not()?
+            }
+            Op::Match => {
+                let value = self.stack.pop()?;
+                let subject = self
+                    .stack
+                    .last_mut()
+                    .ok_or(VmRuntimeError::StackExhausted)?;
+                *subject = ValueType::Bool(value.equals(subject).unwrap_or(false));
+            }
+            Op::Print => self.print()?,
+            Op::StoreGlobal(idx) => self.store_global(idx)?,
+            Op::LoadGlobal(idx) => self.load_global(idx)?,
+            Op::StoreGlobalSlot(slot) => self.store_global_slot(slot)?,
+            Op::LoadGlobalSlot(slot) => self.load_global_slot(slot)?,
+            Op::StoreLocal(offset) => self.store_local(offset)?,
+            Op::LoadLocal(offset) => self.load_local(offset)?,
+            Op::StoreUpvalue(idx) => self.store_upvalue(idx)?,
+            Op::LoadUpvalue(idx) => self.load_upvalue(idx)?,
+            Op::Closure(idx) => self.closure(idx)?,
+            Op::CloseUpvalue => {
+                let slot = self
+                    .stack
+                    .len()
+                    .checked_sub(1)
+                    .ok_or(VmRuntimeError::StackExhausted)?;
+                self.close_upvalues(slot);
+                self.stack.pop()?;
+            }
+            Op::Jump(offset) => self.jump(offset)?,
+            Op::JumpIfFalse(offset) => self.jump_if_false(offset)?,
+            Op::JumpIfTrue(offset) => self.jump_if_true(offset)?,
+            Op::PushHandler(offset) => self.push_handler(offset)?,
+            Op::PopHandler => {
+                self.frames
+                    .last_mut()
+                    .ok_or(VmRuntimeError::NoCallFrame("remove a handler"))?
+                    .pop_handler();
+            }
+            Op::Throw => {
+                let value = self.stack.pop()?;
+                self.throw(value)?;
+            }
+            Op::Assert => self.assert()?,
+            Op::IterInit => self.iter_init()?,
+            Op::IterNext(offset) => self.iter_next(offset)?,
+            Op::Range => self.range()?,
+            Op::Increment => self.increment()?,
+            Op::Tuple(len) => self.tuple(len)?,
+            Op::Unpack(len) => self.unpack(len)?,
         }
         Ok(())
     }
 
+    /// Stops [Vm::step] before executing the instruction at the address of the chunk.
+    ///
+    /// Function chunks can be obtained from the function constants of the program chunk.
+    pub fn add_breakpoint(&mut self, chunk: &Rc<Chunk>, address: usize) {
+        self.breakpoints.insert((Rc::as_ptr(chunk), address));
+    }
+
+    pub fn remove_breakpoint(&mut self, chunk: &Rc<Chunk>, address: usize) {
+        self.breakpoints.remove(&(Rc::as_ptr(chunk), address));
+    }
+
+    /// Address of the next instruction in the current frame.
+    pub fn ip(&self) -> usize {
+        self.frames.last().map(|frame| frame.ip()).unwrap_or(0)
+    }
+
+    /// The frame of the function being executed.
+    pub fn frame(&self) -> Option<&CallFrame> {
+        self.frames.last()
+    }
+
+    /// Trace of the active calls, starting from the innermost one.
+    pub fn backtrace(&self) -> Vec<TraceFrame> {
+        self.frames
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(depth, frame)| {
+                // The called function stays on the stack below its arguments.
+                let function = Some(callee_name(&self.stack, frame.stack_top()))
+                    .filter(|name| depth > 0 && !name.is_empty());
+                frame.trace(function)
+            })
+            .collect()
+    }
+
+    /// All active call frames, starting from the program itself.
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    /// The chunk being executed.
+    pub fn current_chunk(&self) -> Option<&Rc<Chunk>> {
+        self.frame().map(|frame| frame.chunk())
+    }
+
+    pub fn stack(&self) -> &VmStack {
+        &self.stack
+    }
+
+    /// Copy of the defined global variables by name.
+    pub fn globals(&self) -> HashMap<Rc<String>, ValueType> {
+        self.globals
+            .iter()
+            .map(|(name, value)| (name, value.clone()))
+            .collect()
+    }
+
+    /// Value of the global variable, if it is defined.
+    pub fn global(&self, name: &str) -> Option<&ValueType> {
+        self.globals
+            .find(name)
+            .and_then(|slot| self.globals.get(slot))
+    }
+
+    /// Symbol table of the globals, so the compiled code addresses them by slot.
+    pub fn global_slots(&self) -> Rc<RefCell<GlobalSlots>> {
+        self.globals.slots()
+    }
+
+    /// Compiler of the code for this machine, addressing the globals by slot.
+    pub(crate) fn compiler(&self) -> Compiler {
+        Compiler::default().with_global_slots(self.global_slots())
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        self.frames.last().is_some_and(|frame| {
+            self.breakpoints
+                .contains(&(Rc::as_ptr(frame.chunk()), frame.ip()))
+        })
+    }
+
+    /// Unwinds the finished program and returns its result.
+    fn finish(&mut self) -> Result<ValueType, VmRuntimeError> {
+        match self.frames.pop() {
+            // The program ran out of instructions without returning a value.
+            Some(frame) => {
+                self.close_upvalues(frame.stack_top());
+                self.stack.truncate(frame.stack_top());
+                Ok(ValueType::Nil)
+            }
+            // The program returned, leaving the result on the stack.
+            None => Ok(self.stack.pop().unwrap_or(ValueType::Nil)),
+        }
+    }
+
     fn binary_operation(&mut self, operation: Op) -> VmResult {
         let value_a = self.stack.pop()?;
-        let value_b = self.stack.pop()?;
+        // The result replaces the second operand on top of the stack.
+        let value_b = self
+            .stack
+            .last_mut()
+            .ok_or(VmRuntimeError::StackExhausted)?;
 
-        let result = match (operation, &value_a, &value_b) {
-            (Op::Add, ValueType::Number(a), ValueType::Number(b)) => ValueType::Number(a + b),
+        let result = match (operation, &value_a, &*value_b) {
             (Op::Add, ValueType::Text(a), ValueType::Text(b)) => {
                 let concat = format!("{}{}", a, b);
-                ValueType::Text(Box::new(concat))
-            }
-            (Op::Sub, ValueType::Number(a), ValueType::Number(b)) => ValueType::Number(a - b),
-            (Op::Mul, ValueType::Number(a), ValueType::Number(b)) => ValueType::Number(a * b),
-            (Op::Div, ValueType::Number(a), ValueType::Number(b)) => ValueType::Number(a / b),
-            (Op::Ge, ValueType::Number(a), ValueType::Number(b)) => ValueType::Bool(a >= b),
-            (Op::Le, ValueType::Number(a), ValueType::Number(b)) => ValueType::Bool(a <= b),
-            (Op::Cmp, ValueType::Number(a), ValueType::Number(b)) => ValueType::Bool(a == b),
-            (Op::Cmp, ValueType::Bool(a), ValueType::Bool(b)) => ValueType::Bool(a == b),
-            (Op::Cmp, ValueType::Text(a), ValueType::Text(b)) => ValueType::Bool(a == b),
-            (Op::LoadIndex, _, _) => self.load_index(&value_a, &value_b)?,
+                let concat = ValueType::string(concat);
+                if let Some(stats) = &mut self.stats {
+                    stats.allocated(&concat);
+                }
+                concat
+            }
+            (Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Pow | Op::Ge | Op::Le, a, b) => {
+                numeric_operation(operation, a, b).ok_or(VmRuntimeError::TypeMismatch)??
+            }
+            // Any value can be compared with nil, e.g., to check the result of `read_line`.
+            (Op::Cmp, a, b) => ValueType::Bool(a.equals(b).ok_or(VmRuntimeError::TypeMismatch)?),
+            (Op::LoadIndex, _, b) => {
+                let element = value_a.get(b).map_err(VmRuntimeError::TypeError)?;
+                // Characters of a string are new strings, elements of the other collections are shared.
+                if let (Some(stats), ValueType::Text(_)) = (&mut self.stats, &value_a) {
+                    stats.allocated(&element);
+                }
+                element
+            }
             (Op::Not, _, _) => {
                 return Err(VmRuntimeError::WrongOperation);
             }
@@ -157,233 +598,666 @@ impl Vm {
                 return Err(VmRuntimeError::TypeMismatch);
             }
         };
-        self.stack.push(result);
+        *value_b = result;
         Ok(())
     }
 
-    fn load_index(
-        &mut self,
-        value_a: &ValueType,
-        value_b: &ValueType,
-    ) -> Result<ValueType, VmRuntimeError> {
-        value_a
-            .get(value_b)
-            .map_err(VmRuntimeError::ArrayAccessError)
-    }
-
     fn store_index(&mut self) -> VmResult {
         let value = self.stack.pop()?;
+        let idx = self.stack.pop()?;
         let target = self.stack.pop()?;
+        // The changed copy of a string has no variable to go to.
+        if let ValueType::Text(_) = target {
+            return Err(VmRuntimeError::TypeError(TypeError::Immutable(target)));
+        }
+        target
+            .set(&idx, value.clone())
+            .map_err(VmRuntimeError::TypeError)?;
+        self.stack.push(value)?;
+        Ok(())
+    }
+
+    fn replace_index(&mut self) -> VmResult {
+        let value = self.stack.pop()?;
         let idx = self.stack.pop()?;
-        let new_value = target
-            .set(&idx, value)
-            .map_err(VmRuntimeError::ArrayAccessError)?;
-        self.stack.push(new_value);
+        let target = self.stack.pop()?;
+        let changed = target
+            .set(&idx, value.clone())
+            .map_err(VmRuntimeError::TypeError)?;
+        // Strings are replaced by new ones, arrays and maps change in place.
+        if let (Some(stats), ValueType::Text(_)) = (&mut self.stats, &target) {
+            stats.allocated(&changed);
+        }
+        self.stack.push(value)?;
+        self.stack.push(changed)?;
         Ok(())
     }
 
     fn not(&mut self) -> VmResult {
-        let result = match self.stack.pop()? {
-            ValueType::Bool(b) => ValueType::Bool(!b),
-            _ => {
-                return Err(VmRuntimeError::TypeMismatch);
+        let strict = self.strict_conditions;
+        match self.stack.last_mut() {
+            Some(ValueType::Bool(b)) => {
+                *b = !*b;
+                Ok(())
             }
-        };
-        self.stack.push(result);
-        Ok(())
+            Some(_) if strict => Err(VmRuntimeError::TypeMismatch),
+            Some(value) => {
+                *value = ValueType::Bool(!value.is_truthy());
+                Ok(())
+            }
+            None => Err(VmRuntimeError::StackExhausted),
+        }
     }
 
     fn print(&mut self) -> VmResult {
-        let line = match self.stack.pop()? {
-            ValueType::Number(n) => n.to_string(),
-            ValueType::Bool(b) => b.to_string(),
-            ValueType::Address(a) => a.to_string(),
-            ValueType::Nil => "nil".to_string(),
-            ValueType::Function(f) => {
-                format!("{}:{}", "fun", f.name())
-            }
-            ValueType::NativeFunction(f) => {
-                format!("[{}]:{}", "fun", f.name())
-            }
-            ValueType::Text(s) => *s,
-            ValueType::Array(a) => format_args!("[{}]\n", a.len()).to_string(),
-            ValueType::ArrayRef(a) => format_args!("&[{}]\n", a.borrow().len()).to_string(),
-        };
-        self.out
-            .borrow_mut()
-            .write_fmt(format_args!("{}\n", line))
-            .map_err(VmRuntimeError::IoError)
+        let value = self.stack.pop()?;
+        self.write_line(&printable(value))
     }
 
-    fn constant_entry(&self, idx: usize) -> Result<&ValueType, VmRuntimeError> {
-        let value = self
-            .chunk()
-            .constant(idx)
-            .ok_or(VmRuntimeError::UndefinedConstant(idx))?;
-        Ok(value)
+    /// Writes the line to the output of the virtual machine.
+    pub(crate) fn write_line(&mut self, line: &str) -> VmResult {
+        writeln!(self.io.stdout().borrow_mut(), "{}", line).map_err(VmRuntimeError::IoError)
     }
 
-    fn variable_name(&self, idx: usize) -> Result<String, VmRuntimeError> {
-        let value = self.constant_entry(idx)?;
-        if let ValueType::Text(name) = value {
-            Ok(name.to_string())
-        } else {
-            Err(VmRuntimeError::TypeMismatch)
-        }
+    /// Writes the line to the error output of the virtual machine.
+    pub(crate) fn write_error_line(&mut self, line: &str) -> VmResult {
+        writeln!(self.io.stderr().borrow_mut(), "{}", line).map_err(VmRuntimeError::IoError)
+    }
+
+    /// Streams the programs read from and write to.
+    pub(crate) fn io(&self) -> &VmIo {
+        &self.io
     }
 
     fn store_global(&mut self, idx: usize) -> VmResult {
-        let name = self.variable_name(idx)?;
-        let value = self.stack.peek(0).ok_or(VmRuntimeError::StackExhausted)?;
-        self.globals.insert(name, value.clone());
+        let name = variable_name(&self.frames, idx)?;
+        let value = self
+            .stack
+            .last()
+            .ok_or(VmRuntimeError::StackExhausted)?
+            .clone();
+        let slot = self.globals.slot(name);
+        self.globals.set(slot, value);
         Ok(())
     }
 
     fn load_global(&mut self, idx: usize) -> VmResult {
-        let name = self.variable_name(idx)?;
+        let name = variable_name(&self.frames, idx)?;
+        let value = self
+            .globals
+            .find(name)
+            .and_then(|slot| self.globals.get(slot))
+            .ok_or_else(|| VmRuntimeError::UndefinedVariable(name.to_string()))?
+            .clone();
+        self.stack.push(value)?;
+        Ok(())
+    }
+
+    fn store_global_slot(&mut self, slot: usize) -> VmResult {
+        let value = self
+            .stack
+            .last()
+            .ok_or(VmRuntimeError::StackExhausted)?
+            .clone();
+        self.globals.set(slot, value);
+        Ok(())
+    }
+
+    fn load_global_slot(&mut self, slot: usize) -> VmResult {
         let value = self
             .globals
-            .get(&name)
-            .ok_or(VmRuntimeError::UndefinedVariable(name.clone()))?;
-        self.stack.push(value.clone());
+            .get(slot)
+            .ok_or_else(|| VmRuntimeError::UndefinedVariable(self.globals.name(slot)))?
+            .clone();
+        self.stack.push(value)?;
         Ok(())
     }
 
     fn store_local(&mut self, offset: usize) -> VmResult {
         let value = self.stack.last().ok_or(VmRuntimeError::StackExhausted)?;
-        let frame_offset = self.frames.last().unwrap().stack_top() + offset + 1;
+        let frame_offset = self.running_frame("store a local variable")?.stack_top() + offset + 1;
         self.stack.set(frame_offset, value.clone())?;
         Ok(())
     }
 
     fn load_local(&mut self, offset: usize) -> VmResult {
-        let frame_offset = self.frames.last().unwrap().stack_top() + offset + 1;
+        let frame_offset = self.running_frame("load a local variable")?.stack_top() + offset + 1;
         let value = self
-            .stack
             .stack
             .get(frame_offset)
-            .ok_or(VmRuntimeError::UndefinedVariable(frame_offset.to_string()))?;
-        self.stack.push(value.clone());
+            .ok_or_else(|| VmRuntimeError::UndefinedVariable(frame_offset.to_string()))?
+            .clone();
+        self.stack.push(value)?;
+        Ok(())
+    }
+
+    fn upvalue(&self, idx: usize) -> Result<Rc<RefCell<Upvalue>>, VmRuntimeError> {
+        self.frames
+            .last()
+            .and_then(|frame| frame.upvalue(idx))
+            .cloned()
+            .ok_or(VmRuntimeError::UndefinedUpvalue(idx))
+    }
+
+    fn store_upvalue(&mut self, idx: usize) -> VmResult {
+        let value = self
+            .stack
+            .last()
+            .ok_or(VmRuntimeError::StackExhausted)?
+            .clone();
+        let upvalue = self.upvalue(idx)?;
+        let mut upvalue = upvalue.borrow_mut();
+        match &mut *upvalue {
+            Upvalue::Open(slot) => self.stack.set(*slot, value)?,
+            Upvalue::Closed(closed) => *closed = value,
+        }
+        Ok(())
+    }
+
+    fn load_upvalue(&mut self, idx: usize) -> VmResult {
+        let upvalue = self.upvalue(idx)?;
+        let value = match &*upvalue.borrow() {
+            Upvalue::Open(slot) => self
+                .stack
+                .get(*slot)
+                .cloned()
+                .ok_or(VmRuntimeError::StackExhausted)?,
+            Upvalue::Closed(value) => value.clone(),
+        };
+        self.stack.push(value)?;
+        Ok(())
+    }
+
+    fn closure(&mut self, idx: usize) -> VmResult {
+        let function = match self.constant(idx)? {
+            ValueType::Function(function) => function,
+            _ => return Err(VmRuntimeError::TypeMismatch),
+        };
+        let stack_top = self.running_frame("capture variables")?.stack_top();
+        let mut upvalues = vec![];
+        for capture in function.captures() {
+            let upvalue = match capture {
+                Capture::Local(offset) => self.capture_upvalue(stack_top + offset + 1),
+                Capture::Upvalue(idx) => self.upvalue(*idx)?,
+            };
+            upvalues.push(upvalue);
+        }
+        let closure = Closure::new(function, upvalues);
+        self.stack.push(ValueType::Closure(Rc::new(closure)))?;
         Ok(())
     }
 
+    /// Returns the upvalue of the stack slot, so all closures share the same variable.
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| *upvalue.borrow() == Upvalue::Open(slot));
+        if let Some(upvalue) = existing {
+            return upvalue.clone();
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Moves values of the stack slots starting from the given one into their upvalues.
+    fn close_upvalues(&mut self, from_slot: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) if slot >= from_slot => slot,
+                _ => return true,
+            };
+            let value = stack.get(slot).cloned().unwrap_or(ValueType::Nil);
+            *upvalue.borrow_mut() = Upvalue::Closed(value);
+            false
+        });
+    }
+
     fn jump(&mut self, offset: i32) -> VmResult {
         self.offset_ip(offset as isize)?;
         Ok(())
     }
 
     fn jump_if_false(&mut self, offset: i32) -> VmResult {
-        let value = self.stack.pop()?;
-        if let ValueType::Bool(b) = value {
-            if !b {
-                self.offset_ip(offset as isize)?;
-            }
-        } else {
-            return Err(VmRuntimeError::TypeMismatch);
+        let condition = self.stack.pop()?;
+        if !self.is_true(&condition)? {
+            self.offset_ip(offset as isize)?;
         }
         Ok(())
     }
 
+    fn jump_if_true(&mut self, offset: i32) -> VmResult {
+        let condition = self.stack.pop()?;
+        if self.is_true(&condition)? {
+            self.offset_ip(offset as isize)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the value passes a condition, which takes only booleans in the strict mode.
+    pub(crate) fn is_true(&self, value: &ValueType) -> Result<bool, VmRuntimeError> {
+        match value {
+            ValueType::Bool(b) => Ok(*b),
+            _ if self.strict_conditions => Err(VmRuntimeError::TypeMismatch),
+            value => Ok(value.is_truthy()),
+        }
+    }
+
+    /// Calls the function with the arguments and runs it until it returns, so natives can take functions.
+    ///
+    /// Exception handlers outside of the call are out of reach while it runs,
+    /// so the values it throws come back as errors of the native.
+    pub(crate) fn call_value(
+        &mut self,
+        function: ValueType,
+        args: &[ValueType],
+    ) -> Result<ValueType, VmRuntimeError> {
+        let depth = self.frames.len();
+        self.stack.push(function)?;
+        for arg in args {
+            self.stack.push(arg.clone())?;
+        }
+        self.call(args.len())?;
+        let barrier = std::mem::replace(&mut self.handler_barrier, depth);
+        let result = self.run_frames_above(depth);
+        self.handler_barrier = barrier;
+        result
+    }
+
+    fn run_frames_above(&mut self, depth: usize) -> Result<ValueType, VmRuntimeError> {
+        while self.frames.len() > depth {
+            self.step()?;
+        }
+        self.stack.pop()
+    }
+
     fn call(&mut self, arity: usize) -> VmResult {
-        let value = self.peek_value(arity)?.clone();
-        match &value {
-            ValueType::Function(f) => self.call_function(f, arity),
-            ValueType::NativeFunction(f) => self.call_native_function(f, arity),
-            _ => Err(VmRuntimeError::TypeMismatch),
+        if let Some(frame) = self.call_frame(arity)? {
+            if self.frames.len() >= self.max_call_depth {
+                return Err(VmRuntimeError::CallStackOverflow {
+                    depth: self.frames.len(),
+                    function: callee_name(&self.stack, frame.stack_top()).to_string(),
+                });
+            }
+            self.frames.push(frame);
+            self.trace_call();
         }
+        Ok(())
     }
 
-    fn initialize_array(&mut self) -> VmResult {
-        let initial_value = self.stack.pop()?;
-        let size = self.index()?;
-        let mut array = vec![];
-        array.resize(size, initial_value);
-        // self.stack.push(ValueType::Array(Box::new(array)));
-        self.stack
-            .push(ValueType::ArrayRef(Rc::new(RefCell::new(array))));
+    /// Prepares the frame to call the function with its arguments on top of the stack.
+    ///
+    /// Native functions have no frames, they are called right away.
+    fn call_frame(&mut self, arity: usize) -> Result<Option<CallFrame>, VmRuntimeError> {
+        let stack_top = self
+            .stack
+            .len()
+            .checked_sub(arity + 1)
+            .ok_or(VmRuntimeError::StackExhausted)?;
+        let frame = match self.stack.get(stack_top) {
+            Some(ValueType::Function(f)) if f.arity() == arity => {
+                CallFrame::new(f.chunk().clone(), stack_top)
+            }
+            Some(ValueType::Closure(c)) if c.function().arity() == arity => {
+                CallFrame::with_closure(c.clone(), stack_top)
+            }
+            Some(ValueType::NativeFunction(f)) => {
+                let function = f.clone();
+                if let Some(stats) = &mut self.stats {
+                    stats.called();
+                }
+                self.call_native_function(&function, arity)?;
+                return Ok(None);
+            }
+            _ => return Err(VmRuntimeError::TypeMismatch),
+        };
+        if let Some(stats) = &mut self.stats {
+            stats.called();
+        }
+        Ok(Some(frame))
+    }
+
+    /// Calls the function in place of the current one, so deep recursion does not grow the frames.
+    fn tail_call(&mut self, arity: usize) -> VmResult {
+        let Some(mut frame) = self.call_frame(arity)? else {
+            // Native functions are done by now, so their result is returned right away.
+            return self.ret();
+        };
+        let caller = self
+            .frames
+            .pop()
+            .ok_or(VmRuntimeError::NoCallFrame("return"))?;
+        self.close_upvalues(caller.stack_top());
+        // Drop the caller with its locals, moving the callee and its arguments in place.
+        self.stack.remove(caller.stack_top()..frame.stack_top())?;
+        frame.rebase(caller.stack_top());
+        self.frames.push(frame);
+        self.trace_call();
         Ok(())
     }
 
-    fn peek_value(&mut self, arity: usize) -> Result<&ValueType, VmRuntimeError> {
-        self.stack.peek(arity).ok_or(VmRuntimeError::StackExhausted)
+    fn initialize_array(&mut self) -> VmResult {
+        let initial_value = self.stack.pop()?;
+        let size = self.array_size()?;
+        // Every element gets its own copy of an array or map, so changing one leaves the others as they are.
+        let array = (0..size).map(|_| initial_value.deep_copy()).collect();
+        let array = ValueType::ArrayRef(Rc::new(RefCell::new(array)));
+        if let Some(stats) = &mut self.stats {
+            stats.allocated(&array);
+        }
+        self.stack.push(array)?;
+        Ok(())
     }
 
-    fn call_function(&mut self, function: &Function, arity: usize) -> VmResult {
-        if arity != function.arity() {
-            return Err(VmRuntimeError::TypeMismatch);
+    fn initialize_map(&mut self, len: usize) -> VmResult {
+        let entries_start = self
+            .stack
+            .len()
+            .checked_sub(len.saturating_mul(2))
+            .ok_or(VmRuntimeError::StackExhausted)?;
+        let entries = self.stack.split_off(entries_start)?;
+        let mut map = HashMap::with_capacity(len);
+        for entry in entries.chunks(2) {
+            match &entry[0] {
+                ValueType::Text(key) => map.insert(key.to_string(), entry[1].clone()),
+                key => return Err(TypeError::InvalidKeyType(key.clone()).into()),
+            };
         }
-        let stack_top = self.stack.len() - function.arity() - 1;
-        let frame = CallFrame::new(function.chunk().clone(), stack_top);
-        self.frames.push(frame);
+        self.stack
+            .push(ValueType::Map(Rc::new(RefCell::new(map))))?;
         Ok(())
     }
 
     fn call_native_function(&mut self, function: &NativeFunction, arity: usize) -> VmResult {
-        if arity != function.arity() {
+        if !function.accepts(arity) {
             return Err(VmRuntimeError::TypeMismatch);
         }
-        function.call(self)
+        let args = self.stack.split_off(self.stack.len() - arity)?;
+        self.stack.pop()?;
+        let result = function.call(self, &args)?;
+        if let Some(stats) = &mut self.stats {
+            // Values the native returns from its arguments or elsewhere are shared, the new ones are not.
+            let new = match &result {
+                ValueType::Text(text) => Rc::strong_count(text) == 1,
+                ValueType::ArrayRef(array) => Rc::strong_count(array) == 1,
+                _ => false,
+            };
+            if new {
+                stats.allocated(&result);
+            }
+        }
+        self.stack.push(result)?;
+        Ok(())
     }
 
     fn define_native_function(&mut self, native_function: NativeFunction) {
-        let name = native_function.name().to_string();
+        let name = Rc::new(native_function.name().to_string());
         let value = ValueType::NativeFunction(Rc::new(native_function));
-        self.globals.insert(name, value);
+        let slot = self.globals.slot(&name);
+        self.globals.set(slot, value);
     }
 
     fn ret(&mut self) -> VmResult {
         let result = self.stack.pop()?;
-        let frame = self.frames.pop().ok_or(VmRuntimeError::StackExhausted)?;
-        self.stack.stack.truncate(frame.stack_top());
-        self.stack.push(result);
+        let frame = self
+            .frames
+            .pop()
+            .ok_or(VmRuntimeError::NoCallFrame("return"))?;
+        self.close_upvalues(frame.stack_top());
+        self.stack.truncate(frame.stack_top());
+        self.stack.push(result)?;
+        self.trace_return();
+        Ok(())
+    }
+
+    fn push_handler(&mut self, offset: i32) -> VmResult {
+        let stack_len = self.stack.len();
+        let frame = self
+            .frames
+            .last_mut()
+            .ok_or(VmRuntimeError::NoCallFrame("install a handler"))?;
+        let address = frame
+            .ip()
+            .checked_add_signed(offset as isize)
+            .ok_or(VmRuntimeError::IllegalJump(frame.ip(), offset as isize))?;
+        frame.push_handler(Handler::new(address, stack_len));
+        Ok(())
+    }
+
+    fn has_handlers(&self) -> bool {
+        self.frames
+            .iter()
+            .skip(self.handler_barrier)
+            .any(CallFrame::has_handlers)
+    }
+
+    /// Passes the value to the innermost exception handler, leaving the calls made since it was installed.
+    fn throw(&mut self, value: ValueType) -> VmResult {
+        if !self.has_handlers() {
+            return Err(VmRuntimeError::Uncaught(value));
+        }
+        // The handler is above the barrier, so the frames of the native callers are kept.
+        while let Some(frame) = self.frames.last_mut() {
+            if let Some(handler) = frame.pop_handler() {
+                frame.jump_to(handler.address());
+                self.close_upvalues(handler.stack_len());
+                self.stack.truncate(handler.stack_len());
+                self.stack.push(value)?;
+                return Ok(());
+            }
+            let stack_top = frame.stack_top();
+            self.frames.pop();
+            self.close_upvalues(stack_top);
+            self.stack.truncate(stack_top);
+        }
+        Err(VmRuntimeError::Uncaught(value))
+    }
+
+    fn assert(&mut self) -> VmResult {
+        let message = self.stack.pop()?;
+        let condition = self.stack.pop()?;
+        if self.is_true(&condition)? {
+            Ok(())
+        } else {
+            Err(VmRuntimeError::AssertionFailed(match message {
+                ValueType::Nil => None,
+                message => Some(message.as_string()),
+            }))
+        }
+    }
+
+    fn iter_init(&mut self) -> VmResult {
+        let collection = self.stack.pop()?;
+        let elements = collection.elements().map_err(VmRuntimeError::TypeError)?;
+        // Strings and maps are iterated over the arrays made of them.
+        if let (Some(stats), ValueType::Text(_) | ValueType::Map(_)) =
+            (&mut self.stats, &collection)
+        {
+            stats.allocated(&elements);
+        }
+        self.stack.push(elements)?;
+        self.stack.push(ValueType::Int(0))?;
+        Ok(())
+    }
+
+    fn iter_next(&mut self, offset: i32) -> VmResult {
+        let ValueType::Int(index) = self.stack.pop()? else {
+            return Err(VmRuntimeError::TypeMismatch);
+        };
+        let element = match self.stack.last() {
+            Some(ValueType::ArrayRef(array)) => array.borrow().get(index as usize).cloned(),
+            // Ranges starting at a whole number count with integers.
+            Some(ValueType::Range(range))
+                if range.0.fract() == 0.0
+                    && range.0 >= i64::MIN as f64
+                    && range.0 < i64::MAX as f64 =>
+            {
+                let value = (range.0 as i64)
+                    .checked_add(index)
+                    .ok_or(VmRuntimeError::IntegerOverflow)?;
+                Some(ValueType::Int(value)).filter(|_| (value as f64) < range.1)
+            }
+            Some(ValueType::Range(range)) => {
+                let value = range.0 + index as f64;
+                if value < range.1 && index > 0 && value == range.0 + (index - 1) as f64 {
+                    return Err(VmRuntimeError::RangeCounterTooLarge(value));
+                }
+                Some(ValueType::Number(value)).filter(|_| value < range.1)
+            }
+            _ => return Err(VmRuntimeError::TypeMismatch),
+        };
+        self.stack.push(ValueType::Int(index + 1))?;
+        match element {
+            Some(element) => {
+                self.stack.push(element)?;
+                Ok(())
+            }
+            None => self.offset_ip(offset as isize),
+        }
+    }
+
+    fn increment(&mut self) -> VmResult {
+        let counter = match self.stack.pop()? {
+            ValueType::Int(value) => ValueType::Int(
+                value
+                    .checked_add(1)
+                    .ok_or(VmRuntimeError::IntegerOverflow)?,
+            ),
+            ValueType::Number(value) if value + 1.0 == value => {
+                return Err(VmRuntimeError::RangeCounterTooLarge(value))
+            }
+            ValueType::Number(value) => ValueType::Number(value + 1.0),
+            _ => return Err(VmRuntimeError::TypeMismatch),
+        };
+        self.stack.push(counter)
+    }
+
+    fn range(&mut self) -> VmResult {
+        let end = self.stack.pop()?;
+        let start = self.stack.pop()?;
+        match (start.as_float(), end.as_float()) {
+            (Some(start), Some(end)) => {
+                self.stack.push(ValueType::Range(Box::new((start, end))))?;
+                Ok(())
+            }
+            _ => Err(VmRuntimeError::TypeMismatch),
+        }
+    }
+
+    fn tuple(&mut self, len: usize) -> VmResult {
+        let start = self
+            .stack
+            .len()
+            .checked_sub(len)
+            .ok_or(VmRuntimeError::StackExhausted)?;
+        let elements = self.stack.split_off(start)?;
+        self.stack.push(ValueType::Tuple(Box::new(elements)))?;
+        Ok(())
+    }
+
+    fn unpack(&mut self, len: usize) -> VmResult {
+        let ValueType::Tuple(elements) = self.stack.pop()? else {
+            return Err(VmRuntimeError::TypeMismatch);
+        };
+        if elements.len() != len {
+            return Err(VmRuntimeError::UnpackMismatch {
+                expected: len,
+                actual: elements.len(),
+            });
+        }
+        for element in *elements {
+            self.stack.push(element)?;
+        }
         Ok(())
     }
 
     fn offset_ip(&mut self, offset: isize) -> VmResult {
-        let frame = self.frames.last_mut().unwrap();
+        let frame = self
+            .frames
+            .last_mut()
+            .ok_or(VmRuntimeError::NoCallFrame("jump"))?;
         frame
             .jump(offset)
             .map_err(|_| VmRuntimeError::IllegalJump(frame.ip(), offset))
     }
 
-    fn advance(&mut self) -> Option<&Op> {
+    fn advance(&mut self) -> Option<Op> {
         self.frames.last_mut().and_then(|frame| frame.advance())
     }
 
-    fn ip(&self) -> usize {
-        self.frames.last().map(|frame| frame.ip()).unwrap_or(0)
+    /// Frame of the running function, failing with the operation that needs it when there is none.
+    fn running_frame(&self, operation: &'static str) -> Result<&CallFrame, VmRuntimeError> {
+        self.frames
+            .last()
+            .ok_or(VmRuntimeError::NoCallFrame(operation))
     }
 
-    fn chunk(&self) -> &Chunk {
-        let frame = self.frames.last().unwrap();
-        frame.chunk()
+    fn burn_fuel(&mut self) -> VmResult {
+        match self.fuel {
+            Some(0) => Err(VmRuntimeError::OutOfFuel),
+            Some(fuel) => {
+                self.fuel = Some(fuel - 1);
+                Ok(())
+            }
+            None => Ok(()),
+        }
     }
 
-    fn trace_before(&self) {
-        if let Some(ref tracer) = self.trace {
-            tracer.trace_before(self.ip() - 1, self.chunk(), &self.stack);
+    fn trace_instruction(&mut self, address: usize, op: Op) {
+        if let (Some(tracer), Some(frame)) = (&mut self.trace, self.frames.last()) {
+            tracer.trace(TraceEvent::Instruction {
+                address,
+                op,
+                chunk: frame.chunk(),
+            });
         }
     }
 
     fn trace_after(&mut self) {
-        if let Some(trace) = &self.trace {
-            trace.trace_after(self.ip(), self.chunk(), &self.stack);
+        if let Some(tracer) = &mut self.trace {
+            tracer.trace(TraceEvent::Stack(&self.stack));
+        }
+    }
+
+    /// Reports the call of the function in the frame pushed last.
+    fn trace_call(&mut self) {
+        if let (Some(tracer), Some(frame)) = (&mut self.trace, self.frames.last()) {
+            tracer.trace(TraceEvent::CallEntered {
+                function: callee_name(&self.stack, frame.stack_top()),
+                depth: self.frames.len(),
+            });
+        }
+    }
+
+    fn trace_return(&mut self) {
+        if let Some(tracer) = &mut self.trace {
+            tracer.trace(TraceEvent::CallReturned {
+                depth: self.frames.len(),
+            });
         }
     }
 
     fn constant(&self, index: usize) -> Result<ValueType, VmRuntimeError> {
-        let chunk = self.chunk();
-        chunk
+        self.running_frame("read a constant")?
+            .chunk()
             .constant(index)
             .cloned()
             .ok_or(VmRuntimeError::UndefinedConstant(index))
     }
-    fn index(&mut self) -> Result<usize, VmRuntimeError> {
-        let value = self.stack.pop()?;
-        if let ValueType::Number(n) = value {
-            Ok(n as usize)
-        } else {
-            Err(VmRuntimeError::TypeMismatch)
+    /// Takes the size of the array to create from the top of the stack.
+    fn array_size(&mut self) -> Result<usize, VmRuntimeError> {
+        let size = self.stack.pop()?;
+        let len = match size {
+            ValueType::Int(n) => u128::try_from(n).ok(),
+            ValueType::Number(n) if n.fract() == 0.0 && n >= 0.0 => Some(n as u128),
+            ValueType::Number(_) => None,
+            _ => return Err(VmRuntimeError::TypeMismatch),
+        };
+        match len {
+            Some(len) => array_len(len),
+            None => Err(VmRuntimeError::InvalidArraySize(size)),
         }
     }
 
@@ -391,37 +1265,389 @@ impl Vm {
         self.stack.pop()
     }
 
-    pub fn push(&mut self, value: ValueType) {
-        self.stack.push(value);
+    pub fn push(&mut self, value: ValueType) -> Result<(), VmRuntimeError> {
+        self.stack.push(value)
+    }
+}
+
+/// Arithmetic and ordering of numbers, or `None` for the operands that are not numbers.
+///
+/// Integers stay integers, failing on overflow, unless divided or mixed with floats.
+fn numeric_operation(
+    operation: Op,
+    a: &ValueType,
+    b: &ValueType,
+) -> Option<Result<ValueType, VmRuntimeError>> {
+    if let (ValueType::Int(a), ValueType::Int(b)) = (a, b) {
+        return integer_operation(operation, *a, *b);
+    }
+    float_operation(operation, a.as_float()?, b.as_float()?).map(Ok)
+}
+
+fn integer_operation(operation: Op, a: i64, b: i64) -> Option<Result<ValueType, VmRuntimeError>> {
+    let result = match operation {
+        Op::Add => a.checked_add(b),
+        Op::Sub => a.checked_sub(b),
+        Op::Mul => a.checked_mul(b),
+        Op::Pow if b >= 0 => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+        Op::Ge => return Some(Ok(ValueType::Bool(a >= b))),
+        Op::Le => return Some(Ok(ValueType::Bool(a <= b))),
+        // Division keeps the fraction, and negative powers are fractions too.
+        _ => return float_operation(operation, a as f64, b as f64).map(Ok),
+    };
+    Some(
+        result
+            .map(ValueType::Int)
+            .ok_or(VmRuntimeError::IntegerOverflow),
+    )
+}
+
+fn float_operation(operation: Op, a: f64, b: f64) -> Option<ValueType> {
+    let result = match operation {
+        Op::Add => ValueType::Number(a + b),
+        Op::Sub => ValueType::Number(a - b),
+        Op::Mul => ValueType::Number(a * b),
+        Op::Div => ValueType::Number(a / b),
+        Op::Pow => ValueType::Number(a.powf(b)),
+        Op::Ge => ValueType::Bool(a >= b),
+        Op::Le => ValueType::Bool(a <= b),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Text the `print` statement and function show for the value.
+pub(crate) fn printable(value: ValueType) -> String {
+    match value {
+        ValueType::Number(n) => n.to_string(),
+        ValueType::Int(n) => n.to_string(),
+        ValueType::Bool(b) => b.to_string(),
+        ValueType::Address(a) => a.to_string(),
+        ValueType::Nil => "nil".to_string(),
+        ValueType::Function(f) => {
+            format!("{}:{}", "fun", f.name())
+        }
+        ValueType::Closure(c) => {
+            format!("{}:{}", "fun", c.function().name())
+        }
+        ValueType::NativeFunction(f) => {
+            format!("[{}]:{}", "fun", f.name())
+        }
+        ValueType::Text(s) => s.to_string(),
+        ValueType::ArrayRef(a) => format_args!("&[{}]\n", a.borrow().len()).to_string(),
+        ValueType::Range(range) => format!("{}..{}", range.0, range.1),
+        tuple @ ValueType::Tuple(_) => tuple.as_string(),
+        ValueType::Map(m) => format!("{{{}}}", m.borrow().len()),
+        ValueType::Error(message) => format!("error: {}", message),
+    }
+}
+
+/// Checks that the array of the given number of elements fits [MAX_ARRAY_LEN].
+pub(crate) fn array_len(len: u128) -> Result<usize, VmRuntimeError> {
+    usize::try_from(len)
+        .ok()
+        .filter(|len| *len <= MAX_ARRAY_LEN)
+        .ok_or(VmRuntimeError::ArrayTooLarge(len))
+}
+
+/// Name of the function called in the frame starting at the stack position.
+///
+/// The script itself and the values that are not functions have no name.
+fn callee_name(stack: &VmStack, stack_top: usize) -> &str {
+    match stack.get(stack_top) {
+        Some(ValueType::Function(f)) => f.name(),
+        Some(ValueType::Closure(c)) => c.function().name(),
+        _ => "",
+    }
+}
+
+/// Name of the variable from the constant pool of the executed chunk.
+fn variable_name(frames: &[CallFrame], idx: usize) -> Result<&Rc<String>, VmRuntimeError> {
+    let chunk = frames
+        .last()
+        .ok_or(VmRuntimeError::NoCallFrame("read a variable name"))?
+        .chunk();
+    match chunk.constant(idx) {
+        Some(ValueType::Text(name)) => Ok(name),
+        Some(_) => Err(VmRuntimeError::TypeMismatch),
+        None => Err(VmRuntimeError::UndefinedConstant(idx)),
     }
 }
 
 impl Default for Vm {
     fn default() -> Self {
-        let tracer = LoggingTracer;
-        let out = stdout();
-        let mut vm = Vm {
-            stack: VmStack::default(),
-            frames: Vec::new(),
-            globals: HashMap::new(),
-            trace: Some(Box::new(tracer)),
-            out: Rc::new(RefCell::new(out)),
-        };
-        std_lib()
-            .iter()
-            .for_each(|f| vm.define_native_function(f.clone()));
-        vm
+        Vm::builder().build()
     }
 }
 
 impl Vm {
+    /// Starts configuring the virtual machine, see [VmBuilder].
+    pub fn builder() -> VmBuilder {
+        VmBuilder::default()
+    }
+
+    /// Creates the virtual machine printing to the output.
+    ///
+    /// Use [Vm::with_streams] to replace the input and the error output as well.
     pub fn with_io<T>(out: Rc<RefCell<T>>) -> Self
     where
-        T: Write + Send + Sync + 'static,
+        T: Write + 'static,
     {
+        Vm::default().with_streams(VmIo::default().with_stdout(out))
+    }
+
+    /// Sets the input the programs read with the `read_line` function.
+    pub fn with_input<T>(self, input: Rc<RefCell<T>>) -> Self
+    where
+        T: BufRead + 'static,
+    {
+        let io = self.io.clone().with_buffered_stdin(input);
+        self.with_streams(io)
+    }
+
+    /// Sets the streams the programs read from and write to.
+    pub fn with_streams(self, io: VmIo) -> Self {
+        Vm { io, ..self }
+    }
+
+    /// Sets the source of the numbers the `random` and `random_int` functions return.
+    ///
+    /// Use [SeededRandom](provider::SeededRandom) with a fixed seed to get the same numbers on every run.
+    pub fn with_random(self, random: impl RandomSource + 'static) -> Self {
+        Vm {
+            random: Box::new(random),
+            ..self
+        }
+    }
+
+    /// Sets the source of the time the `clock` function returns.
+    pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+        Vm {
+            clock: Box::new(clock),
+            ..self
+        }
+    }
+
+    /// Sets the arguments of the program the `args` function returns.
+    pub fn with_args<T>(self, args: impl IntoIterator<Item = T>) -> Self
+    where
+        T: Into<String>,
+    {
+        Vm {
+            args: args.into_iter().map(Into::into).collect(),
+            ..self
+        }
+    }
+
+    /// Allows or denies reading environment variables with the `env` function.
+    ///
+    /// When denied, the function returns an error value. It is allowed by default.
+    pub fn with_env_access(self, env_access: bool) -> Self {
+        Vm { env_access, ..self }
+    }
+
+    /// Allows or denies stopping the program with the `exit` function.
+    ///
+    /// When allowed, the function stops the program with the [VmRuntimeError::Exit] error.
+    /// When denied, it returns an error value. It is allowed by default.
+    pub fn with_exit(self, exit_allowed: bool) -> Self {
+        Vm {
+            exit_allowed,
+            ..self
+        }
+    }
+
+    /// Sets the tracer receiving the events of the execution, see [trace::TraceEvent].
+    ///
+    /// By default, the events go to the debug log.
+    pub fn with_tracer(self, tracer: impl VmStepTrace + 'static) -> Self {
+        Vm {
+            trace: Some(Box::new(tracer)),
+            ..self
+        }
+    }
+
+    /// Records which source lines the executed instructions come from, see [Vm::coverage].
+    pub fn with_coverage(self) -> Self {
+        Vm {
+            coverage: Some(Coverage::default()),
+            ..self
+        }
+    }
+
+    /// Counts the executed instructions, calls, and allocations, see [Vm::stats].
+    pub fn with_stats(self) -> Self {
+        Vm {
+            stats: Some(Stats::default()),
+            ..self
+        }
+    }
+
+    /// Makes conditions and `!` accept only booleans, failing with [VmRuntimeError::TypeMismatch] otherwise.
+    ///
+    /// By default, `nil` and `false` count as false, and any other value counts as true.
+    pub fn with_strict_conditions(self, strict_conditions: bool) -> Self {
+        Vm {
+            strict_conditions,
+            ..self
+        }
+    }
+
+    /// Limits the number of instructions the virtual machine executes.
+    ///
+    /// Running out of fuel stops the program with the [VmRuntimeError::OutOfFuel] error.
+    /// It is useful to run untrusted programs that may never finish.
+    pub fn with_fuel(self, fuel: usize) -> Self {
+        Vm {
+            fuel: Some(fuel),
+            ..self
+        }
+    }
+
+    /// Limits the number of nested function calls, including the program itself.
+    ///
+    /// Exceeding the limit stops the program with the [VmRuntimeError::CallStackOverflow] error.
+    /// Tail calls reuse the frame of the calling function, so they don't count.
+    /// The default limit is [MAX_CALL_DEPTH].
+    pub fn with_max_call_depth(self, max_call_depth: usize) -> Self {
         Vm {
-            out,
-            ..Default::default()
+            max_call_depth,
+            ..self
         }
     }
+
+    /// Limits the number of values on the operand stack, the locals and the temporaries of all the calls.
+    ///
+    /// Exceeding the limit stops the program with the [VmRuntimeError::StackOverflow] error.
+    /// The default limit is [STACK_SIZE].
+    pub fn with_stack_limit(self, limit: usize) -> Self {
+        Vm {
+            stack: VmStack::with_limit(limit),
+            ..self
+        }
+    }
+
+    /// Defines a global function implemented by the host application.
+    ///
+    /// The function receives the arguments of the call and returns its result.
+    /// The machine checks that the program passes `arity` arguments.
+    /// Registering a function with the name of an existing global replaces it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use std::cell::RefCell;
+    /// # use std::error::Error;
+    /// # use std::rc::Rc;
+    /// # use brainterpreter::compiler::Compiler;
+    /// # use brainterpreter::lexer::Lexer;
+    /// # use brainterpreter::parser::Parser;
+    /// # use brainterpreter::value::ValueType;
+    /// # use brainterpreter::vm::{Vm, VmRuntimeError};
+    /// # fn main() -> Result<(), Box<dyn Error>> {
+    /// let out = Rc::new(RefCell::new(vec![]));
+    /// let mut vm = Vm::with_io(out.clone());
+    /// vm.register_native("double", 1, |_vm, args| match args[0].as_float() {
+    ///     Some(n) => Ok(ValueType::Number(n * 2.0)),
+    ///     None => Err(VmRuntimeError::TypeMismatch),
+    /// });
+    ///
+    /// let program = Parser::new(Lexer::new("print double(21);")).parse_program()?;
+    /// let chunk = Compiler::default().compile(program)?;
+    /// vm.load_and_run(Rc::new(chunk))?;
+    ///
+    /// assert_eq!(out.borrow().as_slice(), b"42\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn register_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&mut Vm, &[ValueType]) -> Result<ValueType, VmRuntimeError> + 'static,
+    {
+        self.define_native_function(NativeFunction::new(name, arity, function));
+    }
+
+    /// Defines a global function implemented by the host application, see [Vm::register_native].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use std::cell::Cell;
+    /// # use std::error::Error;
+    /// # use std::rc::Rc;
+    /// # use brainterpreter::compiler::Compiler;
+    /// # use brainterpreter::lexer::Lexer;
+    /// # use brainterpreter::parser::Parser;
+    /// # use brainterpreter::value::ValueType;
+    /// # use brainterpreter::vm::Vm;
+    /// # fn main() -> Result<(), Box<dyn Error>> {
+    /// let ticks = Rc::new(Cell::new(0));
+    /// let counter = ticks.clone();
+    /// let mut vm = Vm::default().with_native("tick", 0, move |_vm, _args| {
+    ///     counter.set(counter.get() + 1);
+    ///     Ok(ValueType::Nil)
+    /// });
+    ///
+    /// let program = Parser::new(Lexer::new("tick(); tick();")).parse_program()?;
+    /// let chunk = Compiler::default().compile(program)?;
+    /// vm.load_and_run(Rc::new(chunk))?;
+    ///
+    /// assert_eq!(ticks.get(), 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_native<F>(mut self, name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(&mut Vm, &[ValueType]) -> Result<ValueType, VmRuntimeError> + 'static,
+    {
+        self.register_native(name, arity, function);
+        self
+    }
+
+    /// Lines covered by the programs run so far, if the machine records the coverage.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Statistics of the programs run so far, if the machine counts them.
+    pub fn stats(&self) -> Option<&Stats> {
+        self.stats.as_ref()
+    }
+
+    /// Instructions left before the virtual machine runs out of fuel.
+    pub fn fuel(&self) -> Option<usize> {
+        self.fuel
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_missing_call_frame() {
+        let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![])));
+
+        assert!(matches!(
+            vm.load_local(0),
+            Err(VmRuntimeError::NoCallFrame("load a local variable"))
+        ));
+        assert!(matches!(
+            vm.offset_ip(1),
+            Err(VmRuntimeError::NoCallFrame("jump"))
+        ));
+        assert!(matches!(
+            vm.push_handler(1),
+            Err(VmRuntimeError::NoCallFrame("install a handler"))
+        ));
+        assert!(matches!(
+            vm.execute(Op::PopHandler),
+            Err(VmRuntimeError::NoCallFrame("remove a handler"))
+        ));
+        vm.push(ValueType::Nil).unwrap();
+        assert!(matches!(
+            vm.ret(),
+            Err(VmRuntimeError::NoCallFrame("return"))
+        ));
+    }
 }
@@ -1,43 +1,707 @@
-use crate::value::{NativeFunction, ValueType};
-use crate::vm::{Vm, VmRuntimeError};
+//! Native functions of the standard library, grouped by what they give the programs access to.
+//!
+//! [std_lib] has all the groups. Embedders running untrusted programs pick only the groups they need,
+//! e.g. leave out [io] and [process], see [VmBuilder::natives](crate::vm::builder::VmBuilder::natives).
 
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::value::{NativeFunction, TypeError, ValueType};
+use crate::vm::{array_len, printable, Vm, VmRuntimeError};
+
+/// All the native functions of the standard library.
 pub fn std_lib() -> Vec<NativeFunction> {
+    let mut natives = core();
+    natives.extend(io());
+    natives.extend(types());
+    natives.extend(math());
+    natives.extend(strings());
+    natives.extend(formatting());
+    natives.extend(arrays());
+    natives.extend(higher_order());
+    natives.extend(random_and_time());
+    natives.extend(process());
+    natives
+}
+
+/// Functions converting, copying, and comparing values, working with any collection, and making errors.
+pub fn core() -> Vec<NativeFunction> {
     vec![
         NativeFunction::new("len", 1, len),
         NativeFunction::new("as_char", 1, as_char),
         NativeFunction::new("as_string", 1, as_string),
+        NativeFunction::new("as_int", 1, as_int),
+        NativeFunction::new("as_float", 1, as_float),
+        NativeFunction::new("keys", 1, keys),
+        NativeFunction::new("has_key", 2, has_key),
+        NativeFunction::new("error", 1, error),
+        NativeFunction::new("equals", 2, equals),
+        NativeFunction::new("clone", 1, |_vm, args| Ok(args[0].deep_copy())),
+    ]
+}
+
+/// Functions reading the input and writing to the outputs of the virtual machine.
+pub fn io() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("print", 0, print).variadic(),
+        NativeFunction::new("eprint", 0, eprint).variadic(),
+        NativeFunction::new("printf", 1, printf).variadic(),
+        NativeFunction::new("read_line", 0, read_line),
+    ]
+}
+
+/// Functions checking the types of values, so programs can avoid operations on wrong types.
+pub fn types() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("typeof", 1, |_vm, args| {
+            Ok(ValueType::string(args[0].type_name()))
+        }),
+        NativeFunction::new("is_nil", 1, |_vm, args| is_type(args, "nil")),
+        NativeFunction::new("is_bool", 1, |_vm, args| is_type(args, "bool")),
+        NativeFunction::new("is_number", 1, |_vm, args| {
+            Ok(ValueType::Bool(args[0].as_float().is_some()))
+        }),
+        NativeFunction::new("is_int", 1, |_vm, args| is_type(args, "int")),
+        NativeFunction::new("is_string", 1, |_vm, args| is_type(args, "string")),
+        NativeFunction::new("is_function", 1, |_vm, args| is_type(args, "function")),
+        NativeFunction::new("is_array", 1, |_vm, args| is_type(args, "array")),
+        NativeFunction::new("is_map", 1, |_vm, args| is_type(args, "map")),
+        NativeFunction::new("is_error", 1, |_vm, args| is_type(args, "error")),
+    ]
+}
+
+fn is_type(args: &[ValueType], type_name: &str) -> Result<ValueType, VmRuntimeError> {
+    Ok(ValueType::Bool(args[0].type_name() == type_name))
+}
+
+/// Functions working with numbers.
+pub fn math() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("sqrt", 1, |_vm, args| unary_math(args, f64::sqrt)),
+        NativeFunction::new("floor", 1, |_vm, args| unary_math(args, f64::floor)),
+        NativeFunction::new("ceil", 1, |_vm, args| unary_math(args, f64::ceil)),
+        NativeFunction::new("round", 1, |_vm, args| unary_math(args, f64::round)),
+        NativeFunction::new("abs", 1, |_vm, args| unary_math(args, f64::abs)),
+        NativeFunction::new("min", 2, |_vm, args| binary_math(args, f64::min)),
+        NativeFunction::new("max", 2, |_vm, args| binary_math(args, f64::max)),
+    ]
+}
+
+/// Functions working with strings.
+/// Positions in the strings count characters, not bytes.
+pub fn strings() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("substring", 3, substring),
+        NativeFunction::new("index_of", 2, index_of),
+        NativeFunction::new("split", 2, split),
+        NativeFunction::new("to_upper", 1, |_vm, args| {
+            map_text("to_upper", args, str::to_uppercase)
+        }),
+        NativeFunction::new("to_lower", 1, |_vm, args| {
+            map_text("to_lower", args, str::to_lowercase)
+        }),
+        NativeFunction::new("trim", 1, |_vm, args| {
+            map_text("trim", args, |text| text.trim().to_string())
+        }),
     ]
 }
 
-fn len(vm: &mut Vm) -> Result<(), VmRuntimeError> {
-    let value = vm.pop()?;
-    vm.pop()?;
-    let len = match value {
-        ValueType::Text(text) => text.len(),
-        ValueType::Array(array) => array.len(),
-        _ => return Err(VmRuntimeError::TypeMismatch),
+/// Functions putting values into text by a format with `{}` placeholders.
+pub fn formatting() -> Vec<NativeFunction> {
+    vec![NativeFunction::new("format", 1, |_vm, args| {
+        let value = match format_text("format", args) {
+            Ok(text) => ValueType::string(text),
+            Err(error) => error,
+        };
+        Ok(value)
+    })
+    .variadic()]
+}
+
+/// Functions creating arrays and changing their size.
+/// The arrays change in place, so every variable referring to the array sees the change.
+pub fn arrays() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("matrix", 3, matrix),
+        NativeFunction::new("push", 2, push),
+        NativeFunction::new("pop", 1, pop),
+        NativeFunction::new("insert", 3, insert),
+        NativeFunction::new("remove", 2, remove),
+        NativeFunction::new("slice", 3, slice).with_optional(1),
+    ]
+}
+
+/// Functions calling the function they take for every value of an array.
+/// The array is not changed, `map` and `filter` return new arrays.
+pub fn higher_order() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("map", 2, map),
+        NativeFunction::new("filter", 2, filter),
+        NativeFunction::new("reduce", 3, reduce),
+    ]
+}
+
+/// Functions taking random numbers and time from the providers of the virtual machine.
+pub fn random_and_time() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("random", 0, |vm, _args| {
+            Ok(ValueType::Number(vm.random.next_f64()))
+        }),
+        NativeFunction::new("random_int", 2, random_int),
+        NativeFunction::new("clock", 0, |vm, _args| {
+            Ok(ValueType::Number(vm.clock.seconds()))
+        }),
+    ]
+}
+
+/// Functions working with the process running the program.
+/// The virtual machine decides which of them the program may use.
+pub fn process() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("args", 0, |vm, _args| {
+            let args = vm.args.iter().map(ValueType::string).collect();
+            Ok(ValueType::ArrayRef(Rc::new(RefCell::new(args))))
+        }),
+        NativeFunction::new("env", 1, env),
+        NativeFunction::new("exit", 1, exit),
+    ]
+}
+
+/// Returns the value of the environment variable, or nil when it is not set.
+fn env(vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    if !vm.env_access {
+        return Ok(ValueType::error("env is not allowed"));
+    }
+    let ValueType::Text(name) = &args[0] else {
+        return Ok(unexpected_argument("env", "a string", &args[0]));
+    };
+    let value = std::env::var(&**name)
+        .map(ValueType::string)
+        .unwrap_or(ValueType::Nil);
+    Ok(value)
+}
+
+/// Stops the program with the exit code.
+fn exit(vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    if !vm.exit_allowed {
+        return Ok(ValueType::error("exit is not allowed"));
+    }
+    match number(&args[0]) {
+        Ok(code) if code.fract() == 0.0 && (i32::MIN as f64..=i32::MAX as f64).contains(&code) => {
+            Err(VmRuntimeError::Exit(code as i32))
+        }
+        Ok(code) => Ok(ValueType::error(format!(
+            "exit expects a whole code but found {}",
+            code
+        ))),
+        Err(error) => Ok(error),
+    }
+}
+
+/// Returns a random whole number from the lower bound up to the upper one, including both.
+fn random_int(vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let (low, high) = match (number(&args[0]), number(&args[1])) {
+        (Ok(low), Ok(high)) if low.fract() == 0.0 && high.fract() == 0.0 && low <= high => {
+            (low, high)
+        }
+        (Ok(low), Ok(high)) => {
+            return Ok(ValueType::error(format!(
+                "random_int expects whole bounds in order but found {} and {}",
+                low, high
+            )))
+        }
+        (Err(error), _) | (_, Err(error)) => return Ok(error),
+    };
+    let n = low + (vm.random.next_f64() * (high - low + 1.0)).floor();
+    Ok(ValueType::Int(n.min(high) as i64))
+}
+
+fn unary_math(args: &[ValueType], f: fn(f64) -> f64) -> Result<ValueType, VmRuntimeError> {
+    let value = match number(&args[0]) {
+        Ok(a) => ValueType::Number(f(a)),
+        Err(error) => error,
+    };
+    Ok(value)
+}
+
+fn binary_math(args: &[ValueType], f: fn(f64, f64) -> f64) -> Result<ValueType, VmRuntimeError> {
+    let value = match (number(&args[0]), number(&args[1])) {
+        (Ok(a), Ok(b)) => ValueType::Number(f(a, b)),
+        (Err(error), _) | (_, Err(error)) => error,
+    };
+    Ok(value)
+}
+
+/// Takes the number from the argument or makes the error value to return instead.
+fn number(value: &ValueType) -> Result<f64, ValueType> {
+    value
+        .as_float()
+        .ok_or_else(|| ValueType::error(TypeError::ExpectedNumber(value.clone()).to_string()))
+}
+
+fn map_text(
+    function: &str,
+    args: &[ValueType],
+    f: fn(&str) -> String,
+) -> Result<ValueType, VmRuntimeError> {
+    match &args[0] {
+        ValueType::Text(text) => Ok(ValueType::string(f(text))),
+        value => Ok(unexpected_argument(function, "a string", value)),
+    }
+}
+
+/// Returns the characters of the string from the start position up to, but not including, the end one.
+fn substring(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let ValueType::Text(text) = &args[0] else {
+        return Ok(unexpected_argument("substring", "a string", &args[0]));
+    };
+    let len = text.chars().count();
+    let (start, end) = match (position(&args[1], len), position(&args[2], len)) {
+        (Ok(start), Ok(end)) if start <= end => (start, end),
+        (Ok(start), Ok(end)) => {
+            return Ok(ValueType::error(format!(
+                "substring start {} is after the end {}",
+                start, end
+            )))
+        }
+        (Err(error), _) | (_, Err(error)) => return Ok(error),
+    };
+    let substring: String = text.chars().skip(start).take(end - start).collect();
+    Ok(ValueType::string(substring))
+}
+
+/// Takes the position from the argument, checking it is a whole number from zero to the last one.
+fn position(value: &ValueType, last: usize) -> Result<usize, ValueType> {
+    let n = number(value)?;
+    if n.fract() != 0.0 || n < 0.0 || n > last as f64 {
+        return Err(ValueType::error(format!(
+            "position {} is out of range [0, {}]",
+            n, last
+        )));
+    }
+    Ok(n as usize)
+}
+
+/// Takes the array the function changes from the argument.
+fn array<'a>(
+    function: &str,
+    value: &'a ValueType,
+) -> Result<&'a Rc<RefCell<Vec<ValueType>>>, ValueType> {
+    match value {
+        ValueType::ArrayRef(array) => Ok(array),
+        value => Err(unexpected_argument(function, "an array", value)),
+    }
+}
+
+/// Copies the values of the array, so the called functions can change the array while they run.
+fn values(function: &str, value: &ValueType) -> Result<Vec<ValueType>, ValueType> {
+    array(function, value).map(|array| array.borrow().clone())
+}
+
+/// Calls the function with every value of the array and returns the array of the results.
+fn map(vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let values = match values("map", &args[1]) {
+        Ok(values) => values,
+        Err(error) => return Ok(error),
+    };
+    let mut results = Vec::with_capacity(values.len());
+    for value in values {
+        results.push(vm.call_value(args[0].clone(), &[value])?);
+    }
+    Ok(ValueType::ArrayRef(Rc::new(RefCell::new(results))))
+}
+
+/// Returns the array of the values passing the condition the function checks.
+fn filter(vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let values = match values("filter", &args[1]) {
+        Ok(values) => values,
+        Err(error) => return Ok(error),
+    };
+    let mut results = vec![];
+    for value in values {
+        let passed = vm.call_value(args[0].clone(), std::slice::from_ref(&value))?;
+        if vm.is_true(&passed)? {
+            results.push(value);
+        }
+    }
+    Ok(ValueType::ArrayRef(Rc::new(RefCell::new(results))))
+}
+
+/// Combines the values of the array from the first one, starting with the initial value.
+/// The function takes the result so far and the value, e.g. `reduce(fun (sum, x) { return sum + x; }, numbers, 0)`.
+fn reduce(vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let values = match values("reduce", &args[1]) {
+        Ok(values) => values,
+        Err(error) => return Ok(error),
+    };
+    let mut result = args[2].clone();
+    for value in values {
+        result = vm.call_value(args[0].clone(), &[result, value])?;
+    }
+    Ok(result)
+}
+
+/// Appends the value to the end of the array.
+fn push(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    match array("push", &args[0]) {
+        Ok(array) => {
+            array.borrow_mut().push(args[1].clone());
+            Ok(ValueType::Nil)
+        }
+        Err(error) => Ok(error),
+    }
+}
+
+/// Creates the array of rows, each an array of columns with its own copy of the initial value.
+fn matrix(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let (rows, cols) = match (size(&args[0]), size(&args[1])) {
+        (Some(rows), Some(cols)) => (rows, cols),
+        (None, _) => return Ok(unexpected_argument("matrix", "a size", &args[0])),
+        (_, None) => return Ok(unexpected_argument("matrix", "a size", &args[1])),
+    };
+    // Rows without columns are arrays too, so both sizes fit the limit as well as the elements.
+    array_len(rows)?;
+    array_len(cols)?;
+    array_len(rows * cols)?;
+    let matrix = (0..rows as usize)
+        .map(|_| {
+            let row = (0..cols as usize).map(|_| args[2].deep_copy()).collect();
+            ValueType::ArrayRef(Rc::new(RefCell::new(row)))
+        })
+        .collect();
+    Ok(ValueType::ArrayRef(Rc::new(RefCell::new(matrix))))
+}
+
+/// Takes the size of an array from the argument, a whole non-negative number.
+fn size(value: &ValueType) -> Option<u128> {
+    let n = value.as_float()?;
+    (n.fract() == 0.0 && n >= 0.0).then_some(n as u128)
+}
+
+/// Removes the last value of the array and returns it.
+fn pop(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let value = match array("pop", &args[0]) {
+        Ok(array) => array
+            .borrow_mut()
+            .pop()
+            .unwrap_or_else(|| ValueType::error("pop expects a non-empty array")),
+        Err(error) => error,
+    };
+    Ok(value)
+}
+
+/// Inserts the value at the position, moving the following values to the right.
+fn insert(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let array = match array("insert", &args[0]) {
+        Ok(array) => array,
+        Err(error) => return Ok(error),
+    };
+    let len = array.borrow().len();
+    match position(&args[1], len) {
+        Ok(index) => {
+            array.borrow_mut().insert(index, args[2].clone());
+            Ok(ValueType::Nil)
+        }
+        Err(error) => Ok(error),
+    }
+}
+
+/// Removes the value at the position and returns it, moving the following values to the left.
+fn remove(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let array = match array("remove", &args[0]) {
+        Ok(array) => array,
+        Err(error) => return Ok(error),
+    };
+    let len = array.borrow().len();
+    if len == 0 {
+        return Ok(ValueType::error("remove expects a non-empty array"));
+    }
+    match position(&args[1], len - 1) {
+        Ok(index) => Ok(array.borrow_mut().remove(index)),
+        Err(error) => Ok(error),
+    }
+}
+
+/// Returns a new array with the values from the start position up to, but not including, the end one.
+fn slice(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let array = match array("slice", &args[0]) {
+        Ok(array) => array.borrow(),
+        Err(error) => return Ok(error),
+    };
+    let bounds = match &args[1..] {
+        [ValueType::Range(range)] => (ValueType::Number(range.0), ValueType::Number(range.1)),
+        [start, end] => (start.clone(), end.clone()),
+        [other, ..] => return Ok(unexpected_argument("slice", "a range", other)),
+        [] => return Err(VmRuntimeError::TypeMismatch),
+    };
+    let (start, end) = match (
+        position(&bounds.0, array.len()),
+        position(&bounds.1, array.len()),
+    ) {
+        (Ok(start), Ok(end)) if start <= end => (start, end),
+        (Ok(start), Ok(end)) => {
+            return Ok(ValueType::error(format!(
+                "slice start {} is after the end {}",
+                start, end
+            )))
+        }
+        (Err(error), _) | (_, Err(error)) => return Ok(error),
+    };
+    let slice = array[start..end].to_vec();
+    Ok(ValueType::ArrayRef(Rc::new(RefCell::new(slice))))
+}
+
+/// Returns the position of the first occurrence of the substring, or nil if there is none.
+fn index_of(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    match (&args[0], &args[1]) {
+        (ValueType::Text(text), ValueType::Text(pattern)) => Ok(match text.find(&**pattern) {
+            Some(offset) => ValueType::Int(text[..offset].chars().count() as i64),
+            None => ValueType::Nil,
+        }),
+        (ValueType::Text(_), pattern) => {
+            Ok(unexpected_argument("index_of", "a string to find", pattern))
+        }
+        (value, _) => Ok(unexpected_argument("index_of", "a string", value)),
+    }
+}
+
+/// Splits the string by the separator into an array of strings.
+/// The empty separator splits the string into characters.
+fn split(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let parts = match (&args[0], &args[1]) {
+        (ValueType::Text(text), ValueType::Text(separator)) if separator.is_empty() => text
+            .chars()
+            .map(|c| ValueType::string(c.to_string()))
+            .collect(),
+        (ValueType::Text(text), ValueType::Text(separator)) => {
+            text.split(&**separator).map(ValueType::string).collect()
+        }
+        (ValueType::Text(_), separator) => {
+            return Ok(unexpected_argument(
+                "split",
+                "a string separator",
+                separator,
+            ))
+        }
+        (value, _) => return Ok(unexpected_argument("split", "a string", value)),
+    };
+    Ok(ValueType::ArrayRef(Rc::new(RefCell::new(parts))))
+}
+
+fn len(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let len = match &args[0] {
+        ValueType::Text(text) => text.chars().count(),
+        ValueType::ArrayRef(array) => array.borrow().len(),
+        ValueType::Tuple(elements) => elements.len(),
+        ValueType::Map(map) => map.borrow().len(),
+        value => {
+            return Ok(unexpected_argument(
+                "len",
+                "a string, an array or a map",
+                value,
+            ))
+        }
     };
-    vm.push(ValueType::Number(len as f64));
-    Ok(())
+    Ok(ValueType::Int(len as i64))
 }
 
-fn as_char(vm: &mut Vm) -> Result<(), VmRuntimeError> {
-    let value = vm.pop()?;
-    vm.pop()?;
-    match &value {
+fn as_char(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    match args[0].as_float() {
+        Some(n) => match char::from_u32(n as u32) {
+            Some(c) => Ok(ValueType::string(c.to_string())),
+            None => Ok(unexpected_argument("as_char", "a character code", &args[0])),
+        },
+        None => Ok(unexpected_argument("as_char", "a number", &args[0])),
+    }
+}
+
+fn as_string(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    Ok(ValueType::string(args[0].as_string()))
+}
+
+/// Converts the number or the text to an integer, dropping the fraction of floats.
+fn as_int(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let value = match &args[0] {
+        ValueType::Int(n) => ValueType::Int(*n),
+        ValueType::Number(n) if (i64::MIN as f64..-(i64::MIN as f64)).contains(n) => {
+            ValueType::Int(*n as i64)
+        }
         ValueType::Number(n) => {
-            let c = *n as u8 as char;
-            vm.push(ValueType::Text(Box::new(c.to_string())));
-            Ok(())
+            ValueType::error(format!("as_int cannot convert {} to an integer", n))
         }
-        _ => Err(VmRuntimeError::TypeMismatch),
+        ValueType::Text(text) => match text.trim().parse() {
+            Ok(n) => ValueType::Int(n),
+            Err(_) => ValueType::error(format!("as_int cannot convert {} to an integer", text)),
+        },
+        value => unexpected_argument("as_int", "a number or a string", value),
+    };
+    Ok(value)
+}
+
+/// Converts the number or the text to a float.
+fn as_float(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let value = match &args[0] {
+        ValueType::Text(text) => match text.trim().parse() {
+            Ok(n) => ValueType::Number(n),
+            Err(_) => ValueType::error(format!("as_float cannot convert {} to a float", text)),
+        },
+        value => match value.as_float() {
+            Some(n) => ValueType::Number(n),
+            None => unexpected_argument("as_float", "a number or a string", value),
+        },
+    };
+    Ok(value)
+}
+
+/// Prints the arguments on one line, separated by spaces.
+fn print(vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    vm.write_line(&printable_line(args))?;
+    Ok(ValueType::Nil)
+}
+
+/// Prints the arguments to the error output the way `print` does.
+fn eprint(vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    vm.write_error_line(&printable_line(args))?;
+    Ok(ValueType::Nil)
+}
+
+fn printable_line(args: &[ValueType]) -> String {
+    let line: Vec<String> = args.iter().cloned().map(printable).collect();
+    line.join(" ")
+}
+
+/// Prints the formatted text without starting a new line.
+fn printf(vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let text = match format_text("printf", args) {
+        Ok(text) => text,
+        Err(error) => return Ok(error),
+    };
+    vm.io().stdout().borrow_mut().write_all(text.as_bytes())?;
+    Ok(ValueType::Nil)
+}
+
+/// Replaces the placeholders of the format in the first argument with the following arguments.
+///
+/// `{}` shows the value the way `print` does, `{:.N}` shows a number with N digits after the point.
+/// `{{` and `}}` stand for the braces themselves.
+fn format_text(function: &str, args: &[ValueType]) -> Result<String, ValueType> {
+    let ValueType::Text(format) = &args[0] else {
+        return Err(unexpected_argument(function, "a string", &args[0]));
+    };
+    let mut values = args[1..].iter();
+    let mut text = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' if chars.as_str().starts_with(c) => {
+                chars.next();
+                text.push(c);
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let Some(end) = rest.find('}') else {
+                    return Err(ValueType::error(format!(
+                        "{} expects every placeholder to be closed",
+                        function
+                    )));
+                };
+                let Some(value) = values.next() else {
+                    return Err(ValueType::error(format!(
+                        "{} expects an argument for every placeholder",
+                        function
+                    )));
+                };
+                text.push_str(&placeholder(function, &rest[..end], value)?);
+                chars = rest[end + 1..].chars();
+            }
+            '}' => {
+                return Err(ValueType::error(format!(
+                    "{} expects }} in the text to be doubled",
+                    function
+                )))
+            }
+            c => text.push(c),
+        }
+    }
+    if values.len() > 0 {
+        return Err(ValueType::error(format!(
+            "{} expects a placeholder for every argument",
+            function
+        )));
+    }
+    Ok(text)
+}
+
+/// Most digits after the point a placeholder can ask for.
+const MAX_PRECISION: usize = 100;
+
+/// Shows the value as the placeholder with the specification between the braces asks.
+fn placeholder(function: &str, spec: &str, value: &ValueType) -> Result<String, ValueType> {
+    if spec.is_empty() {
+        return Ok(printable(value.clone()));
     }
+    let precision = spec
+        .strip_prefix(":.")
+        .and_then(|digits| digits.parse::<usize>().ok())
+        .filter(|precision| *precision <= MAX_PRECISION)
+        .ok_or_else(|| {
+            ValueType::error(format!(
+                "{} does not support placeholder {{{}}}",
+                function, spec
+            ))
+        })?;
+    match value.as_float() {
+        Some(n) => Ok(format!("{:.*}", precision, n)),
+        None => Err(unexpected_argument(function, "a number", value)),
+    }
+}
+
+/// Reads a line from the input without the line break.
+/// Returns nil when the input has ended.
+fn read_line(vm: &mut Vm, _args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let mut line = String::new();
+    if vm.io().stdin().borrow_mut().read_line(&mut line)? == 0 {
+        return Ok(ValueType::Nil);
+    }
+    let len = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(len);
+    Ok(ValueType::string(line))
+}
+
+/// Returns an array of the map keys in alphabetical order.
+fn keys(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    let ValueType::Map(map) = &args[0] else {
+        return Ok(unexpected_argument("keys", "a map", &args[0]));
+    };
+    let mut keys: Vec<String> = map.borrow().keys().cloned().collect();
+    keys.sort();
+    let keys = keys.into_iter().map(ValueType::string).collect();
+    Ok(ValueType::ArrayRef(Rc::new(RefCell::new(keys))))
+}
+
+fn has_key(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    match (&args[0], &args[1]) {
+        (ValueType::Map(map), ValueType::Text(key)) => {
+            Ok(ValueType::Bool(map.borrow().contains_key(&**key)))
+        }
+        (ValueType::Map(_), key) => Ok(unexpected_argument("has_key", "a string key", key)),
+        (value, _) => Ok(unexpected_argument("has_key", "a map", value)),
+    }
+}
+
+/// Compares the values by their contents like `==`, but takes the values of different types as not equal.
+fn equals(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    Ok(ValueType::Bool(args[0].equals(&args[1]).unwrap_or(false)))
+}
+
+/// Makes an error value with the message, so functions of the program can fail like the native ones.
+fn error(_vm: &mut Vm, args: &[ValueType]) -> Result<ValueType, VmRuntimeError> {
+    Ok(ValueType::error(args[0].as_string()))
 }
 
-fn as_string(vm: &mut Vm) -> Result<(), VmRuntimeError> {
-    let value = vm.pop()?;
-    vm.pop()?;
-    let string = value.as_string();
-    vm.push(ValueType::Text(Box::new(string)));
-    Ok(())
+fn unexpected_argument(function: &str, expected: &str, actual: &ValueType) -> ValueType {
+    ValueType::error(format!(
+        "{} expects {} but found {}",
+        function, expected, actual
+    ))
 }
@@ -2,26 +2,39 @@
 use std::fmt::Display;
 
 /// Operations supported by the virtual machine
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Op {
     /// Print the top value of the stack.
     Return,
     /// Call function stored in the top of the stack.
     Call(usize),
+    /// Calls the function like [Op::Call] and returns its result from the current function.
+    /// The called function reuses the call frame of the current one.
+    TailCall(usize),
     /// Pushes floating-point constant on the stack.
     ConstFloat(f64),
+    /// Pushes integer constant on the stack.
+    ConstInt(i64),
     /// Pushes boolean constant on the stack.
     ConstBool(bool),
     /// Pushes constant from the constant pool on the stack.
     Const(usize),
     /// Loads indexed element from the array and pushes it on the stack.
     LoadIndex,
+    /// Sets the element of the array or map below the index and the value in place,
+    /// leaving the value on the stack.
     StoreIndex,
+    /// Sets the element like [Op::StoreIndex], but pushes the changed array, map, or string above the value,
+    /// so it can be stored back into the variable. Strings are replaced by their changed copies.
+    ReplaceIndex,
     /// Add two top elements of the stack.
     Add,
     Sub,
     Mul,
     Div,
+    /// Raises the first operand to the power of the second.
+    Pow,
     /// Compares top values of the stack. Puts comparison result on top of the stack.
     Cmp,
     /// Inverts boolean value on top of the stack.
@@ -30,6 +43,17 @@ pub enum Op {
     Le,
     /// Pushes true on the stack if the first value is greater or equal to the second.
     Ge,
+    /// Pushes true on the stack if the top values are not equal. Same as [Op::Cmp] followed by [Op::Not].
+    Ne,
+    /// Pushes true on the stack if the first value is less than the second.
+    /// Same as [Op::Ge] followed by [Op::Not].
+    Lt,
+    /// Pushes true on the stack if the first value is greater than the second.
+    /// Same as [Op::Le] followed by [Op::Not].
+    Gt,
+    /// Pushes true on the stack if the top values are equal.
+    /// Unlike [Op::Cmp], values of different types are not equal instead of failing.
+    Match,
     /// Prints value on top of the stack.
     Print,
     /// Takes the value from the top of the stack and stores it in the global variable.
@@ -38,10 +62,22 @@ pub enum Op {
     /// Load global variable value onto the stack.
     /// The name of the variable is taken from the constant pool.
     LoadGlobal(usize),
+    /// Takes the value from the top of the stack and stores it in the global variable in the slot.
+    StoreGlobalSlot(usize),
+    /// Loads the value of the global variable in the slot onto the stack.
+    LoadGlobalSlot(usize),
     /// Takes the value from the top of the stack and stores it in the local variable.
     StoreLocal(usize),
     /// Load local variable value onto the stack.
     LoadLocal(usize),
+    /// Takes the value from the top of the stack and stores it in the captured variable.
+    StoreUpvalue(usize),
+    /// Load captured variable value onto the stack.
+    LoadUpvalue(usize),
+    /// Creates a closure of the function from the constant pool and pushes it on the stack.
+    Closure(usize),
+    /// Moves the captured local variable on top of the stack into its closures and pops it.
+    CloseUpvalue,
     /// Pops value from the top of the stack.
     Pop,
     /// Pushes nil on the stack.
@@ -50,37 +86,352 @@ pub enum Op {
     Jump(i32),
     /// Jump to the given offset if the top value of the stack is false.
     JumpIfFalse(i32),
+    /// Jump to the given offset if the top value of the stack is true.
+    JumpIfTrue(i32),
     Array,
+    /// Creates a map of the given number of key-value pairs on top of the stack.
+    /// Every key is pushed before its value.
+    Map(usize),
+    /// Installs the exception handler at the given offset in the current call frame.
+    PushHandler(i32),
+    /// Removes the innermost exception handler of the current call frame.
+    PopHandler,
+    /// Throws the value on top of the stack to the innermost exception handler.
+    Throw,
+    /// Stops the program if the condition below the message on top of the stack is false.
+    Assert,
+    /// Replaces the collection on top of the stack with the array of its elements and pushes the index 0.
+    /// Strings give their characters and maps their keys in sorted order, while ranges stay as they are.
+    IterInit,
+    /// Pushes the element of the array or the range at the index on top of the stack and increments the index.
+    /// Jumps to the given offset instead when the index is past the end of the elements.
+    IterNext(i32),
+    /// Creates a range from the start number below the end number on top of the stack.
+    Range,
+    /// Adds one to the counter of the range loop on top of the stack.
+    /// Fails when the counter is too large for adding one to change it.
+    Increment,
+    /// Creates a tuple of the given number of values on top of the stack.
+    Tuple(usize),
+    /// Replaces the tuple on top of the stack with its values, the last one on top.
+    /// Fails unless the tuple has the given number of values.
+    Unpack(usize),
+}
+
+impl Op {
+    /// Returns the assembly name of the operation.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Op::Const(_) => "CONST",
+            Op::ConstFloat(_) => "CONST_F",
+            Op::ConstInt(_) => "CONST_I",
+            Op::ConstBool(_) => "CONST_B",
+            Op::Nil => "CONST_NIL",
+            Op::Add => "ADD",
+            Op::Sub => "SUB",
+            Op::Mul => "MUL",
+            Op::Div => "DIV",
+            Op::Pow => "POW",
+            Op::Cmp => "CMP",
+            Op::Le => "LE",
+            Op::Ge => "GE",
+            Op::Ne => "NE",
+            Op::Lt => "LT",
+            Op::Gt => "GT",
+            Op::Match => "MATCH",
+            Op::Not => "NEG",
+            Op::Print => "PRN",
+            Op::LoadGlobal(_) => "LD_G",
+            Op::StoreGlobal(_) => "ST_G",
+            Op::LoadGlobalSlot(_) => "LD_GS",
+            Op::StoreGlobalSlot(_) => "ST_GS",
+            Op::LoadLocal(_) => "LD_L",
+            Op::StoreLocal(_) => "ST_L",
+            Op::LoadUpvalue(_) => "LD_UP",
+            Op::StoreUpvalue(_) => "ST_UP",
+            Op::Closure(_) => "CLOSURE",
+            Op::CloseUpvalue => "CLOSE_UP",
+            Op::Pop => "POP",
+            Op::Return => "RET",
+            Op::Call(_) => "CALL",
+            Op::TailCall(_) => "TAIL_CALL",
+            Op::Jump(_) => "JMP",
+            Op::JumpIfFalse(_) => "JZ",
+            Op::JumpIfTrue(_) => "JNZ",
+            Op::LoadIndex => "LD_IDX",
+            Op::StoreIndex => "ST_IDX",
+            Op::ReplaceIndex => "RPL_IDX",
+            Op::Array => "ARR",
+            Op::Map(_) => "MAP",
+            Op::PushHandler(_) => "PUSH_HANDLER",
+            Op::PopHandler => "POP_HANDLER",
+            Op::Throw => "THROW",
+            Op::Assert => "ASSERT",
+            Op::IterInit => "ITER",
+            Op::IterNext(_) => "ITER_NEXT",
+            Op::Range => "RANGE",
+            Op::Increment => "INC",
+            Op::Tuple(_) => "TUPLE",
+            Op::Unpack(_) => "UNPACK",
+        }
+    }
+
+    /// Returns the number of values the operation takes from the stack
+    /// and the number of values it puts there when the execution goes on to the next operation.
+    ///
+    /// Jumps leave the stack the same way when they jump, except [Op::PushHandler],
+    /// whose handler starts with the thrown value on top of the stack, and [Op::IterNext],
+    /// which only replaces the index when it jumps past the last element.
+    pub fn stack_effect(&self) -> (usize, usize) {
+        match self {
+            Op::Const(_)
+            | Op::ConstFloat(_)
+            | Op::ConstInt(_)
+            | Op::ConstBool(_)
+            | Op::Nil
+            | Op::LoadGlobal(_)
+            | Op::LoadGlobalSlot(_)
+            | Op::LoadLocal(_)
+            | Op::LoadUpvalue(_)
+            | Op::Closure(_) => (0, 1),
+            Op::StoreGlobal(_)
+            | Op::StoreGlobalSlot(_)
+            | Op::StoreLocal(_)
+            | Op::StoreUpvalue(_)
+            | Op::Jump(_)
+            | Op::PushHandler(_)
+            | Op::PopHandler => (0, 0),
+            Op::Not | Op::Increment => (1, 1),
+            Op::Add
+            | Op::Sub
+            | Op::Mul
+            | Op::Div
+            | Op::Pow
+            | Op::Cmp
+            | Op::Le
+            | Op::Ge
+            | Op::Ne
+            | Op::Lt
+            | Op::Gt
+            | Op::Match
+            | Op::LoadIndex
+            | Op::Array
+            | Op::Range => (2, 1),
+            Op::StoreIndex => (3, 1),
+            Op::ReplaceIndex => (3, 2),
+            Op::Print
+            | Op::Pop
+            | Op::CloseUpvalue
+            | Op::JumpIfFalse(_)
+            | Op::JumpIfTrue(_)
+            | Op::Throw
+            | Op::Return => (1, 0),
+            Op::Assert => (2, 0),
+            Op::Call(arity) | Op::TailCall(arity) => (arity + 1, 1),
+            Op::Map(len) => (len * 2, 1),
+            Op::Tuple(len) => (*len, 1),
+            Op::Unpack(len) => (1, *len),
+            Op::IterInit => (1, 2),
+            Op::IterNext(_) => (1, 2),
+        }
+    }
+
+    /// Returns the inline operand of the operation if it has one.
+    pub fn operand(&self) -> Option<Operand> {
+        match self {
+            Op::Const(v)
+            | Op::LoadGlobal(v)
+            | Op::StoreGlobal(v)
+            | Op::LoadGlobalSlot(v)
+            | Op::StoreGlobalSlot(v)
+            | Op::LoadLocal(v)
+            | Op::StoreLocal(v)
+            | Op::LoadUpvalue(v)
+            | Op::StoreUpvalue(v)
+            | Op::Closure(v)
+            | Op::Call(v)
+            | Op::TailCall(v)
+            | Op::Map(v)
+            | Op::Tuple(v)
+            | Op::Unpack(v) => Some(Operand::Index(*v)),
+            Op::ConstFloat(v) => Some(Operand::Number(*v)),
+            Op::ConstInt(v) => Some(Operand::Integer(*v)),
+            Op::ConstBool(v) => Some(Operand::Bool(*v)),
+            Op::Jump(v)
+            | Op::JumpIfFalse(v)
+            | Op::JumpIfTrue(v)
+            | Op::PushHandler(v)
+            | Op::IterNext(v) => Some(Operand::Offset(*v)),
+            _ => None,
+        }
+    }
+
+    /// Encodes the operation into the opcode and the inline operand.
+    ///
+    /// Floating-point and integer operands do not fit the inline operand,
+    /// so they are appended to the number table and the operand holds their index.
+    /// Integers are kept in the table by their bits.
+    ///
+    /// Returns `None` if the operand does not fit 32 bits.
+    pub(crate) fn encode(self, numbers: &mut Vec<f64>) -> Option<(u8, u32)> {
+        let encoded = match self {
+            Op::Return => (0, 0),
+            Op::Call(idx) => (1, inline(idx)?),
+            Op::ConstFloat(n) => {
+                numbers.push(n);
+                (2, inline(numbers.len() - 1)?)
+            }
+            Op::ConstBool(b) => (3, u32::from(b)),
+            Op::Const(idx) => (4, inline(idx)?),
+            Op::LoadIndex => (5, 0),
+            Op::StoreIndex => (6, 0),
+            Op::Add => (7, 0),
+            Op::Sub => (8, 0),
+            Op::Mul => (9, 0),
+            Op::Div => (10, 0),
+            Op::Cmp => (11, 0),
+            Op::Not => (12, 0),
+            Op::Le => (13, 0),
+            Op::Ge => (14, 0),
+            Op::Print => (15, 0),
+            Op::StoreGlobal(idx) => (16, inline(idx)?),
+            Op::LoadGlobal(idx) => (17, inline(idx)?),
+            Op::StoreLocal(idx) => (18, inline(idx)?),
+            Op::LoadLocal(idx) => (19, inline(idx)?),
+            Op::StoreUpvalue(idx) => (20, inline(idx)?),
+            Op::LoadUpvalue(idx) => (21, inline(idx)?),
+            Op::Closure(idx) => (22, inline(idx)?),
+            Op::CloseUpvalue => (23, 0),
+            Op::Pop => (24, 0),
+            Op::Nil => (25, 0),
+            Op::Jump(offset) => (26, offset as u32),
+            Op::JumpIfFalse(offset) => (27, offset as u32),
+            Op::Array => (28, 0),
+            Op::Map(idx) => (29, inline(idx)?),
+            Op::TailCall(idx) => (30, inline(idx)?),
+            Op::Pow => (31, 0),
+            Op::PushHandler(offset) => (32, offset as u32),
+            Op::PopHandler => (33, 0),
+            Op::Throw => (34, 0),
+            Op::Ne => (35, 0),
+            Op::Lt => (36, 0),
+            Op::Gt => (37, 0),
+            Op::Match => (38, 0),
+            Op::Assert => (39, 0),
+            Op::IterInit => (40, 0),
+            Op::IterNext(offset) => (41, offset as u32),
+            Op::Range => (42, 0),
+            Op::Tuple(len) => (43, inline(len)?),
+            Op::Unpack(len) => (44, inline(len)?),
+            Op::ConstInt(n) => {
+                numbers.push(f64::from_bits(n as u64));
+                (45, inline(numbers.len() - 1)?)
+            }
+            Op::StoreGlobalSlot(slot) => (46, inline(slot)?),
+            Op::LoadGlobalSlot(slot) => (47, inline(slot)?),
+            Op::JumpIfTrue(offset) => (48, offset as u32),
+            Op::ReplaceIndex => (49, 0),
+            Op::Increment => (50, 0),
+        };
+        Some(encoded)
+    }
+
+    /// Restores the operation encoded by [Op::encode].
+    ///
+    /// Returns `None` for unknown opcodes and for operands missing from the number table.
+    pub(crate) fn decode(opcode: u8, operand: u32, numbers: &[f64]) -> Option<Op> {
+        let op = match opcode {
+            0 => Op::Return,
+            1 => Op::Call(operand as usize),
+            2 => Op::ConstFloat(*numbers.get(operand as usize)?),
+            3 => Op::ConstBool(operand != 0),
+            4 => Op::Const(operand as usize),
+            5 => Op::LoadIndex,
+            6 => Op::StoreIndex,
+            7 => Op::Add,
+            8 => Op::Sub,
+            9 => Op::Mul,
+            10 => Op::Div,
+            11 => Op::Cmp,
+            12 => Op::Not,
+            13 => Op::Le,
+            14 => Op::Ge,
+            15 => Op::Print,
+            16 => Op::StoreGlobal(operand as usize),
+            17 => Op::LoadGlobal(operand as usize),
+            18 => Op::StoreLocal(operand as usize),
+            19 => Op::LoadLocal(operand as usize),
+            20 => Op::StoreUpvalue(operand as usize),
+            21 => Op::LoadUpvalue(operand as usize),
+            22 => Op::Closure(operand as usize),
+            23 => Op::CloseUpvalue,
+            24 => Op::Pop,
+            25 => Op::Nil,
+            26 => Op::Jump(operand as i32),
+            27 => Op::JumpIfFalse(operand as i32),
+            28 => Op::Array,
+            29 => Op::Map(operand as usize),
+            30 => Op::TailCall(operand as usize),
+            31 => Op::Pow,
+            32 => Op::PushHandler(operand as i32),
+            33 => Op::PopHandler,
+            34 => Op::Throw,
+            35 => Op::Ne,
+            36 => Op::Lt,
+            37 => Op::Gt,
+            38 => Op::Match,
+            39 => Op::Assert,
+            40 => Op::IterInit,
+            41 => Op::IterNext(operand as i32),
+            42 => Op::Range,
+            43 => Op::Tuple(operand as usize),
+            44 => Op::Unpack(operand as usize),
+            45 => Op::ConstInt(numbers.get(operand as usize)?.to_bits() as i64),
+            46 => Op::StoreGlobalSlot(operand as usize),
+            47 => Op::LoadGlobalSlot(operand as usize),
+            48 => Op::JumpIfTrue(operand as i32),
+            49 => Op::ReplaceIndex,
+            50 => Op::Increment,
+            _ => return None,
+        };
+        Some(op)
+    }
+}
+
+/// Fits the index or the count into the inline operand, if it is small enough.
+fn inline(value: usize) -> Option<u32> {
+    u32::try_from(value).ok()
 }
 
 impl Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.operand() {
+            Some(operand) => write!(f, "{}, {}", self.mnemonic(), operand),
+            None => write!(f, "{}", self.mnemonic()),
+        }
+    }
+}
+
+/// Inline operand of an operation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    /// Index into the constant pool, the locals or the upvalues, or the number of values.
+    Index(usize),
+    /// Relative offset of a jump.
+    Offset(i32),
+    Number(f64),
+    Integer(i64),
+    Bool(bool),
+}
+
+impl Display for Operand {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Op::Const(idx) => write!(f, "CONST, {}", idx),
-            Op::ConstFloat(n) => write!(f, "CONST_F, {}", n),
-            Op::ConstBool(b) => write!(f, "CONST_B, {}", b),
-            Op::Nil => write!(f, "CONST_NIL"),
-            Op::Add => write!(f, "ADD"),
-            Op::Sub => write!(f, "SUB"),
-            Op::Mul => write!(f, "MUL"),
-            Op::Div => write!(f, "DIV"),
-            Op::Cmp => write!(f, "CMP"),
-            Op::Le => write!(f, "LE"),
-            Op::Ge => write!(f, "GE"),
-            Op::Not => write!(f, "NEG"),
-            Op::Print => write!(f, "PRN"),
-            Op::LoadGlobal(idx) => write!(f, "LD_G, {}", idx),
-            Op::StoreGlobal(idx) => write!(f, "ST_G, {}", idx),
-            Op::LoadLocal(idx) => write!(f, "LD_L, {}", idx),
-            Op::StoreLocal(idx) => write!(f, "ST_L, {}", idx),
-            Op::Pop => write!(f, "POP"),
-            Op::Return => write!(f, "RET"),
-            Op::Call(arity) => write!(f, "CALL, {}", arity),
-            Op::Jump(offset) => write!(f, "JMP, {}", offset),
-            Op::JumpIfFalse(offset) => write!(f, "JZ, {}", offset),
-            Op::LoadIndex => write!(f, "LD_IDX"),
-            Op::StoreIndex => write!(f, "ST_IDX"),
-            Op::Array => write!(f, "ARR"),
+            Operand::Index(idx) => write!(f, "{}", idx),
+            Operand::Offset(offset) => write!(f, "{}", offset),
+            Operand::Number(n) => write!(f, "{}", n),
+            Operand::Integer(n) => write!(f, "{}", n),
+            Operand::Bool(b) => write!(f, "{}", b),
         }
     }
 }
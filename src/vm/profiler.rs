@@ -0,0 +1,199 @@
+//! Profiler counting the instructions the program executes and the time they take.
+//!
+//! The numbers are collected per instruction kind and per function, so slow parts of the program stand out.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::vm::trace::{TraceEvent, VmStepTrace};
+
+/// Name of the function running the top-level code of the program.
+const SCRIPT_NAME: &str = "$main$";
+
+/// Number of executions and the total time of instructions.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timing {
+    count: usize,
+    time: Duration,
+}
+
+impl Timing {
+    /// Number of the executed instructions.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Time the instructions took together.
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    fn add(&mut self, time: Duration) {
+        self.count += 1;
+        self.time += time;
+    }
+}
+
+/// Collected timings of the instructions.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    ops: HashMap<&'static str, Timing>,
+    functions: HashMap<String, Timing>,
+}
+
+impl Profile {
+    /// Timing of the instructions with the mnemonic, e.g. `ADD`.
+    pub fn op(&self, mnemonic: &str) -> Option<Timing> {
+        self.ops.get(mnemonic).copied()
+    }
+
+    /// Timing of the instructions of the function, not counting the functions it calls.
+    pub fn function(&self, name: &str) -> Option<Timing> {
+        self.functions.get(name).copied()
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:<16} {:>12} {:>14}", "instruction", "count", "time")?;
+        for (mnemonic, timing) in by_time(&self.ops) {
+            writeln!(
+                f,
+                "{:<16} {:>12} {:>14?}",
+                mnemonic, timing.count, timing.time
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<16} {:>12} {:>14}",
+            "function", "instructions", "time"
+        )?;
+        for (name, timing) in by_time(&self.functions) {
+            writeln!(f, "{:<16} {:>12} {:>14?}", name, timing.count, timing.time)?;
+        }
+        Ok(())
+    }
+}
+
+/// Timings sorted from the slowest, with the names breaking ties.
+fn by_time<K: Ord>(timings: &HashMap<K, Timing>) -> Vec<(&K, &Timing)> {
+    let mut timings: Vec<(&K, &Timing)> = timings.iter().collect();
+    timings.sort_by(|(a_name, a), (b_name, b)| b.time.cmp(&a.time).then(a_name.cmp(b_name)));
+    timings
+}
+
+/// Tracer collecting the [Profile] of the program it is attached to.
+///
+/// Clones share the profile, so keep one to read the results after the run.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// # use brainterpreter::vm::Vm;
+/// # use brainterpreter::vm::profiler::Profiler;
+/// let profiler = Profiler::default();
+/// let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![]))).with_tracer(profiler.clone());
+/// vm.eval("let a = 1 + 2;").unwrap();
+///
+/// assert_eq!(profiler.profile().op("ADD").map(|timing| timing.count()), Some(1));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    state: Rc<RefCell<ProfilerState>>,
+}
+
+#[derive(Debug, Default)]
+struct ProfilerState {
+    profile: Profile,
+    /// Functions of the active calls, the innermost one is the last.
+    calls: Vec<String>,
+    /// Instruction being executed and the time it started.
+    running: Option<(&'static str, Instant)>,
+}
+
+impl Profiler {
+    /// Copy of the profile collected so far.
+    pub fn profile(&self) -> Profile {
+        self.state.borrow().profile.clone()
+    }
+}
+
+impl ProfilerState {
+    /// Records the time of the running instruction, if there is one.
+    fn finish_instruction(&mut self) {
+        let Some((mnemonic, start)) = self.running.take() else {
+            return;
+        };
+        let time = start.elapsed();
+        self.profile.ops.entry(mnemonic).or_default().add(time);
+        let function = self.calls.last().map_or(SCRIPT_NAME, String::as_str);
+        match self.profile.functions.get_mut(function) {
+            Some(timing) => timing.add(time),
+            None => {
+                let mut timing = Timing::default();
+                timing.add(time);
+                self.profile.functions.insert(function.to_string(), timing);
+            }
+        }
+    }
+}
+
+impl VmStepTrace for Profiler {
+    fn trace(&mut self, event: TraceEvent) {
+        let mut state = self.state.borrow_mut();
+        match event {
+            TraceEvent::Instruction { op, .. } => {
+                state.finish_instruction();
+                state.running = Some((op.mnemonic(), Instant::now()));
+            }
+            TraceEvent::Stack(_) => state.finish_instruction(),
+            // Calls and returns finish the instruction, so it counts for the function running it.
+            // The program itself runs in the first frame, which is not reported as a call.
+            TraceEvent::CallEntered { function, depth } => {
+                state.finish_instruction();
+                state.calls.truncate(depth.saturating_sub(2));
+                state.calls.push(function.to_string());
+            }
+            TraceEvent::CallReturned { depth } => {
+                state.finish_instruction();
+                state.calls.truncate(depth.saturating_sub(1));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::Vm;
+
+    #[test]
+    fn count_instructions_per_function() {
+        let profiler = Profiler::default();
+        let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![]))).with_tracer(profiler.clone());
+
+        vm.eval(
+            "fun add(a, b) { return a + b; }
+            let x = add(1, 2);
+            let y = add(x, 3) + 4;",
+        )
+        .unwrap();
+
+        let profile = profiler.profile();
+        assert_eq!(profile.op("ADD").map(|t| t.count()), Some(3));
+        let add = profile.function("add").unwrap();
+        let script = profile.function(SCRIPT_NAME).unwrap();
+        assert!(add.count() > 0);
+        assert_eq!(
+            add.count() + script.count(),
+            profile.ops.values().map(Timing::count).sum::<usize>()
+        );
+        assert!(profile.to_string().contains("ADD"));
+    }
+}
@@ -0,0 +1,104 @@
+//! Sources of random numbers and time for the native functions.
+//!
+//! The virtual machine takes them from providers, so tests and embedders can make the programs deterministic.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of random numbers for the `random` and `random_int` functions.
+pub trait RandomSource {
+    /// Returns the next random number in the range `[0, 1)`.
+    fn next_f64(&mut self) -> f64;
+}
+
+/// Source of time for the `clock` function.
+pub trait Clock {
+    /// Returns the current time in seconds.
+    fn seconds(&self) -> f64;
+}
+
+/// Pseudo-random generator giving the same numbers for the same seed.
+///
+/// It is fast, but not suitable for cryptography.
+#[derive(Debug, Clone)]
+pub struct SeededRandom {
+    state: u64,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        SeededRandom { state: seed }
+    }
+}
+
+impl Default for SeededRandom {
+    /// Seeds the generator with the current time, so every run gets different numbers.
+    fn default() -> Self {
+        SeededRandom::new((now() * 1e9) as u64)
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_f64(&mut self) -> f64 {
+        // SplitMix64 sequence.
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        // The top 53 bits fill the mantissa of the number.
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Clock counting seconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn seconds(&self) -> f64 {
+        now()
+    }
+}
+
+/// Seconds since the Unix epoch.
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs_f64())
+        .unwrap_or_default()
+}
+
+/// Seconds since the Unix epoch, taken from JavaScript as the system time is not available.
+#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+fn now() -> f64 {
+    js_sys::Date::now() / 1000.0
+}
+
+/// Without JavaScript bindings the time always starts at zero.
+#[cfg(all(target_arch = "wasm32", not(feature = "wasm")))]
+fn now() -> f64 {
+    0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeat_numbers_for_the_same_seed() {
+        let mut first = SeededRandom::new(42);
+        let mut second = SeededRandom::new(42);
+
+        for _ in 0..100 {
+            let n = first.next_f64();
+            assert_eq!(n, second.next_f64());
+            assert!((0.0..1.0).contains(&n));
+        }
+        assert_ne!(
+            SeededRandom::new(1).next_f64(),
+            SeededRandom::new(2).next_f64()
+        );
+    }
+}
@@ -1,43 +1,125 @@
+use std::ops::Range;
+
 use crate::value::ValueType;
 use crate::vm::{VmRuntimeError, VmStack, STACK_SIZE};
 
 impl VmStack {
     pub fn pop(&mut self) -> Result<ValueType, VmRuntimeError> {
-        self.stack.pop().ok_or(VmRuntimeError::StackExhausted)
+        let value = self.top.take().ok_or(VmRuntimeError::StackExhausted)?;
+        self.top = self.slots.pop();
+        Ok(value)
     }
 
     pub fn get(&self, offset: usize) -> Option<&ValueType> {
-        self.stack.get(offset)
+        match offset.cmp(&self.slots.len()) {
+            std::cmp::Ordering::Less => self.slots.get(offset),
+            std::cmp::Ordering::Equal => self.top.as_ref(),
+            std::cmp::Ordering::Greater => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, offset: usize) -> Option<&mut ValueType> {
+        match offset.cmp(&self.slots.len()) {
+            std::cmp::Ordering::Less => self.slots.get_mut(offset),
+            std::cmp::Ordering::Equal => self.top.as_mut(),
+            std::cmp::Ordering::Greater => None,
+        }
     }
 
     pub fn peek(&self, offset: usize) -> Option<&ValueType> {
-        self.stack.get(self.stack.len() - offset - 1)
+        self.len()
+            .checked_sub(offset + 1)
+            .and_then(|index| self.get(index))
     }
 
     pub fn last(&self) -> Option<&ValueType> {
-        self.stack.last()
+        self.top.as_ref()
+    }
+
+    pub fn last_mut(&mut self) -> Option<&mut ValueType> {
+        self.top.as_mut()
     }
 
     pub fn len(&self) -> usize {
-        self.stack.len()
+        self.slots.len() + usize::from(self.top.is_some())
     }
 
     pub fn is_empty(&self) -> bool {
-        self.stack.is_empty()
+        self.top.is_none()
     }
 
-    pub fn push(&mut self, value: ValueType) {
-        self.stack.push(value);
+    /// Pushes the value on top of the stack, failing when the stack is full.
+    pub fn push(&mut self, value: ValueType) -> Result<(), VmRuntimeError> {
+        if self.len() >= self.limit {
+            return Err(VmRuntimeError::StackOverflow(self.limit));
+        }
+        if let Some(previous) = self.top.replace(value) {
+            self.slots.push(previous);
+        }
+        Ok(())
     }
 
     pub fn set(&mut self, offset: usize, value: ValueType) -> Result<(), VmRuntimeError> {
-        if let Some(v) = self.stack.get_mut(offset) {
+        if let Some(v) = self.get_mut(offset) {
             *v = value;
             Ok(())
         } else {
             Err(VmRuntimeError::StackExhausted)
         }
     }
+
+    /// Keeps the given number of values at the bottom of the stack and removes the rest.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len() {
+            self.slots.truncate(len);
+            self.top = self.slots.pop();
+        }
+    }
+
+    /// Removes the values starting from the offset and returns them in the stack order.
+    pub fn split_off(&mut self, offset: usize) -> Result<Vec<ValueType>, VmRuntimeError> {
+        if offset > self.len() {
+            return Err(VmRuntimeError::StackExhausted);
+        }
+        self.slots.extend(self.top.take());
+        let values = self.slots.split_off(offset);
+        self.top = self.slots.pop();
+        Ok(values)
+    }
+
+    /// Removes the values in the range, moving the values above it down.
+    ///
+    /// The range must end below the top of the stack.
+    pub fn remove(&mut self, range: Range<usize>) -> Result<(), VmRuntimeError> {
+        if range.end > self.slots.len() {
+            return Err(VmRuntimeError::StackExhausted);
+        }
+        self.slots.drain(range);
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.top = None;
+    }
+}
+
+impl VmStack {
+    /// Creates the stack holding up to the given number of values.
+    pub fn with_limit(limit: usize) -> Self {
+        let slots = Vec::with_capacity(limit.min(STACK_SIZE));
+        VmStack {
+            slots,
+            top: None,
+            limit,
+        }
+    }
+}
+
+impl Default for VmStack {
+    fn default() -> Self {
+        VmStack::with_limit(STACK_SIZE)
+    }
 }
 
 #[cfg(test)]
@@ -47,18 +129,47 @@ mod tests {
     #[test]
     fn set_value_by_offset() {
         let mut stack = VmStack::default();
-        stack.push(ValueType::Number(1.0));
-        stack.push(ValueType::Number(2.0));
+        stack.push(ValueType::Number(1.0)).unwrap();
+        stack.push(ValueType::Number(2.0)).unwrap();
         stack.set(0, ValueType::Number(3.0)).unwrap();
         stack.set(1, ValueType::Number(4.0)).unwrap();
-        assert_eq!(stack.stack[0], ValueType::Number(3.0));
-        assert_eq!(stack.stack[1], ValueType::Number(4.0));
+        assert_eq!(stack.get(0), Some(&ValueType::Number(3.0)));
+        assert_eq!(stack.get(1), Some(&ValueType::Number(4.0)));
     }
-}
 
-impl Default for VmStack {
-    fn default() -> Self {
-        let stack = Vec::with_capacity(STACK_SIZE);
-        VmStack { stack }
+    #[test]
+    fn keep_top_value_apart() {
+        let mut stack = VmStack::default();
+        stack.push(ValueType::Number(1.0)).unwrap();
+        stack.push(ValueType::Number(2.0)).unwrap();
+        stack.push(ValueType::Number(3.0)).unwrap();
+
+        assert_eq!(stack.len(), 3);
+        assert_eq!(stack.peek(0), Some(&ValueType::Number(3.0)));
+        assert_eq!(stack.peek(2), Some(&ValueType::Number(1.0)));
+        assert_eq!(stack.peek(3), None);
+        assert_eq!(stack.pop().unwrap(), ValueType::Number(3.0));
+        assert_eq!(stack.last(), Some(&ValueType::Number(2.0)));
+
+        let values = stack.split_off(1).unwrap();
+        assert_eq!(values, vec![ValueType::Number(2.0)]);
+        assert_eq!(stack.last(), Some(&ValueType::Number(1.0)));
+
+        stack.truncate(0);
+        assert!(stack.is_empty());
+        assert!(stack.pop().is_err());
+    }
+
+    #[test]
+    fn reject_values_over_limit() {
+        let mut stack = VmStack::with_limit(2);
+        stack.push(ValueType::Nil).unwrap();
+        stack.push(ValueType::Nil).unwrap();
+
+        assert!(matches!(
+            stack.push(ValueType::Nil),
+            Err(VmRuntimeError::StackOverflow(2))
+        ));
+        assert_eq!(stack.len(), 2);
     }
 }
@@ -0,0 +1,115 @@
+//! Counters of the work the virtual machine did.
+//!
+//! The machine only counts when it is asked to, see [crate::vm::Vm::with_stats],
+//! so running without the statistics costs a single check per instruction.
+
+use std::fmt::Display;
+
+use crate::value::ValueType;
+
+/// Numbers describing the execution of the programs run so far.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    instructions: u64,
+    max_stack_depth: usize,
+    calls: u64,
+    strings: u64,
+    arrays: u64,
+}
+
+impl Stats {
+    /// Number of the instructions executed.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Largest number of values the operand stack held after an instruction.
+    pub fn max_stack_depth(&self) -> usize {
+        self.max_stack_depth
+    }
+
+    /// Number of the calls of functions, including the natives and the tail calls.
+    pub fn calls(&self) -> u64 {
+        self.calls
+    }
+
+    /// Number of the strings the program created while running, not counting the literals.
+    pub fn strings(&self) -> u64 {
+        self.strings
+    }
+
+    /// Number of the arrays the program created while running.
+    pub fn arrays(&self) -> u64 {
+        self.arrays
+    }
+
+    /// Counts the executed instruction, leaving the stack as deep as given.
+    pub(crate) fn executed(&mut self, stack_depth: usize) {
+        self.instructions += 1;
+        self.max_stack_depth = self.max_stack_depth.max(stack_depth);
+    }
+
+    pub(crate) fn called(&mut self) {
+        self.calls += 1;
+    }
+
+    /// Counts the newly created value if it is a string or an array.
+    pub(crate) fn allocated(&mut self, value: &ValueType) {
+        match value {
+            ValueType::Text(_) => self.strings += 1,
+            ValueType::ArrayRef(_) => self.arrays += 1,
+            _ => {}
+        }
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:<16} {:>12}", "instructions", self.instructions)?;
+        writeln!(f, "{:<16} {:>12}", "max stack depth", self.max_stack_depth)?;
+        writeln!(f, "{:<16} {:>12}", "calls", self.calls)?;
+        writeln!(f, "{:<16} {:>12}", "strings", self.strings)?;
+        writeln!(f, "{:<16} {:>12}", "arrays", self.arrays)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::vm::Vm;
+
+    #[test]
+    fn count_calls_and_allocations() {
+        let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![]))).with_stats();
+
+        vm.eval(
+            "fun greet(name) {
+                return \"hello, \" + name;
+            }
+            let names = [\"world\"; 2];
+            for (name in names) {
+                print greet(name);
+            }
+            print len(names);",
+        )
+        .unwrap();
+
+        let stats = vm.stats().unwrap();
+        assert!(stats.instructions() > 0);
+        assert!(stats.max_stack_depth() >= 3);
+        assert_eq!(stats.calls(), 3);
+        assert_eq!(stats.strings(), 2);
+        assert_eq!(stats.arrays(), 1);
+    }
+
+    #[test]
+    fn no_stats_unless_asked() {
+        let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![])));
+
+        vm.eval("print 1;").unwrap();
+
+        assert!(vm.stats().is_none());
+    }
+}
@@ -1,14 +1,137 @@
 //! Instruments to trace virtual machine execution
 
-use std::fmt::Debug;
+use std::fmt::{Debug, Write as _};
+use std::io::Write;
+use std::rc::Rc;
 
+use crate::diagnostic::json_string;
 use crate::vm::exec::Chunk;
+use crate::vm::opcode::Op;
 use crate::vm::VmStack;
 
+/// Step of the execution the virtual machine reports to its tracer.
+#[derive(Debug, Clone, Copy)]
+pub enum TraceEvent<'a> {
+    /// The instruction at the address of the chunk is about to run.
+    Instruction {
+        address: usize,
+        op: Op,
+        chunk: &'a Rc<Chunk>,
+    },
+    /// The function got a new call frame. The depth counts the frames, including the new one.
+    CallEntered { function: &'a str, depth: usize },
+    /// The function returned, leaving the given number of frames.
+    CallReturned { depth: usize },
+    /// Values on the stack after the instruction ran, from the bottom to the top.
+    Stack(&'a VmStack),
+}
+
+/// Receives the events of the virtual machine as it runs the program.
 pub trait VmStepTrace: Debug {
-    // traces execution before opcode is processed
-    fn trace_before(&self, ip: usize, chunk: &Chunk, stack: &VmStack);
+    fn trace(&mut self, event: TraceEvent);
+}
+
+/// Writes the events as JSON objects, one per line, for external analysis tools.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// # use brainterpreter::vm::Vm;
+/// # use brainterpreter::vm::trace::JsonTracer;
+/// let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![]))).with_tracer(JsonTracer::new(vec![]));
+/// vm.eval("let a = 1 + 2;").unwrap();
+/// ```
+#[derive(Debug)]
+pub struct JsonTracer<W> {
+    out: W,
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(out: W) -> Self {
+        JsonTracer { out }
+    }
+
+    /// Takes the output back, e.g. to read the collected events.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write + Debug> VmStepTrace for JsonTracer<W> {
+    fn trace(&mut self, event: TraceEvent) {
+        // Tracing must not stop the program, so failed writes are skipped.
+        let _ = writeln!(self.out, "{}", json_event(&event));
+    }
+}
+
+/// Renders the event as a single-line JSON object.
+pub fn json_event(event: &TraceEvent) -> String {
+    match event {
+        TraceEvent::Instruction { address, op, .. } => format!(
+            r#"{{"event":"instruction","address":{},"op":{}}}"#,
+            address,
+            json_string(&op.to_string())
+        ),
+        TraceEvent::CallEntered { function, depth } => format!(
+            r#"{{"event":"call","function":{},"depth":{}}}"#,
+            json_string(function),
+            depth
+        ),
+        TraceEvent::CallReturned { depth } => {
+            format!(r#"{{"event":"return","depth":{}}}"#, depth)
+        }
+        TraceEvent::Stack(stack) => {
+            let mut json = String::from(r#"{"event":"stack","values":["#);
+            for i in 0..stack.len() {
+                if i > 0 {
+                    json.push(',');
+                }
+                let value = stack.get(i).map(ToString::to_string).unwrap_or_default();
+                write!(json, "{}", json_string(&value)).unwrap();
+            }
+            json.push_str("]}");
+            json
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::ValueType;
+
+    #[test]
+    fn events_as_json() {
+        let chunk = Rc::new(Chunk::try_new([Op::Const(0)], [ValueType::Number(1.0)]).unwrap());
+        let mut stack = VmStack::default();
+        stack.push(ValueType::Number(1.0)).unwrap();
+        stack.push(ValueType::string("a\"b")).unwrap();
+
+        let events = [
+            TraceEvent::Instruction {
+                address: 0,
+                op: Op::Const(0),
+                chunk: &chunk,
+            },
+            TraceEvent::CallEntered {
+                function: "fib",
+                depth: 2,
+            },
+            TraceEvent::CallReturned { depth: 1 },
+            TraceEvent::Stack(&stack),
+        ];
+        let lines: Vec<String> = events.iter().map(json_event).collect();
 
-    // traces execution after opcode is processed
-    fn trace_after(&self, ip: usize, chunk: &Chunk, stack: &VmStack);
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"event":"instruction","address":0,"op":"CONST, 0"}"#,
+                r#"{"event":"call","function":"fib","depth":2}"#,
+                r#"{"event":"return","depth":1}"#,
+                r#"{"event":"stack","values":["f:1","s:a\"b"]}"#,
+            ]
+        );
+    }
 }
@@ -0,0 +1,126 @@
+//! Entry points for running Bauble programs from JavaScript, e.g. in a browser playground.
+//!
+//! Build the crate for the `wasm32-unknown-unknown` target with the `wasm` feature
+//! and generate the bindings with `wasm-bindgen`.
+//! Browsers have no standard input and output, so the programs print through the functions below.
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::vm::Vm;
+
+/// Runs the program and returns everything it prints.
+///
+/// An error stopping the program is appended to the output as the last line.
+#[wasm_bindgen(js_name = interpretToString)]
+pub fn interpret_to_string(source: &str) -> String {
+    let out = Rc::new(RefCell::new(String::new()));
+    let text = out.clone();
+    interpret_with_hook(source, move |line| text.borrow_mut().push_str(line));
+    let out = out.borrow().clone();
+    out
+}
+
+/// Runs the program, passing every line it prints to the JavaScript callback.
+///
+/// An error stopping the program is passed to the callback as the last line.
+#[wasm_bindgen(js_name = interpretWithOutput)]
+pub fn interpret_with_output(source: &str, on_output: &js_sys::Function) {
+    let on_output = on_output.clone();
+    interpret_with_hook(source, move |line| {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let _ = on_output.call1(&JsValue::NULL, &JsValue::from_str(line));
+    });
+}
+
+/// Runs the program on a virtual machine writing its output to the hook.
+fn interpret_with_hook<F>(source: &str, hook: F)
+where
+    F: FnMut(&str) + 'static,
+{
+    let out = Rc::new(RefCell::new(OutputHook::new(hook)));
+    let mut vm = Vm::with_io(out.clone())
+        .with_env_access(false)
+        .with_exit(false);
+    let result = vm.eval(source);
+    let mut out = out.borrow_mut();
+    if let Err(error) = result {
+        let _ = writeln!(out, "{}", error);
+    }
+    let _ = out.flush();
+}
+
+/// Output of the virtual machine passing the printed text to a function line by line.
+///
+/// The text after the last line break waits for the next write or a flush.
+pub struct OutputHook<F> {
+    hook: F,
+    pending: Vec<u8>,
+}
+
+impl<F> OutputHook<F>
+where
+    F: FnMut(&str),
+{
+    pub fn new(hook: F) -> Self {
+        OutputHook {
+            hook,
+            pending: vec![],
+        }
+    }
+}
+
+impl<F> Write for OutputHook<F>
+where
+    F: FnMut(&str),
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        while let Some(end) = self.pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            (self.hook)(&String::from_utf8_lossy(&line));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            let text: Vec<u8> = self.pending.drain(..).collect();
+            (self.hook)(&String::from_utf8_lossy(&text));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpret_program_to_string() {
+        let out = interpret_to_string("print 1 + 2; printf(\"{}\", \"no line break\");");
+
+        assert_eq!(out, "3\nno line break");
+    }
+
+    #[test]
+    fn append_error_to_output() {
+        let out = interpret_to_string("print 1; print unknown;");
+
+        assert!(out.starts_with("1\nvariable unknown is not defined"));
+    }
+
+    #[test]
+    fn pass_output_line_by_line() {
+        let lines = Rc::new(RefCell::new(vec![]));
+        let hooked = lines.clone();
+        interpret_with_hook("print 1; printf(\"a\"); printf(\"b\\n\");", move |line| {
+            hooked.borrow_mut().push(line.to_string())
+        });
+
+        assert_eq!(*lines.borrow(), vec!["1\n", "ab\\n"]);
+    }
+}
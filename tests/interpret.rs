@@ -5,7 +5,14 @@ use std::rc::Rc;
 use brainterpreter::compiler::Compiler;
 use brainterpreter::lexer::Lexer;
 use brainterpreter::parser::Parser;
-use brainterpreter::vm::Vm;
+use brainterpreter::source::Position;
+use brainterpreter::value::ValueType;
+use brainterpreter::vm::exec::Chunk;
+use brainterpreter::vm::io::VmIo;
+use brainterpreter::vm::provider::{Clock, SeededRandom};
+use brainterpreter::vm::trace::{TraceEvent, VmStepTrace};
+use brainterpreter::vm::{Vm, VmRuntimeError};
+use brainterpreter::{interpret_file, interpret_to_string, BaubleError};
 
 #[test]
 fn expression_with_negative_numbers() {
@@ -87,6 +94,29 @@ fn while_loop() {
     assert_eq!(out, "5\n4\n3\n2\n1\n100\n");
 }
 
+#[test]
+fn do_while_loop() {
+    let source = r#"
+    let i = 0;
+    do {
+        print i;
+    } while (i > 0);
+    do {
+        i = i + 1;
+        if (i == 2) {
+            continue;
+        }
+        if (i == 4) {
+            break;
+        }
+        print i;
+    } while (i < 10);
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "0\n1\n3\n");
+}
+
 #[test]
 fn function_call() {
     let source = r#"
@@ -140,12 +170,61 @@ fn change_string_index() {
     w[0] = "D";
     print w;
     "#;
-    let io = interpret(source).unwrap();
-    let out = String::from_utf8(io).unwrap();
+    let out = interpret_to_string(source).unwrap();
 
     assert_eq!(out, "Dust\n");
 }
 
+#[test]
+fn change_string_index_of_variables() {
+    let source = r#"
+    fun shout(word) {
+        let same = word;
+        word[0] = "B";
+        fun last() { word[3] = "!"; }
+        last();
+        print same;
+        return word;
+    }
+    print shout("best");
+    let strings = ["s"; 1];
+    print strings[0] = "t";
+    print strings[0];
+    "#;
+    let out = interpret_to_string(source).unwrap();
+    let error = interpret_to_string("fun f() { return \"a\"; } f()[0] = \"b\";").unwrap_err();
+    let constant = interpret_to_string("const C = \"c\"; C[0] = \"d\";").unwrap_err();
+
+    assert_eq!(out, "best\nBes!\nt\nt\n");
+    assert!(error
+        .to_string()
+        .contains("elements of s:a cannot be changed"));
+    assert!(constant
+        .to_string()
+        .contains("elements of s:c cannot be changed"));
+}
+
+#[test]
+fn assign_to_elements_of_expressions() {
+    let source = r#"
+    let grid = [nil; 2];
+    grid[0] = [0; 2];
+    grid[1] = [0; 2];
+    fun row(i) { return grid[i]; }
+    row(1)[0] = 5;
+    grid[0][1] = grid[1][0] + 1;
+    print grid[1][0];
+    print grid[0][1];
+    let rows = { "first": grid[0] };
+    rows["first"][0] = 7;
+    print grid[0][0];
+    print 1 + (grid[1][1] = 3);
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "5\n6\n7\n4\n");
+}
+
 #[test]
 fn built_in_function() {
     let source = r#"
@@ -219,3 +298,1492 @@ pub fn interpret(source: &str) -> Result<Vec<u8>, Box<dyn Error>> {
     let output = io.borrow();
     Ok(output.clone())
 }
+
+#[test]
+fn script_result_is_last_expression() {
+    let value = brainterpreter::interpret("let a = 40; a + 2;").unwrap();
+
+    assert_eq!(value, ValueType::Int(42));
+}
+
+#[test]
+fn script_result_of_top_level_return() {
+    let value = brainterpreter::interpret("return \"done\"; print 1;").unwrap();
+
+    assert_eq!(value, ValueType::string("done"));
+}
+
+#[test]
+fn interpret_source_to_string() {
+    let out = interpret_to_string("print 1; print \"two\";").unwrap();
+
+    assert_eq!(out, "1\ntwo\n");
+}
+
+#[test]
+fn interpret_missing_file() {
+    let result = interpret_file("tests/missing.bbl");
+
+    assert!(matches!(result, Err(BaubleError::Io(_))));
+}
+
+#[test]
+fn for_loop() {
+    let source = r#"
+    let sum = 0;
+    for (let i = 1; i <= 4; i = i + 1) {
+        sum = sum + i;
+    }
+    print sum;
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "10\n");
+}
+
+#[test]
+fn for_loop_with_outer_variable() {
+    let source = r#"
+    let i = 3;
+    for (; i > 0;) {
+        print i;
+        i = i - 1;
+    }
+    print i;
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "3\n2\n1\n0\n");
+}
+
+#[test]
+fn nested_for_loops_in_function() {
+    let source = r#"
+    fun table(n) {
+        for (let a = 1; a <= n; a = a + 1) {
+            for (let b = 1; b <= n; b = b + 1) {
+                print a * b;
+            }
+        }
+    }
+    table(2);
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "1\n2\n2\n4\n");
+}
+
+#[test]
+fn closure_captures_enclosing_variable() {
+    let source = r#"
+    fun make_counter() {
+        let count = 0;
+        fun increment() {
+            count = count + 1;
+            return count;
+        }
+        return increment;
+    }
+    let counter = make_counter();
+    counter();
+    print counter();
+    let other = make_counter();
+    print other();
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "2\n1\n");
+}
+
+#[test]
+fn nested_functions_are_local() {
+    let source = r#"
+    fun outer(n) {
+        fun factorial(k) {
+            if (k <= 1) { return 1; }
+            return k * factorial(k - 1);
+        }
+        return factorial(n);
+    }
+    print outer(5);
+    {
+        fun twice(x) { return x * 2; }
+        print twice(4);
+    }
+    print factorial;
+    "#;
+
+    let out = Rc::new(RefCell::new(vec![]));
+    let mut vm = Vm::with_io(out.clone());
+
+    let error = vm.eval(source).unwrap_err();
+
+    assert_eq!(String::from_utf8_lossy(&out.borrow()), "120\n8\n");
+    assert_eq!(error.code(), "undefined-variable");
+}
+
+#[test]
+fn functions_as_arguments() {
+    let source = r#"
+    fun map(f, arr) {
+        let result = [nil; len(arr)];
+        for (let i = 0; i < len(arr); i = i + 1) {
+            result[i] = f(arr[i]);
+        }
+        return result;
+    }
+    fun square(x) { return x * x; }
+    let squares = map(square, [3; 2]);
+    print squares[0] + squares[1];
+    print map(fun (x) { return -x; }, [1; 1])[0];
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "18\n-1\n");
+}
+
+#[test]
+fn call_expressions() {
+    let source = r#"
+    fun double(x) { return x * 2; }
+    fun handler() { return double; }
+    let handlers = [double; 2];
+    fun adder(n) { return fun (x) { return x + n; }; }
+    fun first(h, x) { return h[0](x); }
+    print handler()(4);
+    print handlers[1](5);
+    print adder(1)(2);
+    print first(handlers, 3);
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "8\n10\n3\n6\n");
+}
+
+#[test]
+fn closures_share_captured_variable() {
+    let source = r#"
+    let get;
+    let set;
+    fun pair() {
+        let value = "initial";
+        fun getter() { return value; }
+        fun setter(v) { value = v; }
+        get = getter;
+        set = setter;
+        print get();
+    }
+    pair();
+    set("changed");
+    print get();
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "initial\nchanged\n");
+}
+
+#[test]
+fn closure_captures_through_nested_functions() {
+    let source = r#"
+    fun outer(x) {
+        fun middle() {
+            fun inner() {
+                return x * 2;
+            }
+            return inner;
+        }
+        return middle();
+    }
+    let f = outer(21);
+    print f();
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "42\n");
+}
+
+#[test]
+fn closure_outlives_block_scope() {
+    let source = r#"
+    let f;
+    {
+        let x = "kept";
+        fun g() { return x; }
+        f = g;
+    }
+    print f();
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "kept\n");
+}
+
+#[test]
+fn logical_operators() {
+    let source = r#"
+    print true && false;
+    print true && true;
+    print false || true;
+    print false || false;
+    print 1 < 2 && 3 > 2 || false;
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "false\ntrue\ntrue\nfalse\ntrue\n");
+}
+
+#[test]
+fn logical_operators_short_circuit() {
+    let source = r#"
+    fun loud(value) {
+        print "evaluated";
+        return value;
+    }
+    print false && loud(true);
+    print true || loud(false);
+    print true && loud(false);
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "false\ntrue\nevaluated\nfalse\n");
+}
+
+#[test]
+fn run_pipeline_stages_separately() {
+    let program = brainterpreter::parse("let n = 0; n = n + 1; print n;").unwrap();
+    let chunk = brainterpreter::compile(program).unwrap();
+    let out = Rc::new(RefCell::new(vec![]));
+    let mut vm = Vm::with_io(out.clone());
+
+    vm.run_chunk(&chunk).unwrap();
+    vm.run_chunk(&chunk).unwrap();
+
+    assert_eq!(String::from_utf8_lossy(&out.borrow()), "1\n1\n");
+    let error = brainterpreter::parse("print 1 +;").unwrap_err();
+    assert_eq!(error.code(), "missing-operand");
+}
+
+#[test]
+fn run_deserialized_chunk() {
+    let source = r#"
+    fun counter() {
+        let count = 0;
+        fun next() {
+            count = count + 1;
+            return count;
+        }
+        return next;
+    }
+    let next = counter();
+    next();
+    print next();
+    "#;
+    let ast = Parser::new(Lexer::new(source)).parse_program().unwrap();
+    let bytes = Compiler::default()
+        .compile(ast)
+        .unwrap()
+        .serialize()
+        .unwrap();
+    let chunk = Chunk::deserialize(&bytes).unwrap();
+    let io = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::with_io(io.clone());
+
+    vm.load_and_run(Rc::new(chunk)).unwrap();
+
+    assert_eq!(io.borrow().as_slice(), "2\n".as_bytes());
+}
+
+#[test]
+fn runtime_error_position() {
+    let source = r#"let a = 1;
+fun add(x) {
+    return x + "one";
+}
+print add(a);
+"#;
+    let error = brainterpreter::interpret(source).unwrap_err();
+
+    let BaubleError::Runtime(error) = error else {
+        panic!("runtime error expected");
+    };
+    assert_eq!(error.position().map(|p| p.line()), Some(3));
+    assert_eq!(error.address(), Some(2));
+    assert!(matches!(error.kind(), VmRuntimeError::TypeMismatch));
+    assert!(error
+        .to_string()
+        .starts_with("operation is not implemented"));
+}
+
+#[test]
+fn runtime_error_backtrace() {
+    let source = r#"fun inner(x) {
+    return x + "one";
+}
+fun outer(x) {
+    return inner(x) + 1;
+}
+outer(1);
+"#;
+    let error = brainterpreter::interpret(source).unwrap_err();
+
+    let functions: Vec<_> = error
+        .backtrace()
+        .iter()
+        .map(|frame| frame.function())
+        .collect();
+    assert_eq!(functions, vec![Some("inner"), Some("outer"), None]);
+    let lines: Vec<_> = error
+        .backtrace()
+        .iter()
+        .map(|frame| frame.position().map(|p| p.line()))
+        .collect();
+    assert_eq!(lines, vec![Some(2), Some(5), Some(7)]);
+    assert_eq!(error.backtrace()[0].address(), error.address().unwrap());
+}
+
+#[test]
+fn break_and_continue() {
+    let source = r#"
+    for (let i = 0; i < 10; i = i + 1) {
+        if (i == 1) {
+            continue;
+        }
+        let square = i * i;
+        if (square > 10) {
+            break;
+        }
+        print square;
+    }
+    let n = 0;
+    while (true) {
+        n = n + 1;
+        if (n < 3) {
+            continue;
+        }
+        break;
+    }
+    print n;
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "0\n4\n9\n3\n");
+}
+
+#[test]
+fn break_outside_loop() {
+    let result = interpret_to_string("fun f() { break; } while (true) { f(); }");
+
+    assert!(matches!(result, Err(BaubleError::Compile(_))));
+}
+
+#[test]
+fn read_lines_from_input() {
+    let source = r#"
+    let line = read_line();
+    while (line != nil) {
+        print "> " + line;
+        line = read_line();
+    }
+    "#;
+    let ast = Parser::new(Lexer::new(source)).parse_program().unwrap();
+    let chunk = Compiler::default().compile(ast).unwrap();
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let input = Rc::new(RefCell::new("first\r\nsecond".as_bytes()));
+    let mut vm = Vm::with_io(out.clone()).with_input(input);
+
+    vm.load_and_run(Rc::new(chunk)).unwrap();
+
+    assert_eq!(out.borrow().as_slice(), "> first\n> second\n".as_bytes());
+}
+
+#[test]
+fn separate_output_and_error_output() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let err = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let io = VmIo::default()
+        .with_stdin("Bauble".as_bytes())
+        .with_stdout(out.clone())
+        .with_stderr(err.clone());
+    let mut vm = Vm::default().with_streams(io);
+
+    vm.eval(r#"let name = read_line(); print("Hello,", name); eprint("warning:", 1, nil);"#)
+        .unwrap();
+
+    assert_eq!(out.borrow().as_slice(), b"Hello, Bauble\n");
+    assert_eq!(err.borrow().as_slice(), b"warning: 1 nil\n");
+}
+
+#[test]
+fn deterministic_random_and_time() {
+    struct FixedClock;
+
+    impl Clock for FixedClock {
+        fn seconds(&self) -> f64 {
+            1.5
+        }
+    }
+
+    let source = r#"
+    let in_range = true;
+    for (let i = 0; i < 100; i++) {
+        let n = random_int(1, 6);
+        in_range = in_range && n >= 1 && n <= 6 && floor(n) == n;
+        let r = random();
+        in_range = in_range && r >= 0 && r < 1;
+    }
+    print in_range;
+    print random();
+    print clock();
+    print random_int(3, 1);
+    "#;
+    let run = || {
+        let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut vm = Vm::with_io(out.clone())
+            .with_random(SeededRandom::new(7))
+            .with_clock(FixedClock);
+        vm.eval(source).unwrap();
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        out
+    };
+
+    let out = run();
+    let lines: Vec<&str> = out.lines().collect();
+
+    assert_eq!(run(), out);
+    assert_eq!(lines[0], "true");
+    assert_eq!(lines[2], "1.5");
+    assert_eq!(
+        lines[3],
+        "error: random_int expects whole bounds in order but found 3 and 1"
+    );
+}
+
+#[test]
+fn increment_and_decrement() {
+    let source = r#"
+    let g = 1;
+    print g++;
+    print ++g;
+    fun locals() {
+        let l = 5;
+        print l--;
+        print --l;
+        return l;
+    }
+    print locals();
+    let sum = 0;
+    for (let i = 0; i < 3; i++) {
+        sum = sum + i;
+    }
+    ++sum;
+    print sum;
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "1\n3\n5\n3\n3\n4\n");
+}
+
+#[test]
+fn error_codes() {
+    let parsing = brainterpreter::interpret("let = 1;").unwrap_err();
+    let compile = brainterpreter::interpret("break;").unwrap_err();
+    let runtime = brainterpreter::interpret("let a = 1;\nprint unknown;").unwrap_err();
+
+    assert_eq!(parsing.code(), "missing-token");
+    assert_eq!(parsing.position().map(|p| p.line()), Some(1));
+    assert_eq!(compile.code(), "outside-loop");
+    assert_eq!(compile.position().map(|p| p.line()), Some(1));
+    assert_eq!(runtime.code(), "undefined-variable");
+    assert_eq!(runtime.position().map(|p| p.line()), Some(2));
+    assert_eq!(runtime.address(), Some(3));
+}
+
+#[test]
+fn anonymous_functions() {
+    let source = r#"
+    let double = fun (x) { return x * 2; };
+    let triple = fun (x) { return x * 3; };
+    fun pick(first, second, take_first) {
+        if (take_first) {
+            return first;
+        }
+        return second;
+    }
+    let chosen = pick(double, triple, false);
+    print double(2);
+    print chosen(2);
+    fun adder(n) {
+        return fun (x) { return x + n; };
+    }
+    let add_ten = adder(10);
+    print add_ten(5);
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "4\n6\n15\n");
+}
+
+#[test]
+fn maps() {
+    let source = r#"
+    let ages = { "ann": 31, "bob": 27 };
+    ages["cid"] = 40;
+    ages["bob"] = ages["bob"] + 1;
+    let names = keys(ages);
+    for (let i = 0; i < len(names); i++) {
+        print names[i] + " " + as_string(ages[names[i]]);
+    }
+    print has_key(ages, "ann");
+    print has_key(ages, "dan");
+    print ages["dan"];
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "ann 31\nbob 28\ncid 40\ntrue\nfalse\nnil\n");
+}
+
+#[test]
+fn eval_keeps_globals() {
+    let mut vm = Vm::default();
+    vm.register_native("double", 1, |_vm, args| match args[0].as_float() {
+        Some(n) => Ok(ValueType::Number(n * 2.0)),
+        None => Err(VmRuntimeError::TypeMismatch),
+    });
+
+    assert_eq!(vm.eval("let base = 20;").unwrap(), ValueType::Nil);
+    assert_eq!(
+        vm.eval("double(base) + 2;").unwrap(),
+        ValueType::Number(42.0)
+    );
+    assert!(matches!(vm.eval("base +;"), Err(BaubleError::Parsing(_))));
+    assert_eq!(vm.eval("base;").unwrap(), ValueType::Int(20));
+}
+
+#[test]
+fn globals_by_slot_and_by_name() {
+    let mut vm = Vm::default();
+    vm.eval("let a = 40; fun add(x) { return a + x; }").unwrap();
+
+    // Chunks compiled without the machine find the globals by name.
+    let program = Parser::new(Lexer::new("let b = add(1); b + 1;"))
+        .parse_program()
+        .unwrap();
+    let chunk = Compiler::default().compile(program).unwrap();
+    assert_eq!(vm.run_chunk(&chunk).unwrap(), ValueType::Int(42));
+
+    assert_eq!(vm.eval("b;").unwrap(), ValueType::Int(41));
+    assert_eq!(vm.global("a"), Some(&ValueType::Int(40)));
+    assert!(vm.globals().contains_key(&Rc::new("len".to_string())));
+    assert!(matches!(
+        vm.eval("undefined;"),
+        Err(BaubleError::Runtime(e)) if matches!(e.kind(), VmRuntimeError::UndefinedVariable(name) if name == "undefined")
+    ));
+}
+
+#[test]
+fn tail_calls_reuse_frames() {
+    let source = r#"
+    fun count_down(n, acc) {
+        if (n == 0) {
+            return acc;
+        }
+        return count_down(n - 1, acc + 1);
+    }
+    fun identity(x) {
+        return x;
+    }
+    fun capture(n) {
+        let value = n + 1;
+        fun get() { return value; }
+        return identity(get);
+    }
+    print count_down(100000, 0);
+    let get_value = capture(41);
+    print get_value();
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "100000\n42\n");
+}
+
+#[test]
+fn call_stack_overflow() {
+    let source = r#"
+    fun forever(n) {
+        return forever(n + 1) + 1;
+    }
+    fun count(n) {
+        if (n == 0) {
+            return 0;
+        }
+        return count(n - 1);
+    }
+    "#;
+    let mut vm = Vm::default().with_max_call_depth(100);
+    vm.eval(source).unwrap();
+
+    assert_eq!(vm.eval("count(1000);").unwrap(), ValueType::Int(0));
+    let error = vm.eval("forever(0);").unwrap_err();
+    let BaubleError::Runtime(error) = error else {
+        panic!("runtime error expected");
+    };
+    assert!(matches!(
+        error.kind(),
+        VmRuntimeError::CallStackOverflow { depth: 100, function } if function == "forever"
+    ));
+    assert_eq!(error.code(), "call-stack-overflow");
+}
+
+#[test]
+fn stack_limit() {
+    let mut vm = Vm::default().with_stack_limit(50);
+
+    assert_eq!(
+        vm.eval("let a = [0; 3]; len(a);").unwrap(),
+        ValueType::Int(3)
+    );
+    let error = vm
+        .eval("fun deep(n) { let x = n; return deep(n + 1) + x; } deep(0);")
+        .unwrap_err();
+    let BaubleError::Runtime(error) = error else {
+        panic!("runtime error expected");
+    };
+    assert!(matches!(error.kind(), VmRuntimeError::StackOverflow(50)));
+    assert_eq!(error.code(), "stack-overflow");
+}
+
+#[test]
+fn power_and_math_functions() {
+    let source = r#"
+    print 2 ** 10;
+    print -2 ** 2;
+    print 2 ** 3 ** 2;
+    print sqrt(16) + abs(-3);
+    print floor(2.7) + ceil(2.2) + round(2.5);
+    print min(3, 7) * max(3, 7);
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "1024\n-4\n512\n7\n8\n21\n");
+}
+
+#[test]
+fn conditional_expressions() {
+    let source = r#"
+    fun sign(n) {
+        return n < 0 ? -1 : n == 0 ? 0 : 1;
+    }
+    let x = true ? "yes" : "no";
+    print x;
+    print sign(-5) + sign(0) * 10 + sign(7) * 100;
+    let calls = 0;
+    fun count() {
+        calls = calls + 1;
+        return calls;
+    }
+    print false ? count() : 42;
+    print calls;
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "yes\n99\n42\n0\n");
+}
+
+#[test]
+fn try_catch_thrown_values() {
+    let source = r#"
+    fun check(n) {
+        if (n < 0) {
+            throw "negative: " + as_string(n);
+        }
+        return n;
+    }
+    fun total(a, b) {
+        let sum = check(a);
+        return sum + check(b);
+    }
+    {
+        let kept = "kept";
+        try {
+            let inner = 1;
+            print total(1, 2);
+            print total(1, -2);
+            print "unreachable";
+        } catch (e) {
+            print e;
+        }
+        print kept;
+    }
+    try {
+        print 1 + true;
+    } catch (e) {
+        print is_error(e);
+        print e;
+    }
+    let found = nil;
+    for (let i = 0; i < 5; i = i + 1) {
+        try {
+            if (i == 3) {
+                found = i;
+                break;
+            }
+        } catch (e) {
+            print "unreachable";
+        }
+    }
+    try {
+        throw found;
+    } catch (e) {
+        print e;
+    }
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(
+        out,
+        "3\nnegative: -2\nkept\ntrue\nerror: operation is not implemented for operand type\n3\n"
+    );
+}
+
+#[test]
+fn switch_statements() {
+    let source = r#"
+    fun describe(value) {
+        switch (value) {
+            case 1:
+                return "one";
+            case "x":
+                return "letter";
+            case nil:
+                return "nothing";
+            default:
+                return "other";
+        }
+    }
+    print describe(1);
+    print describe("x");
+    print describe(nil);
+    print describe(true);
+    for (let i = 0; i < 5; i = i + 1) {
+        let doubled = i * 2;
+        switch (doubled) {
+            case 2:
+                continue;
+            case 6:
+                break;
+        }
+        print doubled;
+    }
+    switch (3) {
+        case 1 + 2:
+            print "matched";
+    }
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "one\nletter\nnothing\nother\n0\n4\nmatched\n");
+}
+
+#[test]
+fn uncaught_exception() {
+    let source = r#"
+    try {
+        throw "inner";
+    } catch (e) {
+        throw "rethrown " + e;
+    }
+    "#;
+    let error = interpret_to_string(source).unwrap_err();
+
+    assert_eq!(error.code(), "uncaught-exception");
+    assert_eq!(
+        error.to_string(),
+        "uncaught exception: rethrown inner at [5:13]"
+    );
+}
+
+#[test]
+fn constants() {
+    let source = r#"
+    const GREETING = "Hello";
+    const LIMITS = [0; 2];
+    fun greet(name) {
+        return GREETING + ", " + name;
+    }
+    print greet("World");
+    print len(LIMITS);
+    "#;
+
+    assert_eq!(interpret_to_string(source).unwrap(), "Hello, World\n2\n");
+    let error = interpret_to_string("const A = 1; fun f() { A = 2; }").unwrap_err();
+    assert_eq!(error.code(), "assign-to-const");
+}
+
+#[test]
+fn for_in_loops() {
+    let source = r#"
+    let sum = 0;
+    for (n in [2; 3]) sum = sum + n;
+    print sum;
+    for (c in "añb") {
+        if (c == "ñ") continue;
+        print c;
+    }
+    fun first_key(map) {
+        for (key in map) {
+            return key;
+        }
+        return nil;
+    }
+    print first_key({"b": 1, "a": 2});
+    let getters = [nil; 3];
+    let i = 0;
+    for (x in [7; 3]) {
+        if (i == 2) break;
+        let g = fun () { return x + i; };
+        getters[i] = g;
+        i++;
+    }
+    let g = getters[1];
+    print g();
+    "#;
+
+    assert_eq!(interpret_to_string(source).unwrap(), "6\na\nb\na\n9\n");
+    let error = interpret_to_string("for (x in 42) print x;").unwrap_err();
+    assert_eq!(error.code(), "type-error");
+}
+
+#[test]
+fn ranges() {
+    let source = r#"
+    let sum = 0;
+    for (i in 0..5) {
+        if (i == 1) continue;
+        if (i == 4) break;
+        sum = sum + i;
+    }
+    print sum;
+    let r = 1..3;
+    print r;
+    print typeof(r);
+    for (i in r) print i;
+    let letters = ["a"; 6];
+    letters[3] = "d";
+    print len(slice(letters, 2..5));
+    let middle = slice(letters, 3..4);
+    print middle[0];
+    "#;
+
+    assert_eq!(
+        interpret_to_string(source).unwrap(),
+        "5\n1..3\nrange\n1\n2\n3\nd\n"
+    );
+    let error = interpret_to_string("print 1..\"b\";").unwrap_err();
+    assert_eq!(error.code(), "type-mismatch");
+}
+
+#[test]
+fn ranges_stop_when_the_counter_cannot_advance() {
+    for source in [
+        "for (i in 9223372036854775806..10000000000000000000.0) {}",
+        "let r = 9223372036854774784..10000000000000000000.0; for (i in r) {}",
+    ] {
+        let error = interpret_to_string(source).unwrap_err();
+        assert_eq!(error.code(), "integer-overflow", "{}", source);
+    }
+    for source in [
+        "for (i in 10000000000000000000.0..20000000000000000000.0) {}",
+        "let r = 10000000000000000000.0..20000000000000000000.0; for (i in r) {}",
+    ] {
+        let error = interpret_to_string(source).unwrap_err();
+        assert_eq!(error.code(), "range-counter-too-large", "{}", source);
+    }
+    assert_eq!(
+        interpret_to_string(
+            "for (i in 10000000000000000000.0..10000000000000000000.0) print i; print \"done\";"
+        )
+        .unwrap(),
+        "done\n"
+    );
+}
+
+#[test]
+fn tuples() {
+    let source = r#"
+    fun min_max(arr) {
+        let lo = arr[0];
+        let hi = arr[0];
+        for (x in arr) {
+            lo = min(lo, x);
+            hi = max(hi, x);
+        }
+        return (lo, hi);
+    }
+    let numbers = [0; 3];
+    numbers[0] = 4;
+    numbers[2] = 9;
+    let (lo, hi) = min_max(numbers);
+    print lo;
+    print hi;
+    fun swap() {
+        let (a, b) = ("x", "y");
+        let (b2, a2) = (b, a);
+        return b2 + a2;
+    }
+    print swap();
+    let pair = (1, "one");
+    print pair;
+    print pair[1];
+    print len(pair);
+    "#;
+
+    assert_eq!(
+        interpret_to_string(source).unwrap(),
+        "0\n9\nyx\n(1, one)\none\n2\n"
+    );
+    let error = interpret_to_string("let (a, b, c) = (1, 2);").unwrap_err();
+    assert_eq!(error.code(), "unpack-mismatch");
+    let error = interpret_to_string("fun f() { let (a, a) = (1, 2); }").unwrap_err();
+    assert_eq!(error.code(), "variable-already-declared");
+    let error = interpret_to_string("let t = (1, 2); t[0] = 3;").unwrap_err();
+    assert_eq!(error.code(), "type-error");
+    assert_eq!(
+        error.to_string(),
+        "elements of (2) cannot be changed at [1:17]"
+    );
+}
+
+#[test]
+fn block_expressions() {
+    let source = r#"
+    let x = { let a = 2; a * 3 };
+    print x;
+    let get;
+    fun scale(n) {
+        let unset;
+        let factor = 10;
+        let total = n + { let doubled = n * 2; get = fun () { return doubled; }; doubled * factor };
+        print total;
+        print get();
+        print unset;
+        return { print "done"; };
+    }
+    print scale(1);
+    print len("ab" + { let s = "c"; s });
+    "#;
+
+    assert_eq!(
+        interpret_to_string(source).unwrap(),
+        "6\n21\n2\nnil\ndone\nnil\n3\n"
+    );
+    let error = interpret_to_string("while (true) { let x = { break; }; }").unwrap_err();
+    assert_eq!(error.code(), "outside-loop");
+}
+
+#[test]
+fn failed_assertions() {
+    assert_eq!(
+        interpret_to_string("assert(1 < 2, \"math works\"); print \"ok\";").unwrap(),
+        "ok\n"
+    );
+
+    let source = r#"
+    try {
+        assert(len("abc") == 2, "wrong length");
+    } catch (e) {
+        print "caught";
+    }
+    "#;
+    let error = interpret_to_string(source).unwrap_err();
+
+    assert_eq!(error.code(), "assertion-failed");
+    assert_eq!(
+        error.to_string(),
+        "assertion failed: wrong length at [3:14]"
+    );
+    assert_eq!(
+        interpret_to_string("assert(false);")
+            .unwrap_err()
+            .to_string(),
+        "assertion failed at [1:6]"
+    );
+    assert_eq!(
+        interpret_to_string("assert(\"yes\"); assert(1, \"truthy\"); print \"ok\";").unwrap(),
+        "ok\n"
+    );
+    assert_eq!(
+        interpret_to_string("assert(nil, \"nothing\");")
+            .unwrap_err()
+            .code(),
+        "assertion-failed"
+    );
+}
+
+#[test]
+fn math_functions_expect_numbers() {
+    let out = interpret_to_string(r#"print sqrt("16");"#).unwrap();
+
+    assert_eq!(out, "error: expected a number but found s:16\n");
+}
+
+#[test]
+fn non_ascii_strings() {
+    let source = r#"
+    let слово = "привіт";
+    print len(слово);
+    print слово[5];
+    слово[0] = "П";
+    print слово;
+    print as_char(1071);
+    print слово[6];
+    "#;
+    let error = interpret_to_string(source).unwrap_err();
+    let out = interpret_to_string(&source.replace("print слово[6];", "")).unwrap();
+
+    assert_eq!(out, "6\nт\nПривіт\nЯ\n");
+    assert!(error.to_string().contains("index `6` is out of bounds"));
+}
+
+#[test]
+fn string_functions() {
+    let source = r#"
+    let greeting = trim("  Привіт, світ!  ");
+    print greeting;
+    print to_upper(greeting);
+    print to_lower("ÀÉÎ");
+    print substring(greeting, 8, 12);
+    print index_of(greeting, "світ");
+    print index_of(greeting, "world");
+    let parts = split("a,бв,,г", ",");
+    print len(parts);
+    print parts[1];
+    print split("ñö", "")[1];
+    print substring(greeting, 3, 1);
+    print substring(greeting, 0, 99);
+    print split(1, ",");
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(
+        out,
+        "Привіт, світ!\nПРИВІТ, СВІТ!\nàéî\nсвіт\n8\nnil\n4\nбв\nö\n\
+        error: substring start 3 is after the end 1\n\
+        error: position 99 is out of range [0, 13]\n\
+        error: split expects a string but found i:1\n"
+    );
+}
+
+#[test]
+fn array_functions() {
+    let source = r#"
+    let numbers = [0; 2];
+    let same = numbers;
+    print push(numbers, "x");
+    push(numbers, 5);
+    insert(numbers, 0, "first");
+    print len(same);
+    print same[0];
+    print remove(same, 1);
+    print pop(numbers);
+    let part = slice(numbers, 1, 3);
+    part[0] = 42;
+    print len(part);
+    print part[1];
+    print numbers[1];
+    print pop([0; 0]);
+    print insert(numbers, 9, 1);
+    print remove(numbers, 1.5);
+    print slice(numbers, 2, 1);
+    print push("a", 1);
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(
+        out,
+        "nil\n5\nfirst\n0\n5\n2\nx\n0\n\
+        error: pop expects a non-empty array\n\
+        error: position 9 is out of range [0, 3]\n\
+        error: position 1.5 is out of range [0, 2]\n\
+        error: slice start 2 is after the end 1\n\
+        error: push expects an array but found s:a\n"
+    );
+}
+
+#[test]
+fn nested_arrays() {
+    let source = r#"
+    let grid = [[0; 3]; 2];
+    grid[0][1] = 5;
+    print grid[1][1];
+    let row = [0; 2];
+    let rows = [row; 2];
+    rows[0][0] = 1;
+    print row[0];
+    let table = matrix(2, 3, { "n": 0 });
+    table[1][2]["n"] = 7;
+    print len(table);
+    print len(table[0]);
+    print table[0][2]["n"];
+    print table[1][2]["n"];
+    print matrix(2, -1, 0);
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(
+        out,
+        "0\n0\n2\n3\n0\n7\nerror: matrix expects a size but found i:-1\n"
+    );
+}
+
+#[test]
+fn limit_array_sizes() {
+    let code = |source: &str| interpret_to_string(source).unwrap_err().code();
+
+    assert_eq!(interpret_to_string("print len([0; 2.0]);").unwrap(), "2\n");
+    assert_eq!(code("let a = [0; 100000000000];"), "array-too-large");
+    assert_eq!(code("let a = [0; 9223372036854775807];"), "array-too-large");
+    assert_eq!(code("let a = [0; -1];"), "invalid-array-size");
+    assert_eq!(code("let a = [0; -1.5];"), "invalid-array-size");
+    assert_eq!(code("let a = [0; 1.5];"), "invalid-array-size");
+    assert_eq!(code("matrix(100000, 100000, 0);"), "array-too-large");
+    assert_eq!(code("matrix(100000000, 0, 0);"), "array-too-large");
+    assert!(interpret_to_string("let a = [0; -1];")
+        .unwrap_err()
+        .to_string()
+        .contains("i:-1 is not a valid size"));
+}
+
+#[test]
+fn clone_values() {
+    let source = r#"
+    let rows = matrix(2, 2, 0);
+    let shared = rows;
+    let copy = clone(rows);
+    shared[0][0] = 1;
+    copy[1][1] = 2;
+    print rows[0][0];
+    print rows[1][1];
+    print copy[0][0];
+    let ages = { "ann": [31; 1] };
+    let other = clone(ages);
+    other["ann"][0] = 32;
+    other["bob"] = 27;
+    print ages["ann"][0];
+    print len(ages);
+    print clone("text");
+    print clone(rows) == rows;
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "1\n0\n0\n31\n1\ntext\ntrue\n");
+}
+
+#[test]
+fn higher_order_functions() {
+    let source = r#"
+    let numbers = [0; 0];
+    for (let i = 1; i <= 4; i = i + 1) { push(numbers, i); }
+    let squares = map(fun (x) { return x * x; }, numbers);
+    let big = filter(fun (x) { return x > 4; }, squares);
+    print len(big);
+    print big[0];
+    print reduce(fun (sum, x) { return sum + x; }, squares, 0);
+    print len(numbers);
+    try {
+        map(fun (x) { throw x * 10; }, numbers);
+    } catch (e) {
+        print e;
+    }
+    print map(len, "abc");
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(
+        out,
+        "2\n9\n30\n4\n10\nerror: map expects an array but found s:abc\n"
+    );
+}
+
+#[test]
+fn compare_contents() {
+    let source = r#"
+    let a = [1; 3];
+    let b = [1; 3];
+    print a == b;
+    b[2] = 2;
+    print a != b;
+    print { "k": (1, "x") } == { "k": (1, "x") };
+    print equals(a, 1);
+    push(a, a);
+    let c = [1; 3];
+    push(c, c);
+    print a == c;
+    "#;
+    let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![])));
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "true\ntrue\ntrue\nfalse\ntrue\n");
+    assert_eq!(
+        vm.eval("let x = [1; 1] == 1;").unwrap_err().code(),
+        "type-mismatch"
+    );
+}
+
+#[test]
+fn value_types() {
+    let source = r#"
+    let x = 1;
+    fun f() { return x; }
+    print typeof(1.5);
+    print typeof("s");
+    print typeof(true);
+    print typeof(nil);
+    print typeof(f);
+    print typeof(len);
+    print typeof(fun () { return x; });
+    print typeof([0; 1]);
+    print typeof({});
+    print typeof(sqrt("a"));
+    print is_number(1) && is_string("s") && is_bool(false) && is_nil(nil);
+    print is_function(f) && is_array([0; 1]) && is_map({});
+    print is_string(1) || is_map([0; 1]);
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(
+        out,
+        "number\nstring\nbool\nnil\nfunction\nfunction\nfunction\narray\nmap\nerror\ntrue\ntrue\nfalse\n"
+    );
+}
+
+#[test]
+fn integers() {
+    let source = r#"
+    let big = 9007199254740993;
+    print big + 1;
+    print typeof(2 * 3);
+    print typeof(2 * 1.5);
+    print 7 / 2;
+    print 2 ** 10;
+    print 1 == 1.0;
+    print as_int(-2.7);
+    print as_int("42") + 1;
+    print as_float(3) / 2;
+    print is_int(len("abc")) && is_number(1);
+    for (i in 0..2) { print typeof(i); }
+    "#;
+    let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![])));
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(
+        out,
+        "9007199254740994\nint\nnumber\n3.5\n1024\ntrue\n-2\n43\n1.5\ntrue\nint\nint\n"
+    );
+    let error = vm.eval("let x = 9223372036854775807 + 1;").unwrap_err();
+    assert_eq!(error.code(), "integer-overflow");
+}
+
+#[test]
+fn native_errors_are_values() {
+    let source = r#"
+    fun safe_len(value) {
+        let n = len(value);
+        if (is_error(n)) {
+            print as_string(n);
+            return 0;
+        }
+        return n;
+    }
+    fun half(n) {
+        return n < 0 ? error("negative number") : n / 2;
+    }
+    print safe_len("abc") + safe_len(42);
+    print is_error(half(4));
+    print half(-4);
+    print is_error(has_key({}, 1));
+    "#;
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(
+        out,
+        "len expects a string, an array or a map but found i:42\n3\nfalse\nerror: negative number\ntrue\n"
+    );
+}
+
+#[test]
+fn parsing_errors_point_at_token_start() {
+    let error = brainterpreter::interpret("let x = 1 + ==;").unwrap_err();
+
+    assert_eq!(error.position(), Some(Position::new(1, 13)));
+}
+
+#[test]
+fn print_function() {
+    let source = r#"
+    let x = 42;
+    print("x =", x);
+    print("single");
+    let show = print;
+    show(1, true, nil);
+    print show(2) == nil;
+    print();
+    let t = (1, 2);
+    print t;
+    "#;
+
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "x = 42\nsingle\n1 true nil\n2\ntrue\n\n(1, 2)\n");
+}
+
+#[test]
+fn formatted_output() {
+    let source = r#"
+    let name = "pi";
+    print format("{} is {:.2}", name, 3.14159);
+    print format("{{}} and {}", [1; 2][0]);
+    printf("{}, {}!", "Hello", "World");
+    print "";
+    print format("{} and {}", 1);
+    print format("{}", 1, 2);
+    print format("{:.2}", "text");
+    print format("{:x}", 1);
+    print format("{:.70000}", 1.5);
+    "#;
+
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(
+        out,
+        "pi is 3.14\n{} and 1\nHello, World!\n\
+         error: format expects an argument for every placeholder\n\
+         error: format expects a placeholder for every argument\n\
+         error: format expects a number but found s:text\n\
+         error: format does not support placeholder {:x}\n\
+         error: format does not support placeholder {:.70000}\n"
+    );
+}
+
+#[test]
+fn process_natives() {
+    let out = Rc::new(RefCell::new(vec![]));
+    let mut vm = Vm::with_io(out.clone()).with_args(["first", "second"]);
+
+    vm.eval("print len(args()); print args()[1]; print env(\"BAUBLE_UNSET_VARIABLE\");")
+        .unwrap();
+    let error = vm
+        .eval("try { exit(3); } catch (e) { print e; }")
+        .unwrap_err();
+
+    assert_eq!(String::from_utf8_lossy(&out.borrow()), "2\nsecond\nnil\n");
+    assert_eq!(error.exit_code(), Some(3));
+    assert_eq!(error.code(), "exit");
+}
+
+#[test]
+fn denied_process_natives() {
+    let out = Rc::new(RefCell::new(vec![]));
+    let mut vm = Vm::with_io(out.clone())
+        .with_env_access(false)
+        .with_exit(false);
+
+    vm.eval("print env(\"PATH\"); print exit(1);").unwrap();
+
+    assert_eq!(
+        String::from_utf8_lossy(&out.borrow()),
+        "error: env is not allowed\nerror: exit is not allowed\n"
+    );
+}
+
+#[test]
+fn trace_calls() {
+    #[derive(Debug)]
+    struct CallTracer(Rc<RefCell<Vec<String>>>);
+
+    impl VmStepTrace for CallTracer {
+        fn trace(&mut self, event: TraceEvent) {
+            match event {
+                TraceEvent::CallEntered { function, depth } => {
+                    self.0.borrow_mut().push(format!("{} {}", function, depth))
+                }
+                TraceEvent::CallReturned { depth } => {
+                    self.0.borrow_mut().push(format!("return {}", depth))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let calls = Rc::new(RefCell::new(vec![]));
+    let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![]))).with_tracer(CallTracer(calls.clone()));
+
+    vm.eval("fun inner() { return 1; } fun outer() { let a = inner(); return a; } outer();")
+        .unwrap();
+
+    assert_eq!(
+        *calls.borrow(),
+        vec!["outer 2", "inner 3", "return 2", "return 1", "return 0"]
+    );
+}
+
+#[test]
+fn calls_share_function_chunks() {
+    #[derive(Debug)]
+    struct ChunkTracer(Rc<RefCell<Vec<Rc<Chunk>>>>);
+
+    impl VmStepTrace for ChunkTracer {
+        fn trace(&mut self, event: TraceEvent) {
+            if let TraceEvent::Instruction { chunk, .. } = event {
+                self.0.borrow_mut().push(chunk.clone());
+            }
+        }
+    }
+
+    let chunks = Rc::new(RefCell::new(vec![]));
+    let mut vm =
+        Vm::with_io(Rc::new(RefCell::new(vec![]))).with_tracer(ChunkTracer(chunks.clone()));
+
+    vm.eval("fun one() { return 1; } one(); one();").unwrap();
+
+    let Some(ValueType::Function(function)) = vm.global("one") else {
+        panic!("function expected");
+    };
+    let calls = chunks
+        .borrow()
+        .iter()
+        .filter(|chunk| Rc::ptr_eq(chunk, function.chunk()))
+        .count();
+    // Both calls run the loading of the constant and the return from the same chunk.
+    assert_eq!(calls, 4);
+}
+
+#[test]
+fn truthiness() {
+    let source = r#"
+    if (1) { print "number"; }
+    if ("") { print "string"; }
+    if (nil) { print "nil"; } else { print "not nil"; }
+    let i = 2;
+    while (i) { i = i > 0 ? i - 1 : nil; }
+    print i;
+    print !0;
+    print !nil;
+    print 1 && "two";
+    "#;
+
+    let out = interpret_to_string(source).unwrap();
+
+    assert_eq!(out, "number\nstring\nnot nil\nnil\nfalse\ntrue\ntwo\n");
+}
+
+#[test]
+fn strict_conditions() {
+    let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![]))).with_strict_conditions(true);
+
+    let error = vm.eval("if (1) { print 1; }").unwrap_err();
+
+    assert_eq!(error.code(), "type-mismatch");
+    assert!(vm.eval("print !1;").is_err());
+    assert!(vm.eval("if (true) { print !false; }").is_ok());
+    assert_eq!(vm.eval("assert(1);").unwrap_err().code(), "type-mismatch");
+}
@@ -0,0 +1,82 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::rc::Rc;
+
+use brainterpreter::compiler::Compiler;
+use brainterpreter::lexer::Lexer;
+use brainterpreter::parser::Parser;
+use brainterpreter::value::ValueType;
+use brainterpreter::vm::exec::Chunk;
+use brainterpreter::vm::opcode::Op;
+use brainterpreter::vm::{StepOutcome, Vm};
+
+#[test]
+fn step_through_program() -> Result<(), Box<dyn Error>> {
+    let io = Rc::new(RefCell::new(vec![]));
+    let mut vm = Vm::with_io(io.clone());
+    vm.load(compile("print 1 + 2;")?);
+
+    assert_eq!(vm.step()?, StepOutcome::Continued);
+    assert_eq!(vm.ip(), 1);
+    assert_eq!(vm.stack().last(), Some(&ValueType::Int(2)));
+    assert_eq!(vm.step()?, StepOutcome::Continued);
+    assert_eq!(vm.step()?, StepOutcome::Continued);
+    assert_eq!(vm.stack().last(), Some(&ValueType::Int(3)));
+    assert!(io.borrow().is_empty());
+
+    assert_eq!(vm.step()?, StepOutcome::Continued);
+    assert_eq!(io.borrow().as_slice(), "3\n".as_bytes());
+    assert_eq!(vm.step()?, StepOutcome::Finished(ValueType::Nil));
+    assert!(vm.frame().is_none());
+    assert!(vm.stack().is_empty());
+    Ok(())
+}
+
+#[test]
+fn inspect_function_frames() -> Result<(), Box<dyn Error>> {
+    let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![])));
+    vm.load(compile("fun id(a) { return a; } id(7);")?);
+
+    let mut max_depth = 0;
+    while vm.step()? == StepOutcome::Continued {
+        max_depth = max_depth.max(vm.frames().len());
+    }
+
+    assert_eq!(max_depth, 2);
+    Ok(())
+}
+
+#[test]
+fn stop_at_breakpoint() -> Result<(), Box<dyn Error>> {
+    let chunk = Rc::new(
+        Chunk::try_new(
+            [
+                Op::ConstFloat(1.0),
+                Op::ConstFloat(2.0),
+                Op::Add,
+                Op::Return,
+            ],
+            [],
+        )
+        .unwrap(),
+    );
+    let mut vm = Vm::with_io(Rc::new(RefCell::new(vec![])));
+    vm.add_breakpoint(&chunk, 2);
+    vm.load(chunk);
+
+    assert_eq!(vm.step()?, StepOutcome::Continued);
+    assert_eq!(vm.step()?, StepOutcome::Continued);
+    assert_eq!(vm.step()?, StepOutcome::Breakpoint);
+    assert_eq!(vm.ip(), 2);
+    assert_eq!(vm.step()?, StepOutcome::Continued);
+    assert_eq!(vm.stack().last(), Some(&ValueType::Number(3.0)));
+    assert_eq!(vm.step()?, StepOutcome::Continued);
+    assert_eq!(vm.step()?, StepOutcome::Finished(ValueType::Number(3.0)));
+    Ok(())
+}
+
+fn compile(source: &str) -> Result<Rc<Chunk>, Box<dyn Error>> {
+    let ast = Parser::new(Lexer::new(source)).parse_program()?;
+    let chunk = Compiler::default().compile(ast)?;
+    Ok(Rc::new(chunk))
+}